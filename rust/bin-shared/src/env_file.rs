@@ -0,0 +1,77 @@
+//! Reading configuration from `KEY=value` files, in the format of systemd's `EnvironmentFile=`
+//!
+//! The env of a running process can't be changed from the outside,
+//! so the Gateway and Relay re-read their configuration from such a file on SIGHUP.
+
+use anyhow::{Context as _, Result};
+use std::{collections::BTreeMap, path::Path};
+
+/// Reads all `KEY=value` pairs from the file at `path`
+///
+/// Empty lines and lines starting with `#` or `;` are ignored.
+/// Values may be wrapped in single or double quotes.
+pub fn read(path: &Path) -> Result<BTreeMap<String, String>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read `{}`", path.display()))?;
+
+    parse(&content)
+}
+
+fn parse(content: &str) -> Result<BTreeMap<String, String>> {
+    content
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#') && !line.starts_with(';'))
+        .map(|(i, line)| {
+            let (key, value) = line
+                .split_once('=')
+                .with_context(|| format!("Line {} is not of the form `KEY=value`", i + 1))?;
+
+            Ok((key.trim().to_owned(), unquote(value.trim()).to_owned()))
+        })
+        .collect()
+}
+
+fn unquote(value: &str) -> &str {
+    for quote in ['"', '\''] {
+        if let Some(unquoted) = value
+            .strip_prefix(quote)
+            .and_then(|v| v.strip_suffix(quote))
+        {
+            return unquoted;
+        }
+    }
+
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_env_file() {
+        let content = r#"
+# Log more
+RUST_LOG=debug
+; Also a comment
+FIREZONE_TOKEN = "abc=def"
+HEALTH_CHECK_ADDR='0.0.0.0:8081'
+FIREZONE_NAME=
+"#;
+
+        let vars = parse(content).unwrap();
+
+        assert_eq!(vars["RUST_LOG"], "debug");
+        assert_eq!(vars["FIREZONE_TOKEN"], "abc=def");
+        assert_eq!(vars["HEALTH_CHECK_ADDR"], "0.0.0.0:8081");
+        assert_eq!(vars["FIREZONE_NAME"], "");
+        assert_eq!(vars.len(), 4);
+    }
+
+    #[test]
+    fn rejects_lines_without_equals() {
+        assert!(parse("RUST_LOG").is_err());
+    }
+}
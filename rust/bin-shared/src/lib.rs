@@ -1,6 +1,12 @@
 #![cfg_attr(test, allow(clippy::unwrap_used))]
 
+pub mod env_file;
 pub mod http_health_check;
+pub mod log_bundle;
+pub mod portal_tls;
+pub mod reload;
+pub mod telemetry;
+pub mod token;
 
 mod network_changes;
 mod tun_device_manager;
@@ -44,4 +50,4 @@ pub const FIREZONE_MARK: u32 = 0xfd002021;
 pub use network_changes::{new_dns_notifier, new_network_notifier};
 
 #[cfg(any(target_os = "linux", target_os = "windows"))]
-pub use tun_device_manager::TunDeviceManager;
+pub use tun_device_manager::{Tun, TunDeviceManager};
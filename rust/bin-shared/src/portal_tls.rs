@@ -0,0 +1,63 @@
+//! Configuring how we authenticate the portal's TLS certificate
+
+use anyhow::{Context as _, Result};
+use phoenix_channel::{SpkiPin, TlsConfig};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// CLI args (and GUI settings) for trusting a custom CA or pinning the portal's public key
+#[derive(clap::Args, Debug, Default, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct PortalTlsArgs {
+    /// PEM files with additional root certificates to trust for the portal, e.g. of an internal CA
+    #[arg(
+        long = "portal-ca-file",
+        env = "FIREZONE_PORTAL_CA_FILE",
+        value_delimiter = ','
+    )]
+    #[serde(default)]
+    pub ca_files: Vec<PathBuf>,
+
+    /// Only trust the roots from `--portal-ca-file`, not the built-in ones
+    #[arg(
+        long = "portal-ca-only",
+        env = "FIREZONE_PORTAL_CA_ONLY",
+        default_value_t = false,
+        requires = "ca_files"
+    )]
+    #[serde(default)]
+    pub ca_only: bool,
+
+    /// Base64-encoded SHA-256 hashes of public keys, one of which must be part of the portal's certificate chain
+    #[arg(
+        long = "portal-pin-sha256",
+        env = "FIREZONE_PORTAL_PIN_SHA256",
+        value_delimiter = ','
+    )]
+    #[serde(default)]
+    pub pins: Vec<String>,
+}
+
+impl PortalTlsArgs {
+    /// Builds the [`TlsConfig`] for `PhoenixChannel::with_tls`
+    ///
+    /// Returns `Ok(None)` if nothing is configured and the default roots should be used.
+    pub fn tls_config(&self) -> Result<Option<TlsConfig>> {
+        if self == &Self::default() {
+            return Ok(None);
+        }
+
+        let pins = self
+            .pins
+            .iter()
+            .map(|pin| {
+                pin.parse::<SpkiPin>()
+                    .with_context(|| format!("Invalid public key pin `{pin}`"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let config = TlsConfig::new(&self.ca_files, self.ca_only, pins)
+            .context("Failed to configure TLS for the portal")?;
+
+        Ok(Some(config))
+    }
+}
@@ -0,0 +1,150 @@
+//! Settings that the Gateway and Relay can change at runtime, after re-reading their config file on SIGHUP
+
+use crate::{env_file, http_health_check};
+use anyhow::Result;
+use firezone_logging::{anyhow_dyn_err, FilterReloadHandle};
+use std::{collections::BTreeMap, net::SocketAddr, path::PathBuf, sync::Arc};
+use tokio::task::JoinHandle;
+
+/// The config file we were started with, see [`env_file`]
+pub struct ConfigFile {
+    path: PathBuf,
+    /// The values from the last time we read the file.
+    values: BTreeMap<String, String>,
+}
+
+impl ConfigFile {
+    /// Reads the file's current values, so that a later [`ConfigFile::changes`] only returns what changed since startup
+    ///
+    /// Settings may also come from CLI flags, so we can't tell what changed by comparing against our env.
+    pub fn new(path: PathBuf) -> Self {
+        let values = env_file::read(&path).unwrap_or_else(|e| {
+            tracing::warn!(error = anyhow_dyn_err(&e), "Failed to read config file");
+
+            BTreeMap::default()
+        });
+
+        Self { path, values }
+    }
+
+    /// Re-reads the file and returns all settings that were added or changed since the last read
+    pub fn changes(&mut self) -> Result<BTreeMap<String, String>> {
+        let values = env_file::read(&self.path)?;
+
+        let changes = values
+            .iter()
+            .filter(|(key, value)| self.values.get(*key) != Some(*value))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        self.values = values;
+
+        Ok(changes)
+    }
+}
+
+/// Warns that a setting changed in the config file but can only be applied by restarting
+pub fn warn_requires_restart(key: &str) {
+    tracing::warn!(%key, "Changing this setting requires a restart");
+}
+
+/// The log filter, i.e. `RUST_LOG`
+pub struct LogFilter {
+    directives: String,
+    reloader: FilterReloadHandle,
+}
+
+impl LogFilter {
+    pub fn new(reloader: FilterReloadHandle) -> Self {
+        Self {
+            directives: std::env::var("RUST_LOG").unwrap_or_default(),
+            reloader,
+        }
+    }
+
+    pub fn set(&mut self, directives: String) -> Result<()> {
+        if directives == self.directives {
+            return Ok(());
+        }
+
+        self.reloader.reload(&directives)?;
+        tracing::info!(%directives, "Applied new log filter");
+        self.directives = directives;
+
+        Ok(())
+    }
+}
+
+/// The HTTP health check endpoint, which can be moved to a different address
+pub struct HealthCheck {
+    addr: SocketAddr,
+    is_healthy: Arc<dyn Fn() -> bool + Send + Sync>,
+    task: JoinHandle<std::io::Result<()>>,
+}
+
+impl HealthCheck {
+    /// Starts serving the health check on `addr`
+    pub fn spawn(addr: SocketAddr, is_healthy: impl Fn() -> bool + Send + Sync + 'static) -> Self {
+        let is_healthy = Arc::new(is_healthy);
+
+        Self {
+            addr,
+            task: serve(addr, is_healthy.clone()),
+            is_healthy,
+        }
+    }
+
+    pub fn set_addr(&mut self, addr: SocketAddr) {
+        if addr == self.addr {
+            return;
+        }
+
+        self.task.abort();
+        self.task = serve(addr, self.is_healthy.clone());
+        self.addr = addr;
+
+        tracing::info!(%addr, "Moved health check endpoint");
+    }
+}
+
+impl Drop for HealthCheck {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+fn serve(
+    addr: SocketAddr,
+    is_healthy: Arc<dyn Fn() -> bool + Send + Sync>,
+) -> JoinHandle<std::io::Result<()>> {
+    tokio::spawn(http_health_check::serve(addr, move || is_healthy()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_reports_changed_settings() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("gateway.env");
+        std::fs::write(&path, "RUST_LOG=info\nFIREZONE_NAME=gateway-1\n").unwrap();
+
+        let mut config_file = ConfigFile::new(path.clone());
+        std::fs::write(
+            &path,
+            "RUST_LOG=debug\nFIREZONE_NAME=gateway-1\nLOWEST_PORT=50000\n",
+        )
+        .unwrap();
+
+        let changes = config_file.changes().unwrap();
+
+        assert_eq!(
+            changes,
+            BTreeMap::from([
+                ("LOWEST_PORT".to_owned(), "50000".to_owned()),
+                ("RUST_LOG".to_owned(), "debug".to_owned()),
+            ])
+        );
+        assert!(config_file.changes().unwrap().is_empty());
+    }
+}
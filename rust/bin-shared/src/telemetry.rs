@@ -0,0 +1,131 @@
+//! Configuring where telemetry is sent, e.g. for self-hosted deployments
+
+use firezone_telemetry::Config;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// CLI args (and GUI settings) for overriding the telemetry DSN, sample rates and environment
+#[derive(clap::Args, Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+pub struct TelemetryArgs {
+    /// Sentry-compatible DSN to report errors to instead of Firezone, e.g. of a self-hosted Sentry or GlitchTip
+    #[arg(long = "telemetry-dsn", env = "FIREZONE_TELEMETRY_DSN")]
+    #[serde(default)]
+    pub dsn: Option<String>,
+
+    /// Environment name to report instead of the one derived from the API URL
+    #[arg(long = "telemetry-environment", env = "FIREZONE_TELEMETRY_ENVIRONMENT")]
+    #[serde(default)]
+    pub environment: Option<String>,
+
+    /// Fraction of errors to report, from 0.0 to 1.0
+    #[arg(
+        long = "telemetry-sample-rate",
+        env = "FIREZONE_TELEMETRY_SAMPLE_RATE",
+        value_parser = parse_sample_rate
+    )]
+    #[serde(default)]
+    pub sample_rate: Option<f32>,
+
+    /// Fraction of telemetry spans to report, from 0.0 to 1.0
+    #[arg(
+        long = "telemetry-traces-sample-rate",
+        env = "FIREZONE_TELEMETRY_TRACES_SAMPLE_RATE",
+        value_parser = parse_sample_rate
+    )]
+    #[serde(default)]
+    pub traces_sample_rate: Option<f32>,
+
+    /// Append error reports as JSON lines to this file instead of sending them
+    #[arg(long = "telemetry-file", env = "FIREZONE_TELEMETRY_FILE")]
+    #[serde(default)]
+    pub file: Option<PathBuf>,
+}
+
+impl TelemetryArgs {
+    /// Reads the overrides from the `FIREZONE_TELEMETRY_*` env vars only, ignoring the CLI args.
+    pub fn from_env() -> Result<Self, clap::Error> {
+        #[derive(clap::Parser)]
+        struct Env {
+            #[command(flatten)]
+            telemetry: TelemetryArgs,
+        }
+
+        let env = <Env as clap::Parser>::try_parse_from([env!("CARGO_PKG_NAME")])?;
+
+        Ok(env.telemetry)
+    }
+
+    /// Applies the DSN, environment and sample rates of `other` on top of these, e.g. the GUI's settings on top of the IPC service's environment.
+    ///
+    /// Always keeps our own `file` because `other` may be less privileged than us and must not choose where we write to.
+    pub fn with_overrides_from(self, other: Self) -> Self {
+        Self {
+            dsn: other.dsn.or(self.dsn),
+            environment: other.environment.or(self.environment),
+            sample_rate: other.sample_rate.or(self.sample_rate),
+            traces_sample_rate: other.traces_sample_rate.or(self.traces_sample_rate),
+            file: self.file,
+        }
+    }
+
+    /// Builds the [`Config`] for `Telemetry::start_with_config`
+    pub fn config(&self) -> Config {
+        Config {
+            dsn: self.dsn.clone(),
+            environment: self.environment.clone(),
+            sample_rate: self.sample_rate,
+            traces_sample_rate: self.traces_sample_rate,
+            file: self.file.clone(),
+        }
+    }
+}
+
+fn parse_sample_rate(s: &str) -> Result<f32, String> {
+    let rate = s.parse::<f32>().map_err(|e| e.to_string())?;
+
+    if !(0.0..=1.0).contains(&rate) {
+        return Err(format!("{rate} is not between 0.0 and 1.0"));
+    }
+
+    Ok(rate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_rate_must_be_a_fraction() {
+        assert_eq!(parse_sample_rate("0.25"), Ok(0.25));
+        assert!(parse_sample_rate("1.5").is_err());
+        assert!(parse_sample_rate("-0.1").is_err());
+        assert!(parse_sample_rate("all").is_err());
+    }
+
+    #[test]
+    fn overrides_never_replace_the_file() {
+        let own = TelemetryArgs {
+            dsn: Some("https://own@example.com/1".to_owned()),
+            sample_rate: Some(0.5),
+            file: Some(PathBuf::from("/var/log/firezone/telemetry.jsonl")),
+            ..Default::default()
+        };
+        let gui = TelemetryArgs {
+            dsn: Some("https://gui@example.com/2".to_owned()),
+            environment: Some("staging".to_owned()),
+            file: Some(PathBuf::from("/etc/shadow")),
+            ..Default::default()
+        };
+
+        let merged = own.with_overrides_from(gui);
+
+        assert_eq!(merged.dsn.as_deref(), Some("https://gui@example.com/2"));
+        assert_eq!(merged.environment.as_deref(), Some("staging"));
+        assert_eq!(merged.sample_rate, Some(0.5));
+        assert_eq!(merged.traces_sample_rate, None);
+        assert_eq!(
+            merged.file,
+            Some(PathBuf::from("/var/log/firezone/telemetry.jsonl"))
+        );
+    }
+}
@@ -0,0 +1,172 @@
+//! Reading the portal token from stdin or from an external secrets manager
+//!
+//! Env vars and files on disk are handled by each binary itself.
+
+use anyhow::{bail, Context as _, Result};
+use secrecy::{ExposeSecret as _, SecretString};
+use std::{process::Stdio, time::Duration};
+use tokio::{io::AsyncReadExt as _, process::Command};
+
+/// How long we wait for the token command before giving up on it.
+const TOKEN_COMMAND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// CLI args for alternative sources of the portal token
+#[derive(clap::Args, Debug, Default, Clone)]
+pub struct TokenSourceArgs {
+    /// Read the token from stdin
+    #[arg(long, conflicts_with = "token_command")]
+    pub token_stdin: bool,
+
+    /// A shell command that prints the token to stdout, e.g. `systemd-creds cat firezone-token`
+    ///
+    /// If the portal rejects the token, the command is run again to fetch a new one.
+    #[arg(long, env = "FIREZONE_TOKEN_COMMAND")]
+    pub token_command: Option<String>,
+}
+
+impl TokenSourceArgs {
+    /// Reads the token from stdin or the token command, if either of them is set
+    ///
+    /// Completes once stdin is closed or the command exits.
+    /// The command is killed if it doesn't exit within [`TOKEN_COMMAND_TIMEOUT`].
+    pub async fn read(&self) -> Result<Option<SecretString>> {
+        if self.token_stdin {
+            return read_stdin().await.map(Some);
+        }
+
+        if let Some(command) = self.token_command.as_deref() {
+            return run_command(command).await.map(Some);
+        }
+
+        Ok(None)
+    }
+
+    /// Fetches a new token after the portal rejected `rejected`
+    ///
+    /// Returns `Ok(None)` if we have no way of getting a new token or the command gave us the same token again.
+    pub async fn refresh(&self, rejected: &SecretString) -> Result<Option<SecretString>> {
+        let Some(command) = self.token_command.as_deref() else {
+            return Ok(None);
+        };
+
+        tracing::info!("Portal rejected our token, running the token command again");
+
+        let token = run_command(command).await?;
+
+        if token.expose_secret() == rejected.expose_secret() {
+            tracing::warn!("Token command returned the rejected token again");
+
+            return Ok(None);
+        }
+
+        Ok(Some(token))
+    }
+}
+
+async fn read_stdin() -> Result<SecretString> {
+    let mut token = String::new();
+    tokio::io::stdin()
+        .read_to_string(&mut token)
+        .await
+        .context("Failed to read token from stdin")?;
+
+    parse_token(token).context("Failed to read token from stdin")
+}
+
+async fn run_command(command: &str) -> Result<SecretString> {
+    let output = shell(command)
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .kill_on_drop(true) // Ensures a command that hangs doesn't outlive the timeout.
+        .output();
+    let output = tokio::time::timeout(TOKEN_COMMAND_TIMEOUT, output)
+        .await
+        .with_context(|| format!("Token command did not exit within {TOKEN_COMMAND_TIMEOUT:?}"))?
+        .context("Failed to run token command")?;
+
+    if !output.status.success() {
+        bail!("Token command failed: {}", output.status);
+    }
+
+    let token = String::from_utf8(output.stdout).context("Token is not valid UTF-8")?;
+
+    parse_token(token).context("Failed to read token from command")
+}
+
+#[cfg(not(windows))]
+fn shell(command: &str) -> Command {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg(command);
+
+    cmd
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut cmd = Command::new("cmd");
+    cmd.arg("/C").arg(command);
+
+    cmd
+}
+
+fn parse_token(token: String) -> Result<SecretString> {
+    let trimmed = token.trim();
+
+    if trimmed.is_empty() {
+        bail!("Token is empty");
+    }
+
+    Ok(SecretString::from(trimmed.to_owned()))
+}
+
+#[cfg(all(test, not(windows)))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn reads_token_from_command() {
+        let args = TokenSourceArgs {
+            token_stdin: false,
+            token_command: Some("echo ' my-token '".to_owned()),
+        };
+
+        let token = args.read().await.unwrap().unwrap();
+
+        assert_eq!(token.expose_secret(), "my-token");
+    }
+
+    #[tokio::test]
+    async fn failing_command_is_an_error() {
+        let args = TokenSourceArgs {
+            token_stdin: false,
+            token_command: Some("exit 1".to_owned()),
+        };
+
+        assert!(args.read().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn empty_token_is_an_error() {
+        let args = TokenSourceArgs {
+            token_stdin: false,
+            token_command: Some("true".to_owned()),
+        };
+
+        assert!(args.read().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn refresh_gives_up_on_same_token() {
+        let args = TokenSourceArgs {
+            token_stdin: false,
+            token_command: Some("echo my-token".to_owned()),
+        };
+
+        let refreshed = args
+            .refresh(&SecretString::from("my-token".to_owned()))
+            .await
+            .unwrap();
+
+        assert!(refreshed.is_none());
+    }
+}
@@ -11,7 +11,7 @@ pub mod windows;
 pub use windows as platform;
 
 #[cfg(any(target_os = "linux", target_os = "windows"))]
-pub use platform::TunDeviceManager;
+pub use platform::{Tun, TunDeviceManager};
 
 #[cfg(test)]
 #[cfg(any(target_os = "linux", target_os = "windows"))]
@@ -104,6 +104,7 @@ mod tests {
                 packet: Cow::Borrowed(&hex_literal::hex!(
                     "000100002112A4420123456789abcdef01234567"
                 )),
+                segment_size: None,
             })
             .unwrap();
 
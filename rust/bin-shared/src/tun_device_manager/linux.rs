@@ -30,6 +30,12 @@ use tokio::io::unix::AsyncFd;
 use tun::ioctl;
 
 const TUNSETIFF: libc::c_ulong = 0x4004_54ca;
+const TUNSETOFFLOAD: libc::c_ulong = 0x4004_54d0;
+const TUN_F_CSUM: libc::c_uint = 0x01;
+const TUN_F_TSO4: libc::c_uint = 0x02;
+const TUN_F_TSO6: libc::c_uint = 0x04;
+const TUN_F_USO4: libc::c_uint = 0x20;
+const TUN_F_USO6: libc::c_uint = 0x40;
 const TUN_DEV_MAJOR: u32 = 10;
 const TUN_DEV_MINOR: u32 = 200;
 
@@ -41,6 +47,7 @@ const FIREZONE_TABLE: u32 = 0x2021_fd00;
 /// For lack of a better name
 pub struct TunDeviceManager {
     mtu: u32,
+    offload: bool,
     connection: Connection,
     routes: HashSet<IpNetwork>,
 }
@@ -71,11 +78,21 @@ impl TunDeviceManager {
             connection,
             routes: Default::default(),
             mtu: mtu as u32,
+            offload: false,
         })
     }
 
+    /// Opens the TUN device with TSO / USO offloads, see [`Tun::new`].
+    pub fn with_offload(mut self, offload: bool) -> Self {
+        self.offload = offload;
+        self
+    }
+
     pub fn make_tun(&mut self) -> Result<Tun> {
-        Ok(Tun::new()?)
+        let tun = Tun::new(self.offload)?;
+        self.routes.clear(); // A new device starts without any routes.
+
+        Ok(tun)
     }
 
     #[tracing::instrument(level = "trace", skip(self))]
@@ -169,7 +186,21 @@ impl TunDeviceManager {
             .chain(ipv6.into_iter().map(IpNetwork::from))
             .collect();
 
-        tracing::info!(?new_routes, "Setting new routes");
+        let added = new_routes
+            .difference(&self.routes)
+            .copied()
+            .collect::<Vec<_>>();
+        let removed = self
+            .routes
+            .difference(&new_routes)
+            .copied()
+            .collect::<Vec<_>>();
+
+        if added.is_empty() && removed.is_empty() {
+            return Ok(());
+        }
+
+        tracing::info!(?added, ?removed, "Updating routes");
 
         let handle = &self.connection.handle;
 
@@ -184,15 +215,19 @@ impl TunDeviceManager {
             .header
             .index;
 
-        for route in self.routes.difference(&new_routes) {
-            remove_route(route, index, handle).await;
+        // Only remember the routes that are actually installed, so we retry the others on the next update.
+        for route in removed {
+            if remove_route(&route, index, handle).await {
+                self.routes.remove(&route);
+            }
         }
 
-        for route in &new_routes {
-            add_route(route, index, handle).await;
+        for route in added {
+            if add_route(&route, index, handle).await {
+                self.routes.insert(route);
+            }
         }
 
-        self.routes = new_routes;
         Ok(())
     }
 }
@@ -241,7 +276,8 @@ fn make_route_v6(idx: u32, handle: &Handle, route: Ipv6Network) -> RouteAddReque
         .destination_prefix(route.network_address(), route.netmask())
 }
 
-async fn add_route(route: &IpNetwork, idx: u32, handle: &Handle) {
+/// Returns whether the route exists now.
+async fn add_route(route: &IpNetwork, idx: u32, handle: &Handle) -> bool {
     let res = match route {
         IpNetwork::V4(ipnet) => make_route_v4(idx, handle, *ipnet).execute().await,
         IpNetwork::V6(ipnet) => make_route_v6(idx, handle, *ipnet).execute().await,
@@ -250,18 +286,21 @@ async fn add_route(route: &IpNetwork, idx: u32, handle: &Handle) {
     let Err(err) = res else {
         tracing::debug!(%route, iface_idx = %idx, "Created new route");
 
-        return;
+        return true;
     };
 
-    // We expect this to be called often with an already existing route since set_routes always calls for all routes
+    // The route may still exist if we failed to remove it earlier.
     if matches!(&err, NetlinkError(err) if err.raw_code() == -EEXIST) {
-        return;
+        return true;
     }
 
     tracing::warn!(error = std_dyn_err(&err), %route, "Failed to add route");
+
+    false
 }
 
-async fn remove_route(route: &IpNetwork, idx: u32, handle: &Handle) {
+/// Returns whether the route is gone now.
+async fn remove_route(route: &IpNetwork, idx: u32, handle: &Handle) -> bool {
     let message = match route {
         IpNetwork::V4(ipnet) => make_route_v4(idx, handle, *ipnet).message_mut().clone(),
         IpNetwork::V6(ipnet) => make_route_v6(idx, handle, *ipnet).message_mut().clone(),
@@ -272,24 +311,31 @@ async fn remove_route(route: &IpNetwork, idx: u32, handle: &Handle) {
     let Err(err) = res else {
         tracing::debug!(%route, iface_idx = %idx, "Removed route");
 
-        return;
+        return true;
     };
 
     // Our view of the current routes may be stale. Removing a route that no longer exists shouldn't print a warning.
     if matches!(&err, NetlinkError(err) if err.raw_code() == -ENOENT) {
-        return;
+        return true;
     }
 
     tracing::warn!(error = std_dyn_err(&err), %route, "Failed to remove route");
+
+    false
 }
 
 #[derive(Debug)]
 pub struct Tun {
     fd: AsyncFd<RawFd>,
+    vnet_hdr: bool,
 }
 
 impl Tun {
-    pub fn new() -> io::Result<Self> {
+    /// Opens the TUN device.
+    ///
+    /// With `offload`, the kernel hands us TCP (and on Linux 6.2+ also UDP) segments of up to 64KB and accepts such batches when we write.
+    /// Every packet is then prefixed with a [`tun::vnet::Header`].
+    pub fn new(offload: bool) -> io::Result<Self> {
         create_tun_device()?;
 
         let fd = match unsafe { open(TUN_FILE.as_ptr() as _, O_RDWR) } {
@@ -297,19 +343,37 @@ impl Tun {
             fd => fd,
         };
 
+        let mut request =
+            ioctl::Request::<ioctl::SetTunFlagsPayload>::new(TunDeviceManager::IFACE_NAME);
+        if offload {
+            request = request.with_vnet_hdr();
+        }
+
         // Safety: We just opened the file descriptor.
         unsafe {
-            ioctl::exec(
-                fd,
-                TUNSETIFF,
-                &mut ioctl::Request::<ioctl::SetTunFlagsPayload>::new(TunDeviceManager::IFACE_NAME),
-            )?;
+            ioctl::exec(fd, TUNSETIFF, &mut request)?;
+        }
+
+        if offload {
+            // Safety: We just opened the file descriptor.
+            unsafe { set_offload(fd) };
         }
 
         set_non_blocking(fd)?;
 
         // Safety: We just opened the fd.
-        unsafe { Self::from_fd(fd) }
+        unsafe { Self::from_fd(fd, offload) }
+    }
+
+    /// Opens another handle to the same TUN device, e.g. for writing to it from another thread.
+    pub fn try_clone(&self) -> io::Result<Self> {
+        let fd = match unsafe { libc::dup(self.fd.as_raw_fd()) } {
+            -1 => return Err(get_last_error()),
+            fd => fd,
+        };
+
+        // Safety: We just duplicated the fd.
+        unsafe { Self::from_fd(fd, self.vnet_hdr) }
     }
 
     /// Create a new [`Tun`] from a raw file descriptor.
@@ -317,13 +381,39 @@ impl Tun {
     /// # Safety
     ///
     /// The file descriptor must be open.
-    unsafe fn from_fd(fd: RawFd) -> io::Result<Self> {
+    unsafe fn from_fd(fd: RawFd, vnet_hdr: bool) -> io::Result<Self> {
         Ok(Tun {
             fd: AsyncFd::new(fd)?,
+            vnet_hdr,
         })
     }
 }
 
+/// Enables TSO and, if the kernel supports it, USO on the TUN device.
+///
+/// Failing to do so is not fatal: the kernel then just doesn't hand us any GSO batches.
+///
+/// # Safety
+///
+/// The file descriptor must be open.
+unsafe fn set_offload(fd: RawFd) {
+    let tso = TUN_F_CSUM | TUN_F_TSO4 | TUN_F_TSO6;
+
+    if unsafe { libc::ioctl(fd, TUNSETOFFLOAD as _, tso | TUN_F_USO4 | TUN_F_USO6) } == 0 {
+        tracing::debug!("Enabled TSO and USO on TUN device");
+        return;
+    }
+
+    // USO requires Linux 6.2.
+    match unsafe { libc::ioctl(fd, TUNSETOFFLOAD as _, tso) } {
+        0 => tracing::debug!("Enabled TSO on TUN device"),
+        _ => tracing::warn!(
+            error = std_dyn_err(&get_last_error()),
+            "Failed to enable offloads on TUN device"
+        ),
+    }
+}
+
 impl Drop for Tun {
     fn drop(&mut self) {
         unsafe { close(self.fd.as_raw_fd()) };
@@ -346,6 +436,10 @@ impl tun::Tun for Tun {
     fn name(&self) -> &str {
         TunDeviceManager::IFACE_NAME
     }
+
+    fn vnet_hdr(&self) -> bool {
+        self.vnet_hdr
+    }
 }
 
 fn get_last_error() -> io::Error {
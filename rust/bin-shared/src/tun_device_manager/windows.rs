@@ -58,6 +58,7 @@ impl TunDeviceManager {
     pub fn make_tun(&mut self) -> Result<Tun> {
         let tun = Tun::new(self.mtu)?;
         self.iface_idx = Some(tun.iface_idx());
+        self.routes.clear(); // A new adapter starts without any routes.
 
         Ok(tun)
     }
@@ -102,28 +103,42 @@ impl TunDeviceManager {
             .iface_idx
             .context("Cannot set routes without having created TUN device")?;
 
-        let new_routes = HashSet::from_iter(
+        let new_routes: HashSet<IpNetwork> = HashSet::from_iter(
             v4.into_iter()
                 .map(IpNetwork::from)
                 .chain(v6.into_iter().map(IpNetwork::from)),
         );
 
-        for old_route in self.routes.difference(&new_routes) {
-            remove_route(*old_route, iface_idx);
+        let removed = self
+            .routes
+            .difference(&new_routes)
+            .copied()
+            .collect::<Vec<_>>();
+        let added = new_routes
+            .difference(&self.routes)
+            .copied()
+            .collect::<Vec<_>>();
+
+        // Only remember the routes that are actually installed, so we retry the others on the next update.
+        for old_route in removed {
+            if remove_route(old_route, iface_idx) {
+                self.routes.remove(&old_route);
+            }
         }
 
-        for new_route in &new_routes {
-            add_route(*new_route, iface_idx);
+        for new_route in added {
+            if add_route(new_route, iface_idx) {
+                self.routes.insert(new_route);
+            }
         }
 
-        self.routes = new_routes;
-
         Ok(())
     }
 }
 
 // It's okay if this blocks until the route is added in the OS.
-fn add_route(route: IpNetwork, iface_idx: u32) {
+/// Returns whether the route exists now.
+fn add_route(route: IpNetwork, iface_idx: u32) -> bool {
     const DUPLICATE_ERR: u32 = 0x80071392;
     let entry = forward_entry(route, iface_idx);
 
@@ -131,19 +146,23 @@ fn add_route(route: IpNetwork, iface_idx: u32) {
     let Err(e) = unsafe { CreateIpForwardEntry2(&entry) }.ok() else {
         tracing::debug!(%route, %iface_idx, "Created new route");
 
-        return;
+        return true;
     };
 
-    // We expect set_routes to call add_route with the same routes always making this error expected
+    // The route may still exist if we failed to remove it earlier
     if e.code().0 as u32 == DUPLICATE_ERR {
-        return;
+        return true;
     }
 
     tracing::warn!(error = std_dyn_err(&e), %route, "Failed to add route");
+
+    false
 }
 
-// It's okay if this blocks until the route is removed in the OS.
-fn remove_route(route: IpNetwork, iface_idx: u32) {
+/// Returns whether the route is gone now.
+///
+/// It's okay if this blocks until the route is removed in the OS.
+fn remove_route(route: IpNetwork, iface_idx: u32) -> bool {
     const ELEMENT_NOT_FOUND: u32 = 0x80070490;
     let entry = forward_entry(route, iface_idx);
 
@@ -152,14 +171,16 @@ fn remove_route(route: IpNetwork, iface_idx: u32) {
     let Err(e) = unsafe { DeleteIpForwardEntry2(&entry) }.ok() else {
         tracing::debug!(%route, %iface_idx, "Removed route");
 
-        return;
+        return true;
     };
 
     if e.code().0 as u32 == ELEMENT_NOT_FOUND {
-        return;
+        return true;
     }
 
-    tracing::warn!(error = std_dyn_err(&e), %route, "Failed to remove route")
+    tracing::warn!(error = std_dyn_err(&e), %route, "Failed to remove route");
+
+    false
 }
 
 fn forward_entry(route: IpNetwork, iface_idx: u32) -> MIB_IPFORWARD_ROW2 {
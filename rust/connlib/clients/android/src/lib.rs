@@ -369,6 +369,7 @@ fn connect(
         ExponentialBackoffBuilder::default()
             .with_max_elapsed_time(Some(MAX_PARTITION_TIME))
             .build(),
+        None,
         tcp_socket_factory,
     )?;
     let session = Session::connect(
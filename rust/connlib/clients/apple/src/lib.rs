@@ -245,6 +245,7 @@ impl WrappedSession {
             ExponentialBackoffBuilder::default()
                 .with_max_elapsed_time(Some(MAX_PARTITION_TIME))
                 .build(),
+            None,
             Arc::new(socket_factory::tcp),
         )?;
         let session = Session::connect(
@@ -1,4 +1,4 @@
-use connlib_model::ResourceView;
+use connlib_model::{GatewayId, ResourceChanges, ResourceView};
 use ip_network::{Ipv4Network, Ipv6Network};
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
@@ -21,6 +21,14 @@ pub trait Callbacks: Clone + Send + Sync {
     /// or if all Resources for a user are disabled by policy.
     fn on_update_resources(&self, _: Vec<ResourceView>) {}
 
+    /// Called right after [`Callbacks::on_update_resources`] with what changed since the last call.
+    ///
+    /// Allows large resource lists to be updated incrementally instead of rebuilt from scratch.
+    fn on_resources_changed(&self, _: ResourceChanges) {}
+
+    /// Called when the set of Gateways we are connected to changes.
+    fn on_update_gateways(&self, _: Vec<GatewayId>) {}
+
     /// Called when the tunnel is disconnected.
     fn on_disconnect(&self, _: &DisconnectError) {}
 }
@@ -4,7 +4,7 @@ use connlib_model::ResourceId;
 use firezone_logging::{anyhow_dyn_err, err_with_sources, std_dyn_err, telemetry_event};
 use firezone_tunnel::messages::{client::*, *};
 use firezone_tunnel::ClientTunnel;
-use phoenix_channel::{ErrorReply, OutboundRequestId, PhoenixChannel, PublicKeyParam};
+use phoenix_channel::{Delivery, ErrorReply, OutboundRequestId, PhoenixChannel, PublicKeyParam};
 use std::time::Instant;
 use std::{
     collections::{BTreeMap, BTreeSet},
@@ -146,12 +146,14 @@ where
                 resource,
                 ..
             } => {
-                let id = self.portal.send(
+                // Only the latest intent for a resource matters, see `SentConnectionIntents`.
+                let id = self.portal.send_with(
                     PHOENIX_TOPIC,
                     EgressMessages::PrepareConnection {
                         resource_id: resource,
                         connected_gateway_ids,
                     },
+                    Delivery::ReplaceByKey(format!("prepare_connection/{resource}")),
                 );
                 self.connection_intents.register_new_intent(id, resource);
             }
@@ -169,8 +171,19 @@ where
                     }),
                 );
             }
-            firezone_tunnel::ClientEvent::ResourcesChanged { resources } => {
-                self.callbacks.on_update_resources(resources)
+            firezone_tunnel::ClientEvent::ResourcesChanged { resources, changes } => {
+                tracing::debug!(
+                    added = changes.added.len(),
+                    changed = changes.changed.len(),
+                    removed = changes.removed.len(),
+                    "Resources changed"
+                );
+
+                self.callbacks.on_update_resources(resources);
+                self.callbacks.on_resources_changed(changes);
+            }
+            firezone_tunnel::ClientEvent::GatewaysChanged { gateways } => {
+                self.callbacks.on_update_gateways(Vec::from_iter(gateways))
             }
             firezone_tunnel::ClientEvent::TunInterfaceUpdated(config) => {
                 let dns_servers = config.dns_by_sentinel.left_values().copied().collect();
@@ -219,6 +232,16 @@ where
             phoenix_channel::Event::ErrorResponse { res, req_id, topic } => {
                 self.handle_portal_error_reply(res, topic, req_id);
             }
+            phoenix_channel::Event::MessageDropped {
+                topic,
+                req_id,
+                reason,
+            } => {
+                tracing::debug!(%topic, %req_id, %reason, "Message to portal was dropped");
+
+                // We won't get a reply for a dropped intent, a newer one may be on its way though.
+                self.connection_intents.handle_error(req_id);
+            }
             phoenix_channel::Event::HeartbeatSent => {}
             phoenix_channel::Event::JoinedRoom { .. } => {}
             phoenix_channel::Event::Closed => {
@@ -1,6 +1,7 @@
 use ip_network::IpNetwork;
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::Debug;
 
 use crate::ResourceId;
@@ -123,6 +124,67 @@ pub struct InternetResourceView {
     pub status: ResourceStatus,
 }
 
+/// The difference between two resource lists, see [`ResourceChanges::between`].
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct ResourceChanges {
+    /// Resources that weren't in the old list.
+    pub added: Vec<ResourceView>,
+    /// Resources whose details or status differ from the old list.
+    pub changed: Vec<ResourceView>,
+    /// Resources that aren't in the new list anymore.
+    pub removed: Vec<ResourceId>,
+}
+
+impl ResourceChanges {
+    /// Computes what changed from `old` to `new`, the order of the lists doesn't matter.
+    pub fn between(old: &[ResourceView], new: &[ResourceView]) -> Self {
+        let old = old.iter().map(|r| (r.id(), r)).collect::<BTreeMap<_, _>>();
+        let new_ids = new.iter().map(|r| r.id()).collect::<BTreeSet<_>>();
+
+        let mut changes = Self::default();
+
+        for resource in new {
+            match old.get(&resource.id()) {
+                None => changes.added.push(resource.clone()),
+                Some(previous) if *previous != resource => changes.changed.push(resource.clone()),
+                Some(_) => {}
+            }
+        }
+
+        changes.removed = old.into_keys().filter(|id| !new_ids.contains(id)).collect();
+
+        changes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.changed.is_empty() && self.removed.is_empty()
+    }
+
+    /// Applies these changes to the `old` list that they were computed from.
+    ///
+    /// The resulting list is sorted.
+    pub fn apply(self, resources: &mut Vec<ResourceView>) {
+        let removed = self.removed.into_iter().collect::<BTreeSet<_>>();
+        let mut changed = self
+            .changed
+            .into_iter()
+            .map(|r| (r.id(), r))
+            .collect::<BTreeMap<_, _>>();
+
+        resources.retain(|r| !removed.contains(&r.id()));
+
+        for resource in resources.iter_mut() {
+            if let Some(new) = changed.remove(&resource.id()) {
+                *resource = new;
+            }
+        }
+
+        resources.extend(self.added);
+        resources.extend(changed.into_values()); // Shouldn't happen but don't lose them.
+        resources.sort();
+    }
+}
+
 impl PartialOrd for ResourceView {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         Some(self.cmp(other))
@@ -150,7 +212,8 @@ mod tests {
     use itertools::Itertools;
 
     use super::{
-        DnsResourceView, InternetResourceView, ResourceId, ResourceStatus, ResourceView, Site,
+        DnsResourceView, InternetResourceView, ResourceChanges, ResourceId, ResourceStatus,
+        ResourceView, Site,
     };
 
     fn fake_resource(name: &str, uuid: &str) -> ResourceView {
@@ -228,4 +291,53 @@ mod tests {
             expected
         );
     }
+
+    #[test]
+    fn resource_changes_between_lists() {
+        let unchanged = fake_resource("Unchanged", "2efe9c25-bd92-49a0-99d7-8b92da014dd5");
+        let removed = fake_resource("Removed", "613eaf56-6efa-45e5-88aa-ea4ad64d8c18");
+        let renamed_before = fake_resource("Before", "624b7154-08f6-4c9e-bac0-c3a587fc9322");
+        let renamed_after = fake_resource("After", "624b7154-08f6-4c9e-bac0-c3a587fc9322");
+        let added = internet_resource("cb13bca0-490a-4aae-a039-31a8f93e2281");
+
+        let changes = ResourceChanges::between(
+            &[unchanged.clone(), removed.clone(), renamed_before],
+            &[added.clone(), renamed_after.clone(), unchanged],
+        );
+
+        assert_eq!(
+            changes,
+            ResourceChanges {
+                added: vec![added],
+                changed: vec![renamed_after],
+                removed: vec![removed.id()],
+            }
+        );
+    }
+
+    #[test]
+    fn no_resource_changes_for_reordered_list() {
+        let a = fake_resource("A", "2efe9c25-bd92-49a0-99d7-8b92da014dd5");
+        let b = fake_resource("B", "613eaf56-6efa-45e5-88aa-ea4ad64d8c18");
+
+        assert!(ResourceChanges::between(&[a.clone(), b.clone()], &[b, a]).is_empty());
+    }
+
+    #[test]
+    fn applying_resource_changes_yields_new_list() {
+        let unchanged = fake_resource("Unchanged", "2efe9c25-bd92-49a0-99d7-8b92da014dd5");
+        let removed = fake_resource("Removed", "613eaf56-6efa-45e5-88aa-ea4ad64d8c18");
+        let renamed_before = fake_resource("Before", "624b7154-08f6-4c9e-bac0-c3a587fc9322");
+        let renamed_after = fake_resource("After", "624b7154-08f6-4c9e-bac0-c3a587fc9322");
+        let added = internet_resource("cb13bca0-490a-4aae-a039-31a8f93e2281");
+
+        let old = vec![unchanged.clone(), removed, renamed_before];
+        let mut new = vec![added, renamed_after, unchanged];
+        new.sort();
+
+        let mut resources = old.clone();
+        ResourceChanges::between(&old, &new).apply(&mut resources);
+
+        assert_eq!(resources, new);
+    }
 }
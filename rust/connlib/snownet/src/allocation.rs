@@ -20,9 +20,10 @@ use str0m::{net::Protocol, Candidate};
 use stun_codec::{
     rfc5389::{
         attributes::{
-            ErrorCode, MessageIntegrity, Nonce, Realm, Software, Username, XorMappedAddress,
+            AlternateServer, ErrorCode, MessageIntegrity, Nonce, Realm, Software, Username,
+            XorMappedAddress,
         },
-        errors::{StaleNonce, Unauthorized, UnknownAttribute},
+        errors::{StaleNonce, TryAlternate, Unauthorized, UnknownAttribute},
         methods::BINDING,
     },
     rfc5766::{
@@ -198,6 +199,8 @@ pub enum FreeReason {
     NoResponseReceived,
     #[error("TURN protocol failure")]
     ProtocolFailure,
+    #[error("relay is draining and asked us to use another one")]
+    TryAlternate,
 }
 
 impl Allocation {
@@ -413,6 +416,20 @@ impl Allocation {
                 return true;
             }
 
+            // The relay is draining and won't grant us an allocation.
+            // We don't have credentials for the alternate server, so free this allocation to migrate our connections to one of the other relays.
+            if error.code() == TryAlternate::CODEPOINT {
+                let alternate_server = message
+                    .get_attribute::<AlternateServer>()
+                    .map(|a| a.address());
+
+                tracing::info!(?alternate_server, "Relay asked us to try another relay");
+                self.invalidate_allocation();
+                self.explicit_failure = Some(FreeReason::TryAlternate);
+
+                return true;
+            }
+
             // Catch-all error handling if none of the above apply.
             match message.method() {
                 ALLOCATE => {
@@ -1221,7 +1238,8 @@ stun_codec::define_attribute_enums!(
         XorPeerAddress,
         ChannelNumber,
         Lifetime,
-        Software
+        Software,
+        AlternateServer
     ]
 );
 
@@ -2298,6 +2316,16 @@ mod tests {
         assert!(allocation.is_suspended())
     }
 
+    #[test]
+    fn try_alternate_frees_allocation() {
+        let mut allocation = Allocation::for_test_ip4(Instant::now()).with_binding_response(PEER1);
+
+        let allocate = allocation.next_message().unwrap();
+        allocation.handle_test_input_ip4(&try_alternate(&allocate), Instant::now());
+
+        assert_eq!(allocation.can_be_freed(), Some(FreeReason::TryAlternate));
+    }
+
     #[test]
     fn timed_out_refresh_requests_invalid_candidates() {
         let _guard = firezone_logging::test("trace");
@@ -2600,6 +2628,18 @@ mod tests {
         encode(message)
     }
 
+    fn try_alternate(request: &Message<Attribute>) -> Vec<u8> {
+        let mut message = Message::new(
+            MessageClass::ErrorResponse,
+            request.method(),
+            request.transaction_id(),
+        );
+        message.add_attribute(ErrorCode::from(TryAlternate));
+        message.add_attribute(AlternateServer::new(PEER2_IP4));
+
+        encode(message)
+    }
+
     fn stale_nonce_response(request: &Message<Attribute>, nonce: Nonce) -> Vec<u8> {
         let mut message = Message::new(
             MessageClass::ErrorResponse,
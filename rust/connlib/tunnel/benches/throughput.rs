@@ -0,0 +1,957 @@
+//! Throughput and latency of the data plane, measured end-to-end through a [`ClientTunnel`] and a [`GatewayTunnel`].
+//!
+//! Both tunnels run in-process with in-memory TUN devices and talk to each other via UDP sockets on loopback,
+//! either directly or through a local relay.
+//! Connection setup is signalled in-process, the way the portal forwards the messages between Client and Gateway.
+//!
+//! Traffic is generated on the Client's TUN device and answered on the Gateway's.
+//! Each request carries a sequence number, so we can measure the round-trip time of every packet.
+//!
+//! Run `cargo bench -p firezone-tunnel --bench throughput -- --help` to see all options.
+
+use anyhow::{bail, Context as _, Result};
+use chrono::Utc;
+use clap::{Parser, ValueEnum};
+use connlib_model::{ClientId, GatewayId, RelayId, ResourceId, Site, SiteId};
+use firezone_relay::{sockets::Sockets, AddressFamily, ChannelData, ClientSocket, PeerSocket};
+use firezone_tunnel::{
+    messages::{client, gateway, Interface, Relay, Turn},
+    ClientEvent, ClientTunnel, GatewayEvent, GatewayTunnel,
+};
+use futures::FutureExt as _;
+use ip_network::{IpNetwork, Ipv4Network};
+use ip_packet::{make, IpPacket, IpPacketBuf};
+use rand::{rngs::StdRng, SeedableRng as _};
+use secrecy::SecretString;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt, future, io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::PathBuf,
+    pin::Pin,
+    sync::Arc,
+    task::{ready, Context, Poll},
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::{sync::mpsc, task::JoinHandle};
+use tun::Tun;
+
+const CLIENT_IP4: Ipv4Addr = Ipv4Addr::new(100, 64, 0, 1);
+const CLIENT_IP6: Ipv6Addr = Ipv6Addr::new(0xfd00, 0x2021, 0x1111, 0, 0, 0, 0, 1);
+const RESOURCE_IP: Ipv4Addr = Ipv4Addr::new(172, 16, 0, 1);
+
+const CLIENT_PORT: u16 = 40000;
+const SERVER_PORT: u16 = 5201;
+const ICMP_IDENTIFIER: u16 = 1;
+
+/// Payload of a bulk TCP segment, sized to fit the tunnel's MTU of 1280 bytes.
+const BULK_PAYLOAD_LEN: usize = 1200;
+const UDP_PAYLOAD_LEN: usize = 64;
+/// Same as `ping`'s default.
+const ICMP_PAYLOAD_LEN: usize = 56;
+
+/// How long we wait for the first reply, i.e. for the connection to be established.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Requests without a reply after this long are counted as lost.
+const LOSS_TIMEOUT: Duration = Duration::from_secs(1);
+/// Packets that can be queued between a TUN device and its "application" before we drop them, like a full TUN queue would.
+const TUN_QUEUE_LEN: usize = 4096;
+
+#[derive(Debug, Parser)]
+#[command(about = "Measures throughput and latency of the data plane")]
+struct Args {
+    /// Traffic mixes to run, all by default
+    #[arg(long, value_enum, value_delimiter = ',')]
+    mix: Vec<Mix>,
+    /// How the Client and the Gateway should be connected, both by default
+    #[arg(long, value_enum, value_delimiter = ',')]
+    route: Vec<Route>,
+    /// How many seconds to measure each mix for
+    #[arg(long, default_value_t = 5)]
+    seconds: u64,
+    /// How many requests may be awaiting their reply at any time
+    #[arg(long, default_value_t = 512)]
+    window: usize,
+    /// Also write the results to this file, in Bencher Metric Format (BMF) JSON
+    #[arg(long)]
+    output: Option<PathBuf>,
+    /// Passed by `cargo bench`
+    #[arg(long, hide = true)]
+    bench: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Mix {
+    /// Full-sized TCP segments from the Client, answered by small ACKs
+    Bulk,
+    /// Small UDP datagrams, echoed back
+    Udp,
+    /// ICMP echo requests and replies
+    Icmp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Route {
+    /// Only exchange host candidates, i.e. send directly between the sockets
+    Direct,
+    /// Only exchange relay candidates, i.e. send through the relay
+    Relayed,
+}
+
+#[derive(Debug)]
+struct Report {
+    packets_per_sec: f64,
+    bits_per_sec: f64,
+    lost: u64,
+    /// Sorted round-trip times of all requests that were answered
+    latencies: Vec<Duration>,
+}
+
+fn main() -> Result<()> {
+    let mut args = Args::parse();
+    firezone_logging::test_global(&std::env::var("RUST_LOG").unwrap_or_else(|_| "warn".to_owned()));
+
+    if args.mix.is_empty() {
+        args.mix = Mix::value_variants().to_vec();
+    }
+    if args.route.is_empty() {
+        args.route = Route::value_variants().to_vec();
+    }
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    let mut bmf = BTreeMap::new();
+
+    for &route in &args.route {
+        for &mix in &args.mix {
+            let name = format!("{route}-{mix}");
+            let report = runtime
+                .block_on(run(mix, route, &args))
+                .with_context(|| format!("Failed to run `{name}`"))?;
+
+            print_report(&name, &report);
+            bmf.insert(format!("tunnel-{name}"), report.to_bmf());
+        }
+    }
+
+    if let Some(output) = args.output.as_ref() {
+        std::fs::write(output, serde_json::to_string_pretty(&bmf)?)
+            .with_context(|| format!("Failed to write `{}`", output.display()))?;
+    }
+
+    Ok(())
+}
+
+#[expect(clippy::print_stdout, reason = "Printing the results is the point")]
+fn print_report(name: &str, report: &Report) {
+    println!(
+        "{name:<16} {:>10.0} packets/s {:>8.3} Gbit/s  latency p50 {:>9.1?} p90 {:>9.1?} p99 {:>9.1?} max {:>9.1?}  lost {}",
+        report.packets_per_sec,
+        report.bits_per_sec / 1e9,
+        report.percentile(0.5),
+        report.percentile(0.9),
+        report.percentile(0.99),
+        report.latencies.last().copied().unwrap_or_default(),
+        report.lost,
+    );
+}
+
+/// Sets up a relay, a Client and a Gateway and measures one traffic mix between them.
+async fn run(mix: Mix, route: Route, args: &Args) -> Result<Report> {
+    let mut tasks = Tasks::default();
+
+    let relay = LocalRelay::new()?;
+    let relays = |salt: &str| [relay.credentials(salt)];
+    let (relay_addr, client_relays, gateway_relays) =
+        (relay.addr, relays("client"), relays("gateway"));
+    tasks.spawn(relay.run());
+    tracing::debug!(%relay_addr, "Started relay");
+
+    let (client_tun, client_app) = memory_tun("client");
+    let mut client =
+        ClientTunnel::new(Arc::new(socket_factory::tcp), Arc::new(socket_factory::udp));
+    client.set_tun(Box::new(client_tun));
+    client.state_mut().update_interface_config(Interface {
+        ipv4: CLIENT_IP4,
+        ipv6: CLIENT_IP6,
+        upstream_dns: Vec::new(),
+    });
+    client
+        .state_mut()
+        .set_resources(vec![client::ResourceDescription::Cidr(
+            client::ResourceDescriptionCidr {
+                id: resource_id(),
+                address: resource_network(),
+                name: "Benchmark".to_owned(),
+                address_description: None,
+                sites: vec![Site {
+                    id: site_id(),
+                    name: "Benchmark".to_owned(),
+                }],
+            },
+        )]);
+    client.state_mut().update_relays(
+        BTreeSet::new(),
+        firezone_tunnel::turn(&client_relays),
+        Instant::now(),
+    );
+
+    let (gateway_tun, gateway_app) = memory_tun("gateway");
+    let mut gateway = GatewayTunnel::new(
+        Arc::new(socket_factory::tcp),
+        Arc::new(socket_factory::udp),
+        rand::random(),
+    );
+    gateway.set_tun(Box::new(gateway_tun));
+    gateway.state_mut().update_relays(
+        BTreeSet::new(),
+        firezone_tunnel::turn(&gateway_relays),
+        Instant::now(),
+    );
+
+    let portal = Portal {
+        route,
+        client_public_key: client.public_key(),
+        gateway_public_key: gateway.public_key(),
+    };
+
+    let (client_tx, client_rx) = mpsc::unbounded_channel();
+    let (gateway_tx, gateway_rx) = mpsc::unbounded_channel();
+    let (client_events_tx, client_events_rx) = mpsc::unbounded_channel();
+    let (gateway_events_tx, gateway_events_rx) = mpsc::unbounded_channel();
+
+    tasks.spawn(drive(client, client_rx, client_events_tx));
+    tasks.spawn(drive(gateway, gateway_rx, gateway_events_tx));
+    tasks.spawn(portal.run(client_events_rx, gateway_events_rx, client_tx, gateway_tx));
+    tasks.spawn(answer(mix, gateway_app));
+
+    let report = measure(
+        mix,
+        client_app,
+        args.window,
+        Duration::from_secs(args.seconds),
+    )
+    .await;
+
+    tasks.check()?;
+
+    report
+}
+
+/// Sends requests from the Client's TUN device and records when their replies arrive.
+async fn measure(mix: Mix, mut app: App, window: usize, duration: Duration) -> Result<Report> {
+    // The first packet triggers the connection setup, keep sending until we get a reply.
+    let connect_deadline = Instant::now() + CONNECT_TIMEOUT;
+    loop {
+        app.send(mix.request(0)?).await?;
+
+        match tokio::time::timeout(Duration::from_millis(100), app.recv()).await {
+            Ok(reply) => {
+                reply?;
+                break;
+            }
+            Err(_) if Instant::now() > connect_deadline => {
+                bail!("Connection was not established within {CONNECT_TIMEOUT:?}")
+            }
+            Err(_) => continue,
+        }
+    }
+
+    let start = Instant::now();
+    let end = start + duration;
+
+    let mut in_flight = HashMap::<u64, (Instant, usize)>::with_capacity(window);
+    let mut next_seq = 1;
+    let mut latencies = Vec::new();
+    let mut num_bytes = 0;
+    let mut lost = 0;
+    let mut loss_check = tokio::time::interval(LOSS_TIMEOUT / 10);
+
+    while Instant::now() < end {
+        while in_flight.len() < window {
+            let request = mix.request(next_seq)?;
+            in_flight.insert(next_seq, (Instant::now(), request.len()));
+            app.send(request).await?;
+
+            next_seq += 1;
+        }
+
+        tokio::select! {
+            reply = app.recv() => {
+                let reply = reply?;
+                let now = Instant::now();
+
+                let Some((sent_at, len)) = in_flight.remove(&sequence_number(&reply)) else {
+                    continue; // Replies to probes during the connection setup or ones we already counted as lost.
+                };
+
+                latencies.push(now.duration_since(sent_at));
+                num_bytes += len as u64;
+            }
+            _ = loss_check.tick() => {
+                let now = Instant::now();
+                let before = in_flight.len();
+                in_flight.retain(|_, (sent_at, _)| now.duration_since(*sent_at) < LOSS_TIMEOUT);
+
+                lost += (before - in_flight.len()) as u64;
+            }
+        }
+    }
+
+    let elapsed = start.elapsed().as_secs_f64();
+    latencies.sort_unstable();
+
+    Ok(Report {
+        packets_per_sec: latencies.len() as f64 / elapsed,
+        bits_per_sec: num_bytes as f64 * 8.0 / elapsed,
+        lost,
+        latencies,
+    })
+}
+
+/// Answers the requests arriving at the Gateway's TUN device, like a server behind the Gateway would.
+async fn answer(mix: Mix, mut app: App) -> Result<()> {
+    loop {
+        let request = app.recv().await?;
+        let reply = mix.reply(parse(&request)?)?;
+
+        app.send(reply.packet().to_vec()).await?;
+    }
+}
+
+impl Mix {
+    fn request(&self, seq: u64) -> Result<Vec<u8>> {
+        let packet = match self {
+            Mix::Bulk => make::tcp_packet(
+                CLIENT_IP4,
+                RESOURCE_IP,
+                CLIENT_PORT,
+                SERVER_PORT,
+                payload(BULK_PAYLOAD_LEN, seq),
+            )?,
+            Mix::Udp => make::udp_packet(
+                CLIENT_IP4,
+                RESOURCE_IP,
+                CLIENT_PORT,
+                SERVER_PORT,
+                payload(UDP_PAYLOAD_LEN, seq),
+            )?,
+            Mix::Icmp => make::icmp_request_packet(
+                CLIENT_IP4.into(),
+                RESOURCE_IP,
+                seq as u16,
+                ICMP_IDENTIFIER,
+                &payload(ICMP_PAYLOAD_LEN, seq),
+            )?,
+        };
+
+        Ok(packet.packet().to_vec())
+    }
+
+    fn reply(&self, request: IpPacket) -> Result<IpPacket> {
+        let seq = sequence_number(request.packet());
+
+        let reply = match self {
+            Mix::Bulk => make::tcp_packet(
+                request.destination(),
+                request.source(),
+                SERVER_PORT,
+                CLIENT_PORT,
+                seq.to_be_bytes().to_vec(),
+            )?,
+            Mix::Udp => make::echo_reply(request).context("Not a UDP packet")?,
+            Mix::Icmp => make::icmp_reply_packet(
+                request.destination(),
+                request.source(),
+                seq as u16,
+                ICMP_IDENTIFIER,
+                &payload(ICMP_PAYLOAD_LEN, seq),
+            )?,
+        };
+
+        Ok(reply)
+    }
+}
+
+impl Report {
+    fn percentile(&self, p: f64) -> Duration {
+        if self.latencies.is_empty() {
+            return Duration::ZERO;
+        }
+
+        let index = ((self.latencies.len() - 1) as f64 * p).round() as usize;
+
+        self.latencies[index]
+    }
+
+    fn to_bmf(&self) -> serde_json::Value {
+        serde_json::json!({
+            "throughput": { "value": self.bits_per_sec },
+            "latency": {
+                "value": self.percentile(0.5).as_nanos() as f64,
+                "lower_value": self.latencies.first().copied().unwrap_or_default().as_nanos() as f64,
+                "upper_value": self.percentile(0.99).as_nanos() as f64,
+            },
+        })
+    }
+}
+
+impl fmt::Display for Mix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.to_possible_value()
+            .expect("no skipped variants")
+            .get_name()
+            .fmt(f)
+    }
+}
+
+impl fmt::Display for Route {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.to_possible_value()
+            .expect("no skipped variants")
+            .get_name()
+            .fmt(f)
+    }
+}
+
+/// A payload of `len` bytes that ends with the sequence number.
+fn payload(len: usize, seq: u64) -> Vec<u8> {
+    let mut payload = vec![0; len];
+    payload[len - 8..].copy_from_slice(&seq.to_be_bytes());
+
+    payload
+}
+
+/// The sequence number in the last 8 bytes of a packet, see [`payload`].
+fn sequence_number(packet: &[u8]) -> u64 {
+    let mut seq = [0; 8];
+    seq.copy_from_slice(&packet[packet.len().saturating_sub(8)..]);
+
+    u64::from_be_bytes(seq)
+}
+
+fn parse(packet: &[u8]) -> Result<IpPacket> {
+    let mut buf = IpPacketBuf::new();
+    buf.buf()
+        .get_mut(..packet.len())
+        .context("Packet too large")?
+        .copy_from_slice(packet);
+
+    IpPacket::new(buf, packet.len())
+}
+
+fn client_id() -> ClientId {
+    ClientId::from_u128(1)
+}
+
+fn gateway_id() -> GatewayId {
+    GatewayId::from_u128(2)
+}
+
+fn relay_id() -> RelayId {
+    RelayId::from_u128(3)
+}
+
+fn resource_id() -> ResourceId {
+    ResourceId::from_u128(4)
+}
+
+fn site_id() -> SiteId {
+    SiteId::from_u128(5)
+}
+
+fn resource_network() -> IpNetwork {
+    IpNetwork::V4(Ipv4Network::new(RESOURCE_IP, 24).expect("valid network"))
+}
+
+/// Forwards the messages between Client and Gateway that the portal would forward.
+struct Portal {
+    route: Route,
+    client_public_key: connlib_model::PublicKey,
+    gateway_public_key: connlib_model::PublicKey,
+}
+
+type Command<T> = Box<dyn FnOnce(&mut T) + Send>;
+
+impl Portal {
+    async fn run(
+        self,
+        mut client_events: mpsc::UnboundedReceiver<ClientEvent>,
+        mut gateway_events: mpsc::UnboundedReceiver<GatewayEvent>,
+        client: mpsc::UnboundedSender<Command<ClientTunnel>>,
+        gateway: mpsc::UnboundedSender<Command<GatewayTunnel>>,
+    ) -> Result<()> {
+        loop {
+            tokio::select! {
+                Some(event) = client_events.recv() => self.handle_client_event(event, &client, &gateway),
+                Some(event) = gateway_events.recv() => self.handle_gateway_event(event, &client),
+                else => return Ok(()),
+            }
+        }
+    }
+
+    fn handle_client_event(
+        &self,
+        event: ClientEvent,
+        client: &mpsc::UnboundedSender<Command<ClientTunnel>>,
+        gateway: &mpsc::UnboundedSender<Command<GatewayTunnel>>,
+    ) {
+        match event {
+            ClientEvent::ConnectionIntent { resource, .. } => {
+                send(client, move |client| {
+                    match client.state_mut().on_routing_details(
+                        resource,
+                        gateway_id(),
+                        site_id(),
+                        Instant::now(),
+                    ) {
+                        Ok(Ok(())) => {}
+                        Ok(Err(e)) => tracing::warn!("Failed to request connection: {e}"),
+                        Err(e) => tracing::warn!("Failed to request connection: {e:#}"),
+                    }
+                });
+            }
+            ClientEvent::RequestConnection {
+                offer,
+                preshared_key,
+                resource_id,
+                ..
+            } => {
+                let client = client.clone();
+                let client_public_key = self.client_public_key;
+                let gateway_public_key = self.gateway_public_key;
+
+                send(gateway, move |gateway| {
+                    let now = Instant::now();
+                    let state = gateway.state_mut();
+
+                    let answer = match state.accept(
+                        client_id(),
+                        offer.into_snownet_offer(preshared_key),
+                        client_public_key,
+                        now,
+                    ) {
+                        Ok(answer) => answer,
+                        Err(e) => {
+                            tracing::warn!("Failed to accept connection: {e}");
+                            return;
+                        }
+                    };
+                    allow_access(state, now);
+
+                    send(&client, move |client| {
+                        if let Err(e) = client.state_mut().accept_answer(
+                            answer,
+                            resource_id,
+                            gateway_public_key,
+                            Instant::now(),
+                        ) {
+                            tracing::warn!("Failed to accept answer: {e:#}");
+                        }
+                    });
+                });
+            }
+            ClientEvent::RequestAccess { .. } => {
+                send(gateway, |gateway| {
+                    allow_access(gateway.state_mut(), Instant::now())
+                });
+            }
+            ClientEvent::AddedIceCandidates { candidates, .. } => {
+                let candidates = self.filter(candidates);
+
+                send(gateway, move |gateway| {
+                    for candidate in candidates {
+                        gateway.state_mut().add_ice_candidate(
+                            client_id(),
+                            candidate,
+                            Instant::now(),
+                        );
+                    }
+                });
+            }
+            ClientEvent::RemovedIceCandidates { candidates, .. } => {
+                let candidates = self.filter(candidates);
+
+                send(gateway, move |gateway| {
+                    for candidate in candidates {
+                        gateway.state_mut().remove_ice_candidate(
+                            client_id(),
+                            candidate,
+                            Instant::now(),
+                        );
+                    }
+                });
+            }
+            ClientEvent::ResourcesChanged { .. }
+            | ClientEvent::GatewaysChanged { .. }
+            | ClientEvent::TunInterfaceUpdated(_) => {}
+        }
+    }
+
+    fn handle_gateway_event(
+        &self,
+        event: GatewayEvent,
+        client: &mpsc::UnboundedSender<Command<ClientTunnel>>,
+    ) {
+        match event {
+            GatewayEvent::AddedIceCandidates { candidates, .. } => {
+                let candidates = self.filter(candidates);
+
+                send(client, move |client| {
+                    for candidate in candidates {
+                        client.state_mut().add_ice_candidate(
+                            gateway_id(),
+                            candidate,
+                            Instant::now(),
+                        );
+                    }
+                });
+            }
+            GatewayEvent::RemovedIceCandidates { candidates, .. } => {
+                let candidates = self.filter(candidates);
+
+                send(client, move |client| {
+                    for candidate in candidates {
+                        client.state_mut().remove_ice_candidate(
+                            gateway_id(),
+                            candidate,
+                            Instant::now(),
+                        );
+                    }
+                });
+            }
+            GatewayEvent::RefreshDns { .. } | GatewayEvent::ResolveDns(_) => {}
+        }
+    }
+
+    /// Only lets through the candidates that result in the desired [`Route`].
+    fn filter(&self, candidates: BTreeSet<String>) -> Vec<String> {
+        candidates
+            .into_iter()
+            .filter(|candidate| match self.route {
+                Route::Direct => candidate.contains("typ host"),
+                Route::Relayed => candidate.contains("typ relay"),
+            })
+            .collect()
+    }
+}
+
+fn allow_access(gateway: &mut firezone_tunnel::GatewayState, now: Instant) {
+    let resource = gateway::ResourceDescription::Cidr(gateway::ResourceDescriptionCidr {
+        id: resource_id(),
+        address: resource_network(),
+        name: "Benchmark".to_owned(),
+        filters: Vec::new(),
+    });
+
+    if let Err(e) = gateway.allow_access(
+        client_id(),
+        CLIENT_IP4,
+        CLIENT_IP6,
+        None,
+        resource,
+        None,
+        now,
+    ) {
+        tracing::warn!("Failed to allow access: {e:#}");
+    }
+}
+
+fn send<T>(tx: &mpsc::UnboundedSender<Command<T>>, command: impl FnOnce(&mut T) + Send + 'static) {
+    let _ = tx.send(Box::new(command));
+}
+
+trait PollEvent {
+    type Event;
+
+    fn poll_event(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<Self::Event>>;
+}
+
+impl PollEvent for ClientTunnel {
+    type Event = ClientEvent;
+
+    fn poll_event(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<Self::Event>> {
+        self.poll_next_event(cx)
+    }
+}
+
+impl PollEvent for GatewayTunnel {
+    type Event = GatewayEvent;
+
+    fn poll_event(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<Self::Event>> {
+        self.poll_next_event(cx)
+    }
+}
+
+/// Runs a tunnel, applying the commands from our [`Portal`] and passing its events on.
+async fn drive<T>(
+    mut tunnel: T,
+    mut commands: mpsc::UnboundedReceiver<Command<T>>,
+    events: mpsc::UnboundedSender<T::Event>,
+) -> Result<()>
+where
+    T: PollEvent,
+{
+    future::poll_fn(|cx| loop {
+        match commands.poll_recv(cx) {
+            Poll::Ready(Some(command)) => {
+                command(&mut tunnel);
+                continue;
+            }
+            Poll::Ready(None) => return Poll::Ready(Ok(())),
+            Poll::Pending => {}
+        }
+
+        let event = ready!(tunnel.poll_event(cx))?;
+        let _ = events.send(event);
+    })
+    .await
+}
+
+/// A TURN server on loopback, runs the same event-loop as the relay binary but without the portal connection.
+struct LocalRelay {
+    server: firezone_relay::Server<StdRng>,
+    sockets: Sockets,
+    sleep: firezone_relay::Sleep,
+    addr: SocketAddr,
+    buffer: Box<[u8; 65536]>,
+}
+
+impl LocalRelay {
+    fn new() -> Result<Self> {
+        // Find a free port by letting the OS pick one.
+        let port = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?
+            .local_addr()?
+            .port();
+
+        let server = firezone_relay::Server::new(
+            Ipv4Addr::LOCALHOST,
+            StdRng::from_entropy(),
+            port,
+            49152..=65535,
+        );
+        let mut sockets = Sockets::new();
+        sockets.bind(port, AddressFamily::V4)?;
+
+        Ok(Self {
+            server,
+            sockets,
+            sleep: Default::default(),
+            addr: SocketAddr::from((Ipv4Addr::LOCALHOST, port)),
+            buffer: Box::new([0; 65536]),
+        })
+    }
+
+    /// TURN credentials in the format the portal hands out.
+    fn credentials(&self, salt: &str) -> Relay {
+        let expires_at = Utc::now() + chrono::Duration::days(1);
+        let expiry = SystemTime::UNIX_EPOCH + Duration::from_secs(expires_at.timestamp() as u64);
+        let secret: &SecretString = self.server.auth_secret();
+
+        Relay::Turn(Turn {
+            id: relay_id(),
+            expires_at,
+            addr: self.addr,
+            username: format!("{}:{salt}", expires_at.timestamp()),
+            password: firezone_relay::auth::generate_password(secret, expiry, salt),
+        })
+    }
+
+    async fn run(mut self) -> Result<()> {
+        future::poll_fn(|cx| self.poll(cx)).await
+    }
+
+    fn poll(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        loop {
+            if let Some(command) = self.server.next_command() {
+                match command {
+                    firezone_relay::Command::SendMessage { payload, recipient } => {
+                        if let Err(e) = self.sockets.try_send(
+                            self.server.listen_port(),
+                            recipient.into_socket(),
+                            &payload,
+                        ) {
+                            tracing::warn!(%recipient, "Failed to send message: {e}");
+                        }
+                    }
+                    firezone_relay::Command::CreateAllocation { port, family } => {
+                        self.sockets.bind(port.value(), family)?;
+                    }
+                    firezone_relay::Command::FreeAllocation { port, family } => {
+                        self.sockets.unbind(port.value(), family)?;
+                    }
+                }
+
+                continue;
+            }
+
+            // Leave room for the channel-data header, see the relay's event-loop.
+            let (header, payload) = self.buffer.split_at_mut(4);
+
+            match self.sockets.poll_recv_from(payload, cx) {
+                Poll::Ready(Ok(received)) if received.port == self.server.listen_port() => {
+                    if let Some((port, peer)) = self.server.handle_client_input(
+                        received.packet,
+                        ClientSocket::new(received.from),
+                        Instant::now(),
+                    ) {
+                        let payload = ChannelData::parse(received.packet)
+                            .expect("valid ChannelData if we should relay it")
+                            .data();
+
+                        if let Err(e) =
+                            self.sockets
+                                .try_send(port.value(), peer.into_socket(), payload)
+                        {
+                            tracing::warn!(%peer, "Failed to relay data to peer: {e}");
+                        }
+                    }
+                    continue;
+                }
+                Poll::Ready(Ok(received)) => {
+                    if let Some((client, channel)) = self.server.handle_peer_traffic(
+                        received.packet,
+                        PeerSocket::new(received.from),
+                        firezone_relay::AllocationPort::new(received.port),
+                    ) {
+                        let total_length = ChannelData::encode_header_to_slice(
+                            channel,
+                            received.packet.len() as u16,
+                            header,
+                        );
+
+                        if let Err(e) = self.sockets.try_send(
+                            self.server.listen_port(),
+                            client.into_socket(),
+                            &self.buffer[..total_length],
+                        ) {
+                            tracing::warn!(%client, "Failed to relay data to client: {e}");
+                        }
+                    }
+                    continue;
+                }
+                Poll::Ready(Err(firezone_relay::sockets::Error::Io(e))) => {
+                    tracing::warn!("Failed to receive message: {e}");
+                    continue;
+                }
+                Poll::Ready(Err(firezone_relay::sockets::Error::MioTaskCrashed(e))) => {
+                    return Poll::Ready(Err(e))
+                }
+                Poll::Pending => {}
+            }
+
+            if let Some(timeout) = self.server.poll_timeout() {
+                Pin::new(&mut self.sleep).reset(timeout);
+            }
+
+            if let Poll::Ready(deadline) = self.sleep.poll_unpin(cx) {
+                self.server.handle_timeout(deadline);
+                continue;
+            }
+
+            return Poll::Pending;
+        }
+    }
+}
+
+/// A TUN device whose other end is an in-memory "application", see [`App`].
+struct MemoryTun {
+    name: &'static str,
+    from_app: mpsc::Receiver<Vec<u8>>,
+    to_app: mpsc::Sender<Vec<u8>>,
+}
+
+/// The application side of a [`MemoryTun`].
+struct App {
+    to_tun: mpsc::Sender<Vec<u8>>,
+    from_tun: mpsc::Receiver<Vec<u8>>,
+}
+
+fn memory_tun(name: &'static str) -> (MemoryTun, App) {
+    let (to_tun, from_app) = mpsc::channel(TUN_QUEUE_LEN);
+    let (to_app, from_tun) = mpsc::channel(TUN_QUEUE_LEN);
+
+    (
+        MemoryTun {
+            name,
+            from_app,
+            to_app,
+        },
+        App { to_tun, from_tun },
+    )
+}
+
+impl MemoryTun {
+    fn write(&self, buf: &[u8]) -> io::Result<usize> {
+        // Like a real TUN device, drop packets if the application doesn't keep up.
+        let _ = self.to_app.try_send(buf.to_vec());
+
+        Ok(buf.len())
+    }
+}
+
+impl Tun for MemoryTun {
+    fn write4(&self, buf: &[u8]) -> io::Result<usize> {
+        self.write(buf)
+    }
+
+    fn write6(&self, buf: &[u8]) -> io::Result<usize> {
+        self.write(buf)
+    }
+
+    fn poll_read(&mut self, buf: &mut [u8], cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        let Some(packet) = ready!(self.from_app.poll_recv(cx)) else {
+            return Poll::Ready(Ok(0)); // The application is gone, treat it like a closed device.
+        };
+
+        let n = packet.len().min(buf.len());
+        buf[..n].copy_from_slice(&packet[..n]);
+
+        Poll::Ready(Ok(n))
+    }
+
+    fn name(&self) -> &str {
+        self.name
+    }
+}
+
+impl App {
+    async fn send(&self, packet: Vec<u8>) -> Result<()> {
+        self.to_tun
+            .send(packet)
+            .await
+            .context("TUN device is closed")
+    }
+
+    async fn recv(&mut self) -> Result<Vec<u8>> {
+        self.from_tun.recv().await.context("TUN device is closed")
+    }
+}
+
+/// The tasks of a single run, aborted when the run is over.
+#[derive(Default)]
+struct Tasks {
+    handles: Vec<JoinHandle<Result<()>>>,
+}
+
+impl Tasks {
+    fn spawn(&mut self, task: impl future::Future<Output = Result<()>> + Send + 'static) {
+        self.handles.push(tokio::spawn(task));
+    }
+
+    /// Fails if any of the tasks failed before the run was over.
+    fn check(self) -> Result<()> {
+        for handle in &self.handles {
+            handle.abort();
+        }
+
+        for handle in self.handles {
+            if let Some(Ok(Err(e))) = handle.now_or_never() {
+                return Err(e);
+            }
+        }
+
+        Ok(())
+    }
+}
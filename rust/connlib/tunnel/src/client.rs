@@ -12,7 +12,9 @@ use crate::{dns, TunConfig};
 use anyhow::Context;
 use bimap::BiMap;
 use connlib_model::PublicKey;
-use connlib_model::{GatewayId, RelayId, ResourceId, ResourceStatus, ResourceView};
+use connlib_model::{
+    GatewayId, RelayId, ResourceChanges, ResourceId, ResourceStatus, ResourceView,
+};
 use connlib_model::{Site, SiteId};
 use firezone_logging::{
     anyhow_dyn_err, err_with_sources, std_dyn_err, telemetry_event, unwrap_or_debug, unwrap_or_warn,
@@ -35,7 +37,7 @@ use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::num::NonZeroUsize;
 use std::ops::ControlFlow;
 use std::time::{Duration, Instant};
-use std::{io, iter};
+use std::{io, iter, mem};
 
 pub(crate) const IPV4_RESOURCES: Ipv4Network =
     match Ipv4Network::new(Ipv4Addr::new(100, 96, 0, 0), 11) {
@@ -137,8 +139,14 @@ pub struct ClientState {
     ///
     /// We use this as a hint to the portal to re-connect us to the same gateway for a resource.
     recently_connected_gateways: LruCache<GatewayId, ()>,
+    /// The gateways we currently have an established connection to.
+    connected_gateways: BTreeSet<GatewayId>,
 
     buffered_events: VecDeque<ClientEvent>,
+    /// Whether we need to emit [`ClientEvent::ResourcesChanged`] once all other events are out.
+    resources_changed: bool,
+    /// The resources of the last [`ClientEvent::ResourcesChanged`] we emitted, `None` until the first one.
+    reported_resources: Option<Vec<ResourceView>>,
     buffered_packets: VecDeque<IpPacket>,
     buffered_transmits: VecDeque<Transmit<'static>>,
     buffered_dns_queries: VecDeque<dns::RecursiveQuery>,
@@ -164,6 +172,8 @@ impl ClientState {
             peers: Default::default(),
             dns_mapping: Default::default(),
             buffered_events: Default::default(),
+            resources_changed: false,
+            reported_resources: None,
             tun_config: Default::default(),
             buffered_packets: Default::default(),
             node: ClientNode::new(seed),
@@ -176,6 +186,7 @@ impl ClientState {
             buffered_transmits: Default::default(),
             internet_resource: None,
             recently_connected_gateways: LruCache::new(MAX_REMEMBERED_GATEWAYS),
+            connected_gateways: Default::default(),
             upstream_dns: Default::default(),
             buffered_dns_queries: Default::default(),
             tcp_dns_client: dns_over_tcp::Client::new(now, seed),
@@ -207,6 +218,11 @@ impl ClientState {
         self.node.num_connections()
     }
 
+    #[cfg(all(test, feature = "proptest"))]
+    pub(crate) fn established_gateways(&self) -> BTreeSet<GatewayId> {
+        self.node.stats().1.map(|(gateway, _)| gateway).collect()
+    }
+
     pub(crate) fn resources(&self) -> Vec<ResourceView> {
         self.resources_by_id
             .values()
@@ -1139,6 +1155,7 @@ impl ClientState {
 
     fn drain_node_events(&mut self) {
         let mut resources_changed = false; // Track this separately to batch together `ResourcesChanged` events.
+        let mut gateways_changed = false;
         let mut added_ice_candidates = BTreeMap::<GatewayId, BTreeSet<String>>::default();
         let mut removed_ice_candidates = BTreeMap::<GatewayId, BTreeSet<String>>::default();
 
@@ -1147,6 +1164,7 @@ impl ClientState {
                 snownet::Event::ConnectionFailed(id) | snownet::Event::ConnectionClosed(id) => {
                     self.cleanup_connected_gateway(&id);
                     resources_changed = true;
+                    gateways_changed |= self.connected_gateways.remove(&id);
                 }
                 snownet::Event::NewIceCandidate {
                     connection,
@@ -1169,6 +1187,7 @@ impl ClientState {
                 snownet::Event::ConnectionEstablished(id) => {
                     self.update_site_status_by_gateway(&id, ResourceStatus::Online);
                     resources_changed = true;
+                    gateways_changed |= self.connected_gateways.insert(id);
                 }
             }
         }
@@ -1177,6 +1196,10 @@ impl ClientState {
             self.emit_resources_changed()
         }
 
+        if gateways_changed {
+            self.emit_gateways_changed()
+        }
+
         for (conn_id, candidates) in added_ice_candidates.into_iter() {
             self.buffered_events
                 .push_back(ClientEvent::AddedIceCandidates {
@@ -1206,7 +1229,28 @@ impl ClientState {
     }
 
     pub(crate) fn poll_event(&mut self) -> Option<ClientEvent> {
-        self.buffered_events.pop_front()
+        if let Some(event) = self.buffered_events.pop_front() {
+            return Some(event);
+        }
+
+        if !mem::take(&mut self.resources_changed) {
+            return None;
+        }
+
+        let resources = self.resources();
+        let changes = ResourceChanges::between(
+            self.reported_resources.as_deref().unwrap_or_default(),
+            &resources,
+        );
+
+        // Rebuilding the resource list can be expensive for clients, skip it if nothing changed.
+        if self.reported_resources.is_some() && changes.is_empty() {
+            return None;
+        }
+
+        self.reported_resources = Some(resources.clone());
+
+        Some(ClientEvent::ResourcesChanged { resources, changes })
     }
 
     pub(crate) fn reset(&mut self) {
@@ -1216,6 +1260,12 @@ impl ClientState {
         self.recently_connected_gateways.clear(); // Ensure we don't have sticky gateways when we roam.
         self.drain_node_events();
 
+        // Don't rely on `snownet` having reported every connection we know about as closed.
+        if !self.connected_gateways.is_empty() {
+            self.connected_gateways.clear();
+            self.emit_gateways_changed();
+        }
+
         // Resetting the client will trigger a failed `QueryResult` for each one that is in-progress.
         // Failed queries get translated into `SERVFAIL` responses to the client.
         // This will also allocate new local ports for our outgoing TCP connections.
@@ -1261,14 +1311,15 @@ impl ClientState {
 
         // First, remove all resources that are not present in the new resource list.
         for id in current_resource_ids.difference(&new_resource_ids).copied() {
-            self.remove_resource(id);
+            self.forget_resource(id);
         }
 
-        // Second, add all resources.
+        // Second, add or update all resources. Those that didn't change are skipped.
         for resource in new_resources {
-            self.add_resource(resource)
+            self.upsert_resource(resource);
         }
 
+        // Only recompute routes and resources once for the entire batch.
         self.maybe_update_tun_routes();
         self.emit_resources_changed();
     }
@@ -1282,17 +1333,38 @@ impl ClientState {
             }
         };
 
-        if let Some(resource) = self.resources_by_id.get(&new_resource.id()) {
-            if resource.has_different_address(&new_resource) {
-                self.remove_resource(resource.id());
+        if !self.upsert_resource(new_resource) {
+            return;
+        }
+
+        self.maybe_update_tun_routes();
+        self.emit_resources_changed();
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(?id))]
+    pub fn remove_resource(&mut self, id: ResourceId) {
+        self.forget_resource(id);
+        self.maybe_update_tun_routes();
+        self.emit_resources_changed();
+    }
+
+    /// Adds a new resource or updates an existing one, without updating the routes or emitting events.
+    ///
+    /// Returns `false` if we already knew the resource exactly like this.
+    fn upsert_resource(&mut self, new_resource: Resource) -> bool {
+        match self.resources_by_id.get(&new_resource.id()) {
+            Some(resource) if resource == &new_resource => return false,
+            Some(resource) if resource.has_different_address(&new_resource) => {
+                self.forget_resource(new_resource.id());
             }
+            Some(_) | None => {}
         }
 
         self.resources_by_id
             .insert(new_resource.id(), new_resource.clone());
 
         if !self.is_resource_enabled(&(new_resource.id())) {
-            return;
+            return true;
         }
 
         let added = match &new_resource {
@@ -1311,7 +1383,7 @@ impl ClientState {
         };
 
         if !added {
-            return;
+            return true;
         }
 
         let name = new_resource.name();
@@ -1320,28 +1392,32 @@ impl ClientState {
 
         tracing::info!(%name, address, %sites, "Activating resource");
 
-        self.maybe_update_tun_routes();
-        self.emit_resources_changed();
+        true
     }
 
-    #[tracing::instrument(level = "debug", skip_all, fields(?id))]
-    pub fn remove_resource(&mut self, id: ResourceId) {
+    /// Removes a resource without updating the routes or emitting events.
+    fn forget_resource(&mut self, id: ResourceId) {
         self.disable_resource(id);
         self.resources_by_id.remove(&id);
-        self.maybe_update_tun_routes();
-        self.emit_resources_changed();
     }
 
     /// Emit a [`ClientEvent::ResourcesChanged`] event.
     ///
-    /// Each instance of this event contains the latest state of the resources.
-    /// To not spam clients with multiple updates, we remove all prior instances of that event.
+    /// The event contains the latest state of the resources and what changed since the last one, see [`ClientState::poll_event`].
+    /// To not spam clients with multiple updates, we only emit it once for all changes in between two calls to [`ClientState::poll_event`].
     fn emit_resources_changed(&mut self) {
+        self.resources_changed = true;
+    }
+
+    /// Emit a [`ClientEvent::GatewaysChanged`] event.
+    ///
+    /// Like [`ClientEvent::ResourcesChanged`], only the latest instance of this event is kept.
+    fn emit_gateways_changed(&mut self) {
         self.buffered_events
-            .retain(|e| !matches!(e, ClientEvent::ResourcesChanged { .. }));
+            .retain(|e| !matches!(e, ClientEvent::GatewaysChanged { .. }));
         self.buffered_events
-            .push_back(ClientEvent::ResourcesChanged {
-                resources: self.resources(),
+            .push_back(ClientEvent::GatewaysChanged {
+                gateways: self.connected_gateways.clone(),
             });
     }
 
@@ -1756,6 +1832,19 @@ mod tests {
         )
     }
 
+    #[test]
+    fn reset_clears_connected_gateways() {
+        let mut state = ClientState::for_test();
+        state.connected_gateways.insert(GatewayId::from_u128(1));
+
+        state.reset();
+
+        assert!(state.connected_gateways.is_empty());
+        assert!(state.buffered_events.iter().any(
+            |e| matches!(e, ClientEvent::GatewaysChanged { gateways } if gateways.is_empty())
+        ));
+    }
+
     impl ClientState {
         pub fn for_test() -> ClientState {
             ClientState::new(BTreeMap::new(), rand::random(), Instant::now())
@@ -1958,6 +2047,29 @@ mod proptests {
         );
     }
 
+    #[test_strategy::proptest]
+    fn only_changed_resources_emit_events(#[strategy(dns_resource())] resource: DnsResource) {
+        let mut client_state = ClientState::for_test();
+        client_state.add_resource(Resource::Dns(resource.clone()));
+        while client_state.poll_event().is_some() {}
+
+        client_state.add_resource(Resource::Dns(resource.clone()));
+
+        assert!(client_state.poll_event().is_none());
+
+        client_state.add_resource(Resource::Dns(DnsResource {
+            name: format!("{} (renamed)", resource.name),
+            ..resource
+        }));
+
+        let Some(ClientEvent::ResourcesChanged { changes, .. }) = client_state.poll_event() else {
+            panic!("Expected `ResourcesChanged` event");
+        };
+        assert!(changes.added.is_empty());
+        assert_eq!(changes.changed.len(), 1);
+        assert!(changes.removed.is_empty());
+    }
+
     #[test_strategy::proptest]
     fn setting_gateway_online_sets_all_related_resources_online(
         #[strategy(resources_sharing_n_sites(1))] resources_online: Vec<Resource>,
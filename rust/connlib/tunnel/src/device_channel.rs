@@ -1,16 +1,26 @@
+mod offload;
+
 use domain::base::iana::Rcode;
 use domain::base::{Message, ParsedName, Rtype};
 use domain::rdata::AllRecordData;
 use ip_packet::{IpPacket, IpPacketBuf};
 use itertools::Itertools;
+use std::collections::VecDeque;
 use std::io;
-use std::task::{Context, Poll, Waker};
+use std::task::{ready, Context, Poll, Waker};
 use tracing::Level;
 use tun::Tun;
 
 pub struct Device {
     tun: Option<Box<dyn Tun>>,
     waker: Option<Waker>,
+
+    /// Buffer for reading GSO batches if the TUN device has offloads enabled.
+    offload_buf: Box<[u8; offload::MAX_BATCH_LEN]>,
+    /// Packets of the last GSO batch that we haven't returned yet.
+    segments: VecDeque<IpPacket>,
+    /// Packets we are about to write, coalesced into GSO batches.
+    gro_batches: offload::GroBatches,
 }
 
 impl Device {
@@ -18,12 +28,19 @@ impl Device {
         Self {
             tun: None,
             waker: None,
+            offload_buf: Box::new([0u8; offload::MAX_BATCH_LEN]),
+            segments: VecDeque::new(),
+            gro_batches: offload::GroBatches::default(),
         }
     }
 
     pub(crate) fn set_tun(&mut self, tun: Box<dyn Tun>) {
         tracing::info!(name = %tun.name(), "Initializing TUN device");
 
+        if let Err(e) = self.flush() {
+            tracing::debug!("Failed to write TUN packets to previous device: {e}");
+        }
+
         self.tun = Some(tun);
 
         if let Some(waker) = self.waker.take() {
@@ -32,21 +49,62 @@ impl Device {
     }
 
     pub(crate) fn poll_read(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<IpPacket>> {
+        let packet = match self.segments.pop_front() {
+            Some(packet) => packet,
+            None => ready!(self.poll_read_tun(cx))?,
+        };
+
+        if tracing::event_enabled!(target: "wire::dns::qry", Level::TRACE) {
+            if let Some((qtype, qname, qid)) = parse_dns_query(&packet) {
+                tracing::trace!(target: "wire::dns::qry", %qid, "{:5} {qname}", qtype.to_string());
+            }
+        }
+
+        if packet.is_fz_p2p_control() {
+            tracing::warn!("Packet matches heuristics of FZ-internal p2p control protocol");
+        }
+
+        tracing::trace!(target: "wire::dev::recv", dst = %packet.destination(), src = %packet.source(), bytes = %packet.packet().len());
+
+        Poll::Ready(Ok(packet))
+    }
+
+    fn poll_read_tun(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<IpPacket>> {
         let Some(tun) = self.tun.as_mut() else {
             self.waker = Some(cx.waker().clone());
             return Poll::Pending;
         };
 
+        if tun.vnet_hdr() {
+            let n = ready!(tun.poll_read(self.offload_buf.as_mut(), cx))?;
+
+            if n == 0 {
+                self.tun = None;
+
+                return Poll::Ready(Err(io_error_closed()));
+            }
+
+            offload::segment(&self.offload_buf[..n], &mut self.segments).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Failed to segment GSO batch: {e:#}"),
+                )
+            })?;
+
+            let packet = self.segments.pop_front().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "GSO batch without segments")
+            })?;
+
+            return Poll::Ready(Ok(packet));
+        }
+
         let mut ip_packet = IpPacketBuf::new();
-        let n = std::task::ready!(tun.poll_read(ip_packet.buf(), cx))?;
+        let n = ready!(tun.poll_read(ip_packet.buf(), cx))?;
 
         if n == 0 {
             self.tun = None;
 
-            return Poll::Ready(Err(io::Error::new(
-                io::ErrorKind::UnexpectedEof,
-                "device is closed",
-            )));
+            return Poll::Ready(Err(io_error_closed()));
         }
 
         let packet = IpPacket::new(ip_packet, n).map_err(|e| {
@@ -56,22 +114,10 @@ impl Device {
             )
         })?;
 
-        if tracing::event_enabled!(target: "wire::dns::qry", Level::TRACE) {
-            if let Some((qtype, qname, qid)) = parse_dns_query(&packet) {
-                tracing::trace!(target: "wire::dns::qry", %qid, "{:5} {qname}", qtype.to_string());
-            }
-        }
-
-        if packet.is_fz_p2p_control() {
-            tracing::warn!("Packet matches heuristics of FZ-internal p2p control protocol");
-        }
-
-        tracing::trace!(target: "wire::dev::recv", dst = %packet.destination(), src = %packet.source(), bytes = %packet.packet().len());
-
         Poll::Ready(Ok(packet))
     }
 
-    pub fn write(&self, packet: IpPacket) -> io::Result<usize> {
+    pub fn write(&mut self, packet: IpPacket) -> io::Result<usize> {
         if tracing::event_enabled!(target: "wire::dns::res", Level::TRACE) {
             if let Some((qtype, qname, records, rcode, qid)) = parse_dns_response(&packet) {
                 tracing::trace!(target: "wire::dns::res", %qid, %rcode, "{:5} {qname} => [{records}]", qtype.to_string());
@@ -85,12 +131,40 @@ impl Device {
             "FZ p2p control protocol packets should never leave `connlib`"
         );
 
+        if self.tun()?.vnet_hdr() {
+            self.gro_batches.push(packet.packet());
+
+            if self.gro_batches.is_full() {
+                self.flush()?;
+            }
+
+            return Ok(packet.packet().len());
+        }
+
         match packet {
             IpPacket::Ipv4(msg) => self.tun()?.write4(msg.packet()),
             IpPacket::Ipv6(msg) => self.tun()?.write6(msg.packet()),
         }
     }
 
+    /// Writes all packets that we coalesced into GSO batches.
+    ///
+    /// Must be called once there are no more packets to write for now.
+    pub fn flush(&mut self) -> io::Result<()> {
+        let Some(tun) = self.tun.as_deref() else {
+            return Ok(());
+        };
+
+        self.gro_batches.flush(|batch, is_v4| {
+            match is_v4 {
+                true => tun.write4(batch)?,
+                false => tun.write6(batch)?,
+            };
+
+            Ok(())
+        })
+    }
+
     fn tun(&self) -> io::Result<&dyn Tun> {
         Ok(self
             .tun
@@ -104,6 +178,10 @@ fn io_error_not_initialized() -> io::Error {
     io::Error::new(io::ErrorKind::NotConnected, "device is not initialized yet")
 }
 
+fn io_error_closed() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "device is closed")
+}
+
 fn parse_dns_query(packet: &IpPacket) -> Option<(Rtype, ParsedName<&[u8]>, u16)> {
     let udp = packet.as_udp()?;
     if udp.destination_port() != crate::dns::DNS_PORT {
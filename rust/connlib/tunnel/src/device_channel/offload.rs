@@ -0,0 +1,537 @@
+//! Segmentation and coalescing of GSO batches for TUN devices opened with `IFF_VNET_HDR`.
+//!
+//! With offloads enabled, the kernel hands us TCP and UDP segments of up to 64KB that we need to split into individual packets before we can encrypt them.
+//! In the other direction, we coalesce consecutive TCP packets of the same flow into such segments, saving syscalls and work in the kernel's TCP stack.
+
+use anyhow::{bail, Context as _, Result};
+use ip_packet::{IpPacket, IpPacketBuf};
+use std::collections::VecDeque;
+use tun::vnet;
+
+/// The largest GSO batch the kernel hands us or accepts, including its [`vnet::Header`].
+pub const MAX_BATCH_LEN: usize = vnet::HEADER_LEN + u16::MAX as usize;
+
+/// How many packets we coalesce at most into a single batch.
+const MAX_SEGMENTS: usize = 64;
+/// How many batches we hold at most before writing them to the device.
+const MAX_PENDING_BATCHES: usize = 32;
+
+const TCP: u8 = 6;
+const UDP: u8 = 17;
+
+const TCP_FLAG_FIN: u8 = 0x01;
+const TCP_FLAG_PSH: u8 = 0x08;
+const TCP_FLAG_ACK: u8 = 0x10;
+const TCP_FLAG_CWR: u8 = 0x80;
+
+/// Splits a GSO batch read from the TUN device (including its [`vnet::Header`]) into individual packets.
+///
+/// The checksums of all segments are computed from scratch because the kernel only hands us partial ones.
+pub fn segment(batch: &[u8], out: &mut VecDeque<IpPacket>) -> Result<()> {
+    let header = vnet::Header::parse(batch).context("Missing virtio-net header")?;
+    let packet = &batch[vnet::HEADER_LEN..];
+
+    match header.gso_type & !vnet::GSO_ECN {
+        vnet::GSO_NONE => {
+            let mut packet = to_ip_packet(packet)?;
+
+            if header.flags & vnet::FLAG_NEEDS_CSUM != 0 {
+                packet.update_checksum();
+            }
+
+            out.push_back(packet);
+
+            return Ok(());
+        }
+        vnet::GSO_TCPV4 | vnet::GSO_TCPV6 | vnet::GSO_UDP_L4 => {}
+        other => bail!("Unsupported GSO type: {other}"),
+    }
+
+    let headers = Headers::parse(packet)?;
+    let gso_size = header.gso_size as usize;
+
+    if gso_size == 0 {
+        bail!("GSO batch without segment size");
+    }
+
+    let (head, payload) = packet.split_at(headers.len());
+    let ip_id = u16::from_be_bytes([head[4], head[5]]);
+    let tcp_seq = read_u32(head, headers.ip_len + 4);
+    let num_segments = payload.len().div_ceil(gso_size);
+
+    for (i, chunk) in payload.chunks(gso_size).enumerate() {
+        let len = head.len() + chunk.len();
+
+        let mut buf = IpPacketBuf::new();
+        let segment = buf
+            .buf()
+            .get_mut(..len)
+            .with_context(|| format!("Segment of {len} bytes is too large"))?;
+        segment[..head.len()].copy_from_slice(head);
+        segment[head.len()..].copy_from_slice(chunk);
+
+        headers.set_ip_len(segment, len);
+
+        if headers.is_v4 {
+            segment[4..6].copy_from_slice(&ip_id.wrapping_add(i as u16).to_be_bytes());
+        }
+
+        match headers.protocol {
+            TCP => {
+                let seq = tcp_seq.wrapping_add((i * gso_size) as u32);
+                segment[headers.ip_len + 4..headers.ip_len + 8].copy_from_slice(&seq.to_be_bytes());
+
+                // Like the kernel, only keep FIN and PSH on the last and CWR on the first segment.
+                let flags = &mut segment[headers.ip_len + 13];
+                if i + 1 < num_segments {
+                    *flags &= !(TCP_FLAG_FIN | TCP_FLAG_PSH);
+                }
+                if i > 0 {
+                    *flags &= !TCP_FLAG_CWR;
+                }
+            }
+            UDP => {
+                let udp_len = (len - headers.ip_len) as u16;
+                segment[headers.ip_len + 4..headers.ip_len + 6]
+                    .copy_from_slice(&udp_len.to_be_bytes());
+            }
+            _ => unreachable!("`Headers::parse` only accepts TCP and UDP"),
+        }
+
+        let mut packet = IpPacket::new(buf, len)?;
+        packet.update_checksum();
+
+        out.push_back(packet);
+    }
+
+    Ok(())
+}
+
+/// Coalesces TCP packets of the same flow into GSO batches before we write them to the TUN device.
+///
+/// All other packets are written individually, in order.
+#[derive(Default)]
+pub struct GroBatches {
+    batches: Vec<Batch>,
+
+    /// Allocations of batches we already wrote, ready to be reused.
+    buffer_pool: Vec<Vec<u8>>,
+}
+
+struct Batch {
+    /// Space for the [`vnet::Header`], followed by the first packet and the payloads of all subsequent ones.
+    buf: Vec<u8>,
+    /// The headers of the first packet, if it is TCP.
+    tcp: Option<Headers>,
+
+    gso_size: usize,
+    num_segments: usize,
+    next_seq: u32,
+    /// Whether we must not add any further segments, e.g. because the last one was shorter.
+    closed: bool,
+}
+
+impl GroBatches {
+    pub fn push(&mut self, packet: &[u8]) {
+        let tcp = Headers::parse(packet).ok().filter(|h| h.protocol == TCP);
+
+        if let Some(headers) = tcp {
+            let batch = self.batches.iter_mut().rev().find(|b| {
+                b.tcp.is_some_and(|first| {
+                    same_flow(&first, &b.buf[vnet::HEADER_LEN..], &headers, packet)
+                })
+            });
+
+            if let Some(batch) = batch {
+                if batch.try_append(&headers, packet) {
+                    return;
+                }
+            }
+        }
+
+        let mut buf = self.buffer_pool.pop().unwrap_or_default();
+        buf.extend_from_slice(&[0; vnet::HEADER_LEN]);
+        buf.extend_from_slice(packet);
+
+        let payload_len = packet.len() - tcp.map_or(0, |h| h.len());
+        let flags = tcp.map_or(0, |h| packet[h.ip_len + 13]);
+
+        self.batches.push(Batch {
+            buf,
+            tcp,
+            gso_size: payload_len,
+            num_segments: 1,
+            next_seq: tcp.map_or(0, |h| {
+                read_u32(packet, h.ip_len + 4).wrapping_add(payload_len as u32)
+            }),
+            closed: payload_len == 0 || flags & !TCP_FLAG_ACK != 0,
+        });
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.batches.len() >= MAX_PENDING_BATCHES
+    }
+
+    /// Hands all batches, prefixed with their [`vnet::Header`], to `write`.
+    ///
+    /// Returns the first error but attempts to write all batches regardless.
+    pub fn flush(
+        &mut self,
+        mut write: impl FnMut(&[u8], bool) -> std::io::Result<()>,
+    ) -> std::io::Result<()> {
+        let mut result = Ok(());
+
+        for mut batch in self.batches.drain(..) {
+            let is_v4 = batch.finish();
+
+            if let Err(e) = write(&batch.buf, is_v4) {
+                result = result.and(Err(e));
+            }
+
+            batch.buf.clear();
+            self.buffer_pool.push(batch.buf);
+        }
+
+        result
+    }
+}
+
+impl Batch {
+    fn try_append(&mut self, headers: &Headers, packet: &[u8]) -> bool {
+        let payload = &packet[headers.len()..];
+        let flags = packet[headers.ip_len + 13];
+
+        let first = &self.buf[vnet::HEADER_LEN..];
+
+        let can_append = !self.closed
+            && self.num_segments < MAX_SEGMENTS
+            && self.buf.len() + payload.len() <= MAX_BATCH_LEN
+            && !payload.is_empty()
+            && payload.len() <= self.gso_size
+            && read_u32(packet, headers.ip_len + 4) == self.next_seq
+            && flags & !(TCP_FLAG_ACK | TCP_FLAG_PSH) == 0
+            && self
+                .tcp
+                .is_some_and(|h| headers_match(&h, &first[..h.len()], &packet[..headers.len()]));
+
+        if !can_append {
+            return false;
+        }
+
+        self.buf.extend_from_slice(payload);
+        self.num_segments += 1;
+        self.next_seq = self.next_seq.wrapping_add(payload.len() as u32);
+
+        if flags & TCP_FLAG_PSH != 0 {
+            let ip_len = headers.ip_len;
+            self.buf[vnet::HEADER_LEN + ip_len + 13] |= TCP_FLAG_PSH;
+            self.closed = true;
+        }
+        if payload.len() < self.gso_size {
+            self.closed = true;
+        }
+
+        true
+    }
+
+    /// Writes the [`vnet::Header`] and fixes up the IP and TCP headers for the total length of the batch.
+    ///
+    /// Returns whether the batch is IPv4.
+    fn finish(&mut self) -> bool {
+        let (header_buf, packet) = self.buf.split_at_mut(vnet::HEADER_LEN);
+        let header_buf: &mut [u8; vnet::HEADER_LEN] = header_buf
+            .try_into()
+            .expect("we split at the header length");
+
+        let Some(headers) = self.tcp.filter(|_| self.num_segments > 1) else {
+            vnet::Header::default().write_to(header_buf);
+
+            return packet.first().is_some_and(|b| b >> 4 == 4);
+        };
+
+        headers.set_ip_len(packet, packet.len());
+
+        if headers.is_v4 {
+            packet[10..12].copy_from_slice(&[0, 0]);
+            let checksum = !fold(sum_words(&packet[..headers.ip_len]));
+            packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+        }
+
+        // The kernel completes the TCP checksum of each segment, we only provide the one of the pseudo-header.
+        let tcp_len = (packet.len() - headers.ip_len) as u32;
+        let pseudo_header_checksum =
+            fold(sum_words(headers.addresses(packet)) + TCP as u32 + tcp_len);
+        packet[headers.ip_len + 16..headers.ip_len + 18]
+            .copy_from_slice(&pseudo_header_checksum.to_be_bytes());
+
+        vnet::Header {
+            flags: vnet::FLAG_NEEDS_CSUM,
+            gso_type: if headers.is_v4 {
+                vnet::GSO_TCPV4
+            } else {
+                vnet::GSO_TCPV6
+            },
+            hdr_len: headers.len() as u16,
+            gso_size: self.gso_size as u16,
+            csum_start: headers.ip_len as u16,
+            csum_offset: 16,
+        }
+        .write_to(header_buf);
+
+        headers.is_v4
+    }
+}
+
+/// Offsets into the IP and transport headers of a packet.
+#[derive(Debug, Clone, Copy)]
+struct Headers {
+    is_v4: bool,
+    ip_len: usize,
+    transport_len: usize,
+    protocol: u8,
+}
+
+impl Headers {
+    fn parse(packet: &[u8]) -> Result<Self> {
+        let first = *packet.first().context("Empty packet")?;
+
+        let (is_v4, ip_len, protocol) = match first >> 4 {
+            4 => (
+                true,
+                (first & 0x0f) as usize * 4,
+                *packet.get(9).context("Packet too short")?,
+            ),
+            6 => (false, 40, *packet.get(6).context("Packet too short")?),
+            v => bail!("Unknown IP version: {v}"),
+        };
+
+        let transport_len = match protocol {
+            TCP => (*packet.get(ip_len + 12).context("Packet too short")? >> 4) as usize * 4,
+            UDP => 8,
+            other => bail!("Unsupported protocol: {other}"),
+        };
+
+        let headers = Self {
+            is_v4,
+            ip_len,
+            transport_len,
+            protocol,
+        };
+
+        if packet.len() < headers.len() {
+            bail!("Packet too short");
+        }
+
+        Ok(headers)
+    }
+
+    fn len(&self) -> usize {
+        self.ip_len + self.transport_len
+    }
+
+    /// Sets the total length (IPv4) or payload length (IPv6) of the packet.
+    fn set_ip_len(&self, packet: &mut [u8], len: usize) {
+        let (offset, value) = match self.is_v4 {
+            true => (2, len),
+            false => (4, len - self.ip_len),
+        };
+
+        packet[offset..offset + 2].copy_from_slice(&(value as u16).to_be_bytes());
+    }
+
+    fn addresses<'p>(&self, packet: &'p [u8]) -> &'p [u8] {
+        match self.is_v4 {
+            true => &packet[12..20],
+            false => &packet[8..40],
+        }
+    }
+
+    fn ports<'p>(&self, packet: &'p [u8]) -> &'p [u8] {
+        &packet[self.ip_len..self.ip_len + 4]
+    }
+}
+
+fn same_flow(a: &Headers, a_packet: &[u8], b: &Headers, b_packet: &[u8]) -> bool {
+    a.is_v4 == b.is_v4
+        && a.protocol == b.protocol
+        && a.addresses(a_packet) == b.addresses(b_packet)
+        && a.ports(a_packet) == b.ports(b_packet)
+}
+
+/// Whether the IP and TCP headers `a` and `b` only differ in the fields that change between segments.
+fn headers_match(headers: &Headers, a: &[u8], b: &[u8]) -> bool {
+    let ip_len = headers.ip_len;
+    let is_mutable = |i: usize| match headers.is_v4 {
+        true if i < ip_len => matches!(i, 2..=5 | 10 | 11), // Total length, identification and checksum.
+        false if i < ip_len => matches!(i, 4 | 5),          // Payload length.
+        _ => matches!(i - ip_len, 4..=7 | 13 | 16 | 17),    // Sequence number, flags and checksum.
+    };
+
+    a.len() == b.len()
+        && a.iter()
+            .zip(b)
+            .enumerate()
+            .all(|(i, (x, y))| x == y || is_mutable(i))
+}
+
+fn to_ip_packet(packet: &[u8]) -> Result<IpPacket> {
+    let mut buf = IpPacketBuf::new();
+    buf.buf()
+        .get_mut(..packet.len())
+        .with_context(|| format!("Packet of {} bytes is too large", packet.len()))?
+        .copy_from_slice(packet);
+
+    IpPacket::new(buf, packet.len())
+}
+
+fn read_u32(packet: &[u8], offset: usize) -> u32 {
+    packet
+        .get(offset..offset + 4)
+        .and_then(|b| b.try_into().ok())
+        .map_or(0, u32::from_be_bytes)
+}
+
+fn sum_words(data: &[u8]) -> u32 {
+    data.chunks(2)
+        .map(|w| u16::from_be_bytes([w[0], w.get(1).copied().unwrap_or(0)]) as u32)
+        .sum()
+}
+
+fn fold(mut sum: u32) -> u16 {
+    while sum > 0xffff {
+        sum = (sum >> 16) + (sum & 0xffff);
+    }
+
+    sum as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    const SRC: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+    const DST: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+
+    #[test]
+    fn coalesced_batch_segments_into_original_packets() {
+        let packets = (0..5)
+            .map(|i| tcp_packet(1000 + i * 100, &[i as u8; 100]))
+            .collect::<Vec<_>>();
+
+        let mut batches = GroBatches::default();
+        for packet in &packets {
+            batches.push(packet.packet());
+        }
+
+        let mut written = Vec::new();
+        batches
+            .flush(|batch, _| {
+                written.push(batch.to_vec());
+                Ok(())
+            })
+            .unwrap();
+
+        assert_eq!(written.len(), 1);
+
+        let header = vnet::Header::parse(&written[0]).unwrap();
+        assert_eq!(header.gso_type, vnet::GSO_TCPV4);
+        assert_eq!(header.gso_size, 100);
+
+        let mut segments = VecDeque::new();
+        segment(&written[0], &mut segments).unwrap();
+
+        assert_eq!(segments.len(), packets.len());
+        for (segment, packet) in segments.iter().zip(&packets) {
+            // The IP identification differs, everything else including the checksums must be the same.
+            assert_eq!(
+                segment.as_tcp().unwrap().to_header(),
+                packet.as_tcp().unwrap().to_header()
+            );
+            assert_eq!(segment.payload(), packet.payload());
+        }
+    }
+
+    #[test]
+    fn does_not_coalesce_gaps_in_sequence_numbers() {
+        let mut batches = GroBatches::default();
+        batches.push(tcp_packet(1000, &[0; 100]).packet());
+        batches.push(tcp_packet(1200, &[0; 100]).packet());
+
+        assert_eq!(num_written(batches), 2);
+    }
+
+    #[test]
+    fn shorter_segment_closes_batch() {
+        let mut batches = GroBatches::default();
+        batches.push(tcp_packet(1000, &[0; 100]).packet());
+        batches.push(tcp_packet(1100, &[0; 50]).packet());
+        batches.push(tcp_packet(1150, &[0; 100]).packet());
+
+        assert_eq!(num_written(batches), 2);
+    }
+
+    #[test]
+    fn single_packet_is_written_without_gso() {
+        let packet = ip_packet::make::udp_packet(SRC, DST, 1, 2, vec![0; 100]).unwrap();
+
+        let mut batches = GroBatches::default();
+        batches.push(packet.packet());
+
+        batches
+            .flush(|batch, is_v4| {
+                assert!(is_v4);
+                assert_eq!(vnet::Header::parse(batch).unwrap(), vnet::Header::default());
+                assert_eq!(&batch[vnet::HEADER_LEN..], packet.packet());
+                Ok(())
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn segments_udp_batch() {
+        let packet = ip_packet::make::udp_packet(SRC, DST, 1, 2, vec![0; 250]).unwrap();
+
+        let mut batch = vec![0; vnet::HEADER_LEN];
+        vnet::Header {
+            gso_type: vnet::GSO_UDP_L4,
+            gso_size: 100,
+            ..Default::default()
+        }
+        .write_to((&mut batch[..vnet::HEADER_LEN]).try_into().unwrap());
+        batch.extend_from_slice(packet.packet());
+
+        let mut segments = VecDeque::new();
+        segment(&batch, &mut segments).unwrap();
+
+        let payload_lens = segments
+            .iter()
+            .map(|s| s.as_udp().unwrap().payload().len())
+            .collect::<Vec<_>>();
+        assert_eq!(payload_lens, vec![100, 100, 50]);
+    }
+
+    fn tcp_packet(seq: u32, payload: &[u8]) -> IpPacket {
+        try_tcp_packet(seq, payload).unwrap()
+    }
+
+    fn try_tcp_packet(seq: u32, payload: &[u8]) -> Result<IpPacket> {
+        let packet = ip_packet::PacketBuilder::ipv4(SRC.octets(), DST.octets(), 64)
+            .tcp(1234, 443, seq, 128)
+            .ack(1);
+
+        ip_packet::build!(packet, payload)
+    }
+
+    fn num_written(mut batches: GroBatches) -> usize {
+        let mut num_written = 0;
+        batches
+            .flush(|_, _| {
+                num_written += 1;
+                Ok(())
+            })
+            .unwrap();
+
+        num_written
+    }
+}
@@ -798,6 +798,43 @@ mod tests {
     }
 }
 
+#[cfg(feature = "fuzzing")]
+pub(crate) mod fuzzing {
+    use super::*;
+
+    /// Handles `query` with a fresh [`StubResolver`] that knows a few hosts and DNS resources.
+    ///
+    /// Returns the response if the query was answered locally and `None` if it would have been forwarded upstream.
+    pub fn handle_dns_query(query: Message<&[u8]>) -> Option<Message<Vec<u8>>> {
+        let known_hosts = BTreeMap::from([(
+            "localhost.example".to_owned(),
+            vec![
+                IpAddr::from(Ipv4Addr::LOCALHOST),
+                IpAddr::from(Ipv6Addr::LOCALHOST),
+            ],
+        )]);
+        let mut resolver = StubResolver::new(known_hosts);
+
+        for (n, pattern) in [
+            "example.com",
+            "*.example.com",
+            "**.foo.example.com",
+            "?.bar.example.com",
+            "*ample.org",
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            resolver.add_resource(ResourceId::from_u128(n as u128), pattern.to_owned());
+        }
+
+        match resolver.handle(query) {
+            ResolveStrategy::LocalResponse(response) => Some(response),
+            ResolveStrategy::Recurse => None,
+        }
+    }
+}
+
 #[cfg(feature = "divan")]
 #[allow(clippy::unwrap_used)]
 mod benches {
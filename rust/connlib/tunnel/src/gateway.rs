@@ -167,6 +167,11 @@ impl GatewayState {
         self.peers.remove(id);
     }
 
+    /// Whether we currently have a connection to or grant access to `id`.
+    pub fn has_client(&self, id: &ClientId) -> bool {
+        self.peers.get(id).is_some()
+    }
+
     pub fn add_ice_candidate(&mut self, conn_id: ClientId, ice_candidate: String, now: Instant) {
         self.node.add_remote_candidate(conn_id, ice_candidate, now);
         self.node.handle_timeout(now);
@@ -535,6 +540,10 @@ impl ResolveDnsRequest {
     pub fn domain(&self) -> &DomainName {
         &self.domain
     }
+
+    pub fn client(&self) -> ClientId {
+        self.client
+    }
 }
 
 fn is_client(dst: IpAddr) -> bool {
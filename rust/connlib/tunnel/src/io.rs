@@ -1,3 +1,5 @@
+mod gso_queue;
+
 use crate::{device_channel::Device, dns, sockets::Sockets};
 use domain::base::Message;
 use firezone_logging::{err_with_sources, telemetry_event, telemetry_span};
@@ -7,9 +9,10 @@ use futures::{
 };
 use futures_bounded::FuturesTupleSet;
 use futures_util::FutureExt as _;
+use gso_queue::GsoQueue;
 use ip_packet::{IpPacket, MAX_DATAGRAM_PAYLOAD};
 use snownet::{EncryptBuffer, EncryptedPacket};
-use socket_factory::{DatagramIn, DatagramOut, SocketFactory, TcpSocket, UdpSocket};
+use socket_factory::{DatagramIn, SocketFactory, TcpSocket, UdpSocket};
 use std::{
     collections::VecDeque,
     io,
@@ -31,7 +34,8 @@ use tun::Tun;
 pub struct Io {
     /// The UDP sockets used to send & receive packets from the network.
     sockets: Sockets,
-    unwritten_packet: Option<EncryptedPacket>,
+    /// Datagrams we still need to send, batched for GSO.
+    gso_queue: GsoQueue,
 
     tcp_socket_factory: Arc<dyn SocketFactory<TcpSocket>>,
     udp_socket_factory: Arc<dyn SocketFactory<UdpSocket>>,
@@ -53,19 +57,19 @@ struct DnsQueryMetaData {
     transport: dns::Transport,
 }
 
-#[expect(
-    clippy::large_enum_variant,
-    reason = "We purposely don't want to allocate each IP packet."
-)]
-pub enum Input<I> {
+pub enum Input<D, I> {
     Timeout(Instant),
-    Device(IpPacket),
+    Device(D),
     Network(I),
     DnsResponse(dns::RecursiveResponse),
 }
 
 const DNS_QUERY_TIMEOUT: Duration = Duration::from_secs(5);
 const IP_CHANNEL_SIZE: usize = 1000;
+/// How many packets we read at most from the TUN device in one go.
+///
+/// Encrypting them all before sending anything allows us to batch them for GSO.
+pub const MAX_INBOUND_PACKET_BATCH: usize = 100;
 
 impl Io {
     /// Creates a new I/O abstraction
@@ -99,7 +103,7 @@ impl Io {
             sockets,
             tcp_socket_factory,
             udp_socket_factory,
-            unwritten_packet: None,
+            gso_queue: GsoQueue::default(),
             dns_queries: FuturesTupleSet::new(DNS_QUERY_TIMEOUT, 1000),
         }
     }
@@ -113,16 +117,25 @@ impl Io {
         cx: &mut Context<'_>,
         ip4_buffer: &'b mut [u8],
         ip6_bffer: &'b mut [u8],
-        encrypt_buffer: &EncryptBuffer,
-    ) -> Poll<io::Result<Input<impl Iterator<Item = DatagramIn<'b>>>>> {
-        ready!(self.poll_send_unwritten(cx, encrypt_buffer)?);
+        device_buffer: &'b mut Vec<IpPacket>,
+    ) -> Poll<
+        io::Result<
+            Input<impl Iterator<Item = IpPacket> + 'b, impl Iterator<Item = DatagramIn<'b>>>,
+        >,
+    > {
+        ready!(self.poll_send_unwritten(cx)?);
 
         if let Poll::Ready(network) = self.sockets.poll_recv_from(ip4_buffer, ip6_bffer, cx)? {
             return Poll::Ready(Ok(Input::Network(network.filter(is_max_wg_packet_size))));
         }
 
-        if let Poll::Ready(Some(packet)) = self.inbound_packet_rx.poll_recv(cx) {
-            return Poll::Ready(Ok(Input::Device(packet)));
+        if let Poll::Ready(num_packets @ 1..) =
+            self.inbound_packet_rx
+                .poll_recv_many(cx, device_buffer, MAX_INBOUND_PACKET_BATCH)
+        {
+            debug_assert_eq!(num_packets, device_buffer.len());
+
+            return Poll::Ready(Ok(Input::Device(device_buffer.drain(..))));
         }
 
         match self.dns_queries.poll_unpin(cx) {
@@ -159,17 +172,21 @@ impl Io {
         Poll::Pending
     }
 
-    fn poll_send_unwritten(
-        &mut self,
-        cx: &mut Context<'_>,
-        buf: &EncryptBuffer,
-    ) -> Poll<io::Result<()>> {
-        ready!(self.sockets.poll_send_ready(cx))?;
+    fn poll_send_unwritten(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        loop {
+            ready!(self.sockets.poll_send_ready(cx))?;
 
-        // If the `unwritten_packet` is set, `EncryptBuffer` is still holding a packet that we need so send.
-        if let Some(unwritten_packet) = self.unwritten_packet.take() {
-            self.send_encrypted_packet(unwritten_packet, buf)?;
-        };
+            let sockets = &mut self.sockets;
+
+            match self.gso_queue.send(|datagram| sockets.send(datagram)) {
+                Ok(()) => break,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    tracing::debug!("Socket busy");
+                    continue; // Register for wake-up once the socket is ready again.
+                }
+                Err(e) => return Poll::Ready(Err(e)),
+            }
+        }
 
         loop {
             // First, acquire a slot in the channel.
@@ -220,14 +237,12 @@ impl Io {
         }
     }
 
-    pub fn send_network(&mut self, transmit: snownet::Transmit) -> io::Result<()> {
-        self.sockets.send(DatagramOut {
-            src: transmit.src,
-            dst: transmit.dst,
-            packet: transmit.payload,
-        })?;
+    /// Queues `transmit` to be sent on the next call to [`Io::poll`].
+    pub fn send_network(&mut self, transmit: snownet::Transmit) {
+        let max_segments = self.sockets.max_gso_segments(transmit.dst);
 
-        Ok(())
+        self.gso_queue
+            .enqueue(transmit.src, transmit.dst, &transmit.payload, max_segments);
     }
 
     pub fn send_dns_query(&mut self, query: dns::RecursiveQuery) {
@@ -317,26 +332,8 @@ impl Io {
         }
     }
 
-    pub fn send_encrypted_packet(
-        &mut self,
-        packet: EncryptedPacket,
-        buf: &EncryptBuffer,
-    ) -> io::Result<()> {
-        let transmit = packet.to_transmit(buf);
-        let res = self.send_network(transmit);
-
-        if res
-            .as_ref()
-            .err()
-            .is_some_and(|e| e.kind() == io::ErrorKind::WouldBlock)
-        {
-            tracing::debug!(dst = %packet.dst(), "Socket busy");
-            self.unwritten_packet = Some(packet);
-        }
-
-        res?;
-
-        Ok(())
+    pub fn send_encrypted_packet(&mut self, packet: EncryptedPacket, buf: &EncryptBuffer) {
+        self.send_network(packet.to_transmit(buf));
     }
 }
 
@@ -353,31 +350,40 @@ async fn tun_send_recv(
     ]);
 
     loop {
-        match future::select(
-            command_stream.next(),
-            future::poll_fn(|cx| device.poll_read(cx)),
-        )
-        .await
-        {
-            Either::Left((Some(Command::SendPacket(p)), _)) => {
+        let event = future::poll_fn(|cx| {
+            if let Poll::Ready(command) = command_stream.poll_next_unpin(cx) {
+                return Poll::Ready(Either::Left(command));
+            }
+
+            // We are out of packets to write for now, write the ones we coalesced.
+            if let Err(e) = device.flush() {
+                tracing::debug!("Failed to write TUN packets: {}", err_with_sources(&e));
+            }
+
+            device.poll_read(cx).map(Either::Right)
+        })
+        .await;
+
+        match event {
+            Either::Left(Some(Command::SendPacket(p))) => {
                 if let Err(e) = device.write(p) {
                     tracing::debug!("Failed to write TUN packet: {}", err_with_sources(&e));
                 };
             }
-            Either::Left((Some(Command::UpdateTun(tun)), _)) => {
+            Either::Left(Some(Command::UpdateTun(tun))) => {
                 device.set_tun(tun);
             }
-            Either::Left((None, _)) => {
+            Either::Left(None) => {
                 tracing::debug!("Command stream closed");
                 return;
             }
-            Either::Right((Ok(p), _)) => {
+            Either::Right(Ok(p)) => {
                 if inbound_packet_tx.send(p).await.is_err() {
                     tracing::debug!("Inbound packet channel closed");
                     return;
                 };
             }
-            Either::Right((Err(e), _)) => {
+            Either::Right(Err(e)) => {
                 tracing::debug!(
                     "Failed to read packet from TUN device: {}",
                     err_with_sources(&e)
@@ -456,7 +462,7 @@ mod tests {
 
         io.reset_timeout(now + Duration::from_secs(1));
 
-        let poll_fn = poll_fn(|cx| io.poll(cx, &mut [], &mut [], &EncryptBuffer::new()))
+        let poll_fn = poll_fn(|cx| io.poll(cx, &mut [], &mut [], &mut Vec::new()))
             .await
             .unwrap();
 
@@ -470,7 +476,7 @@ mod tests {
             &mut Context::from_waker(noop_waker_ref()),
             &mut [],
             &mut [],
-            &EncryptBuffer::new(),
+            &mut Vec::new(),
         );
 
         assert!(poll.is_pending());
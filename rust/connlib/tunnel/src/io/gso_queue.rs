@@ -0,0 +1,239 @@
+//! Batching of outgoing UDP datagrams for GSO (generic segmentation offload).
+
+use socket_factory::DatagramOut;
+use std::{borrow::Cow, collections::VecDeque, io, net::SocketAddr};
+
+/// The maximum size of a single GSO batch, i.e. the maximum payload of a UDP datagram.
+const MAX_BATCH_SIZE: usize = u16::MAX as usize;
+
+/// Holds UDP datagrams that we need to send, batched by source, destination and size.
+///
+/// All datagrams within one batch have the same size which allows us to send them with a single syscall.
+/// Batches are sent in the order they were started and a datagram is only added to the latest batch for its source and destination.
+/// Thus, datagrams to the same destination are never reordered.
+#[derive(Default)]
+pub struct GsoQueue {
+    inner: VecDeque<Batch>,
+
+    /// Allocations of batches we already sent, ready to be reused.
+    buffer_pool: Vec<Vec<u8>>,
+}
+
+struct Batch {
+    src: Option<SocketAddr>,
+    dst: SocketAddr,
+    segment_size: usize,
+    max_segments: usize,
+    payload: Vec<u8>,
+}
+
+impl Batch {
+    fn is_full(&self) -> bool {
+        self.payload.len() >= self.segment_size * self.max_segments
+    }
+}
+
+impl GsoQueue {
+    /// Appends `payload` to the latest batch for its source and destination if it has the same size.
+    ///
+    /// Otherwise, or once the latest batch holds `max_segments` datagrams, a new batch is started.
+    pub fn enqueue(
+        &mut self,
+        src: Option<SocketAddr>,
+        dst: SocketAddr,
+        payload: &[u8],
+        max_segments: usize,
+    ) {
+        let segment_size = payload.len();
+        let max_segments = max_segments
+            .min(MAX_BATCH_SIZE / segment_size.max(1))
+            .max(1);
+
+        let latest = self
+            .inner
+            .iter_mut()
+            .rev()
+            .find(|batch| batch.src == src && batch.dst == dst);
+
+        match latest {
+            Some(batch) if batch.segment_size == segment_size && !batch.is_full() => {
+                batch.payload.extend_from_slice(payload);
+            }
+            _ => {
+                let mut batch = self.buffer_pool.pop().unwrap_or_default();
+                batch.extend_from_slice(payload);
+
+                self.inner.push_back(Batch {
+                    src,
+                    dst,
+                    segment_size,
+                    max_segments,
+                    payload: batch,
+                });
+            }
+        }
+    }
+
+    /// Hands all batches to `send`, one at a time.
+    ///
+    /// `send` returns how many bytes of the batch it sent.
+    /// Stops at the first error or partially sent batch.
+    /// In case of [`io::ErrorKind::WouldBlock`], the unsent part of the batch is retained and will be sent again on the next call.
+    /// All other errors discard the batch.
+    pub fn send(
+        &mut self,
+        mut send: impl FnMut(DatagramOut) -> io::Result<usize>,
+    ) -> io::Result<()> {
+        while let Some(batch) = self.inner.front_mut() {
+            let result = send(DatagramOut {
+                src: batch.src,
+                dst: batch.dst,
+                packet: Cow::Borrowed(&batch.payload),
+                segment_size: Some(batch.segment_size),
+            });
+
+            match result {
+                Ok(sent) if sent < batch.payload.len() => {
+                    batch.payload.drain(..sent);
+
+                    return Err(io::ErrorKind::WouldBlock.into());
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Err(e),
+                Ok(_) | Err(_) => {}
+            }
+
+            let mut batch = self.inner.pop_front().expect("we just peeked at it");
+            batch.payload.clear();
+            self.buffer_pool.push(batch.payload);
+
+            result?;
+        }
+
+        Ok(())
+    }
+
+    #[cfg(test)]
+    fn num_batches(&self) -> usize {
+        self.inner.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddrV4};
+
+    use super::*;
+
+    const DST_1: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(1, 1, 1, 1), 443));
+    const DST_2: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(2, 2, 2, 2), 443));
+
+    #[test]
+    fn batches_datagrams_of_same_size_to_same_destination() {
+        let mut queue = GsoQueue::default();
+
+        queue.enqueue(None, DST_1, &[1; 100], 64);
+        queue.enqueue(None, DST_2, &[2; 100], 64);
+        queue.enqueue(None, DST_1, &[3; 100], 64);
+
+        let sent = send_all(&mut queue);
+
+        assert_eq!(sent, vec![(DST_1, 200, 100), (DST_2, 100, 100)]);
+    }
+
+    #[test]
+    fn preserves_order_of_datagrams_to_same_destination() {
+        let mut queue = GsoQueue::default();
+
+        queue.enqueue(None, DST_1, &[1; 100], 64);
+        queue.enqueue(None, DST_1, &[2; 50], 64);
+        queue.enqueue(None, DST_1, &[3; 100], 64);
+        queue.enqueue(None, DST_1, &[4; 100], 64);
+
+        let sent = send_all(&mut queue);
+
+        assert_eq!(
+            sent,
+            vec![(DST_1, 100, 100), (DST_1, 50, 50), (DST_1, 200, 100)]
+        );
+    }
+
+    #[test]
+    fn starts_new_batch_after_max_segments() {
+        let mut queue = GsoQueue::default();
+
+        for _ in 0..5 {
+            queue.enqueue(None, DST_1, &[0; 100], 2);
+        }
+
+        assert_eq!(queue.num_batches(), 3);
+    }
+
+    #[test]
+    fn batch_does_not_exceed_max_udp_payload() {
+        let mut queue = GsoQueue::default();
+
+        for _ in 0..64 {
+            queue.enqueue(None, DST_1, &[0; 1300], 64);
+        }
+
+        let sent = send_all(&mut queue);
+
+        assert!(sent.iter().all(|(_, len, _)| *len <= MAX_BATCH_SIZE));
+        assert_eq!(sent.iter().map(|(_, len, _)| len).sum::<usize>(), 64 * 1300);
+    }
+
+    #[test]
+    fn retains_batch_on_would_block() {
+        let mut queue = GsoQueue::default();
+        queue.enqueue(None, DST_1, &[0; 100], 64);
+
+        let result = queue.send(|_| Err(io::ErrorKind::WouldBlock.into()));
+
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::WouldBlock);
+        assert_eq!(queue.num_batches(), 1);
+    }
+
+    #[test]
+    fn retains_unsent_part_of_batch() {
+        let mut queue = GsoQueue::default();
+        queue.enqueue(None, DST_1, &[1; 100], 64);
+        queue.enqueue(None, DST_1, &[2; 100], 64);
+        queue.enqueue(None, DST_1, &[3; 100], 64);
+
+        let result = queue.send(|_| Ok(100));
+
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::WouldBlock);
+        assert_eq!(send_all(&mut queue), vec![(DST_1, 200, 100)]);
+    }
+
+    #[test]
+    fn discards_batch_on_other_errors() {
+        let mut queue = GsoQueue::default();
+        queue.enqueue(None, DST_1, &[0; 100], 64);
+
+        let result = queue.send(|_| Err(io::ErrorKind::NotConnected.into()));
+
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::NotConnected);
+        assert_eq!(queue.num_batches(), 0);
+    }
+
+    fn send_all(queue: &mut GsoQueue) -> Vec<(SocketAddr, usize, usize)> {
+        let mut sent = Vec::new();
+
+        queue
+            .send(|datagram| {
+                sent.push((
+                    datagram.dst,
+                    datagram.packet.len(),
+                    datagram.segment_size.unwrap(),
+                ));
+
+                Ok(datagram.packet.len())
+            })
+            .unwrap();
+
+        assert_eq!(queue.num_batches(), 0);
+
+        sent
+    }
+}
@@ -8,9 +8,12 @@
 use crate::messages::{Offer, ResolveRequest, SecretKey};
 use bimap::BiMap;
 use chrono::Utc;
-use connlib_model::{ClientId, DomainName, GatewayId, PublicKey, ResourceId, ResourceView};
+use connlib_model::{
+    ClientId, DomainName, GatewayId, PublicKey, ResourceChanges, ResourceId, ResourceView,
+};
 use io::Io;
 use ip_network::{Ipv4Network, Ipv6Network};
+use ip_packet::IpPacket;
 use snownet::EncryptBuffer;
 use socket_factory::{SocketFactory, TcpSocket, UdpSocket};
 use std::{
@@ -40,6 +43,12 @@ mod sockets;
 mod tests;
 mod utils;
 
+/// Internals exposed for the fuzz targets in `rust/fuzz`.
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing {
+    pub use crate::dns::fuzzing::handle_dns_query;
+}
+
 const MAX_UDP_SIZE: usize = (1 << 16) - 1;
 const REALM: &str = "firezone";
 
@@ -74,11 +83,18 @@ pub struct Tunnel<TRoleState> {
     ip4_read_buf: Box<[u8; MAX_UDP_SIZE]>,
     ip6_read_buf: Box<[u8; MAX_UDP_SIZE]>,
 
+    /// Packets read from the TUN device that we haven't processed yet.
+    device_read_buf: Vec<IpPacket>,
+
     /// Buffer for encrypting a single packet.
     encrypt_buf: EncryptBuffer,
 }
 
 impl<TRoleState> Tunnel<TRoleState> {
+    pub fn state(&self) -> &TRoleState {
+        &self.role_state
+    }
+
     pub fn state_mut(&mut self) -> &mut TRoleState {
         &mut self.role_state
     }
@@ -98,6 +114,7 @@ impl ClientTunnel {
             role_state: ClientState::new(BTreeMap::default(), rand::random(), Instant::now()),
             ip4_read_buf: Box::new([0u8; MAX_UDP_SIZE]),
             ip6_read_buf: Box::new([0u8; MAX_UDP_SIZE]),
+            device_read_buf: Vec::with_capacity(io::MAX_INBOUND_PACKET_BATCH),
             encrypt_buf: Default::default(),
         }
     }
@@ -125,7 +142,7 @@ impl ClientTunnel {
             }
 
             if let Some(transmit) = self.role_state.poll_transmit() {
-                self.io.send_network(transmit)?;
+                self.io.send_network(transmit);
                 continue;
             }
 
@@ -142,24 +159,26 @@ impl ClientTunnel {
                 cx,
                 self.ip4_read_buf.as_mut(),
                 self.ip6_read_buf.as_mut(),
-                &self.encrypt_buf,
+                &mut self.device_read_buf,
             )? {
                 Poll::Ready(io::Input::Timeout(timeout)) => {
                     self.role_state.handle_timeout(timeout);
                     continue;
                 }
-                Poll::Ready(io::Input::Device(packet)) => {
+                Poll::Ready(io::Input::Device(packets)) => {
                     let now = Instant::now();
-                    let Some(enc_packet) =
-                        self.role_state
-                            .handle_tun_input(packet, now, &mut self.encrypt_buf)
-                    else {
-                        self.role_state.handle_timeout(now);
-                        continue;
-                    };
 
-                    self.io
-                        .send_encrypted_packet(enc_packet, &self.encrypt_buf)?;
+                    for packet in packets {
+                        let Some(enc_packet) =
+                            self.role_state
+                                .handle_tun_input(packet, now, &mut self.encrypt_buf)
+                        else {
+                            self.role_state.handle_timeout(now);
+                            continue;
+                        };
+
+                        self.io.send_encrypted_packet(enc_packet, &self.encrypt_buf);
+                    }
 
                     continue;
                 }
@@ -200,15 +219,20 @@ impl ClientTunnel {
 }
 
 impl GatewayTunnel {
+    /// Creates a new Gateway tunnel.
+    ///
+    /// The private key is derived from `seed`, thus tunnels created with the same seed share a public key.
     pub fn new(
         tcp_socket_factory: Arc<dyn SocketFactory<TcpSocket>>,
         udp_socket_factory: Arc<dyn SocketFactory<UdpSocket>>,
+        seed: [u8; 32],
     ) -> Self {
         Self {
             io: Io::new(tcp_socket_factory, udp_socket_factory),
-            role_state: GatewayState::new(rand::random()),
+            role_state: GatewayState::new(seed),
             ip4_read_buf: Box::new([0u8; MAX_UDP_SIZE]),
             ip6_read_buf: Box::new([0u8; MAX_UDP_SIZE]),
+            device_read_buf: Vec::with_capacity(io::MAX_INBOUND_PACKET_BATCH),
             encrypt_buf: Default::default(),
         }
     }
@@ -226,7 +250,7 @@ impl GatewayTunnel {
             }
 
             if let Some(transmit) = self.role_state.poll_transmit() {
-                self.io.send_network(transmit)?;
+                self.io.send_network(transmit);
                 continue;
             }
 
@@ -238,7 +262,7 @@ impl GatewayTunnel {
                 cx,
                 self.ip4_read_buf.as_mut(),
                 self.ip6_read_buf.as_mut(),
-                &self.encrypt_buf,
+                &mut self.device_read_buf,
             )? {
                 Poll::Ready(io::Input::DnsResponse(_)) => {
                     unreachable!("Gateway doesn't use user-space DNS resolution")
@@ -247,44 +271,69 @@ impl GatewayTunnel {
                     self.role_state.handle_timeout(timeout, Utc::now());
                     continue;
                 }
-                Poll::Ready(io::Input::Device(packet)) => {
+                Poll::Ready(io::Input::Device(packets)) => {
                     let now = Instant::now();
-                    let Some(enc_packet) = self
-                        .role_state
-                        .handle_tun_input(packet, now, &mut self.encrypt_buf)
-                        .map_err(std::io::Error::other)?
-                    else {
-                        self.role_state.handle_timeout(now, Utc::now());
-                        continue;
-                    };
-
-                    self.io
-                        .send_encrypted_packet(enc_packet, &self.encrypt_buf)?;
+                    let utc_now = Utc::now();
+                    let mut error = None;
+
+                    // Handle the entire batch, even if individual packets fail.
+                    for packet in packets {
+                        let enc_packet = match self.role_state.handle_tun_input(
+                            packet,
+                            now,
+                            &mut self.encrypt_buf,
+                        ) {
+                            Ok(Some(enc_packet)) => enc_packet,
+                            Ok(None) => {
+                                self.role_state.handle_timeout(now, utc_now);
+                                continue;
+                            }
+                            Err(e) => {
+                                record_batch_error(&mut error, e);
+                                continue;
+                            }
+                        };
+
+                        self.io.send_encrypted_packet(enc_packet, &self.encrypt_buf);
+                    }
+
+                    if let Some(e) = error {
+                        return Poll::Ready(Err(std::io::Error::other(e)));
+                    }
 
                     continue;
                 }
                 Poll::Ready(io::Input::Network(packets)) => {
                     let now = Instant::now();
                     let utc_now = Utc::now();
+                    let mut error = None;
 
+                    // Handle the entire batch, even if individual packets fail.
                     for received in packets {
-                        let Some(packet) = self
-                            .role_state
-                            .handle_network_input(
-                                received.local,
-                                received.from,
-                                received.packet,
-                                now,
-                            )
-                            .map_err(std::io::Error::other)?
-                        else {
-                            self.role_state.handle_timeout(now, utc_now);
-                            continue;
+                        let packet = match self.role_state.handle_network_input(
+                            received.local,
+                            received.from,
+                            received.packet,
+                            now,
+                        ) {
+                            Ok(Some(packet)) => packet,
+                            Ok(None) => {
+                                self.role_state.handle_timeout(now, utc_now);
+                                continue;
+                            }
+                            Err(e) => {
+                                record_batch_error(&mut error, e);
+                                continue;
+                            }
                         };
 
                         self.io.send_tun(packet);
                     }
 
+                    if let Some(e) = error {
+                        return Poll::Ready(Err(std::io::Error::other(e)));
+                    }
+
                     continue;
                 }
                 Poll::Pending => {}
@@ -335,6 +384,12 @@ pub enum ClientEvent {
     /// The list of resources has changed and UI clients may have to be updated.
     ResourcesChanged {
         resources: Vec<ResourceView>,
+        /// What changed compared to the previous event, i.e. everything in `resources` for the first one.
+        changes: ResourceChanges,
+    },
+    /// The set of gateways we have an established connection to has changed.
+    GatewaysChanged {
+        gateways: BTreeSet<GatewayId>,
     },
     TunInterfaceUpdated(TunConfig),
 }
@@ -376,6 +431,21 @@ pub enum GatewayEvent {
     ResolveDns(ResolveDnsRequest),
 }
 
+/// Keeps the first error within a batch of packets so we can return it once the entire batch is handled.
+///
+/// All further errors are only logged.
+fn record_batch_error(first: &mut Option<anyhow::Error>, e: anyhow::Error) {
+    if first.is_some() {
+        tracing::debug!(
+            error = firezone_logging::anyhow_dyn_err(&e),
+            "Failed to handle packet"
+        );
+        return;
+    }
+
+    *first = Some(e);
+}
+
 fn fmt_routes<T>(routes: &BTreeSet<T>, f: &mut fmt::Formatter) -> fmt::Result
 where
     T: fmt::Display,
@@ -57,7 +57,18 @@ impl Sockets {
         Poll::Ready(Ok(()))
     }
 
-    pub fn send(&mut self, datagram: DatagramOut) -> io::Result<()> {
+    /// How many datagrams we can at most send to `dst` in a single GSO batch.
+    pub fn max_gso_segments(&self, dst: SocketAddr) -> usize {
+        let socket = match dst {
+            SocketAddr::V4(_) => self.socket_v4.as_ref(),
+            SocketAddr::V6(_) => self.socket_v6.as_ref(),
+        };
+
+        socket.map_or(1, |s| s.max_gso_segments())
+    }
+
+    /// Sends `datagram`, returning how many bytes of it were sent, see [`socket_factory::UdpSocket::send`].
+    pub fn send(&mut self, datagram: DatagramOut) -> io::Result<usize> {
         let socket = match datagram.dst {
             SocketAddr::V4(dst) => self.socket_v4.as_mut().ok_or_else(|| {
                 io::Error::new(
@@ -72,9 +83,7 @@ impl Sockets {
                 )
             })?,
         };
-        socket.send(datagram)?;
-
-        Ok(())
+        socket.send(datagram)
     }
 
     pub fn poll_recv_from<'b>(
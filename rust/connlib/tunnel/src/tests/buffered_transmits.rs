@@ -26,28 +26,28 @@ impl BufferedTransmits {
     pub(crate) fn push_from<T>(
         &mut self,
         transmit: impl Into<Option<Transmit<'static>>>,
-        sending_host: &Host<T>,
+        sending_host: &mut Host<T>,
         now: Instant,
     ) {
         let Some(transmit) = transmit.into() else {
             return;
         };
 
-        if transmit.src.is_some() {
-            self.push(transmit, sending_host.latency(), now);
-            return;
-        }
-
         // The `src` of a [`Transmit`] is empty if we want to send if via the default interface.
         // In production, the kernel does this for us.
         // In this test, we need to always set a `src` so that the remote peer knows where the packet is coming from.
-
-        let Some(src) = sending_host.sending_socket_for(transmit.dst.ip()) else {
+        let Some(src) = transmit
+            .src
+            .or_else(|| sending_host.sending_socket_for(transmit.dst.ip()))
+        else {
             tracing::debug!(dst = %transmit.dst, "No socket");
 
             return;
         };
 
+        // Lastly, the packet passes through the NAT in front of the sending host (if any).
+        let src = sending_host.translate_outbound(src, transmit.dst);
+
         self.push(
             Transmit {
                 src: Some(src),
@@ -37,6 +37,12 @@ pub(crate) struct ReferenceState {
     pub(crate) global_dns_records: DnsRecords,
 
     pub(crate) network: RoutingTable,
+
+    /// The gateways we had an undisturbed connection to before the current transition.
+    ///
+    /// ICE may still upgrade a connection from relayed to direct in the transition it got established in.
+    #[derivative(Debug = "ignore")]
+    settled_gateways: BTreeSet<GatewayId>,
 }
 
 /// Implementation of our reference state machine.
@@ -137,6 +143,7 @@ impl ReferenceState {
                         global_dns_records,
                         network,
                         drop_direct_client_traffic,
+                        settled_gateways: BTreeSet::default(),
                     }
                 },
             )
@@ -166,6 +173,7 @@ impl ReferenceState {
                 sample::select(resource_ids).prop_map(Transition::DeactivateResource)
             })
             .with(1, roam_client())
+            .with(1, rebind_client_port())
             .with(1, relays(relay_id()).prop_map(Transition::DeployNewRelays))
             .with(1, Just(Transition::PartitionRelaysFromPortal))
             .with(
@@ -177,6 +185,8 @@ impl ReferenceState {
             )
             .with(1, Just(Transition::ReconnectPortal))
             .with(1, Just(Transition::Idle))
+            .with(1, impair_network())
+            .with(1, Just(Transition::HealNetwork))
             .with_if_not_empty(1, state.client.inner().all_resource_ids(), |resources_id| {
                 sample::subsequence(resources_id.clone(), resources_id.len()).prop_map(
                     |resources_id| Transition::DisableResources(BTreeSet::from_iter(resources_id)),
@@ -299,6 +309,13 @@ impl ReferenceState {
     ///
     /// Here is where we implement the "expected" logic.
     pub(crate) fn apply(mut state: Self, transition: &Transition) -> Self {
+        state
+            .settled_gateways
+            .clone_from(state.client.inner().undisturbed_gateways());
+        state
+            .client
+            .exec_mut(|client| client.reliably_sent_to.clear());
+
         match transition {
             Transition::ActivateResource(resource) => {
                 state.client.exec_mut(|client| match resource {
@@ -461,10 +478,9 @@ impl ReferenceState {
                     .client
                     .exec_mut(|client| client.set_upstream_dns_resolvers(servers));
             }
-            Transition::RoamClient { ip4, ip6, .. } => {
+            Transition::RoamClient { ip4, ip6, port } => {
                 state.network.remove_host(&state.client);
-                state.client.ip4.clone_from(ip4);
-                state.client.ip6.clone_from(ip6);
+                state.client.update_interface(*ip4, *ip6, *port);
                 debug_assert!(state
                     .network
                     .add_host(state.client.inner().id, &state.client));
@@ -475,6 +491,12 @@ impl ReferenceState {
                     client.readd_all_resources()
                 });
             }
+            Transition::RebindClientPort { port } => {
+                state.client.rebind_public_port(*port);
+
+                // Our gateways keep talking to the old port until connlib notices the new one or fails the connection.
+                state.client.exec_mut(|client| client.recover_connections());
+            }
             Transition::ReconnectPortal => {
                 // Reconnecting to the portal should have no noticeable impact on the data plane.
                 // We do re-add all resources though so depending on the order they are added in, overlapping CIDR resources may change.
@@ -488,8 +510,16 @@ impl ReferenceState {
             Transition::PartitionRelaysFromPortal => {
                 if state.drop_direct_client_traffic {
                     state.client.exec_mut(|client| client.reset_connections());
+                } else {
+                    state.recover_relay_dependent_connections();
                 }
             }
+            Transition::ImpairNetwork(_) => {
+                state.client.exec_mut(|client| client.impair_network());
+            }
+            Transition::HealNetwork => {
+                state.client.exec_mut(|client| client.heal_network());
+            }
         };
 
         state
@@ -624,6 +654,11 @@ impl ReferenceState {
                                 return false;
                             };
 
+                            // We don't model DNS queries that may get lost.
+                            if state.client.inner().is_unreliable_connection(r, *gateway) {
+                                return false;
+                            }
+
                             state.gateways.contains_key(gateway)
                         }
                         None => true,
@@ -643,6 +678,13 @@ impl ReferenceState {
 
                 !is_assigned_ip4 && !is_assigned_ip6 && !is_previous_port
             }
+            Transition::RebindClientPort { port } => {
+                // Without a NAT, there is nothing that could rebind us.
+                let has_nat = state.client.nat_kind() != NatKind::None;
+
+                // Rebinding to our current or a previous port wouldn't be noticeable.
+                has_nat && !state.client.has_used_port(*port)
+            }
             Transition::ReconnectPortal => true,
             Transition::DeactivateResource(r) => {
                 state.client.inner().all_resource_ids().contains(r)
@@ -662,6 +704,8 @@ impl ReferenceState {
             }
             Transition::Idle => true,
             Transition::PartitionRelaysFromPortal => true,
+            Transition::ImpairNetwork(_) => true,
+            Transition::HealNetwork => state.client.inner().network_impaired,
         }
     }
 
@@ -748,6 +792,67 @@ impl ReferenceState {
         // In case we were using the relays, all connections will be cut and require us to make a new one.
         if self.drop_direct_client_traffic {
             self.client.exec_mut(|client| client.reset_connections());
+        } else {
+            self.recover_relay_dependent_connections();
+        }
+    }
+
+    /// Behind a NAT, even direct connections depend on the server-reflexive candidates discovered via our relays.
+    ///
+    /// When the relays change, we therefore don't know whether those connections survive.
+    fn recover_relay_dependent_connections(&mut self) {
+        let client_nat = self.client.nat_kind();
+        let affected_gateways = self
+            .client
+            .inner()
+            .connected_gateways
+            .iter()
+            .copied()
+            .filter(|gid| {
+                let gateway_nat = self.gateways.get(gid).map(|g| g.nat_kind());
+
+                client_nat != NatKind::None || gateway_nat != Some(NatKind::None)
+            })
+            .collect::<Vec<_>>();
+
+        for gid in affected_gateways {
+            self.client.exec_mut(|client| {
+                client.recover_connection_via(gid, |r| self.portal.gateway_for_resource(r).copied())
+            });
+        }
+    }
+
+    /// The paths we expect data from the client to take in the last transition, for all gateways that data MUST have reached.
+    pub(crate) fn expected_data_paths(&self) -> BTreeMap<GatewayId, DataPath> {
+        self.client
+            .inner()
+            .reliably_sent_to
+            .iter()
+            .filter_map(|gid| Some((*gid, self.expected_data_path(*gid)?)))
+            .collect()
+    }
+
+    /// The path we expect data from the client to take to the given gateway, if we can tell.
+    fn expected_data_path(&self, gid: GatewayId) -> Option<DataPath> {
+        if !self.settled_gateways.contains(&gid) || !self.client.inner().is_undisturbed_gateway(gid)
+        {
+            return None;
+        }
+
+        if self.drop_direct_client_traffic {
+            return Some(DataPath::Relayed);
+        }
+
+        let gateway = self.gateways.get(&gid)?;
+
+        if self
+            .client
+            .nat_kind()
+            .allows_direct_connection_with(gateway.nat_kind())
+        {
+            Some(DataPath::Direct)
+        } else {
+            Some(DataPath::Relayed)
         }
     }
 }
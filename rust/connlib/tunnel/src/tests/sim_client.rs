@@ -1,7 +1,7 @@
 use super::{
     dns_records::DnsRecords,
     reference::{private_key, PrivateKey},
-    sim_net::{any_ip_stack, any_port, host, Host},
+    sim_net::{any_ip_stack, any_nat, any_port, host, Host},
     sim_relay::{map_explode, SimRelay},
     strategies::latency,
     transition::{DPort, Destination, DnsQuery, DnsTransport, Identifier, SPort, Seq},
@@ -67,6 +67,9 @@ pub(crate) struct SimClient {
 
     pub(crate) tcp_dns_client: dns_over_tcp::Client,
 
+    /// The gateways connlib still had an established connection to after each time the network was healed.
+    pub(crate) connections_after_heal: Vec<BTreeSet<GatewayId>>,
+
     enc_buffer: EncryptBuffer,
 }
 
@@ -90,6 +93,7 @@ impl SimClient {
             received_tcp_replies: Default::default(),
             sent_udp_requests: Default::default(),
             received_udp_replies: Default::default(),
+            connections_after_heal: Default::default(),
             enc_buffer: Default::default(),
             ipv4_routes: Default::default(),
             ipv6_routes: Default::default(),
@@ -365,6 +369,9 @@ impl SimClient {
     }
 }
 
+/// Packets sent to each gateway, indexed by their payload.
+type Handshakes<E> = BTreeMap<GatewayId, BTreeMap<u64, E>>;
+
 /// Reference state for a particular client.
 ///
 /// The reference state machine is designed to be as abstract as possible over connlib's functionality.
@@ -423,6 +430,12 @@ pub struct RefClient {
     #[derivative(Debug = "ignore")]
     pub(crate) connected_gateways: BTreeSet<GatewayId>,
 
+    /// Gateways we connected to on a healthy network and whose connection hasn't been disturbed since.
+    ///
+    /// Only for those can we tell, which path the connection should take through the network.
+    #[derivative(Debug = "ignore")]
+    undisturbed_gateways: BTreeSet<GatewayId>,
+
     /// Actively disabled resources by the UI
     #[derivative(Debug = "ignore")]
     pub(crate) disabled_resources: BTreeSet<ResourceId>,
@@ -442,6 +455,51 @@ pub struct RefClient {
     pub(crate) expected_tcp_exchanges:
         BTreeMap<GatewayId, BTreeMap<u64, (Destination, SPort, DPort)>>,
 
+    /// ICMP handshakes that may or may not succeed because the network was unreliable at the time.
+    #[derivative(Debug = "ignore")]
+    pub(crate) best_effort_icmp_handshakes:
+        BTreeMap<GatewayId, BTreeMap<u64, (Destination, Seq, Identifier)>>,
+
+    /// UDP handshakes that may or may not succeed because the network was unreliable at the time.
+    #[derivative(Debug = "ignore")]
+    pub(crate) best_effort_udp_handshakes:
+        BTreeMap<GatewayId, BTreeMap<u64, (Destination, SPort, DPort)>>,
+
+    /// TCP exchanges that may or may not succeed because the network was unreliable at the time.
+    #[derivative(Debug = "ignore")]
+    pub(crate) best_effort_tcp_exchanges:
+        BTreeMap<GatewayId, BTreeMap<u64, (Destination, SPort, DPort)>>,
+
+    /// Gateways that a packet of the current transition MUST reach.
+    #[derivative(Debug = "ignore")]
+    pub(crate) reliably_sent_to: BTreeSet<GatewayId>,
+
+    /// Whether the network is currently losing, duplicating or delaying packets.
+    pub(crate) network_impaired: bool,
+
+    /// Resources whose connection may have broken down due to an impaired network or NAT rebinding.
+    ///
+    /// The next packet to these resources may get lost whilst connlib re-establishes the connection.
+    #[derivative(Debug = "ignore")]
+    recovering_resources: HashSet<(ResourceId, Option<DomainName>)>,
+
+    /// Gateways whose connection may have broken down due to an impaired network or NAT rebinding.
+    #[derivative(Debug = "ignore")]
+    recovering_gateways: BTreeSet<GatewayId>,
+
+    /// Gateways whose connection is only recovering because the network was healed, together with the index of that heal.
+    #[derivative(Debug = "ignore")]
+    healed_gateways: BTreeMap<GatewayId, usize>,
+
+    /// Best-effort packets that were only unreliable because the network was healed just before, by gateway and payload.
+    ///
+    /// If connlib kept the connection through the impaired network, these MUST arrive.
+    #[derivative(Debug = "ignore")]
+    healed_packets: BTreeMap<GatewayId, BTreeMap<u64, usize>>,
+
+    /// How many times the network was healed.
+    num_heals: usize,
+
     /// The expected UDP DNS handshakes.
     #[derivative(Debug = "ignore")]
     pub(crate) expected_udp_dns_handshakes: VecDeque<(SocketAddr, QueryId)>,
@@ -472,6 +530,7 @@ impl RefClient {
 
         self.connected_cidr_resources.remove(resource);
         self.connected_dns_resources.retain(|(r, _)| r != resource);
+        self.recovering_resources.retain(|(r, _)| r != resource);
 
         if self.internet_resource.is_some_and(|r| &r == resource) {
             self.connected_internet_resource = false;
@@ -518,6 +577,99 @@ impl RefClient {
         self.connected_dns_resources.clear();
         self.connected_internet_resource = false;
         self.connected_gateways.clear();
+        self.undisturbed_gateways.clear();
+        self.recovering_resources.clear();
+        self.recovering_gateways.clear();
+        self.healed_gateways.clear();
+    }
+
+    pub(crate) fn impair_network(&mut self) {
+        self.network_impaired = true;
+        self.undisturbed_gateways.clear();
+        self.healed_gateways.clear();
+    }
+
+    /// Marks all current connections as recovering from the impaired network.
+    ///
+    /// Unlike other disruptions, connlib can tell us afterwards which connections survived.
+    pub(crate) fn heal_network(&mut self) {
+        self.network_impaired = false;
+        self.recover_connections();
+        self.healed_gateways = self
+            .connected_gateways
+            .iter()
+            .map(|gateway| (*gateway, self.num_heals))
+            .collect();
+        self.num_heals += 1;
+    }
+
+    /// Returns which heal of the network made the given packet a best-effort one, if any.
+    pub(crate) fn healed_by(&self, gateway: GatewayId, payload: u64) -> Option<usize> {
+        self.healed_packets.get(&gateway)?.get(&payload).copied()
+    }
+
+    /// Marks all current connections as recovering.
+    ///
+    /// After the network was impaired or our NAT rebound us, we don't know which of connlib's connections survived.
+    /// Those that didn't will be re-established by the next packet, which may get lost in the process.
+    pub(crate) fn recover_connections(&mut self) {
+        self.recovering_resources = self.connected_resources().collect();
+        self.recovering_gateways = self.connected_gateways.clone();
+        self.undisturbed_gateways.clear();
+        self.healed_gateways.clear();
+    }
+
+    /// Marks the connection to a single gateway as recovering.
+    pub(crate) fn recover_connection_via(
+        &mut self,
+        gateway: GatewayId,
+        gateway_by_resource: impl Fn(ResourceId) -> Option<GatewayId>,
+    ) {
+        let resources = self
+            .connected_resources()
+            .filter(|(r, _)| gateway_by_resource(*r) == Some(gateway))
+            .collect_vec();
+
+        self.recovering_resources.extend(resources);
+        self.recovering_gateways.insert(gateway);
+        self.undisturbed_gateways.remove(&gateway);
+        self.healed_gateways.remove(&gateway);
+    }
+
+    fn connected_resources(&self) -> impl Iterator<Item = (ResourceId, Option<DomainName>)> + '_ {
+        let internet_resource = self
+            .active_internet_resource()
+            .filter(|_| self.connected_internet_resource);
+
+        self.connected_cidr_resources
+            .iter()
+            .copied()
+            .chain(internet_resource)
+            .map(|r| (r, None))
+            .chain(
+                self.connected_dns_resources
+                    .iter()
+                    .map(|(r, domain)| (*r, Some(domain.clone()))),
+            )
+    }
+
+    pub(crate) fn is_undisturbed_gateway(&self, gateway: GatewayId) -> bool {
+        self.undisturbed_gateways.contains(&gateway)
+    }
+
+    pub(crate) fn undisturbed_gateways(&self) -> &BTreeSet<GatewayId> {
+        &self.undisturbed_gateways
+    }
+
+    /// Whether packets to the given CIDR or Internet resource may currently get lost.
+    pub(crate) fn is_unreliable_connection(
+        &self,
+        resource: ResourceId,
+        gateway: GatewayId,
+    ) -> bool {
+        self.network_impaired
+            || self.recovering_gateways.contains(&gateway)
+            || self.recovering_resources.contains(&(resource, None))
     }
 
     pub(crate) fn add_internet_resource(&mut self, r: InternetResource) {
@@ -595,7 +747,12 @@ impl RefClient {
             src,
             dst.clone(),
             (dst, seq, identifier),
-            |ref_client| &mut ref_client.expected_icmp_handshakes,
+            |ref_client| {
+                (
+                    &mut ref_client.expected_icmp_handshakes,
+                    &mut ref_client.best_effort_icmp_handshakes,
+                )
+            },
             payload,
             gateway_by_resource,
         );
@@ -614,7 +771,12 @@ impl RefClient {
             src,
             dst.clone(),
             (dst, sport, dport),
-            |ref_client| &mut ref_client.expected_udp_handshakes,
+            |ref_client| {
+                (
+                    &mut ref_client.expected_udp_handshakes,
+                    &mut ref_client.best_effort_udp_handshakes,
+                )
+            },
             payload,
             gateway_by_resource,
         );
@@ -633,7 +795,12 @@ impl RefClient {
             src,
             dst.clone(),
             (dst, sport, dport),
-            |ref_client| &mut ref_client.expected_tcp_exchanges,
+            |ref_client| {
+                (
+                    &mut ref_client.expected_tcp_exchanges,
+                    &mut ref_client.best_effort_tcp_exchanges,
+                )
+            },
             payload,
             gateway_by_resource,
         );
@@ -645,7 +812,7 @@ impl RefClient {
         src: IpAddr,
         dst: Destination,
         packet_id: E,
-        map: impl FnOnce(&mut Self) -> &mut BTreeMap<GatewayId, BTreeMap<u64, E>>,
+        map: impl FnOnce(&mut Self) -> (&mut Handshakes<E>, &mut Handshakes<E>),
         payload: u64,
         gateway_by_resource: impl Fn(ResourceId) -> Option<GatewayId>,
     ) {
@@ -661,8 +828,31 @@ impl RefClient {
         };
 
         if self.is_connected_to_resource(resource, &dst) && self.is_tunnel_ip(src) {
+            let may_get_lost = self.may_lose_packet(resource, &dst, gateway);
+
+            if let Some(heal) = self.healed_gateways.get(&gateway).filter(|_| may_get_lost) {
+                self.healed_packets
+                    .entry(gateway)
+                    .or_default()
+                    .insert(payload, *heal);
+            }
+
+            if may_get_lost {
+                tracing::debug!(
+                    "Connected to resource but connection is unreliable, packet may get lost"
+                );
+                let (_, best_effort) = map(self);
+                best_effort
+                    .entry(gateway)
+                    .or_default()
+                    .insert(payload, packet_id);
+                return;
+            }
+
             tracing::debug!("Connected to resource, expecting packet to be routed");
-            map(self)
+            self.reliably_sent_to.insert(gateway);
+            let (expected, _) = map(self);
+            expected
                 .entry(gateway)
                 .or_default()
                 .insert(payload, packet_id);
@@ -676,10 +866,32 @@ impl RefClient {
             );
         }
 
+        if !self.network_impaired {
+            self.recovering_gateways.remove(&gateway);
+        }
+
         tracing::debug!("Not connected to resource, expecting to trigger connection intent");
         self.connect_to_resource(resource, dst, gateway);
     }
 
+    /// Whether a packet to a connected resource may get lost, either because the network is impaired or because the connection is still recovering.
+    ///
+    /// Once the network is reliable again, the first packet to a resource re-establishes the connection if necessary.
+    fn may_lose_packet(
+        &mut self,
+        resource: ResourceId,
+        destination: &Destination,
+        gateway: GatewayId,
+    ) -> bool {
+        if self.network_impaired {
+            return true;
+        }
+
+        self.recovering_gateways.remove(&gateway);
+        self.recovering_resources
+            .remove(&(resource, destination.domain_name().cloned()))
+    }
+
     fn connect_to_resource(
         &mut self,
         resource: ResourceId,
@@ -690,7 +902,7 @@ impl RefClient {
             Destination::DomainName { name, .. } => {
                 if !self.disabled_resources.contains(&resource) {
                     self.connected_dns_resources.insert((resource, name));
-                    self.connected_gateways.insert(gateway);
+                    self.connect_to_gateway(gateway);
                 }
             }
             Destination::IpAddr(_) => self.connect_to_internet_or_cidr_resource(resource, gateway),
@@ -712,13 +924,21 @@ impl RefClient {
     ) {
         if self.internet_resource.is_some_and(|r| r == resource) {
             self.connected_internet_resource = true;
-            self.connected_gateways.insert(gateway);
+            self.connect_to_gateway(gateway);
             return;
         }
 
         if self.cidr_resources.iter().any(|(_, r)| *r == resource) {
             self.connected_cidr_resources.insert(resource);
-            self.connected_gateways.insert(gateway);
+            self.connect_to_gateway(gateway);
+        }
+    }
+
+    fn connect_to_gateway(&mut self, gateway: GatewayId) {
+        let is_new = self.connected_gateways.insert(gateway);
+
+        if is_new && !self.network_impaired {
+            self.undisturbed_gateways.insert(gateway);
         }
     }
 
@@ -838,31 +1058,39 @@ impl RefClient {
         identifier: &Identifier,
         payload: &u64,
     ) -> bool {
-        self.expected_icmp_handshakes.values().flatten().all(
-            |(existig_payload, (_, existing_seq, existing_identifier))| {
-                existing_seq != seq
-                    && existing_identifier != identifier
-                    && existig_payload != payload
-            },
-        )
+        self.expected_icmp_handshakes
+            .values()
+            .chain(self.best_effort_icmp_handshakes.values())
+            .flatten()
+            .all(
+                |(existig_payload, (_, existing_seq, existing_identifier))| {
+                    existing_seq != seq
+                        && existing_identifier != identifier
+                        && existig_payload != payload
+                },
+            )
     }
 
     /// An UDP packet is valid if we didn't yet send an UDP packet with the same sport, dport and payload.
     pub(crate) fn is_valid_udp_packet(&self, sport: &SPort, dport: &DPort, payload: &u64) -> bool {
-        self.expected_udp_handshakes.values().flatten().all(
-            |(existig_payload, (_, existing_sport, existing_dport))| {
+        self.expected_udp_handshakes
+            .values()
+            .chain(self.best_effort_udp_handshakes.values())
+            .flatten()
+            .all(|(existig_payload, (_, existing_sport, existing_dport))| {
                 existing_dport != dport && existing_sport != sport && existig_payload != payload
-            },
-        )
+            })
     }
 
     /// An TCP packet is valid if we didn't yet send an TCP packet with the same sport, dport and payload.
     pub(crate) fn is_valid_tcp_packet(&self, sport: &SPort, dport: &DPort, payload: &u64) -> bool {
-        self.expected_tcp_exchanges.values().flatten().all(
-            |(existig_payload, (_, existing_sport, existing_dport))| {
+        self.expected_tcp_exchanges
+            .values()
+            .chain(self.best_effort_tcp_exchanges.values())
+            .flatten()
+            .all(|(existig_payload, (_, existing_sport, existing_dport))| {
                 existing_dport != dport && existing_sport != sport && existig_payload != payload
-            },
-        )
+            })
     }
 
     pub(crate) fn resolved_v4_domains(&self) -> Vec<DomainName> {
@@ -1050,6 +1278,7 @@ pub(crate) fn ref_client_host(
         any_port(),
         ref_client(tunnel_ip4s, tunnel_ip6s, system_dns, upstream_dns),
         latency(300), // TODO: Increase with #6062.
+        any_nat(),
     )
 }
 
@@ -1095,6 +1324,16 @@ fn ref_client(
                     expected_icmp_handshakes: Default::default(),
                     expected_udp_handshakes: Default::default(),
                     expected_tcp_exchanges: Default::default(),
+                    best_effort_icmp_handshakes: Default::default(),
+                    best_effort_udp_handshakes: Default::default(),
+                    best_effort_tcp_exchanges: Default::default(),
+                    reliably_sent_to: Default::default(),
+                    network_impaired: false,
+                    recovering_resources: Default::default(),
+                    recovering_gateways: Default::default(),
+                    healed_gateways: Default::default(),
+                    healed_packets: Default::default(),
+                    num_heals: 0,
                     expected_udp_dns_handshakes: Default::default(),
                     expected_tcp_dns_handshakes: Default::default(),
                     disabled_resources: Default::default(),
@@ -1102,6 +1341,7 @@ fn ref_client(
                     ipv4_routes: Default::default(),
                     ipv6_routes: Default::default(),
                     connected_gateways: Default::default(),
+                    undisturbed_gateways: Default::default(),
                 }
             },
         )
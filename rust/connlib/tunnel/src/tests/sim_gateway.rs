@@ -2,7 +2,7 @@ use super::{
     dns_records::DnsRecords,
     dns_server_resource::{TcpDnsServerResource, UdpDnsServerResource},
     reference::{private_key, PrivateKey},
-    sim_net::{any_port, dual_ip_stack, host, Host},
+    sim_net::{any_nat, any_port, dual_ip_stack, host, Host},
     sim_relay::{map_explode, SimRelay},
     strategies::latency,
 };
@@ -250,6 +250,7 @@ pub(crate) fn ref_gateway_host() -> impl Strategy<Value = Host<RefGateway>> {
         any_port(),
         ref_gateway(),
         latency(200), // We assume gateways have a somewhat decent Internet connection.
+        any_nat(),
     )
 }
 
@@ -7,9 +7,10 @@ use ip_network_table::IpNetworkTable;
 use itertools::Itertools as _;
 use prop::sample;
 use proptest::prelude::*;
+use rand::{rngs::StdRng, Rng as _, SeedableRng as _};
 use snownet::Transmit;
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     fmt,
     net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
     num::NonZeroU16,
@@ -31,6 +32,8 @@ pub(crate) struct Host<T> {
     pub(crate) old_ports: HashSet<u16>,
 
     default_port: u16,
+    /// The NAT in front of this host's `default_port`.
+    nat: Nat,
     #[derivative(Debug = "ignore")]
     allocated_ports: HashSet<(u16, AddressFamily)>,
 
@@ -55,6 +58,7 @@ impl<T> Host<T> {
             ip6: None,
             span: Span::none(),
             default_port: 0,
+            nat: Nat::none(),
             allocated_ports: HashSet::default(),
             old_ports: HashSet::default(),
             latency,
@@ -94,8 +98,9 @@ impl<T> Host<T> {
         ip6: Option<Ipv6Addr>,
         port: u16,
     ) {
-        // 1. Remember what the current port was.
+        // 1. Remember what the current ports were.
         self.old_ports.insert(self.default_port);
+        self.old_ports.extend(self.nat.reset());
 
         // 2. Update to the new IPs.
         self.ip4 = ip4;
@@ -115,6 +120,34 @@ impl<T> Host<T> {
         }
     }
 
+    /// Simulates the NAT in front of this host dropping all its mappings and assigning new public ports, starting with `port`.
+    ///
+    /// Unlike [`Host::update_interface`], the host itself doesn't notice: Its IPs and sockets stay the same.
+    pub(crate) fn rebind_public_port(&mut self, port: u16) {
+        self.old_ports.extend(self.nat.reset());
+        self.nat.next_port = port;
+    }
+
+    /// Whether this host is using the given port locally or used it before roaming or a rebind.
+    ///
+    /// Ignores the ports of the NAT's current mappings: Only the system under test sends traffic, so only its NAT has any.
+    pub(crate) fn has_used_port(&self, port: u16) -> bool {
+        port == self.default_port || self.old_ports.contains(&port)
+    }
+
+    pub(crate) fn nat_kind(&self) -> NatKind {
+        self.nat.kind
+    }
+
+    /// Translates the source of an outgoing packet according to the mapping of our NAT.
+    pub(crate) fn translate_outbound(&mut self, src: SocketAddr, dst: SocketAddr) -> SocketAddr {
+        if src.port() != self.default_port {
+            return src;
+        }
+
+        self.nat.translate_outbound(src, dst, self.default_port)
+    }
+
     pub(crate) fn is_sender(&self, src: IpAddr) -> bool {
         match src {
             IpAddr::V4(src) => self.ip4.is_some_and(|v4| v4 == src),
@@ -126,8 +159,26 @@ impl<T> Host<T> {
         self.latency
     }
 
-    pub(crate) fn receive(&mut self, transmit: Transmit<'static>, now: Instant) {
-        self.inbox.push(transmit, self.latency, now);
+    /// Receives a packet from the network.
+    ///
+    /// Returns `false` if the NAT in front of this host dropped it.
+    pub(crate) fn receive(&mut self, transmit: Transmit<'static>, now: Instant) -> bool {
+        let src = transmit
+            .src
+            .expect("`src` should always be set in these tests");
+
+        let Some(dst) = self
+            .nat
+            .translate_inbound(src, transmit.dst, self.default_port)
+        else {
+            tracing::debug!(%src, dst = %transmit.dst, kind = ?self.nat.kind, "Dropping packet at NAT");
+            return false;
+        };
+
+        self.inbox
+            .push(Transmit { dst, ..transmit }, self.latency, now);
+
+        true
     }
 
     pub(crate) fn poll_transmit(&mut self, now: Instant) -> Option<Transmit<'static>> {
@@ -150,6 +201,7 @@ where
             ip6: self.ip6,
             span,
             default_port: self.default_port,
+            nat: self.nat.clone(),
             allocated_ports: self.allocated_ports.clone(),
             old_ports: self.old_ports.clone(),
             latency: self.latency,
@@ -158,6 +210,209 @@ where
     }
 }
 
+/// The behaviour of a NAT as classified by [RFC3489](https://datatracker.ietf.org/doc/html/rfc3489#section-5).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum NatKind {
+    /// The host isn't behind a NAT and directly reachable.
+    None,
+    /// All packets to a mapped port are forwarded to the host.
+    FullCone,
+    /// Packets to a mapped port are only forwarded from IPs the host has sent packets to.
+    AddressRestricted,
+    /// Packets to a mapped port are only forwarded from IPs and ports the host has sent packets to.
+    PortRestricted,
+    /// Like [`NatKind::PortRestricted`] but every destination gets its own mapping.
+    Symmetric,
+}
+
+impl NatKind {
+    /// Whether two hosts behind these NATs can talk to each other directly after exchanging their server-reflexive candidates.
+    ///
+    /// A symmetric NAT uses a port for the remote peer that the peer doesn't know about.
+    /// Unless the peer's NAT lets packets from any port of our IP through, the peer never learns about it.
+    pub(crate) fn allows_direct_connection_with(self, other: NatKind) -> bool {
+        !matches!(
+            (self, other),
+            (NatKind::Symmetric, NatKind::Symmetric)
+                | (NatKind::Symmetric, NatKind::PortRestricted)
+                | (NatKind::PortRestricted, NatKind::Symmetric)
+        )
+    }
+}
+
+/// A NAT in front of a host's default socket.
+///
+/// For simplicity, the NAT uses the same IP on both sides and only translates ports.
+#[derive(Debug, Clone)]
+pub(crate) struct Nat {
+    kind: NatKind,
+    /// The public ports assigned to the host's default socket.
+    ///
+    /// Only symmetric NATs key their mappings by destination.
+    mappings: HashMap<Option<SocketAddr>, u16>,
+    /// The destinations the host has sent packets to, per public port.
+    contacted: HashSet<(u16, SocketAddr)>,
+    /// The next public port we hand out.
+    next_port: u16,
+}
+
+impl Nat {
+    pub(crate) fn none() -> Self {
+        Self::new(NatKind::None, 0)
+    }
+
+    fn new(kind: NatKind, next_port: u16) -> Self {
+        Self {
+            kind,
+            mappings: HashMap::default(),
+            contacted: HashSet::default(),
+            next_port,
+        }
+    }
+
+    /// Drops all mappings, returning the public ports that were in use.
+    fn reset(&mut self) -> impl Iterator<Item = u16> + '_ {
+        self.contacted.clear();
+
+        self.mappings.drain().map(|(_, port)| port)
+    }
+
+    fn translate_outbound(
+        &mut self,
+        src: SocketAddr,
+        dst: SocketAddr,
+        default_port: u16,
+    ) -> SocketAddr {
+        let key = match self.kind {
+            NatKind::None => return src,
+            NatKind::FullCone | NatKind::AddressRestricted | NatKind::PortRestricted => None,
+            NatKind::Symmetric => Some(dst),
+        };
+
+        let next_port = &mut self.next_port;
+        let port = *self.mappings.entry(key).or_insert_with(|| loop {
+            let port = *next_port;
+            *next_port = next_port.checked_add(1).unwrap_or(1);
+
+            if port != 0 && port != default_port {
+                break port;
+            }
+        });
+        self.contacted.insert((port, dst));
+
+        SocketAddr::new(src.ip(), port)
+    }
+
+    /// Returns `None` if the NAT doesn't have a mapping for `dst` or filters packets from `src`.
+    fn translate_inbound(
+        &self,
+        src: SocketAddr,
+        dst: SocketAddr,
+        default_port: u16,
+    ) -> Option<SocketAddr> {
+        if self.kind == NatKind::None {
+            return Some(dst);
+        }
+
+        if !self.mappings.values().any(|p| *p == dst.port()) {
+            return None;
+        }
+
+        let is_allowed = match self.kind {
+            NatKind::None | NatKind::FullCone => true,
+            NatKind::AddressRestricted => self
+                .contacted
+                .iter()
+                .any(|(port, remote)| *port == dst.port() && remote.ip() == src.ip()),
+            NatKind::PortRestricted | NatKind::Symmetric => {
+                self.contacted.contains(&(dst.port(), src))
+            }
+        };
+
+        is_allowed.then_some(SocketAddr::new(dst.ip(), default_port))
+    }
+}
+
+/// The path that packets between client and gateway take through the network.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum DataPath {
+    Direct,
+    Relayed,
+}
+
+/// Degrades the delivery of packets, similar to what we see on flaky Wi-Fi or LTE connections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Impairment {
+    /// Percentage of packets that get lost.
+    pub(crate) loss: u8,
+    /// Percentage of packets that get delivered twice.
+    pub(crate) duplication: u8,
+    /// Additional latency for every packet.
+    pub(crate) delay: Duration,
+    /// Upper bound of random latency added on top of `delay`.
+    ///
+    /// Packets sent in quick succession may overtake each other as a result.
+    pub(crate) jitter: Duration,
+    /// Seed for deciding the fate of individual packets.
+    pub(crate) seed: u64,
+}
+
+/// Applies an [`Impairment`] to the packets travelling through the network.
+#[derive(Debug, Clone)]
+pub(crate) struct ImpairedNetwork {
+    impairment: Impairment,
+    rng: StdRng,
+}
+
+impl ImpairedNetwork {
+    pub(crate) fn new(impairment: Impairment) -> Self {
+        Self {
+            impairment,
+            rng: StdRng::seed_from_u64(impairment.seed),
+        }
+    }
+
+    /// Computes when a packet sent at `at` arrives at its destination.
+    ///
+    /// Lost packets don't arrive at all, duplicated packets arrive twice.
+    pub(crate) fn arrivals(&mut self, at: Instant) -> Vec<Instant> {
+        if self.rng.gen_ratio(self.impairment.loss.into(), 100) {
+            return Vec::new();
+        }
+
+        let copies = if self.rng.gen_ratio(self.impairment.duplication.into(), 100) {
+            2
+        } else {
+            1
+        };
+
+        (0..copies)
+            .map(|_| {
+                let jitter = self.rng.gen_range(Duration::ZERO..=self.impairment.jitter);
+
+                at + self.impairment.delay + jitter
+            })
+            .collect()
+    }
+}
+
+pub(crate) fn impairment() -> impl Strategy<Value = Impairment> {
+    (
+        0..=20u8,
+        0..=10u8,
+        (0..=200u64).prop_map(Duration::from_millis),
+        (0..=100u64).prop_map(Duration::from_millis),
+        any::<u64>(),
+    )
+        .prop_map(|(loss, duplication, delay, jitter, seed)| Impairment {
+            loss,
+            duplication,
+            delay,
+            jitter,
+            seed,
+        })
+}
+
 #[derive(Debug, Clone)]
 pub(crate) struct RoutingTable {
     routes: IpNetworkTable<HostId>,
@@ -277,16 +532,32 @@ pub(crate) fn host<T>(
     default_port: impl Strategy<Value = u16>,
     state: impl Strategy<Value = T>,
     latency: impl Strategy<Value = Duration>,
+    nat: impl Strategy<Value = Nat>,
 ) -> impl Strategy<Value = Host<T>>
 where
     T: fmt::Debug,
 {
-    (state, socket_ips, default_port, latency).prop_map(move |(state, ip_stack, port, latency)| {
-        let mut host = Host::new(state, latency);
-        host.update_interface(ip_stack.as_v4().copied(), ip_stack.as_v6().copied(), port);
+    (state, socket_ips, default_port, latency, nat).prop_map(
+        move |(state, ip_stack, port, latency, nat)| {
+            let mut host = Host::new(state, latency);
+            host.update_interface(ip_stack.as_v4().copied(), ip_stack.as_v6().copied(), port);
+            host.nat = nat;
+
+            host
+        },
+    )
+}
+
+pub(crate) fn any_nat() -> impl Strategy<Value = Nat> {
+    let kind = prop_oneof![
+        Just(NatKind::None),
+        Just(NatKind::FullCone),
+        Just(NatKind::AddressRestricted),
+        Just(NatKind::PortRestricted),
+        Just(NatKind::Symmetric),
+    ];
 
-        host
-    })
+    (kind, any_port()).prop_map(|(kind, port)| Nat::new(kind, port))
 }
 
 pub(crate) fn any_port() -> impl Strategy<Value = u16> {
@@ -1,5 +1,5 @@
 use super::{
-    sim_net::{dual_ip_stack, host, Host},
+    sim_net::{dual_ip_stack, host, Host, Nat},
     strategies::latency,
 };
 use connlib_model::RelayId;
@@ -208,6 +208,7 @@ pub(crate) fn ref_relay_host() -> impl Strategy<Value = Host<u64>> {
         dual_ip_stack(), // For this test, our relays always run in dual-stack mode to ensure connectivity!
         Just(3478),
         any::<u64>(),
-        latency(50), // We assume our relays have a good Internet connection.
+        latency(50),       // We assume our relays have a good Internet connection.
+        Just(Nat::none()), // Relays need to be reachable from everywhere.
     )
 }
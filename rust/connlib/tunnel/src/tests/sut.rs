@@ -3,7 +3,7 @@ use super::dns_records::DnsRecords;
 use super::reference::ReferenceState;
 use super::sim_client::SimClient;
 use super::sim_gateway::SimGateway;
-use super::sim_net::{Host, HostId, RoutingTable};
+use super::sim_net::{DataPath, Host, HostId, ImpairedNetwork, RoutingTable};
 use super::sim_relay::SimRelay;
 use super::stub_portal::StubPortal;
 use super::transition::{Destination, DnsQuery};
@@ -29,6 +29,11 @@ use std::{
 };
 use tracing::debug_span;
 
+/// How long we give connlib to recover its connections after the network changed underneath it.
+///
+/// Idle connections are only checked infrequently so this needs to be long enough for connlib to notice broken ones.
+const RECOVERY_DURATION: Duration = Duration::from_secs(6 * 60);
+
 /// The actual system-under-test.
 ///
 /// [`proptest`] manipulates this using [`Transition`]s and we assert it against [`ReferenceState`].
@@ -41,6 +46,10 @@ pub(crate) struct TunnelTest {
 
     drop_direct_client_traffic: bool,
     network: RoutingTable,
+    impaired_network: Option<ImpairedNetwork>,
+
+    /// The path WireGuard data from the client took to each gateway during the current transition.
+    data_paths: BTreeMap<GatewayId, DataPath>,
 }
 
 impl TunnelTest {
@@ -86,6 +95,8 @@ impl TunnelTest {
             flux_capacitor: flux_capacitor.clone(),
             network: ref_state.network.clone(),
             drop_direct_client_traffic: ref_state.drop_direct_client_traffic,
+            impaired_network: None,
+            data_paths: BTreeMap::default(),
             client,
             gateways,
             relays,
@@ -105,6 +116,7 @@ impl TunnelTest {
     ) -> Self {
         let mut buffered_transmits = BufferedTransmits::default();
         let now = state.flux_capacitor.now();
+        state.data_paths.clear();
 
         // Act: Apply the transition
         match transition {
@@ -157,7 +169,7 @@ impl TunnelTest {
                     .client
                     .exec_mut(|sim| Some(sim.encapsulate(packet, now)?.into_owned()));
 
-                buffered_transmits.push_from(transmit, &state.client, now);
+                buffered_transmits.push_from(transmit, &mut state.client, now);
             }
             Transition::SendUdpPacket {
                 src,
@@ -181,7 +193,7 @@ impl TunnelTest {
                     .client
                     .exec_mut(|sim| Some(sim.encapsulate(packet, now)?.into_owned()));
 
-                buffered_transmits.push_from(transmit, &state.client, now);
+                buffered_transmits.push_from(transmit, &mut state.client, now);
             }
             Transition::SendTcpPayload {
                 src,
@@ -205,7 +217,7 @@ impl TunnelTest {
                     .client
                     .exec_mut(|sim| Some(sim.encapsulate(packet, now)?.into_owned()));
 
-                buffered_transmits.push_from(transmit, &state.client, now);
+                buffered_transmits.push_from(transmit, &mut state.client, now);
             }
             Transition::SendDnsQueries(queries) => {
                 for DnsQuery {
@@ -220,7 +232,7 @@ impl TunnelTest {
                         sim.send_dns_query_for(domain, r_type, query_id, dns_server, transport, now)
                     });
 
-                    buffered_transmits.push_from(transmit, &state.client, now);
+                    buffered_transmits.push_from(transmit, &mut state.client, now);
                 }
             }
             Transition::UpdateSystemDnsServers(servers) => {
@@ -253,6 +265,12 @@ impl TunnelTest {
                         .set_resources(ref_state.client.inner().all_resources());
                 });
             }
+            Transition::RebindClientPort { port } => {
+                state.client.rebind_public_port(port);
+
+                // Give connlib time to either migrate its connections or fail them.
+                state.idle(ref_state, RECOVERY_DURATION, &mut buffered_transmits);
+            }
             Transition::ReconnectPortal => {
                 let ipv4 = state.client.inner().sut.tunnel_ip4().unwrap();
                 let ipv6 = state.client.inner().sut.tunnel_ip6().unwrap();
@@ -278,14 +296,10 @@ impl TunnelTest {
             }
             Transition::Idle => {
                 const IDLE_DURATION: Duration = Duration::from_secs(6 * 60); // Ensure idling twice in a row puts us in the 10-15 minute window where TURN data channels are cooling down.
-                let cut_off = state.flux_capacitor.now::<Instant>() + IDLE_DURATION;
 
                 debug_assert_eq!(buffered_transmits.packet_counter(), 0);
 
-                while state.flux_capacitor.now::<Instant>() <= cut_off {
-                    state.flux_capacitor.tick(Duration::from_secs(5));
-                    state.advance(ref_state, &mut buffered_transmits);
-                }
+                state.idle(ref_state, IDLE_DURATION, &mut buffered_transmits);
 
                 let num_packets = buffered_transmits.packet_counter() as f64;
                 let num_connections = state.client.inner().sut.num_connections() as f64 + 1.0; // +1 because we may have 0 connections.
@@ -296,7 +310,8 @@ impl TunnelTest {
                 // This has been chosen through experimentation. It primarily serves as a regression tool to ensure our idle-traffic doesn't suddenly spike.
                 const THRESHOLD: f64 = 2.0;
 
-                if packets_per_sec > THRESHOLD {
+                // Retransmissions on an impaired network push us above the threshold.
+                if state.impaired_network.is_none() && packets_per_sec > THRESHOLD {
                     tracing::error!("Expected at most {THRESHOLD} packets / sec in the network while idling. Got: {packets_per_sec}");
                 }
             }
@@ -330,6 +345,20 @@ impl TunnelTest {
 
                 state.deploy_new_relays(new_relays, now, to_remove);
             }
+            Transition::ImpairNetwork(impairment) => {
+                state.impaired_network = Some(ImpairedNetwork::new(impairment));
+            }
+            Transition::HealNetwork => {
+                state.impaired_network = None;
+
+                // Give connlib time to either recover its connections or fail them.
+                state.idle(ref_state, RECOVERY_DURATION, &mut buffered_transmits);
+
+                state.client.exec_mut(|c| {
+                    let gateways = c.sut.established_gateways();
+                    c.connections_after_heal.push(gateways);
+                });
+            }
         };
         state.advance(ref_state, &mut buffered_transmits);
 
@@ -370,6 +399,7 @@ impl TunnelTest {
         assert_known_hosts_are_valid(ref_client, sim_client);
         assert_dns_servers_are_valid(ref_client, sim_client);
         assert_routes_are_valid(ref_client, sim_client);
+        assert_data_paths(&state.data_paths, &ref_state.expected_data_paths());
     }
 }
 
@@ -481,7 +511,7 @@ impl TunnelTest {
             }
 
             if let Some(transmit) = self.client.exec_mut(|sim| sim.sut.poll_transmit()) {
-                buffered_transmits.push_from(transmit, &self.client, now);
+                buffered_transmits.push_from(transmit, &mut self.client, now);
                 continue;
             }
             self.client.exec_mut(|sim| {
@@ -516,6 +546,21 @@ impl TunnelTest {
         }
     }
 
+    /// Lets time pass in steps of 5s, allowing all state machines to act on their timers.
+    fn idle(
+        &mut self,
+        ref_state: &ReferenceState,
+        duration: Duration,
+        buffered_transmits: &mut BufferedTransmits,
+    ) {
+        let cut_off = self.flux_capacitor.now::<Instant>() + duration;
+
+        while self.flux_capacitor.now::<Instant>() <= cut_off {
+            self.flux_capacitor.tick(Duration::from_secs(5));
+            self.advance(ref_state, buffered_transmits);
+        }
+    }
+
     fn handle_timeout(
         &mut self,
         global_dns_records: &DnsRecords,
@@ -546,7 +591,7 @@ impl TunnelTest {
             let packet = c.tcp_dns_client.poll_outbound()?;
             c.encapsulate(packet, now)
         }) {
-            buffered_transmits.push_from(transmit, &self.client, now)
+            buffered_transmits.push_from(transmit, &mut self.client, now)
         }
 
         // Handle the client's `Transmit`s and timeout.
@@ -621,8 +666,26 @@ impl TunnelTest {
     /// This function is basically the "network layer" of our tests.
     /// It takes a [`Transmit`] and checks, which host accepts it, i.e. has configured the correct IP address.
     ///
-    /// Currently, the network topology of our tests are a single subnet without NAT.
+    /// Currently, the network topology of our tests are a single subnet where clients and gateways may sit behind a NAT (see [`Host::receive`]).
+    /// If the network is impaired, packets may get lost, duplicated or delayed on the way.
     fn dispatch_transmit(&mut self, transmit: Transmit<'static>, at: Instant) {
+        let Some(network) = self.impaired_network.as_mut() else {
+            self.deliver_transmit(transmit, at);
+            return;
+        };
+
+        let arrivals = network.arrivals(at);
+
+        if arrivals.is_empty() {
+            tracing::trace!(src = ?transmit.src, dst = %transmit.dst, "Dropping packet due to impaired network");
+        }
+
+        for at in arrivals {
+            self.deliver_transmit(transmit.clone(), at);
+        }
+    }
+
+    fn deliver_transmit(&mut self, transmit: Transmit<'static>, at: Instant) {
         let src = transmit
             .src
             .expect("`src` should always be set in these tests");
@@ -652,10 +715,28 @@ impl TunnelTest {
                     return;
                 }
 
-                self.gateways
+                // Traffic relayed via the gateway's own allocation arrives as ChannelData, behind a 4-byte header starting with `0b01`.
+                let payload = match transmit.payload.first() {
+                    Some(0x40..=0x7F) => transmit.payload.get(4..).unwrap_or_default(),
+                    _ => &transmit.payload[..],
+                };
+                // WireGuard's data messages start with their type (4) as a little-endian u32.
+                let is_wireguard_data = payload.starts_with(&[4, 0, 0, 0]);
+                let path = if self.client.is_sender(src.ip()) {
+                    DataPath::Direct
+                } else {
+                    DataPath::Relayed
+                };
+
+                let accepted = self
+                    .gateways
                     .get_mut(&id)
                     .expect("unknown gateway")
                     .receive(transmit, at);
+
+                if accepted && is_wireguard_data {
+                    self.data_paths.insert(id, path);
+                }
             }
             HostId::Relay(id) => {
                 self.relays
@@ -744,7 +825,7 @@ impl TunnelTest {
                         .unwrap();
                 });
             }
-            ClientEvent::ResourcesChanged { .. } => {
+            ClientEvent::ResourcesChanged { .. } | ClientEvent::GatewaysChanged { .. } => {
                 tracing::warn!("Unimplemented");
             }
             ClientEvent::TunInterfaceUpdated(config) => {
@@ -4,7 +4,7 @@ use crate::{
 };
 use connlib_model::RelayId;
 
-use super::sim_net::{any_ip_stack, any_port, Host};
+use super::sim_net::{any_ip_stack, any_port, impairment, Host, Impairment};
 use crate::messages::DnsServer;
 use connlib_model::{DomainName, ResourceId};
 use domain::base::Rtype;
@@ -64,6 +64,11 @@ pub(crate) enum Transition {
         port: u16,
     },
 
+    /// The NAT in front of the client assigned a new public port to its socket.
+    ///
+    /// The client's IPs stay the same and it doesn't get notified about the change.
+    RebindClientPort { port: u16 },
+
     /// Reconnect to the portal.
     ReconnectPortal,
 
@@ -83,6 +88,12 @@ pub(crate) enum Transition {
     ///
     /// In this case, we won't receive a `relays_presence` but instead we will receive relays with the same ID yet different credentials.
     RebootRelaysWhilePartitioned(BTreeMap<RelayId, Host<u64>>),
+
+    /// Start losing, duplicating and delaying packets in the network.
+    ImpairNetwork(Impairment),
+
+    /// Stop impairing the network and give connlib time to recover.
+    HealNetwork,
 }
 
 #[derive(Debug, Clone)]
@@ -126,6 +137,15 @@ pub(crate) enum Destination {
     IpAddr(IpAddr),
 }
 
+impl Destination {
+    pub(crate) fn domain_name(&self) -> Option<&DomainName> {
+        match self {
+            Destination::DomainName { name, .. } => Some(name),
+            Destination::IpAddr(_) => None,
+        }
+    }
+}
+
 /// Helper enum
 #[derive(Debug, Clone)]
 enum PacketDestination {
@@ -366,3 +386,11 @@ pub(crate) fn roam_client() -> impl Strategy<Value = Transition> {
         port,
     })
 }
+
+pub(crate) fn rebind_client_port() -> impl Strategy<Value = Transition> {
+    any_port().prop_map(|port| Transition::RebindClientPort { port })
+}
+
+pub(crate) fn impair_network() -> impl Strategy<Value = Transition> {
+    impairment().prop_map(Transition::ImpairNetwork)
+}
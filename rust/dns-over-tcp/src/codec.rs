@@ -7,7 +7,7 @@
 
 use anyhow::{Context as _, Result};
 use domain::{
-    base::{iana::Rcode, Message, ParsedName, Rtype},
+    base::{iana::Rcode, message::ShortMessage, Message, ParsedName, Rtype},
     rdata::AllRecordData,
 };
 use itertools::Itertools as _;
@@ -60,18 +60,7 @@ pub fn try_send(socket: &mut tcp::Socket, message: Message<&[u8]>) -> Result<()>
 
 pub fn try_recv<'b>(socket: &'b mut tcp::Socket) -> Result<Option<Message<&'b [u8]>>> {
     let maybe_message = socket
-        .recv(|r| {
-            // DNS over TCP has a 2-byte length prefix at the start, see <https://datatracker.ietf.org/doc/html/rfc1035#section-4.2.2>.
-            let Some((header, message)) = r.split_first_chunk::<2>() else {
-                return (0, None);
-            };
-            let dns_message_length = u16::from_be_bytes(*header) as usize;
-            if message.len() < dns_message_length {
-                return (0, None); // Don't consume any bytes unless we can read the full message at once.
-            }
-
-            (2 + dns_message_length, Some(Message::from_octets(message)))
-        })
+        .recv(decode)
         .context("Failed to recv TCP data")?
         .transpose()
         .context("Failed to parse DNS message")?;
@@ -98,6 +87,22 @@ pub fn try_recv<'b>(socket: &'b mut tcp::Socket) -> Result<Option<Message<&'b [u
     Ok(maybe_message)
 }
 
+/// Decodes a single length-prefixed DNS message from the start of `r`.
+///
+/// Returns how many bytes were consumed and the parsed message, if `r` contains a complete one.
+pub fn decode(r: &[u8]) -> (usize, Option<Result<Message<&[u8]>, ShortMessage>>) {
+    // DNS over TCP has a 2-byte length prefix at the start, see <https://datatracker.ietf.org/doc/html/rfc1035#section-4.2.2>.
+    let Some((header, message)) = r.split_first_chunk::<2>() else {
+        return (0, None);
+    };
+    let dns_message_length = u16::from_be_bytes(*header) as usize;
+    let Some(message) = message.get(..dns_message_length) else {
+        return (0, None); // Don't consume any bytes unless we can read the full message at once.
+    };
+
+    (2 + dns_message_length, Some(Message::from_octets(message)))
+}
+
 fn parse(message: Message<&[u8]>) -> Option<ParsedMessage<'_>> {
     let question = message.sole_question().ok()?;
     let answers = message.answer().ok()?;
@@ -139,3 +144,59 @@ struct ParsedMessage<'a> {
     response: bool,
     records: Vec<AllRecordData<&'a [u8], ParsedName<&'a [u8]>>>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use domain::base::{MessageBuilder, Name, Question};
+
+    #[test]
+    fn decodes_pipelined_queries_one_at_a_time() {
+        let first = query(1, "example.com");
+        let second = query(2, "firezone.dev");
+        let stream = [length_prefixed(&first), length_prefixed(&second)].concat();
+
+        let (consumed, message) = decode(&stream);
+        let message = message.unwrap().unwrap();
+
+        assert_eq!(consumed, 2 + first.len());
+        assert_eq!(message.as_slice(), first);
+        assert_eq!(message.header().id(), 1);
+
+        let (consumed, message) = decode(&stream[consumed..]);
+        let message = message.unwrap().unwrap();
+
+        assert_eq!(consumed, 2 + second.len());
+        assert_eq!(message.as_slice(), second);
+        assert_eq!(message.header().id(), 2);
+    }
+
+    #[test]
+    fn does_not_consume_partial_message() {
+        let message = length_prefixed(&query(1, "example.com"));
+
+        let (consumed, message) = decode(&message[..message.len() - 1]);
+
+        assert_eq!(consumed, 0);
+        assert!(message.is_none());
+    }
+
+    fn query(id: u16, domain: &str) -> Vec<u8> {
+        let mut builder = MessageBuilder::new_vec();
+        builder.header_mut().set_id(id);
+
+        let mut builder = builder.question();
+        builder
+            .push(Question::new_in(
+                Name::<Vec<u8>>::vec_from_str(domain).unwrap(),
+                Rtype::A,
+            ))
+            .unwrap();
+
+        builder.into_message().into_octets()
+    }
+
+    fn length_prefixed(message: &[u8]) -> Vec<u8> {
+        [&(message.len() as u16).to_be_bytes()[..], message].concat()
+    }
+}
@@ -1,3 +1,5 @@
+#![cfg_attr(test, allow(clippy::unwrap_used))]
+
 mod client;
 mod codec;
 mod interface;
@@ -8,6 +10,12 @@ mod time;
 pub use client::{Client, QueryResult};
 pub use server::{Query, Server, SocketHandle};
 
+/// Internals exposed for the fuzz targets in `rust/fuzz`.
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing {
+    pub use crate::codec::decode;
+}
+
 fn create_tcp_socket() -> smoltcp::socket::tcp::Socket<'static> {
     /// The 2-byte length prefix of DNS over TCP messages limits their size to effectively u16::MAX.
     /// It is quite unlikely that we have to buffer _multiple_ of these max-sized messages.
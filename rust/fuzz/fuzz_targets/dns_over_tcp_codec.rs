@@ -0,0 +1,32 @@
+#![no_main]
+
+use dns_over_tcp::fuzzing::decode;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut remaining = data;
+
+    // A TCP stream may contain several messages back-to-back.
+    loop {
+        let (consumed, maybe_message) = decode(remaining);
+
+        let Some(result) = maybe_message else {
+            assert_eq!(consumed, 0, "incomplete messages must not be consumed");
+            break;
+        };
+
+        let length = u16::from_be_bytes([remaining[0], remaining[1]]) as usize;
+
+        assert_eq!(consumed, 2 + length);
+        assert!(consumed <= remaining.len());
+
+        if let Ok(message) = result {
+            assert_eq!(message.as_slice(), &remaining[2..consumed]);
+
+            let _ = message.sole_question();
+            let _ = message.answer().map(|records| records.count());
+        }
+
+        remaining = &remaining[consumed..];
+    }
+});
@@ -0,0 +1,14 @@
+#![no_main]
+
+use ip_packet::FzP2pControlSlice;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(slice) = FzP2pControlSlice::from_slice(data) else {
+        assert!(data.len() < 8);
+        return;
+    };
+
+    let _ = slice.event_type();
+    assert_eq!(slice.payload(), &data[8..]);
+});
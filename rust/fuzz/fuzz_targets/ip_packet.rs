@@ -0,0 +1,35 @@
+#![no_main]
+
+use ip_packet::{IpPacket, IpPacketBuf, PACKET_SIZE};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() > PACKET_SIZE {
+        return;
+    }
+
+    let mut buf = IpPacketBuf::new();
+    buf.buf()[..data.len()].copy_from_slice(data);
+
+    let Ok(mut packet) = IpPacket::new(buf, data.len()) else {
+        return;
+    };
+
+    assert_eq!(packet.packet(), data);
+    assert!(packet.payload().len() <= data.len());
+
+    let _ = packet.source();
+    let _ = packet.destination();
+    let _ = packet.source_protocol();
+    let _ = packet.destination_protocol();
+    let _ = packet.as_fz_p2p_control();
+    let _ = format!("{packet:?}");
+
+    packet.update_checksum();
+
+    assert_eq!(
+        packet.packet().len(),
+        data.len(),
+        "updating the checksum must not change the length"
+    );
+});
@@ -0,0 +1,24 @@
+#![no_main]
+
+use ip_packet::{fuzzing::nat46_translate_in_place, Ipv6HeaderSlice, NAT46_OVERHEAD};
+use libfuzzer_sys::fuzz_target;
+use std::net::Ipv6Addr;
+
+const SRC: Ipv6Addr = Ipv6Addr::new(0xfd00, 0x2021, 0x1111, 0, 0, 0, 0, 1);
+const DST: Ipv6Addr = Ipv6Addr::new(0x64, 0xff9b, 0, 0, 0, 0, 0x0a00, 0x0001);
+
+fuzz_target!(|data: &[u8]| {
+    // `translate_in_place` expects the IPv4 packet at an offset so it can grow the header in place.
+    let mut buf = vec![0u8; NAT46_OVERHEAD];
+    buf.extend_from_slice(data);
+
+    let Ok(offset) = nat46_translate_in_place(&mut buf, SRC, DST) else {
+        return;
+    };
+
+    let header = Ipv6HeaderSlice::from_slice(&buf[offset..])
+        .expect("NAT46 must produce a valid IPv6 header");
+
+    assert_eq!(header.source_addr(), SRC);
+    assert_eq!(header.destination_addr(), DST);
+});
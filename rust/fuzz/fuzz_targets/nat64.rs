@@ -0,0 +1,23 @@
+#![no_main]
+
+use ip_packet::{fuzzing::nat64_translate_in_place, Ipv4HeaderSlice};
+use libfuzzer_sys::fuzz_target;
+use std::net::Ipv4Addr;
+
+const SRC: Ipv4Addr = Ipv4Addr::new(100, 64, 0, 1);
+const DST: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+
+fuzz_target!(|data: &[u8]| {
+    let mut buf = data.to_vec();
+
+    if nat64_translate_in_place(&mut buf, SRC, DST).is_err() {
+        return;
+    }
+
+    // The IPv4 header is always 20 bytes shorter than the IPv6 header, see `translate_in_place`.
+    let header =
+        Ipv4HeaderSlice::from_slice(&buf[20..]).expect("NAT64 must produce a valid IPv4 header");
+
+    assert_eq!(header.source_addr(), SRC);
+    assert_eq!(header.destination_addr(), DST);
+});
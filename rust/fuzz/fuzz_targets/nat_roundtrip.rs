@@ -0,0 +1,67 @@
+#![no_main]
+
+use ip_packet::{IpPacket, IpPacketBuf, Protocol, PACKET_SIZE};
+use libfuzzer_sys::fuzz_target;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+const PROXY_V4: Ipv4Addr = Ipv4Addr::new(100, 100, 111, 1);
+const PROXY_V6: Ipv6Addr = Ipv6Addr::new(0xfd00, 0x2021, 0x1111, 0x8000, 0, 0, 0, 1);
+
+// Translating a packet to the other IP version and back must yield the packet we started with.
+fuzz_target!(|data: &[u8]| {
+    if data.len() > PACKET_SIZE {
+        return;
+    }
+
+    let mut buf = IpPacketBuf::new();
+    buf.buf()[..data.len()].copy_from_slice(data);
+
+    let Ok(mut packet) = IpPacket::new(buf, data.len()) else {
+        return;
+    };
+
+    // We only ever translate packets we can route.
+    let (Ok(src_proto), Ok(dst_proto)) = (packet.source_protocol(), packet.destination_protocol())
+    else {
+        return;
+    };
+    packet.update_checksum(); // Translation always emits valid checksums.
+
+    let src = packet.source();
+    let dst = packet.destination();
+    let payload = packet.payload().to_vec();
+
+    let proxy_dst = match dst {
+        IpAddr::V4(_) => IpAddr::V6(PROXY_V6),
+        IpAddr::V6(_) => IpAddr::V4(PROXY_V4),
+    };
+
+    let Ok(translated) = packet.translate_destination(PROXY_V4, PROXY_V6, src_proto, proxy_dst)
+    else {
+        return;
+    };
+
+    assert_eq!(translated.destination(), proxy_dst);
+
+    let mut packet = translate_back(translated, src, dst, src_proto)
+        .expect("translating a translated packet back must succeed");
+    packet.update_checksum();
+
+    assert_eq!(packet.source(), src);
+    assert_eq!(packet.destination(), dst);
+    assert_eq!(packet.source_protocol().ok(), Some(src_proto));
+    assert_eq!(packet.destination_protocol().ok(), Some(dst_proto));
+    assert_eq!(packet.payload(), payload);
+});
+
+fn translate_back(
+    packet: IpPacket,
+    src: IpAddr,
+    dst: IpAddr,
+    src_proto: Protocol,
+) -> anyhow::Result<IpPacket> {
+    match src {
+        IpAddr::V4(src) => packet.translate_destination(src, Ipv6Addr::UNSPECIFIED, src_proto, dst),
+        IpAddr::V6(src) => packet.translate_destination(Ipv4Addr::UNSPECIFIED, src, src_proto, dst),
+    }
+}
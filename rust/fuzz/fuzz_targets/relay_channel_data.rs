@@ -0,0 +1,23 @@
+#![no_main]
+
+use firezone_relay::ChannelData;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(msg) = ChannelData::parse(data) else {
+        return;
+    };
+
+    let payload = msg.data();
+
+    assert_eq!(msg.as_msg(), data);
+    assert_eq!(payload, &data[4..(4 + payload.len())]);
+
+    // Re-encoding the header must yield the bytes we parsed.
+    let mut header = [0u8; 4];
+    let total_len =
+        ChannelData::encode_header_to_slice(msg.channel(), payload.len() as u16, &mut header);
+
+    assert_eq!(total_len, 4 + payload.len());
+    assert_eq!(header, data[..4]);
+});
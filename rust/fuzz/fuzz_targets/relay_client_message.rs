@@ -0,0 +1,38 @@
+#![no_main]
+
+use firezone_relay::{fuzzing::Decoder, ClientMessage};
+use libfuzzer_sys::fuzz_target;
+
+/// A STUN binding request without any attributes.
+const BINDING_REQUEST: [u8; 20] = [
+    0x00, 0x01, 0x00, 0x00, 0x21, 0x12, 0xa4, 0x42, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+    0x09, 0x0a, 0x0b, 0x0c,
+];
+
+fuzz_target!(|data: &[u8]| {
+    // The relay uses a single decoder for all incoming traffic.
+    let mut decoder = Decoder::default();
+
+    match decoder.decode(data) {
+        Ok(Ok(ClientMessage::ChannelData(msg))) => {
+            assert_eq!(msg.as_msg(), data);
+            assert!(msg.data().len() + 4 <= data.len());
+        }
+        Ok(Ok(msg)) => {
+            assert!(
+                msg.transaction_id().is_some(),
+                "STUN requests have a transaction ID"
+            );
+        }
+        Ok(Err(error_response)) => {
+            let _ = error_response.method();
+        }
+        Err(_) => {}
+    }
+
+    // Whatever we just decoded must not affect the next message.
+    assert!(matches!(
+        decoder.decode(&BINDING_REQUEST),
+        Ok(Ok(ClientMessage::Binding(_)))
+    ));
+});
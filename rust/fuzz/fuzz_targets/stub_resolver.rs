@@ -0,0 +1,19 @@
+#![no_main]
+
+use domain::base::Message;
+use firezone_tunnel::fuzzing::handle_dns_query;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(query) = Message::from_octets(data) else {
+        return;
+    };
+
+    let Some(response) = handle_dns_query(query) else {
+        return;
+    };
+
+    // Every local answer, including `SERVFAIL`, must be a response to the query it answers.
+    assert!(response.header().qr());
+    assert_eq!(response.header().id(), query.header().id());
+});
@@ -4,26 +4,27 @@ use connlib_model::DomainName;
 use connlib_model::{ClientId, ResourceId};
 #[cfg(not(target_os = "windows"))]
 use dns_lookup::{AddrInfoHints, AddrInfoIter, LookupError};
-use firezone_logging::{
-    anyhow_dyn_err, err_with_sources, std_dyn_err, telemetry_event, telemetry_span,
-};
+use firezone_logging::{anyhow_dyn_err, err_with_sources, std_dyn_err, telemetry_span};
 use firezone_tunnel::messages::gateway::{
     AllowAccess, ClientIceCandidates, ClientsIceCandidates, ConnectionReady, EgressMessages,
     IngressMessages, RejectAccess, RequestConnection,
 };
 use firezone_tunnel::messages::{ConnectionAccepted, GatewayResponse, Interface, RelaysPresence};
-use firezone_tunnel::{DnsResourceNatEntry, GatewayTunnel, ResolveDnsRequest};
+use firezone_tunnel::{DnsResourceNatEntry, ResolveDnsRequest};
 use futures::channel::mpsc;
+use futures::StreamExt as _;
 use futures_bounded::Timeout;
-use phoenix_channel::{PhoenixChannel, PublicKeyParam};
+use phoenix_channel::{LoginUrl, PhoenixChannel, PublicKeyParam};
+use secrecy::Secret;
 use std::collections::BTreeSet;
 use std::convert::Infallible;
-use std::io;
 use std::net::IpAddr;
 use std::task::{Context, Poll};
 use std::time::{Duration, Instant};
 use tracing::Instrument;
 
+use crate::shards::Shards;
+
 pub const PHOENIX_TOPIC: &str = "gateway";
 
 /// How long we allow a DNS resolution via `libc::get_addr_info`.
@@ -44,47 +45,47 @@ enum ResolveTrigger {
 }
 
 pub struct Eventloop {
-    tunnel: GatewayTunnel,
+    shards: Shards,
     portal: PhoenixChannel<(), IngressMessages, (), PublicKeyParam>,
     tun_device_channel: mpsc::Sender<Interface>,
+    /// New login URLs, e.g. with a rotated token, to use on the next reconnect to the portal.
+    login_rx: mpsc::Receiver<LoginUrl<PublicKeyParam>>,
 
     resolve_tasks: futures_bounded::FuturesTupleSet<Vec<IpAddr>, ResolveTrigger>,
 }
 
 impl Eventloop {
     pub(crate) fn new(
-        tunnel: GatewayTunnel,
+        shards: Shards,
         mut portal: PhoenixChannel<(), IngressMessages, (), PublicKeyParam>,
         tun_device_channel: mpsc::Sender<Interface>,
+        login_rx: mpsc::Receiver<LoginUrl<PublicKeyParam>>,
     ) -> Self {
-        portal.connect(PublicKeyParam(tunnel.public_key().to_bytes()));
+        portal.connect(PublicKeyParam(shards.public_key().to_bytes()));
 
         Self {
-            tunnel,
+            shards,
             portal,
             resolve_tasks: futures_bounded::FuturesTupleSet::new(DNS_RESOLUTION_TIMEOUT, 1000),
             tun_device_channel,
+            login_rx,
         }
     }
 }
 
 impl Eventloop {
+    /// Reconnects to the portal using a new login URL, e.g. after refreshing our token.
+    pub(crate) fn set_login_url(&mut self, url: LoginUrl<PublicKeyParam>) {
+        self.portal.set_login_url(Secret::new(url));
+        self.portal
+            .connect(PublicKeyParam(self.shards.public_key().to_bytes()));
+    }
+
     pub fn poll(&mut self, cx: &mut Context<'_>) -> Poll<Result<Infallible>> {
         loop {
-            match self.tunnel.poll_next_event(cx) {
-                Poll::Ready(Ok(event)) => {
-                    self.handle_tunnel_event(event);
-                    continue;
-                }
-                Poll::Ready(Err(e)) if e.kind() == io::ErrorKind::WouldBlock => {
-                    continue;
-                }
-                Poll::Ready(Err(e)) => {
-                    tracing::debug!("Tunnel error: {}", err_with_sources(&e));
-                    telemetry_event!(error = std_dyn_err(&e), "Tunnel error");
-                    continue;
-                }
-                Poll::Pending => {}
+            if let Poll::Ready(event) = self.shards.poll_next_event(cx) {
+                self.handle_tunnel_event(event);
+                continue;
             }
 
             match self.resolve_tasks.poll_unpin(cx) {
@@ -110,7 +111,7 @@ impl Eventloop {
                         })
                         .unwrap_or_default();
 
-                    if let Err(e) = self.tunnel.state_mut().handle_domain_resolved(
+                    if let Err(e) = self.shards.client(request.client()).handle_domain_resolved(
                         request,
                         addresses,
                         Instant::now(),
@@ -134,6 +135,14 @@ impl Eventloop {
                 Poll::Pending => {}
             }
 
+            match self.login_rx.poll_next_unpin(cx) {
+                Poll::Ready(Some(url)) => {
+                    self.portal.set_login_url(Secret::new(url));
+                    continue;
+                }
+                Poll::Ready(None) | Poll::Pending => {}
+            }
+
             return Poll::Pending;
         }
     }
@@ -201,23 +210,28 @@ impl Eventloop {
                 msg: IngressMessages::AuthorizeFlow(msg),
                 ..
             } => {
-                if let Err(snownet::NoTurnServers {}) = self.tunnel.state_mut().authorize_flow(
-                    msg.client.id,
-                    PublicKey::from(msg.client.public_key.0),
-                    msg.client.preshared_key,
-                    msg.client_ice_credentials,
-                    msg.gateway_ice_credentials,
-                    msg.client.ipv4,
-                    msg.client.ipv6,
-                    msg.expires_at,
-                    msg.resource,
-                    Instant::now(),
-                ) {
+                self.shards
+                    .route(msg.client.id, msg.client.ipv4, msg.client.ipv6);
+
+                if let Err(snownet::NoTurnServers {}) =
+                    self.shards.client(msg.client.id).authorize_flow(
+                        msg.client.id,
+                        PublicKey::from(msg.client.public_key.0),
+                        msg.client.preshared_key,
+                        msg.client_ice_credentials,
+                        msg.gateway_ice_credentials,
+                        msg.client.ipv4,
+                        msg.client.ipv6,
+                        msg.expires_at,
+                        msg.resource,
+                        Instant::now(),
+                    )
+                {
                     tracing::debug!("Failed to authorise flow: No TURN servers available");
 
                     // Re-connecting to the portal means we will receive another `init` and thus new TURN servers.
                     self.portal
-                        .connect(PublicKeyParam(self.tunnel.public_key().to_bytes()));
+                        .connect(PublicKeyParam(self.shards.public_key().to_bytes()));
                     return;
                 };
 
@@ -267,9 +281,11 @@ impl Eventloop {
                 ..
             } => {
                 for candidate in candidates {
-                    self.tunnel
-                        .state_mut()
-                        .add_ice_candidate(client_id, candidate, Instant::now());
+                    self.shards.client(client_id).add_ice_candidate(
+                        client_id,
+                        candidate,
+                        Instant::now(),
+                    );
                 }
             }
             phoenix_channel::Event::InboundMessage {
@@ -281,7 +297,7 @@ impl Eventloop {
                 ..
             } => {
                 for candidate in candidates {
-                    self.tunnel.state_mut().remove_ice_candidate(
+                    self.shards.client(client_id).remove_ice_candidate(
                         client_id,
                         candidate,
                         Instant::now(),
@@ -296,8 +312,8 @@ impl Eventloop {
                     }),
                 ..
             } => {
-                self.tunnel
-                    .state_mut()
+                self.shards
+                    .client(client_id)
                     .remove_access(&client_id, &resource_id);
             }
             phoenix_channel::Event::InboundMessage {
@@ -307,20 +323,26 @@ impl Eventloop {
                         connected,
                     }),
                 ..
-            } => self.tunnel.state_mut().update_relays(
-                BTreeSet::from_iter(disconnected_ids),
-                firezone_tunnel::turn(&connected),
-                Instant::now(),
-            ),
+            } => {
+                for mut shard in self.shards.all() {
+                    shard.update_relays(
+                        BTreeSet::from_iter(disconnected_ids.clone()),
+                        firezone_tunnel::turn(&connected),
+                        Instant::now(),
+                    )
+                }
+            }
             phoenix_channel::Event::InboundMessage {
                 msg: IngressMessages::Init(init),
                 ..
             } => {
-                self.tunnel.state_mut().update_relays(
-                    BTreeSet::default(),
-                    firezone_tunnel::turn(&init.relays),
-                    Instant::now(),
-                );
+                for mut shard in self.shards.all() {
+                    shard.update_relays(
+                        BTreeSet::default(),
+                        firezone_tunnel::turn(&init.relays),
+                        Instant::now(),
+                    );
+                }
 
                 // FIXME(tech-debt): Currently, the `Tunnel` creates the TUN device as part of `set_interface`.
                 // For the gateway, it doesn't do anything else so in an ideal world, we would cause the side-effect out here and just pass an opaque `Device` to the `Tunnel`.
@@ -333,13 +355,20 @@ impl Eventloop {
                 msg: IngressMessages::ResourceUpdated(resource_description),
                 ..
             } => {
-                self.tunnel
-                    .state_mut()
-                    .update_resource(resource_description);
+                for mut shard in self.shards.all() {
+                    shard.update_resource(resource_description.clone());
+                }
             }
             phoenix_channel::Event::ErrorResponse { topic, req_id, res } => {
                 tracing::warn!(%topic, %req_id, "Request failed: {res:?}");
             }
+            phoenix_channel::Event::MessageDropped {
+                topic,
+                req_id,
+                reason,
+            } => {
+                tracing::debug!(%topic, %req_id, %reason, "Message to portal was dropped");
+            }
             phoenix_channel::Event::Closed => {
                 unimplemented!("Gateway never actively closes the portal connection")
             }
@@ -358,7 +387,11 @@ impl Eventloop {
             .inspect_err(|e| tracing::debug!(client = %req.client.id, reference = %req.reference, "DNS resolution timed out as part of connection request: {}", err_with_sources(e)))
             .unwrap_or_default();
 
-        let answer = match self.tunnel.state_mut().accept(
+        self.shards
+            .route(req.client.id, req.client.peer.ipv4, req.client.peer.ipv6);
+        let mut shard = self.shards.client(req.client.id);
+
+        let answer = match shard.accept(
             req.client.id,
             req.client
                 .payload
@@ -369,17 +402,18 @@ impl Eventloop {
         ) {
             Ok(a) => a,
             Err(snownet::NoTurnServers {}) => {
+                drop(shard);
                 tracing::debug!("Failed to accept new connection: No TURN servers available");
 
                 // Re-connecting to the portal means we will receive another `init` and thus new TURN servers.
                 self.portal
-                    .connect(PublicKeyParam(self.tunnel.public_key().to_bytes()));
+                    .connect(PublicKeyParam(self.shards.public_key().to_bytes()));
 
                 return;
             }
         };
 
-        if let Err(e) = self.tunnel.state_mut().allow_access(
+        if let Err(e) = shard.allow_access(
             req.client.id,
             req.client.peer.ipv4,
             req.client.peer.ipv6,
@@ -393,10 +427,11 @@ impl Eventloop {
         ) {
             let client = req.client.id;
 
-            self.tunnel.state_mut().cleanup_connection(&client);
+            shard.cleanup_connection(&client);
             tracing::debug!(%client, "Connection request failed: {e:#}");
             return;
         }
+        drop(shard);
 
         self.portal.send(
             PHOENIX_TOPIC,
@@ -414,7 +449,10 @@ impl Eventloop {
             .inspect_err(|e| tracing::debug!(client = %req.client_id, reference = %req.reference, "DNS resolution timed out as part of allow access request: {}", err_with_sources(e)))
             .unwrap_or_default();
 
-        if let Err(e) = self.tunnel.state_mut().allow_access(
+        self.shards
+            .route(req.client_id, req.client_ipv4, req.client_ipv6);
+
+        if let Err(e) = self.shards.client(req.client_id).allow_access(
             req.client_id,
             req.client_ipv4,
             req.client_ipv6,
@@ -438,7 +476,7 @@ impl Eventloop {
             .inspect_err(|e| tracing::debug!(%conn_id, "DNS resolution timed out as part of allow access request: {}", err_with_sources(e)))
             .unwrap_or_default();
 
-        self.tunnel.state_mut().refresh_translation(
+        self.shards.client(conn_id).refresh_translation(
             conn_id,
             resource_id,
             name,
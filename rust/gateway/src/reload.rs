@@ -0,0 +1,100 @@
+//! Re-reading the configuration on SIGHUP and applying it without a restart
+
+use crate::Login;
+use anyhow::{Context as _, Result};
+use firezone_bin_shared::reload::{warn_requires_restart, ConfigFile, HealthCheck, LogFilter};
+use firezone_logging::{anyhow_dyn_err, FilterReloadHandle};
+use futures::channel::mpsc;
+use phoenix_channel::{LoginUrl, PublicKeyParam};
+use secrecy::{ExposeSecret as _, SecretString};
+use std::{
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+use tokio::signal::unix::{signal, Signal, SignalKind};
+
+pub(crate) struct Reloader {
+    sighup: Signal,
+    config_file: Option<ConfigFile>,
+
+    log_filter: LogFilter,
+
+    login: Arc<Mutex<Login>>,
+    /// Hands new login URLs to the [`Eventloop`](crate::eventloop::Eventloop), to be used on the next reconnect to the portal.
+    login_tx: mpsc::Sender<LoginUrl<PublicKeyParam>>,
+
+    health_check: HealthCheck,
+}
+
+impl Reloader {
+    pub(crate) fn new(
+        config_file: Option<PathBuf>,
+        filter_reloader: FilterReloadHandle,
+        login: Arc<Mutex<Login>>,
+        login_tx: mpsc::Sender<LoginUrl<PublicKeyParam>>,
+        health_check_addr: SocketAddr,
+    ) -> Result<Self> {
+        Ok(Self {
+            sighup: signal(SignalKind::hangup())?,
+            config_file: config_file.map(ConfigFile::new),
+            log_filter: LogFilter::new(filter_reloader),
+            login,
+            login_tx,
+            health_check: HealthCheck::spawn(health_check_addr, || true),
+        })
+    }
+
+    pub(crate) async fn run(mut self) {
+        while self.sighup.recv().await.is_some() {
+            tracing::info!("Caught SIGHUP");
+
+            if let Err(e) = self.reload() {
+                tracing::warn!(error = anyhow_dyn_err(&e), "Failed to reload configuration");
+            }
+        }
+    }
+
+    fn reload(&mut self) -> Result<()> {
+        let Some(config_file) = self.config_file.as_mut() else {
+            tracing::info!(
+                "No config file to reload, set `FIREZONE_CONFIG_FILE` to enable reloading"
+            );
+            return Ok(());
+        };
+
+        for (key, value) in config_file.changes()? {
+            match key.as_str() {
+                "RUST_LOG" => self.log_filter.set(value)?,
+                "FIREZONE_TOKEN" => self.set_token(value)?,
+                "HEALTH_CHECK_ADDR" => self.health_check.set_addr(
+                    value
+                        .parse()
+                        .context("Failed to parse `HEALTH_CHECK_ADDR`")?,
+                ),
+                _ => warn_requires_restart(&key),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn set_token(&mut self, token: String) -> Result<()> {
+        let mut login = self.login.lock().unwrap_or_else(|e| e.into_inner());
+
+        if login.token.expose_secret() == &token {
+            return Ok(());
+        }
+
+        // Only store the new token once the eventloop has its URL, so that both agree on which token is in use.
+        let token = SecretString::new(token);
+        self.login_tx
+            .try_send(login.url_with(&token)?)
+            .context("Failed to hand new token to eventloop")?;
+        login.token = token;
+
+        tracing::info!("Using new token on next reconnect to the portal");
+
+        Ok(())
+    }
+}
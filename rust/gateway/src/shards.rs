@@ -0,0 +1,695 @@
+//! Spreading the data plane of the Gateway across multiple cores.
+//!
+//! Each shard is a [`GatewayTunnel`] with its own UDP sockets and WireGuard state, polled by its own task.
+//! Clients are assigned to a shard based on their [`ClientId`], meaning all packets of a client are processed in order by the same shard.
+//! The [`Eventloop`](crate::eventloop::Eventloop) remains the only component talking to the portal and applies connection setup and policy to the respective shard's [`GatewayState`].
+//!
+//! With more than one shard, a dispatcher task reads packets from the TUN device and forwards them to the shard responsible for the destination IP.
+//!
+//! Every shard has its own [`snownet`] node and thus makes its own allocations on the relays and runs its own ICE for each of its clients.
+//! With `N` shards, we hold `N` allocations on every relay and send `N` times the STUN keep-alives and refreshes.
+//! ICE traffic does not multiply as each client is only handled by a single shard.
+//! To bound the load we put on the relays, we never run more than [`MAX_SHARDS`].
+
+use boringtun::x25519::PublicKey;
+use connlib_model::ClientId;
+use firezone_bin_shared::{
+    linux::{tcp_socket_factory, udp_socket_factory},
+    Tun,
+};
+use firezone_logging::{err_with_sources, std_dyn_err, telemetry_event};
+use firezone_tunnel::{GatewayEvent, GatewayState, GatewayTunnel};
+use futures::{channel::mpsc, task::AtomicWaker, SinkExt as _, StreamExt as _};
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash as _, Hasher as _},
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    ops::{Deref, DerefMut},
+    sync::{Arc, Mutex, MutexGuard, RwLock},
+    task::{ready, Context, Poll},
+    time::{Duration, Instant},
+};
+use tokio::task::JoinHandle;
+use tun::Tun as _;
+
+/// How many packets we buffer at most for each shard before dropping them.
+const SHARD_PACKET_QUEUE: usize = 1000;
+/// The largest packet (or GSO batch) we may read from the TUN device.
+const MAX_TUN_READ: usize = tun::vnet::HEADER_LEN + u16::MAX as usize;
+/// How long we keep the route of a client that its shard doesn't know (yet), e.g. while we are still setting up the connection.
+const ROUTE_GRACE_PERIOD: Duration = Duration::from_secs(60);
+
+/// The most shards we run, regardless of how many cores we have.
+///
+/// Every shard makes its own allocations on the relays, see the module docs.
+pub const MAX_SHARDS: u16 = 8;
+
+pub struct Shards {
+    shards: Vec<Shard>,
+    events: mpsc::Receiver<GatewayEvent>,
+
+    /// Which shard to hand packets to, by their destination IP.
+    routes: Arc<RwLock<Routes>>,
+    /// Packets from the TUN device, forwarded to the respective shard.
+    dispatch_task: Option<JoinHandle<()>>,
+    /// Removes the routes of clients that disconnected, even if no new clients connect.
+    prune_routes: tokio::time::Interval,
+
+    public_key: PublicKey,
+}
+
+struct Shard {
+    tunnel: Arc<Mutex<GatewayTunnel>>,
+    /// Wakes the shard's task after we changed its state.
+    waker: Arc<AtomicWaker>,
+    task: JoinHandle<()>,
+}
+
+impl Shards {
+    /// Spawns `num_shards` tunnels that share the TUN device `tun`, but at most [`MAX_SHARDS`].
+    ///
+    /// Must be called within a Tokio runtime.
+    pub fn new(num_shards: usize, tun: Tun) -> io::Result<Self> {
+        let num_shards = num_shards.clamp(1, MAX_SHARDS as usize);
+        let seed = rand::random(); // All shards must share the same key.
+        let (events_tx, events_rx) = mpsc::channel(1000);
+        let routes = Arc::new(RwLock::new(Routes::default()));
+
+        let mut shards = Vec::with_capacity(num_shards);
+        let mut shard_tuns = Vec::with_capacity(num_shards);
+        let (recycle_tx, recycle_rx) = tokio::sync::mpsc::unbounded_channel();
+
+        for _ in 0..num_shards {
+            let mut tunnel = GatewayTunnel::new(
+                Arc::new(tcp_socket_factory),
+                Arc::new(udp_socket_factory),
+                seed,
+            );
+
+            if num_shards > 1 {
+                let (packets_tx, packets_rx) = tokio::sync::mpsc::channel(SHARD_PACKET_QUEUE);
+
+                tunnel.set_tun(Box::new(ShardTun {
+                    packets: packets_rx,
+                    recycle: recycle_tx.clone(),
+                    device: tun.try_clone()?,
+                }));
+                shard_tuns.push(packets_tx);
+            }
+
+            let tunnel = Arc::new(Mutex::new(tunnel));
+            let waker = Arc::new(AtomicWaker::new());
+            let task = tokio::spawn(poll_shard(tunnel.clone(), waker.clone(), events_tx.clone()));
+
+            shards.push(Shard {
+                tunnel,
+                waker,
+                task,
+            });
+        }
+
+        let dispatch_task = if num_shards > 1 {
+            tracing::info!(%num_shards, "Processing packets on multiple shards");
+
+            Some(tokio::spawn(dispatch(
+                tun,
+                shard_tuns,
+                recycle_rx,
+                routes.clone(),
+            )))
+        } else {
+            lock(&shards[0].tunnel).set_tun(Box::new(tun));
+
+            None
+        };
+
+        let public_key = lock(&shards[0].tunnel).public_key();
+
+        Ok(Self {
+            shards,
+            events: events_rx,
+            routes,
+            dispatch_task,
+            prune_routes: tokio::time::interval(ROUTE_GRACE_PERIOD),
+            public_key,
+        })
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        self.public_key
+    }
+
+    /// Grants access to the state of the shard responsible for `client`.
+    pub fn client(&self, client: ClientId) -> StateGuard<'_> {
+        self.shards[self.shard_idx(client)].state()
+    }
+
+    /// Grants access to the state of all shards, e.g. to update the relays.
+    pub fn all(&self) -> impl Iterator<Item = StateGuard<'_>> {
+        self.shards.iter().map(|s| s.state())
+    }
+
+    /// Routes packets for the given IPs on the TUN device to the shard responsible for `client`.
+    pub fn route(&self, client: ClientId, ipv4: Ipv4Addr, ipv6: Ipv6Addr) {
+        if self.dispatch_task.is_none() {
+            return;
+        }
+
+        let shard = self.shard_idx(client);
+
+        self.routes
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(client, shard, ipv4, ipv6, Instant::now());
+    }
+
+    pub fn poll_next_event(&mut self, cx: &mut Context<'_>) -> Poll<GatewayEvent> {
+        if self.prune_routes.poll_tick(cx).is_ready() {
+            self.prune_routes();
+        }
+
+        let event = ready!(self.events.poll_next_unpin(cx))
+            .expect("We hold a sender for every shard, the channel cannot close");
+
+        Poll::Ready(event)
+    }
+
+    /// Removes the routes of all clients that their shard forgot about, so we only keep routes for active clients.
+    fn prune_routes(&self) {
+        if self.dispatch_task.is_none() {
+            return;
+        }
+
+        self.routes
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(Instant::now(), |shard, client| {
+                lock(&self.shards[shard].tunnel).state().has_client(client)
+            });
+    }
+
+    fn shard_idx(&self, client: ClientId) -> usize {
+        shard_idx(client, self.shards.len())
+    }
+}
+
+fn shard_idx(client: ClientId, num_shards: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    client.hash(&mut hasher);
+
+    (hasher.finish() % num_shards as u64) as usize
+}
+
+impl Drop for Shards {
+    fn drop(&mut self) {
+        for shard in &self.shards {
+            shard.task.abort();
+        }
+
+        if let Some(task) = self.dispatch_task.take() {
+            task.abort();
+        }
+    }
+}
+
+impl Shard {
+    fn state(&self) -> StateGuard<'_> {
+        StateGuard {
+            tunnel: lock(&self.tunnel),
+            waker: &self.waker,
+        }
+    }
+}
+
+/// Exclusive access to the [`GatewayState`] of a shard.
+///
+/// Wakes the shard once dropped so it can act on the changes, e.g. send packets.
+pub struct StateGuard<'a> {
+    tunnel: MutexGuard<'a, GatewayTunnel>,
+    waker: &'a AtomicWaker,
+}
+
+impl Deref for StateGuard<'_> {
+    type Target = GatewayState;
+
+    fn deref(&self) -> &Self::Target {
+        self.tunnel.state()
+    }
+}
+
+impl DerefMut for StateGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.tunnel.state_mut()
+    }
+}
+
+impl Drop for StateGuard<'_> {
+    fn drop(&mut self) {
+        self.waker.wake();
+    }
+}
+
+async fn poll_shard(
+    tunnel: Arc<Mutex<GatewayTunnel>>,
+    waker: Arc<AtomicWaker>,
+    mut events: mpsc::Sender<GatewayEvent>,
+) {
+    loop {
+        let event = futures::future::poll_fn(|cx| {
+            waker.register(cx.waker());
+
+            let mut tunnel = lock(&tunnel);
+
+            loop {
+                match tunnel.poll_next_event(cx) {
+                    Poll::Ready(Ok(event)) => return Poll::Ready(event),
+                    Poll::Ready(Err(e)) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                    Poll::Ready(Err(e)) => {
+                        tracing::debug!("Tunnel error: {}", err_with_sources(&e));
+                        telemetry_event!(error = std_dyn_err(&e), "Tunnel error");
+                        continue;
+                    }
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+        })
+        .await;
+
+        if events.send(event).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Reads packets from the TUN device and forwards them to the shard responsible for their destination.
+async fn dispatch(
+    mut tun: impl tun::Tun,
+    shards: Vec<tokio::sync::mpsc::Sender<Packet>>,
+    mut recycled: tokio::sync::mpsc::UnboundedReceiver<Vec<u8>>,
+    routes: Arc<RwLock<Routes>>,
+) {
+    let (offset, buf_size) = if tun.vnet_hdr() {
+        (tun::vnet::HEADER_LEN, MAX_TUN_READ)
+    } else {
+        (0, ip_packet::MAX_DATAGRAM_PAYLOAD)
+    };
+
+    loop {
+        // Reuse the buffers of packets the shards already processed instead of allocating a new one for every packet.
+        let mut buf = recycled.try_recv().unwrap_or_else(|_| vec![0u8; buf_size]);
+
+        let len = match futures::future::poll_fn(|cx| tun.poll_read(&mut buf, cx)).await {
+            Ok(0) => {
+                tracing::debug!("TUN device closed");
+                return;
+            }
+            Ok(n) => n,
+            Err(e) => {
+                tracing::debug!("Failed to read from TUN device: {}", err_with_sources(&e));
+                continue;
+            }
+        };
+
+        let Some(shard) = routes
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .shard_for(&buf[offset.min(len)..len])
+        else {
+            tracing::trace!("No shard for packet, dropping");
+            continue;
+        };
+
+        if shards[shard].try_send(Packet { buf, len }).is_err() {
+            tracing::debug!(%shard, "Shard is busy, dropping packet");
+        }
+    }
+}
+
+/// A packet read from the TUN device, stored in the first `len` bytes of `buf`.
+struct Packet {
+    buf: Vec<u8>,
+    len: usize,
+}
+
+/// Which shard is responsible for which client IPs.
+#[derive(Default)]
+struct Routes {
+    by_ip: HashMap<IpAddr, usize>,
+    by_client: HashMap<ClientId, Route>,
+}
+
+struct Route {
+    shard: usize,
+    ipv4: Ipv4Addr,
+    ipv6: Ipv6Addr,
+    added_at: Instant,
+}
+
+impl Routes {
+    fn insert(
+        &mut self,
+        client: ClientId,
+        shard: usize,
+        ipv4: Ipv4Addr,
+        ipv6: Ipv6Addr,
+        now: Instant,
+    ) {
+        if let Some(previous) = self.by_client.insert(
+            client,
+            Route {
+                shard,
+                ipv4,
+                ipv6,
+                added_at: now,
+            },
+        ) {
+            self.by_ip.remove(&IpAddr::from(previous.ipv4));
+            self.by_ip.remove(&IpAddr::from(previous.ipv6));
+        }
+
+        self.by_ip.insert(ipv4.into(), shard);
+        self.by_ip.insert(ipv6.into(), shard);
+    }
+
+    /// Removes the routes of all clients that `is_active` returns `false` for, unless we only just added them.
+    fn retain(&mut self, now: Instant, mut is_active: impl FnMut(usize, &ClientId) -> bool) {
+        let by_ip = &mut self.by_ip;
+
+        self.by_client.retain(|client, route| {
+            if now.duration_since(route.added_at) < ROUTE_GRACE_PERIOD
+                || is_active(route.shard, client)
+            {
+                return true;
+            }
+
+            by_ip.remove(&IpAddr::from(route.ipv4));
+            by_ip.remove(&IpAddr::from(route.ipv6));
+
+            false
+        });
+    }
+
+    /// The shard that should process `packet`, based on its destination IP.
+    fn shard_for(&self, packet: &[u8]) -> Option<usize> {
+        self.by_ip.get(&destination(packet)?).copied()
+    }
+}
+
+fn destination(packet: &[u8]) -> Option<IpAddr> {
+    match packet.first()? >> 4 {
+        4 => {
+            let dst: [u8; 4] = packet.get(16..20)?.try_into().ok()?;
+
+            Some(Ipv4Addr::from(dst).into())
+        }
+        6 => {
+            let dst: [u8; 16] = packet.get(24..40)?.try_into().ok()?;
+
+            Some(Ipv6Addr::from(dst).into())
+        }
+        _ => None,
+    }
+}
+
+/// The TUN device as seen by a single shard.
+///
+/// Reads the packets dispatched to this shard and writes directly to the TUN device.
+struct ShardTun {
+    packets: tokio::sync::mpsc::Receiver<Packet>,
+    /// Hands the buffers of processed packets back to the dispatcher.
+    recycle: tokio::sync::mpsc::UnboundedSender<Vec<u8>>,
+    device: Tun,
+}
+
+impl tun::Tun for ShardTun {
+    fn write4(&self, buf: &[u8]) -> io::Result<usize> {
+        self.device.write4(buf)
+    }
+
+    fn write6(&self, buf: &[u8]) -> io::Result<usize> {
+        self.device.write6(buf)
+    }
+
+    fn poll_read(&mut self, buf: &mut [u8], cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        let Some(Packet { buf: packet, len }) = ready!(self.packets.poll_recv(cx)) else {
+            return Poll::Ready(Ok(0)); // The dispatcher is gone, treat it like a closed device.
+        };
+
+        let result = match buf.get_mut(..len) {
+            Some(buf) => {
+                buf.copy_from_slice(&packet[..len]);
+
+                Ok(len)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Packet of {len} bytes doesn't fit into buffer of {} bytes",
+                    buf.len()
+                ),
+            )),
+        };
+
+        let _ = self.recycle.send(packet); // The dispatcher may be gone, in which case we don't need to recycle anything.
+
+        Poll::Ready(result)
+    }
+
+    fn name(&self) -> &str {
+        self.device.name()
+    }
+
+    fn vnet_hdr(&self) -> bool {
+        self.device.vnet_hdr()
+    }
+}
+
+fn lock(tunnel: &Mutex<GatewayTunnel>) -> MutexGuard<'_, GatewayTunnel> {
+    tunnel.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ip_packet::make::udp_packet;
+    use std::collections::{HashSet, VecDeque};
+
+    const CLIENT_1_V4: Ipv4Addr = Ipv4Addr::new(100, 64, 0, 1);
+    const CLIENT_1_V6: Ipv6Addr = Ipv6Addr::new(0xfd00, 0x2021, 0x1111, 0, 0, 0, 0, 1);
+    const CLIENT_2_V4: Ipv4Addr = Ipv4Addr::new(100, 64, 0, 2);
+    const CLIENT_2_V6: Ipv6Addr = Ipv6Addr::new(0xfd00, 0x2021, 0x1111, 0, 0, 0, 0, 2);
+
+    #[test]
+    fn reads_destination_of_ipv4_and_ipv6_packets() {
+        let ipv4 = udp_packet(Ipv4Addr::new(10, 0, 0, 1), CLIENT_1_V4, 53, 1000, vec![]).unwrap();
+        let ipv6 = udp_packet(Ipv6Addr::LOCALHOST, CLIENT_1_V6, 53, 1000, vec![]).unwrap();
+
+        assert_eq!(destination(ipv4.packet()), Some(CLIENT_1_V4.into()));
+        assert_eq!(destination(ipv6.packet()), Some(CLIENT_1_V6.into()));
+    }
+
+    #[test]
+    fn no_destination_for_truncated_or_unknown_packets() {
+        assert_eq!(destination(&[]), None);
+        assert_eq!(destination(&[0x45, 0, 0, 20]), None);
+        assert_eq!(destination(&[0x00; 40]), None);
+    }
+
+    #[test]
+    fn dispatches_packets_to_shard_of_destination() {
+        let now = Instant::now();
+        let mut routes = Routes::default();
+        routes.insert(client(1), 0, CLIENT_1_V4, CLIENT_1_V6, now);
+        routes.insert(client(2), 1, CLIENT_2_V4, CLIENT_2_V6, now);
+
+        assert_eq!(routes.shard_for(&packet_to(CLIENT_1_V4)), Some(0));
+        assert_eq!(routes.shard_for(&packet_to(CLIENT_2_V4)), Some(1));
+        assert_eq!(routes.shard_for(&packet_to(CLIENT_2_V6)), Some(1));
+    }
+
+    #[test]
+    fn drops_packets_for_unknown_destination() {
+        let mut routes = Routes::default();
+        routes.insert(client(1), 1, CLIENT_1_V4, CLIENT_1_V6, Instant::now());
+
+        assert_eq!(routes.shard_for(&packet_to(CLIENT_2_V4)), None);
+    }
+
+    #[test]
+    fn new_ips_replace_previous_route_of_client() {
+        let now = Instant::now();
+        let mut routes = Routes::default();
+        routes.insert(client(1), 1, CLIENT_1_V4, CLIENT_1_V6, now);
+        routes.insert(client(1), 1, CLIENT_2_V4, CLIENT_2_V6, now);
+
+        assert_eq!(routes.shard_for(&packet_to(CLIENT_1_V4)), None);
+        assert_eq!(routes.shard_for(&packet_to(CLIENT_2_V4)), Some(1));
+    }
+
+    #[test]
+    fn removes_routes_of_inactive_clients_after_grace_period() {
+        let now = Instant::now();
+        let mut routes = Routes::default();
+        routes.insert(client(1), 0, CLIENT_1_V4, CLIENT_1_V6, now);
+        routes.insert(client(2), 1, CLIENT_2_V4, CLIENT_2_V6, now);
+
+        routes.retain(now, |_, _| false);
+        assert_eq!(routes.shard_for(&packet_to(CLIENT_1_V4)), Some(0));
+
+        routes.retain(now + ROUTE_GRACE_PERIOD, |_, id| id == &client(2));
+
+        assert_eq!(routes.shard_for(&packet_to(CLIENT_1_V4)), None);
+        assert_eq!(routes.shard_for(&packet_to(CLIENT_1_V6)), None);
+        assert_eq!(routes.shard_for(&packet_to(CLIENT_2_V4)), Some(1));
+        assert_eq!(routes.by_client.len(), 1);
+    }
+
+    #[test]
+    fn clients_are_spread_across_all_shards() {
+        let shards = (0..100)
+            .map(|id| shard_idx(client(id), 4))
+            .collect::<HashSet<_>>();
+
+        assert_eq!(shards, HashSet::from([0, 1, 2, 3]));
+    }
+
+    #[test]
+    fn client_always_maps_to_same_shard() {
+        assert_eq!(shard_idx(client(1), 4), shard_idx(client(1), 4));
+    }
+
+    #[tokio::test]
+    async fn dispatcher_forwards_packets_to_shard_of_destination() {
+        let now = Instant::now();
+        let mut routes = Routes::default();
+        routes.insert(client(1), 0, CLIENT_1_V4, CLIENT_1_V6, now);
+        routes.insert(client(2), 1, CLIENT_2_V4, CLIENT_2_V6, now);
+
+        let (shard_0, mut shard_0_rx) = tokio::sync::mpsc::channel(10);
+        let (shard_1, mut shard_1_rx) = tokio::sync::mpsc::channel(10);
+        let (_recycle_tx, recycle_rx) = tokio::sync::mpsc::unbounded_channel();
+
+        let tun = FakeTun::new([
+            packet_to(CLIENT_1_V4),
+            packet_to(CLIENT_2_V6),
+            packet_to(Ipv4Addr::new(100, 64, 0, 3)),
+            packet_to(CLIENT_1_V6),
+        ]);
+
+        dispatch(
+            tun,
+            vec![shard_0, shard_1],
+            recycle_rx,
+            Arc::new(RwLock::new(routes)),
+        )
+        .await;
+
+        assert_eq!(
+            received(&mut shard_0_rx),
+            vec![packet_to(CLIENT_1_V4), packet_to(CLIENT_1_V6)]
+        );
+        assert_eq!(received(&mut shard_1_rx), vec![packet_to(CLIENT_2_V6)]);
+    }
+
+    #[tokio::test]
+    async fn dispatcher_drops_packets_for_busy_shard() {
+        let mut routes = Routes::default();
+        routes.insert(client(1), 0, CLIENT_1_V4, CLIENT_1_V6, Instant::now());
+
+        let (shard_0, mut shard_0_rx) = tokio::sync::mpsc::channel(1);
+        let (_recycle_tx, recycle_rx) = tokio::sync::mpsc::unbounded_channel();
+
+        let tun = FakeTun::new([packet_to(CLIENT_1_V4), packet_to(CLIENT_1_V6)]);
+
+        dispatch(
+            tun,
+            vec![shard_0],
+            recycle_rx,
+            Arc::new(RwLock::new(routes)),
+        )
+        .await;
+
+        assert_eq!(received(&mut shard_0_rx), vec![packet_to(CLIENT_1_V4)]);
+    }
+
+    #[tokio::test]
+    async fn dispatcher_reuses_recycled_buffers() {
+        let mut routes = Routes::default();
+        routes.insert(client(1), 0, CLIENT_1_V4, CLIENT_1_V6, Instant::now());
+
+        let (shard_0, mut shard_0_rx) = tokio::sync::mpsc::channel(1);
+        let (recycle_tx, recycle_rx) = tokio::sync::mpsc::unbounded_channel();
+        recycle_tx.send(vec![0xFF; 2000]).unwrap();
+
+        let tun = FakeTun::new([packet_to(CLIENT_1_V4)]);
+
+        dispatch(
+            tun,
+            vec![shard_0],
+            recycle_rx,
+            Arc::new(RwLock::new(routes)),
+        )
+        .await;
+
+        let packet = shard_0_rx.try_recv().unwrap();
+        assert_eq!(packet.buf.len(), 2000);
+        assert_eq!(packet.buf[..packet.len], packet_to(CLIENT_1_V4));
+    }
+
+    /// A TUN device that yields the given packets and then closes.
+    struct FakeTun {
+        packets: VecDeque<Vec<u8>>,
+    }
+
+    impl FakeTun {
+        fn new(packets: impl IntoIterator<Item = Vec<u8>>) -> Self {
+            Self {
+                packets: packets.into_iter().collect(),
+            }
+        }
+    }
+
+    impl tun::Tun for FakeTun {
+        fn write4(&self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn write6(&self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn poll_read(&mut self, buf: &mut [u8], _: &mut Context<'_>) -> Poll<io::Result<usize>> {
+            let Some(packet) = self.packets.pop_front() else {
+                return Poll::Ready(Ok(0));
+            };
+
+            buf[..packet.len()].copy_from_slice(&packet);
+
+            Poll::Ready(Ok(packet.len()))
+        }
+
+        fn name(&self) -> &str {
+            "fake"
+        }
+    }
+
+    fn received(shard: &mut tokio::sync::mpsc::Receiver<Packet>) -> Vec<Vec<u8>> {
+        std::iter::from_fn(|| shard.try_recv().ok())
+            .map(|Packet { buf, len }| buf[..len].to_vec())
+            .collect()
+    }
+
+    fn client(id: u128) -> ClientId {
+        ClientId::from_u128(id)
+    }
+
+    fn packet_to(dst: impl Into<IpAddr>) -> Vec<u8> {
+        let packet = match dst.into() {
+            IpAddr::V4(dst) => udp_packet(Ipv4Addr::new(10, 0, 0, 1), dst, 53, 1000, vec![]),
+            IpAddr::V6(dst) => udp_packet(Ipv6Addr::LOCALHOST, dst, 53, 1000, vec![]),
+        };
+
+        packet.unwrap().packet().to_vec()
+    }
+}
@@ -33,6 +33,8 @@ pub struct Controller<'a, I: GuiIntegration> {
     auth: auth::Auth,
     clear_logs_callback: Option<oneshot::Sender<Result<(), String>>>,
     ctlr_tx: CtlrTx,
+    /// Are we already downloading `release`? Avoids racing downloads if the user clicks twice.
+    downloading_update: bool,
     ipc_client: ipc::Client,
     ipc_rx: mpsc::Receiver<ipc::Event>,
     integration: I,
@@ -76,6 +78,7 @@ impl<'a, I: GuiIntegration> Builder<'a, I> {
             auth: auth::Auth::new()?,
             clear_logs_callback: None,
             ctlr_tx,
+            downloading_update: false,
             ipc_client,
             ipc_rx,
             integration,
@@ -121,6 +124,8 @@ pub enum ControllerRequest {
     SchemeRequest(SecretString),
     SignIn,
     SystemTrayMenu(TrayMenuEvent),
+    /// The path of the verified installer, or why we couldn't download or verify it
+    UpdateDownloaded(Result<PathBuf, String>),
     UpdateNotificationClicked(Url),
 }
 
@@ -181,7 +186,7 @@ impl Status {
         }
     }
 
-    /// True if we should react to `OnUpdateResources`
+    /// True if we should react to `OnResourcesChanged`
     fn needs_resource_updates(&self) -> bool {
         match self {
             Status::Disconnected
@@ -212,8 +217,13 @@ impl<'a, I: GuiIntegration> Controller<'a, I> {
             const VERSION: &str = env!("CARGO_PKG_VERSION");
 
             let environment = self.advanced_settings.api_url.to_string();
-            self.telemetry
-                .start(&environment, VERSION, firezone_telemetry::GUI_DSN);
+            let telemetry = self.advanced_settings.telemetry.clone();
+            self.telemetry.start_with_config(
+                &environment,
+                VERSION,
+                firezone_telemetry::GUI_DSN,
+                &telemetry.config(),
+            );
             if let Some(account_slug) = account_slug.clone() {
                 self.telemetry.set_account_slug(account_slug);
             }
@@ -223,6 +233,7 @@ impl<'a, I: GuiIntegration> Controller<'a, I> {
                     environment,
                     version: VERSION.to_owned(),
                     account_slug,
+                    telemetry,
                 })
                 .await?;
         }
@@ -343,7 +354,12 @@ impl<'a, I: GuiIntegration> Controller<'a, I> {
         // Count the start instant from before we connect
         let start_instant = Instant::now();
         self.ipc_client
-            .connect_to_firezone(api_url.as_str(), token.expose_secret().clone().into())
+            .connect_to_firezone(
+                api_url.as_str(),
+                token.expose_secret().clone().into(),
+                self.advanced_settings.proxy_url.as_deref(),
+                &self.advanced_settings.portal_tls,
+            )
             .await?;
         // Change the status after we begin connecting
         self.status = Status::WaitingForPortal {
@@ -433,6 +449,7 @@ impl<'a, I: GuiIntegration> Controller<'a, I> {
                 .context("Couldn't access clipboard")?
                 .set_text(s)
                 .context("Couldn't copy resource URL or other text to clipboard")?,
+            Req::SystemTrayMenu(TrayMenuEvent::DownloadUpdate) => self.download_update(),
             Req::SystemTrayMenu(TrayMenuEvent::CancelSignIn) => {
                 match &self.status {
                     Status::Disconnected | Status::RetryingConnection { .. } | Status::WaitingForPortal { .. } => {
@@ -485,10 +502,24 @@ impl<'a, I: GuiIntegration> Controller<'a, I> {
             Req::SystemTrayMenu(TrayMenuEvent::Quit) => Err(anyhow!(
                 "Impossible error: `Quit` should be handled before this"
             ))?,
-            Req::UpdateNotificationClicked(download_url) => {
+            Req::UpdateDownloaded(result) => {
+                self.downloading_update = false;
+                match result {
+                    Ok(path) => self.integration.open_url(path.display().to_string())
+                        .context("Couldn't open update installer")?,
+                    Err(error) => {
+                        tracing::error!(%error, "Couldn't download update");
+                        self.integration.show_notification(
+                            "Couldn't download update",
+                            "The update couldn't be verified. Please try again later.",
+                        )?;
+                    }
+                }
+            }
+            Req::UpdateNotificationClicked(_) => {
                 tracing::info!("UpdateNotificationClicked in run_controller!");
-                self.integration.open_url(&download_url)
-                    .context("Couldn't open update page")?;
+                // The notification's URL is only for display, we always download the release that the checker verified.
+                self.download_update();
             }
         }
         Ok(())
@@ -557,11 +588,22 @@ impl<'a, I: GuiIntegration> Controller<'a, I> {
                         .context("Couldn't show Disconnected alert")?;
                 }
             }
-            IpcServerMsg::OnUpdateResources(resources) => {
+            IpcServerMsg::OnResourcesChanged(changes) => {
                 if !self.status.needs_resource_updates() {
                     return Ok(ControlFlow::Continue(()));
                 }
-                tracing::debug!(len = resources.len(), "Got new Resources");
+                tracing::debug!(
+                    added = changes.added.len(),
+                    changed = changes.changed.len(),
+                    removed = changes.removed.len(),
+                    "Got Resource changes"
+                );
+                #[expect(clippy::wildcard_enum_match_arm)]
+                let mut resources = match std::mem::take(&mut self.status) {
+                    Status::TunnelReady { resources } => resources,
+                    _ => Vec::new(),
+                };
+                changes.apply(&mut resources);
                 self.status = Status::TunnelReady { resources };
                 if let Err(error) = self.refresh_system_tray_menu() {
                     tracing::error!(error = anyhow_dyn_err(&error), "Failed to refresh menu");
@@ -678,6 +720,33 @@ impl<'a, I: GuiIntegration> Controller<'a, I> {
         Ok(())
     }
 
+    /// Downloads and verifies the latest release in the background, then opens its installer
+    fn download_update(&mut self) {
+        let Some(release) = self.release.clone() else {
+            tracing::warn!("No update to download");
+            return;
+        };
+        if self.downloading_update {
+            tracing::debug!("Already downloading the update");
+            return;
+        }
+        self.downloading_update = true;
+
+        let ctlr_tx = self.ctlr_tx.clone();
+        tokio::spawn(async move {
+            let result = updates::download(&release)
+                .await
+                .map_err(|error| format!("{error:#}"));
+            if ctlr_tx
+                .send(ControllerRequest::UpdateDownloaded(result))
+                .await
+                .is_err()
+            {
+                tracing::debug!("Controller stopped while downloading update");
+            }
+        });
+    }
+
     async fn update_disabled_resources(&mut self) -> Result<()> {
         settings::save(&self.advanced_settings).await?;
 
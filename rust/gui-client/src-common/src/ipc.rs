@@ -1,4 +1,5 @@
 use anyhow::{Context as _, Result};
+use firezone_bin_shared::portal_tls::PortalTlsArgs;
 use firezone_headless_client::{
     ipc::{self, Error},
     IpcClientMsg, IpcServerMsg,
@@ -65,11 +66,15 @@ impl Client {
         &mut self,
         api_url: &str,
         token: SecretString,
+        proxy_url: Option<&str>,
+        portal_tls: &PortalTlsArgs,
     ) -> Result<(), Error> {
         let token = token.expose_secret().clone();
         self.send_msg(&IpcClientMsg::Connect {
             api_url: api_url.to_string(),
             token,
+            proxy_url: proxy_url.map(str::to_owned),
+            portal_tls: portal_tls.clone(),
         })
         .await
         .context("Couldn't send Connect message")
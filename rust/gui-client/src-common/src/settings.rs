@@ -1,9 +1,11 @@
 //! Everything related to the Settings window, including
 //! advanced settings and code for manipulating diagnostic logs.
 
+use crate::updates::UpdateChannel;
 use anyhow::{Context as _, Result};
 use atomicwrites::{AtomicFile, OverwriteBehavior};
 use connlib_model::ResourceId;
+use firezone_bin_shared::{portal_tls::PortalTlsArgs, telemetry::TelemetryArgs};
 use firezone_headless_client::known_dirs;
 use firezone_logging::std_dyn_err;
 use serde::{Deserialize, Serialize};
@@ -19,6 +21,20 @@ pub struct AdvancedSettings {
     #[serde(default)]
     pub internet_resource_enabled: Option<bool>,
     pub log_filter: String,
+    /// Proxy to connect to the portal through.
+    ///
+    /// The IPC service doesn't see the user's `HTTPS_PROXY`, so we pass it explicitly.
+    #[serde(default)]
+    pub proxy_url: Option<String>,
+    /// Custom CA and public key pins for the portal, passed to the IPC service like [`Self::proxy_url`].
+    #[serde(default)]
+    pub portal_tls: PortalTlsArgs,
+    /// Telemetry overrides for self-hosted deployments, applied to the GUI and the IPC service.
+    #[serde(default)]
+    pub telemetry: TelemetryArgs,
+    /// Which releases the update checker tells the user about. Read once at startup.
+    #[serde(default)]
+    pub update_channel: UpdateChannel,
 }
 
 #[cfg(debug_assertions)]
@@ -43,6 +59,10 @@ impl Default for AdvancedSettings {
             favorite_resources: Default::default(),
             internet_resource_enabled: Default::default(),
             log_filter: defaults::LOG_FILTER.to_string(),
+            proxy_url: None,
+            portal_tls: Default::default(),
+            telemetry: Default::default(),
+            update_channel: Default::default(),
         }
     }
 }
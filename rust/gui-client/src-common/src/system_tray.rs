@@ -266,7 +266,7 @@ impl Menu {
         self = self.separator();
         if let Some(release) = release {
             self = self.item(
-                Event::Url(release.download_url),
+                Event::DownloadUpdate,
                 format!("Download Firezone {}...", release.version),
             )
         }
@@ -48,6 +48,8 @@ pub enum Event {
     CancelSignIn,
     /// Copies this string to the desktop clipboard
     Copy(String),
+    /// Downloads the available update, verifies it and opens the installer
+    DownloadUpdate,
     /// Marks this Resource as non-favorite
     RemoveFavorite(ResourceId),
     /// If a Portal connection has failed, try again immediately
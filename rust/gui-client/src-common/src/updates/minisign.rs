@@ -0,0 +1,226 @@
+//! Verifies [minisign](https://jedisct1.github.io/minisign/) signatures of release manifests
+//!
+//! We only need to verify, so this implements just enough of the format to avoid
+//! pulling in a whole signing tool.
+
+use anyhow::{bail, ensure, Context as _, Result};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use blake2::{Blake2b512, Digest as _};
+use ring::signature::{UnparsedPublicKey, ED25519};
+use std::str::FromStr;
+
+/// Signs the message itself. Produced by `minisign -l` and older versions of minisign.
+const ALGORITHM_PURE: [u8; 2] = *b"Ed";
+/// Signs the BLAKE2b-512 hash of the message. The default since minisign 0.8.
+const ALGORITHM_PREHASHED: [u8; 2] = *b"ED";
+
+const TRUSTED_COMMENT_PREFIX: &str = "trusted comment: ";
+
+/// A minisign public key, e.g. the second line of `minisign.pub`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublicKey {
+    key_id: [u8; 8],
+    key: [u8; 32],
+}
+
+/// The contents of a `.minisig` file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    prehashed: bool,
+    key_id: [u8; 8],
+    signature: [u8; 64],
+    trusted_comment: String,
+    global_signature: [u8; 64],
+}
+
+impl PublicKey {
+    /// Checks that `signature` was made for `message` by this key
+    ///
+    /// Also checks the signature of the trusted comment, even though we don't use it,
+    /// so a tampered signature file never passes.
+    pub fn verify(&self, message: &[u8], signature: &Signature) -> Result<()> {
+        ensure!(
+            self.key_id == signature.key_id,
+            "Signature was made with key {}, expected {}",
+            hex::encode_upper(signature.key_id),
+            hex::encode_upper(self.key_id)
+        );
+
+        let key = UnparsedPublicKey::new(&ED25519, &self.key);
+
+        let verified = if signature.prehashed {
+            key.verify(&Blake2b512::digest(message), &signature.signature)
+        } else {
+            key.verify(message, &signature.signature)
+        };
+        verified.ok().context("Invalid signature")?;
+
+        let global_message = [
+            signature.signature.as_slice(),
+            signature.trusted_comment.as_bytes(),
+        ]
+        .concat();
+        key.verify(&global_message, &signature.global_signature)
+            .ok()
+            .context("Invalid signature for the trusted comment")?;
+
+        Ok(())
+    }
+}
+
+impl FromStr for PublicKey {
+    type Err = anyhow::Error;
+
+    /// Accepts either just the base64 line or the whole `minisign.pub` file
+    fn from_str(s: &str) -> Result<Self> {
+        let line = s
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with("untrusted comment:"))
+            .last()
+            .context("Public key is empty")?;
+        let bytes = STANDARD
+            .decode(line)
+            .context("Public key is not valid base64")?;
+
+        ensure!(bytes.len() == 42, "Public key must be 42 bytes");
+        ensure!(bytes[..2] == ALGORITHM_PURE, "Public key isn't for Ed25519");
+
+        Ok(Self {
+            key_id: bytes[2..10].try_into()?,
+            key: bytes[10..].try_into()?,
+        })
+    }
+}
+
+impl FromStr for Signature {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut lines = s.lines();
+
+        let Some(_untrusted_comment) = lines.next() else {
+            bail!("Signature is empty");
+        };
+        let signature = lines.next().context("Signature is missing")?;
+        let trusted_comment = lines
+            .next()
+            .and_then(|line| line.strip_prefix(TRUSTED_COMMENT_PREFIX))
+            .context("Signature is missing the trusted comment")?;
+        let global_signature = lines
+            .next()
+            .context("Signature is missing the global signature")?;
+
+        let signature = STANDARD
+            .decode(signature.trim())
+            .context("Signature is not valid base64")?;
+        ensure!(signature.len() == 74, "Signature must be 74 bytes");
+        let prehashed = match [signature[0], signature[1]] {
+            ALGORITHM_PURE => false,
+            ALGORITHM_PREHASHED => true,
+            other => bail!("Unknown signature algorithm {other:?}"),
+        };
+
+        let global_signature = STANDARD
+            .decode(global_signature.trim())
+            .context("Global signature is not valid base64")?;
+
+        Ok(Self {
+            prehashed,
+            key_id: signature[2..10].try_into()?,
+            signature: signature[10..].try_into()?,
+            trusted_comment: trusted_comment.to_owned(),
+            global_signature: global_signature
+                .as_slice()
+                .try_into()
+                .context("Global signature must be 64 bytes")?,
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use ring::signature::{Ed25519KeyPair, KeyPair as _};
+
+    const KEY_ID: [u8; 8] = [1, 2, 3, 4, 5, 6, 7, 8];
+
+    /// Signs like `minisign -S` would, returns the public key and the signature file
+    pub(crate) fn sign(message: &[u8], prehashed: bool) -> (String, String) {
+        let key_pair = Ed25519KeyPair::from_seed_unchecked(&[7; 32]).unwrap();
+
+        let public_key = [
+            ALGORITHM_PURE.as_slice(),
+            &KEY_ID,
+            key_pair.public_key().as_ref(),
+        ]
+        .concat();
+
+        let (algorithm, signature) = if prehashed {
+            (
+                ALGORITHM_PREHASHED,
+                key_pair.sign(&Blake2b512::digest(message)),
+            )
+        } else {
+            (ALGORITHM_PURE, key_pair.sign(message))
+        };
+        let trusted_comment = "timestamp:1729000000\tfile:manifest.json";
+        let global_signature =
+            key_pair.sign(&[signature.as_ref(), trusted_comment.as_bytes()].concat());
+
+        let signature_file = format!(
+            "untrusted comment: signature from minisign secret key\n{}\n{TRUSTED_COMMENT_PREFIX}{trusted_comment}\n{}\n",
+            STANDARD.encode([algorithm.as_slice(), &KEY_ID, signature.as_ref()].concat()),
+            STANDARD.encode(global_signature.as_ref()),
+        );
+
+        (STANDARD.encode(public_key), signature_file)
+    }
+
+    #[test]
+    fn verifies_pure_and_prehashed_signatures() {
+        for prehashed in [false, true] {
+            let (public_key, signature) = sign(b"manifest", prehashed);
+            let public_key = public_key.parse::<PublicKey>().unwrap();
+            let signature = signature.parse::<Signature>().unwrap();
+
+            public_key.verify(b"manifest", &signature).unwrap();
+            public_key.verify(b"manifesto", &signature).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn rejects_tampered_trusted_comment() {
+        let (public_key, signature) = sign(b"manifest", true);
+        let public_key = public_key.parse::<PublicKey>().unwrap();
+        let signature = signature
+            .replace("timestamp:1729000000", "timestamp:1729000001")
+            .parse::<Signature>()
+            .unwrap();
+
+        public_key.verify(b"manifest", &signature).unwrap_err();
+    }
+
+    #[test]
+    fn rejects_other_keys() {
+        let (public_key, signature) = sign(b"manifest", true);
+        let mut public_key = public_key.parse::<PublicKey>().unwrap();
+        public_key.key_id = [0; 8];
+
+        public_key
+            .verify(b"manifest", &signature.parse().unwrap())
+            .unwrap_err();
+    }
+
+    #[test]
+    fn parses_whole_public_key_file() {
+        let (public_key, _) = sign(b"manifest", true);
+        let file =
+            format!("untrusted comment: minisign public key 0807060504030201\n{public_key}\n");
+
+        assert_eq!(
+            file.parse::<PublicKey>().unwrap(),
+            public_key.parse::<PublicKey>().unwrap()
+        );
+    }
+}
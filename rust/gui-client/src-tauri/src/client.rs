@@ -62,10 +62,11 @@ pub(crate) fn run() -> Result<()> {
             // Can't check elevation here because the Windows CI is always elevated
             let settings = common::settings::load_advanced_settings().unwrap_or_default();
             let mut telemetry = telemetry::Telemetry::default();
-            telemetry.start(
+            telemetry.start_with_config(
                 settings.api_url.as_ref(),
                 env!("CARGO_PKG_VERSION"),
                 telemetry::GUI_DSN,
+                &settings.telemetry.config(),
             );
             // Don't fix the log filter for smoke tests
             let common::logging::Handles {
@@ -94,10 +95,11 @@ fn run_gui(cli: Cli) -> Result<()> {
     let mut settings = common::settings::load_advanced_settings().unwrap_or_default();
     let mut telemetry = telemetry::Telemetry::default();
     // In the future telemetry will be opt-in per organization, that's why this isn't just at the top of `main`
-    telemetry.start(
+    telemetry.start_with_config(
         settings.api_url.as_ref(),
         env!("CARGO_PKG_VERSION"),
         telemetry::GUI_DSN,
+        &settings.telemetry.config(),
     );
     // Get the device ID before starting Tokio, so that all the worker threads will inherit the correct scope.
     // Technically this means we can fail to get the device ID on a newly-installed system, since the IPC service may not have fully started up when the GUI process reaches this point, but in practice it's unlikely.
@@ -136,6 +136,7 @@ pub(crate) fn run(
 
     let (ctlr_tx, ctlr_rx) = mpsc::channel(5);
     let (updates_tx, updates_rx) = mpsc::channel(1);
+    let update_channel = advanced_settings.update_channel;
 
     let managed = Managed {
         ctlr_tx: ctlr_tx.clone(),
@@ -174,7 +175,7 @@ pub(crate) fn run(
             let setup_inner = move || {
                 // Check for updates
                 tokio::spawn(async move {
-                    if let Err(error) = updates::checker_task(updates_tx, cli.debug_update_check).await
+                    if let Err(error) = updates::checker_task(updates_tx, update_channel, cli.debug_update_check).await
                     {
                         tracing::error!(error = anyhow_dyn_err(&error), "Error in updates::checker_task");
                     }
@@ -5,6 +5,28 @@ interface Settings {
   auth_base_url: string;
   api_url: string;
   log_filter: string;
+  proxy_url: string | null;
+  update_channel: UpdateChannel;
+  portal_tls: PortalTls;
+  telemetry: Telemetry;
+}
+
+type UpdateChannel = "stable" | "beta";
+
+// Not editable in the form, only in `advanced_settings.json`
+interface PortalTls {
+  ca_files: string[];
+  ca_only: boolean;
+  pins: string[];
+}
+
+// Not editable in the form, only in `advanced_settings.json`
+interface Telemetry {
+  dsn: string | null;
+  environment: string | null;
+  sample_rate: number | null;
+  traces_sample_rate: number | null;
+  file: string | null;
 }
 
 interface FileCount {
@@ -21,6 +43,12 @@ const apiUrlInput = <HTMLInputElement>document.getElementById("api-url-input");
 const logFilterInput = <HTMLInputElement>(
   document.getElementById("log-filter-input")
 );
+const proxyUrlInput = <HTMLInputElement>(
+  document.getElementById("proxy-url-input")
+);
+const updateChannelInput = <HTMLSelectElement>(
+  document.getElementById("update-channel-input")
+);
 const logCountOutput = <HTMLParagraphElement>(
   document.getElementById("log-count-output")
 );
@@ -38,6 +66,16 @@ const clearLogsBtn = <HTMLButtonElement>(
 );
 const logsTabBtn = <HTMLButtonElement>document.getElementById("logs-tab");
 
+// Preserved when applying the form
+let portalTls: PortalTls = { ca_files: [], ca_only: false, pins: [] };
+let telemetry: Telemetry = {
+  dsn: null,
+  environment: null,
+  sample_rate: null,
+  traces_sample_rate: null,
+  file: null,
+};
+
 // Rust bridge functions
 
 // Lock the UI when we're saving to disk, since disk writes are technically async.
@@ -45,6 +83,8 @@ function lockAdvancedSettingsForm() {
   authBaseUrlInput.disabled = true;
   apiUrlInput.disabled = true;
   logFilterInput.disabled = true;
+  proxyUrlInput.disabled = true;
+  updateChannelInput.disabled = true;
   resetAdvancedSettingsBtn.disabled = true;
   applyAdvancedSettingsBtn.disabled = true;
 
@@ -56,6 +96,8 @@ function unlockAdvancedSettingsForm() {
   authBaseUrlInput.disabled = false;
   apiUrlInput.disabled = false;
   logFilterInput.disabled = false;
+  proxyUrlInput.disabled = false;
+  updateChannelInput.disabled = false;
   resetAdvancedSettingsBtn.disabled = false;
   applyAdvancedSettingsBtn.disabled = false;
 
@@ -83,6 +125,10 @@ async function applyAdvancedSettings() {
         auth_base_url: authBaseUrlInput.value,
         api_url: apiUrlInput.value,
         log_filter: logFilterInput.value,
+        proxy_url: proxyUrlInput.value || null,
+        update_channel: updateChannelInput.value as UpdateChannel,
+        portal_tls: portalTls,
+        telemetry: telemetry,
       },
     });
   } catch (e) {
@@ -101,6 +147,10 @@ async function resetAdvancedSettings() {
     authBaseUrlInput.value = settings.auth_base_url;
     apiUrlInput.value = settings.api_url;
     logFilterInput.value = settings.log_filter;
+    proxyUrlInput.value = settings.proxy_url ?? "";
+    updateChannelInput.value = settings.update_channel;
+    portalTls = settings.portal_tls;
+    telemetry = settings.telemetry;
   } catch (e) {
     console.error(e);
   } finally {
@@ -117,6 +167,10 @@ async function getAdvancedSettings() {
     authBaseUrlInput.value = settings.auth_base_url;
     apiUrlInput.value = settings.api_url;
     logFilterInput.value = settings.log_filter;
+    proxyUrlInput.value = settings.proxy_url ?? "";
+    updateChannelInput.value = settings.update_channel;
+    portalTls = settings.portal_tls;
+    telemetry = settings.telemetry;
   } catch (e) {
     console.error(e);
   } finally {
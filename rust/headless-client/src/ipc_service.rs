@@ -4,9 +4,11 @@ use crate::{
 };
 use anyhow::{bail, Context as _, Result};
 use clap::Parser;
-use connlib_model::ResourceView;
+use connlib_model::ResourceChanges;
 use firezone_bin_shared::{
     platform::{tcp_socket_factory, udp_socket_factory, DnsControlMethod},
+    portal_tls::PortalTlsArgs,
+    telemetry::TelemetryArgs,
     TunDeviceManager, TOKEN_ENV_KEY,
 };
 use firezone_logging::{anyhow_dyn_err, std_dyn_err, telemetry_span};
@@ -31,7 +33,7 @@ pub mod ipc;
 use backoff::ExponentialBackoffBuilder;
 use connlib_model::ResourceId;
 use ipc::{Server as IpcServer, ServiceId};
-use phoenix_channel::{get_user_agent, PhoenixChannel};
+use phoenix_channel::{get_user_agent, PhoenixChannel, Proxy};
 use secrecy::Secret;
 
 #[cfg(target_os = "linux")]
@@ -82,6 +84,12 @@ pub enum ClientMsg {
     Connect {
         api_url: String,
         token: String,
+        /// Proxy to connect to the portal through, see [`phoenix_channel::Proxy::from_url`].
+        #[serde(default)]
+        proxy_url: Option<String>,
+        /// Custom CA and public key pins for the portal.
+        #[serde(default)]
+        portal_tls: PortalTlsArgs,
     },
     Disconnect,
     ReloadLogFilter,
@@ -92,6 +100,11 @@ pub enum ClientMsg {
         environment: String,
         version: String,
         account_slug: Option<String>,
+        /// Overrides from the GUI's advanced settings, e.g. a self-hosted DSN.
+        ///
+        /// The IPC service ignores the `file` transport in here and only takes it from its own environment.
+        #[serde(default)]
+        telemetry: TelemetryArgs,
     },
     StopTelemetry,
 }
@@ -107,7 +120,8 @@ pub enum ServerMsg {
         error_msg: String,
         is_authentication_error: bool,
     },
-    OnUpdateResources(Vec<ResourceView>),
+    /// The GUI applies these to its copy of the Resource list.
+    OnResourcesChanged(ResourceChanges),
     /// The IPC service is terminating, maybe due to a software update
     ///
     /// This is a hint that the Client should exit with a message like,
@@ -448,14 +462,16 @@ impl<'a> Handler<'a> {
                     .await
                     .context("Error while sending IPC message `TunnelReady`")?;
             }
-            ConnlibMsg::OnUpdateResources(resources) => {
+            ConnlibMsg::OnUpdateResources(_) => {} // The GUI only needs the changes.
+            ConnlibMsg::OnResourcesChanged(changes) => {
                 // On every resources update, flush DNS to mitigate <https://github.com/firezone/firezone/issues/5052>
                 self.dns_controller.flush()?;
                 self.ipc_tx
-                    .send(&ServerMsg::OnUpdateResources(resources))
+                    .send(&ServerMsg::OnResourcesChanged(*changes))
                     .await
-                    .context("Error while sending IPC message `OnUpdateResources`")?;
+                    .context("Error while sending IPC message `OnResourcesChanged`")?;
             }
+            ConnlibMsg::OnUpdateGateways(_) => {} // The GUI doesn't show Gateways.
             ConnlibMsg::OnUpdateRoutes { ipv4, ipv6 } => {
                 self.tun_device.set_routes(ipv4, ipv6).await?;
                 self.dns_controller.flush()?;
@@ -476,10 +492,16 @@ impl<'a> Handler<'a> {
                     .await
                     .context("Error while sending IPC message")?
             }
-            ClientMsg::Connect { api_url, token } => {
+            ClientMsg::Connect {
+                api_url,
+                token,
+                proxy_url,
+                portal_tls,
+            } => {
                 // Warning: Connection errors don't bubble to callers of `handle_ipc_msg`.
                 let token = secrecy::SecretString::from(token);
-                let result = self.connect_to_firezone(&api_url, token);
+                let result =
+                    self.connect_to_firezone(&api_url, token, proxy_url.as_deref(), &portal_tls);
                 if let Err(error) = &result {
                     tracing::error!(
                         error = std_dyn_err(error),
@@ -542,9 +564,24 @@ impl<'a> Handler<'a> {
                 environment,
                 version,
                 account_slug,
+                telemetry,
             } => {
-                self.telemetry
-                    .start(&environment, &version, firezone_telemetry::IPC_SERVICE_DSN);
+                // We run as root, so the file transport must come from our own environment and never from the GUI.
+                let own = TelemetryArgs::from_env().unwrap_or_else(|error| {
+                    tracing::error!(
+                        error = std_dyn_err(&error),
+                        "Invalid telemetry overrides in the environment, ignoring them"
+                    );
+                    TelemetryArgs::default()
+                });
+                let config = own.with_overrides_from(telemetry).config();
+
+                self.telemetry.start_with_config(
+                    &environment,
+                    &version,
+                    firezone_telemetry::IPC_SERVICE_DSN,
+                    &config,
+                );
 
                 if let Some(account_slug) = account_slug {
                     self.telemetry.set_account_slug(account_slug);
@@ -562,7 +599,13 @@ impl<'a> Handler<'a> {
     /// Panics if there's no Tokio runtime or if connlib is already connected
     ///
     /// Throws matchable errors for bad URLs, unable to reach the portal, or unable to create the tunnel device
-    fn connect_to_firezone(&mut self, api_url: &str, token: SecretString) -> Result<(), Error> {
+    fn connect_to_firezone(
+        &mut self,
+        api_url: &str,
+        token: SecretString,
+        proxy_url: Option<&str>,
+        portal_tls: &PortalTlsArgs,
+    ) -> Result<(), Error> {
         let _connect_span = telemetry_span!("connect_to_firezone").entered();
 
         assert!(self.session.is_none());
@@ -577,6 +620,10 @@ impl<'a> Handler<'a> {
             device_id::device_info(),
         )
         .context("Failed to create `LoginUrl`")?;
+        // Without a proxy from the GUI, use the one from the IPC service's environment, e.g. its systemd unit.
+        let proxy = Proxy::from_url_or_env(proxy_url, url.host_and_port().0)
+            .context("Failed to parse proxy URL")?;
+        let tls = portal_tls.tls_config()?;
 
         self.last_connlib_start_instant = Some(Instant::now());
         let (cb_tx, cb_rx) = mpsc::channel(1_000);
@@ -594,8 +641,10 @@ impl<'a> Handler<'a> {
             ExponentialBackoffBuilder::default()
                 .with_max_elapsed_time(Some(Duration::from_secs(60 * 60 * 24 * 30)))
                 .build(),
+            proxy,
             Arc::new(tcp_socket_factory),
-        )?; // Turn this `io::Error` directly into an `Error` so we can distinguish it from others in the GUI client.
+        )?
+        .with_tls(tls); // Turn this `io::Error` directly into an `Error` so we can distinguish it from others in the GUI client.
 
         // Read the resolvers before starting connlib, in case connlib's startup interferes.
         let dns = self.dns_controller.system_resolvers();
@@ -632,6 +681,7 @@ impl<'a> Handler<'a> {
 /// and flushes the log file.
 fn setup_logging(
     log_dir: Option<PathBuf>,
+    log_rotation: firezone_logging::file::Rotation,
 ) -> Result<(firezone_logging::file::Handle, LogFilterReloader)> {
     // If `log_dir` is Some, use that. Else call `ipc_service_logs`
     let log_dir = log_dir.map_or_else(
@@ -640,7 +690,7 @@ fn setup_logging(
     )?;
     std::fs::create_dir_all(&log_dir)
         .context("We should have permissions to create our log dir")?;
-    let (layer, handle) = firezone_logging::file::layer(&log_dir);
+    let (layer, handle) = firezone_logging::file::layer_with_rotation(&log_dir, log_rotation);
     let directives = get_log_filter().context("Couldn't read log filter")?;
     let (filter, reloader) =
         tracing_subscriber::reload::Layer::new(firezone_logging::try_filter(&directives)?);
@@ -205,7 +205,7 @@ mod tests {
                 while let Some(req) = rx.next().await {
                     let req = req.expect("Error while reading from IPC client");
                     ensure!(req == IpcClientMsg::Reset);
-                    tx.send(&IpcServerMsg::OnUpdateResources(vec![]))
+                    tx.send(&IpcServerMsg::OnResourcesChanged(Default::default()))
                         .await
                         .expect("Error while writing to IPC client");
                 }
@@ -230,7 +230,7 @@ mod tests {
                         .await
                         .expect("Should have gotten a reply from the IPC server")
                         .expect("Error while reading from IPC server");
-                    ensure!(matches!(resp, IpcServerMsg::OnUpdateResources(_)));
+                    ensure!(matches!(resp, IpcServerMsg::OnResourcesChanged(_)));
                 }
             }
             Ok(())
@@ -6,7 +6,8 @@ use anyhow::{bail, Result};
 ///
 /// Linux uses the CLI args from here, Windows does not
 pub(crate) fn run_ipc_service(cli: CliCommon) -> Result<()> {
-    let (_handle, log_filter_reloader) = super::setup_logging(cli.log_dir)?;
+    let log_rotation = cli.log_rotation();
+    let (_handle, log_filter_reloader) = super::setup_logging(cli.log_dir, log_rotation)?;
     if !elevation_check()? {
         bail!("IPC service failed its elevation check, try running as admin / root");
     }
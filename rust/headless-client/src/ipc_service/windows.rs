@@ -131,7 +131,7 @@ fn service_run(arguments: Vec<OsString>) {
     // `arguments` doesn't seem to work right when running as a Windows service
     // (even though it's meant for that) so just use the default log dir.
     let (handle, log_filter_reloader) =
-        super::setup_logging(None).expect("Should be able to set up logging");
+        super::setup_logging(None, Default::default()).expect("Should be able to set up logging");
     if let Err(error) = fallible_service_run(arguments, handle, log_filter_reloader) {
         tracing::error!(
             error = anyhow_dyn_err(&error),
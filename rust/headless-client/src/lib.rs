@@ -12,7 +12,7 @@
 
 use anyhow::{Context as _, Result};
 use connlib_client_shared::Callbacks;
-use connlib_model::ResourceView;
+use connlib_model::{GatewayId, ResourceChanges, ResourceView};
 use firezone_bin_shared::platform::DnsControlMethod;
 use firezone_logging::std_dyn_err;
 use std::{
@@ -63,12 +63,41 @@ pub struct CliCommon {
     #[arg(short, long, env = "LOG_DIR")]
     pub log_dir: Option<PathBuf>,
 
+    /// Start a new log file once the current one exceeds this many MiB. By default, log files are never rotated.
+    #[arg(long, env = "FIREZONE_LOG_MAX_FILE_SIZE_MIB")]
+    pub log_max_file_size_mib: Option<u64>,
+
+    /// Start a new log file once the current one is this old. Accepts human times, e.g. "1d".
+    #[arg(long, env = "FIREZONE_LOG_MAX_FILE_AGE")]
+    pub log_max_file_age: Option<humantime::Duration>,
+
+    /// Delete the oldest log files once the log directory exceeds this many MiB. By default, log files are never deleted.
+    #[arg(long, env = "FIREZONE_LOG_MAX_TOTAL_SIZE_MIB")]
+    pub log_max_total_size_mib: Option<u64>,
+
+    /// Compress log files with gzip once we stop writing to them.
+    #[arg(long, env = "FIREZONE_LOG_COMPRESS")]
+    pub log_compress: bool,
+
     /// Maximum length of time to retry connecting to the portal if we're having internet issues or
     /// it's down. Accepts human times. e.g. "5m" or "1h" or "30d".
     #[arg(short, long, env = "MAX_PARTITION_TIME")]
     pub max_partition_time: Option<humantime::Duration>,
 }
 
+impl CliCommon {
+    pub fn log_rotation(&self) -> firezone_logging::file::Rotation {
+        const MIB: u64 = 1024 * 1024;
+
+        firezone_logging::file::Rotation {
+            max_file_size: self.log_max_file_size_mib.map(|mib| mib * MIB),
+            max_file_age: self.log_max_file_age.map(Into::into),
+            max_total_size: self.log_max_total_size_mib.map(|mib| mib * MIB),
+            compress: self.log_compress,
+        }
+    }
+}
+
 /// Messages that connlib can produce and send to the headless Client, IPC service, or GUI process.
 ///
 /// i.e. callbacks
@@ -86,6 +115,9 @@ pub enum ConnlibMsg {
         dns: Vec<IpAddr>,
     },
     OnUpdateResources(Vec<ResourceView>),
+    /// Boxed to keep the other messages small.
+    OnResourcesChanged(Box<ResourceChanges>),
+    OnUpdateGateways(Vec<GatewayId>),
     OnUpdateRoutes {
         ipv4: Vec<Ipv4Network>,
         ipv6: Vec<Ipv6Network>,
@@ -126,6 +158,19 @@ impl Callbacks for CallbackHandler {
             .expect("Should be able to send OnUpdateResources");
     }
 
+    fn on_resources_changed(&self, changes: ResourceChanges) {
+        self.cb_tx
+            .try_send(ConnlibMsg::OnResourcesChanged(Box::new(changes)))
+            .expect("Should be able to send OnResourcesChanged");
+    }
+
+    fn on_update_gateways(&self, gateways: Vec<GatewayId>) {
+        tracing::debug!(len = gateways.len(), "New list of connected Gateways");
+        self.cb_tx
+            .try_send(ConnlibMsg::OnUpdateGateways(gateways))
+            .expect("Should be able to send OnUpdateGateways");
+    }
+
     fn on_update_routes(&self, ipv4: Vec<Ipv4Network>, ipv6: Vec<Ipv6Network>) {
         self.cb_tx
             .try_send(ConnlibMsg::OnUpdateRoutes { ipv4, ipv6 })
@@ -0,0 +1,256 @@
+//! Machine-readable status output, so that scripts can wait for the tunnel and its Resources
+
+use anyhow::{Context as _, Result};
+use connlib_model::{GatewayId, ResourceStatus, ResourceView};
+use firezone_headless_client::ConnlibMsg;
+use ip_network::{Ipv4Network, Ipv6Network};
+use serde::Serialize;
+use std::{
+    io::Write as _,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+/// How to print the tunnel's status on stdout
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum StatusFormat {
+    /// Print one JSON document once the tunnel is ready, then exit
+    Json,
+    /// Print one JSON object per line for every update from connlib
+    JsonLines,
+}
+
+/// Everything connlib told us about the tunnel so far
+#[derive(Serialize)]
+pub(crate) struct Status {
+    ipv4: Option<Ipv4Addr>,
+    ipv6: Option<Ipv6Addr>,
+    /// The sentinel IPs on which connlib intercepts DNS queries
+    dns: Vec<IpAddr>,
+    ipv4_routes: Vec<Ipv4Network>,
+    ipv6_routes: Vec<Ipv6Network>,
+    resources: Vec<ResourceView>,
+    /// Gateways we have an established connection to
+    gateways: Vec<GatewayId>,
+
+    #[serde(skip)]
+    format: StatusFormat,
+    /// IDs or names of Resources that must be online before we print the status in `json` mode
+    #[serde(skip)]
+    wait_for: Vec<String>,
+    /// `on_update_routes` is always called right after `on_set_interface_config`, so the status isn't complete before it.
+    #[serde(skip)]
+    has_routes: bool,
+}
+
+/// A single line in `json-lines` mode, one per connlib callback
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Event<'a> {
+    InterfaceConfig {
+        ipv4: Ipv4Addr,
+        ipv6: Ipv6Addr,
+        dns: &'a [IpAddr],
+    },
+    Routes {
+        ipv4: &'a [Ipv4Network],
+        ipv6: &'a [Ipv6Network],
+    },
+    Resources {
+        resources: &'a [ResourceView],
+    },
+    Gateways {
+        gateways: &'a [GatewayId],
+    },
+    Disconnect {
+        error: &'a str,
+        is_authentication_error: bool,
+    },
+}
+
+impl Status {
+    pub(crate) fn new(format: StatusFormat, wait_for: Vec<String>) -> Self {
+        Self {
+            ipv4: None,
+            ipv6: None,
+            dns: Vec::default(),
+            ipv4_routes: Vec::default(),
+            ipv6_routes: Vec::default(),
+            resources: Vec::default(),
+            gateways: Vec::default(),
+            format,
+            wait_for,
+            has_routes: false,
+        }
+    }
+
+    /// Records a connlib callback and prints it as an event in `json-lines` mode
+    pub(crate) fn handle(&mut self, msg: &ConnlibMsg) -> Result<()> {
+        let event = match msg {
+            ConnlibMsg::OnDisconnect {
+                error_msg,
+                is_authentication_error,
+            } => Event::Disconnect {
+                error: error_msg,
+                is_authentication_error: *is_authentication_error,
+            },
+            ConnlibMsg::OnSetInterfaceConfig { ipv4, ipv6, dns } => {
+                self.ipv4 = Some(*ipv4);
+                self.ipv6 = Some(*ipv6);
+                self.dns.clone_from(dns);
+
+                Event::InterfaceConfig {
+                    ipv4: *ipv4,
+                    ipv6: *ipv6,
+                    dns,
+                }
+            }
+            ConnlibMsg::OnUpdateResources(resources) => {
+                self.resources.clone_from(resources);
+
+                Event::Resources { resources }
+            }
+            ConnlibMsg::OnResourcesChanged(_) => return Ok(()), // Covered by `OnUpdateResources`.
+            ConnlibMsg::OnUpdateGateways(gateways) => {
+                self.gateways.clone_from(gateways);
+
+                Event::Gateways { gateways }
+            }
+            ConnlibMsg::OnUpdateRoutes { ipv4, ipv6 } => {
+                self.ipv4_routes.clone_from(ipv4);
+                self.ipv6_routes.clone_from(ipv6);
+                self.has_routes = true;
+
+                Event::Routes { ipv4, ipv6 }
+            }
+        };
+
+        if self.format == StatusFormat::JsonLines {
+            print_json(&event)?;
+        }
+
+        Ok(())
+    }
+
+    /// In `json` mode, prints the status once the tunnel is ready and all Resources we wait for are online
+    ///
+    /// Returns `true` if we printed it and the Client should exit.
+    pub(crate) fn print_if_ready(&self) -> Result<bool> {
+        if self.format != StatusFormat::Json || self.ipv4.is_none() || !self.has_routes {
+            return Ok(false);
+        }
+
+        if !self.wait_for.iter().all(|r| self.is_online(r)) {
+            return Ok(false);
+        }
+
+        self.print()?;
+
+        Ok(true)
+    }
+
+    /// Prints whatever we know about the tunnel so far, even if it isn't ready yet
+    pub(crate) fn print(&self) -> Result<()> {
+        print_json(self)
+    }
+
+    fn is_online(&self, id_or_name: &str) -> bool {
+        self.resources.iter().any(|r| {
+            (r.id().to_string() == id_or_name || r.name() == id_or_name)
+                && r.status() == ResourceStatus::Online
+        })
+    }
+}
+
+fn print_json(value: &impl Serialize) -> Result<()> {
+    let mut stdout = std::io::stdout().lock();
+    serde_json::to_writer(&mut stdout, value).context("Failed to serialize status")?;
+    writeln!(stdout)?;
+    stdout.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn event_format() {
+        let event = Event::Disconnect {
+            error: "bad token",
+            is_authentication_error: true,
+        };
+
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"event":"disconnect","error":"bad token","is_authentication_error":true}"#
+        );
+    }
+
+    #[test]
+    fn json_waits_for_routes() {
+        let mut status = Status::new(StatusFormat::Json, vec![]);
+
+        status
+            .handle(&ConnlibMsg::OnSetInterfaceConfig {
+                ipv4: Ipv4Addr::new(100, 64, 0, 1),
+                ipv6: Ipv6Addr::LOCALHOST,
+                dns: vec![],
+            })
+            .unwrap();
+        assert!(!status.print_if_ready().unwrap());
+
+        status
+            .handle(&ConnlibMsg::OnUpdateRoutes {
+                ipv4: vec![],
+                ipv6: vec![],
+            })
+            .unwrap();
+        assert!(status.print_if_ready().unwrap());
+    }
+
+    #[test]
+    fn json_waits_for_resources_to_come_online() {
+        let mut status = Status::new(StatusFormat::Json, vec!["GitLab".to_owned()]);
+
+        status
+            .handle(&ConnlibMsg::OnSetInterfaceConfig {
+                ipv4: Ipv4Addr::new(100, 64, 0, 1),
+                ipv6: Ipv6Addr::LOCALHOST,
+                dns: vec![],
+            })
+            .unwrap();
+        status
+            .handle(&ConnlibMsg::OnUpdateRoutes {
+                ipv4: vec![],
+                ipv6: vec![],
+            })
+            .unwrap();
+        status
+            .handle(&ConnlibMsg::OnUpdateResources(vec![resource(
+                "GitLab",
+                ResourceStatus::Unknown,
+            )]))
+            .unwrap();
+        assert!(!status.print_if_ready().unwrap());
+
+        status
+            .handle(&ConnlibMsg::OnUpdateResources(vec![resource(
+                "GitLab",
+                ResourceStatus::Online,
+            )]))
+            .unwrap();
+        assert!(status.print_if_ready().unwrap());
+    }
+
+    fn resource(name: &str, status: ResourceStatus) -> ResourceView {
+        ResourceView::Cidr(connlib_model::CidrResourceView {
+            id: connlib_model::ResourceId::from_u128(1),
+            address: "10.0.0.0/24".parse().unwrap(),
+            name: name.to_owned(),
+            address_description: None,
+            sites: vec![],
+            status,
+        })
+    }
+}
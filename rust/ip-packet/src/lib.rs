@@ -24,6 +24,13 @@ pub use fz_p2p_control_slice::FzP2pControlSlice;
 #[cfg(all(test, feature = "proptest"))]
 mod proptests;
 
+/// Internals exposed for the fuzz targets in `rust/fuzz`.
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing {
+    pub use crate::nat46::translate_in_place as nat46_translate_in_place;
+    pub use crate::nat64::translate_in_place as nat64_translate_in_place;
+}
+
 use anyhow::{bail, Context as _, Result};
 use icmpv4_header_slice_mut::Icmpv4HeaderSliceMut;
 use icmpv6_header_slice_mut::Icmpv6EchoHeaderSliceMut;
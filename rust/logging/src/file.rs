@@ -2,10 +2,13 @@
 //!
 //! This module implements a file-based logger for connlib using tracing-appender.
 //!
-//! The log files are never rotated for the duration of the process; this prevents
+//! By default, the log files are never rotated for the duration of the process; this prevents
 //! tracing_appender from trying to prune old log files which triggers privacy
 //! alerts in Apple app store submissions.
 //!
+//! Long-running processes on platforms without that constraint can opt into [`Rotation`]
+//! via [`layer_with_rotation`].
+//!
 //! Since these will be leaving the user's device, these logs should contain *only*
 //! the necessary debugging information, and **not** any sensitive information,
 //! including but not limited to:
@@ -14,30 +17,68 @@
 //! - Device names
 //! - Device serials
 //! - MAC addresses
+//!
+//! Setting [`REDACT_LOGS_ENV`](crate::redact::REDACT_LOGS_ENV) additionally redacts IP addresses,
+//! domain names and device identifiers, see [`crate::redact`].
 
+use std::ffi::OsString;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use std::{fs, io};
 
+use flate2::write::GzEncoder;
 use time::OffsetDateTime;
 use tracing::Subscriber;
 use tracing_appender::non_blocking::{NonBlocking, WorkerGuard};
 use tracing_subscriber::Layer;
 
+use crate::redact::{Redact, Redactor};
+
 const LOG_FILE_BASE_NAME: &str = "connlib";
 pub const TIME_FORMAT: &str = "[year]-[month]-[day]-[hour]-[minute]-[second]";
+const MAX_FILES_PER_SECOND: u32 = 1000;
+
+/// When to start a new log file and which old ones to delete.
+///
+/// The default never rotates or deletes any files, see the module docs for why.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Rotation {
+    /// Start a new file once the current one would exceed this many bytes.
+    pub max_file_size: Option<u64>,
+    /// Start a new file once the current one has been open for this long.
+    pub max_file_age: Option<Duration>,
+    /// Delete the oldest files once all log files together exceed this many bytes.
+    ///
+    /// The budget is split evenly between the `.log` and `.jsonl` files.
+    pub max_total_size: Option<u64>,
+    /// Compress files with gzip once we stop writing to them.
+    pub compress: bool,
+}
 
 /// Create a new file logger layer.
 pub fn layer<T>(log_dir: &Path) -> (Box<dyn Layer<T> + Send + Sync + 'static>, Handle)
 where
     T: Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
 {
-    let (appender_json, handle_json) = new_appender(log_dir.to_path_buf(), "jsonl");
+    layer_with_rotation(log_dir, Rotation::default())
+}
+
+/// Create a new file logger layer that rotates and prunes its files according to `rotation`.
+pub fn layer_with_rotation<T>(
+    log_dir: &Path,
+    rotation: Rotation,
+) -> (Box<dyn Layer<T> + Send + Sync + 'static>, Handle)
+where
+    T: Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+{
+    let (appender_json, handle_json) =
+        new_appender(log_dir.to_path_buf(), "jsonl", rotation.clone());
     let layer_json = tracing_stackdriver::layer()
         .with_writer(appender_json)
         .boxed();
 
-    let (appender_fmt, handle_fmt) = new_appender(log_dir.to_path_buf(), "log");
+    let (appender_fmt, handle_fmt) = new_appender(log_dir.to_path_buf(), "log", rotation);
     let layer_fmt = tracing_subscriber::fmt::layer()
         .with_ansi(false)
         .with_writer(appender_fmt)
@@ -52,14 +93,22 @@ where
     // Return the guard so that the caller maintains a handle to it. Otherwise,
     // we have to wait for tracing_appender to flush the logs before exiting.
     // See https://docs.rs/tracing-appender/latest/tracing_appender/non_blocking/struct.WorkerGuard.html
-    (vec![layer_json, layer_fmt].boxed(), handle)
+    (
+        Redact::new(vec![layer_json, layer_fmt], Redactor::from_env()).boxed(),
+        handle,
+    )
 }
 
-fn new_appender(directory: PathBuf, file_extension: &'static str) -> (NonBlocking, WorkerGuard) {
+fn new_appender(
+    directory: PathBuf,
+    file_extension: &'static str,
+    rotation: Rotation,
+) -> (NonBlocking, WorkerGuard) {
     let appender = Appender {
         directory,
         current: None,
         file_extension,
+        rotation,
     };
 
     let (non_blocking, guard) = tracing_appender::non_blocking(appender);
@@ -82,27 +131,116 @@ pub struct Handle {
 struct Appender {
     directory: PathBuf,
     file_extension: &'static str,
+    rotation: Rotation,
     // Leaving this so that I/O errors come up through `write` instead of panicking
     // in `layer`
-    current: Option<(fs::File, String)>,
+    current: Option<CurrentFile>,
+}
+
+#[derive(Debug)]
+struct CurrentFile {
+    file: fs::File,
+    name: String,
+    size: u64,
+    opened_at: Instant,
 }
 
 impl Appender {
-    fn with_current_file<R>(
-        &mut self,
-        cb: impl Fn(&mut fs::File) -> io::Result<R>,
-    ) -> io::Result<R> {
-        match self.current.as_mut() {
+    fn current_file(&mut self) -> io::Result<&mut CurrentFile> {
+        let current = match self.current.take() {
+            Some(current) => current,
             None => {
-                let (mut file, name) = self.create_new_writer()?;
+                let (file, name) = self.create_new_writer()?;
+                let size = file.metadata().map(|m| m.len()).unwrap_or_default();
+
+                // Rotating or (re)starting is a good time to delete old files.
+                self.prune(&name);
+
+                CurrentFile {
+                    file,
+                    name,
+                    size,
+                    opened_at: Instant::now(),
+                }
+            }
+        };
+
+        Ok(self.current.insert(current))
+    }
+
+    /// Closes the current file if writing `incoming` more bytes would violate our [`Rotation`].
+    fn maybe_rotate(&mut self, incoming: u64) {
+        let Some(current) = self.current.as_ref() else {
+            return;
+        };
+
+        let too_large = self
+            .rotation
+            .max_file_size
+            .is_some_and(|max| current.size > 0 && current.size + incoming > max);
+        let too_old = self
+            .rotation
+            .max_file_age
+            .is_some_and(|max| current.opened_at.elapsed() >= max);
+
+        if !too_large && !too_old {
+            return;
+        }
+
+        let Some(CurrentFile { file, name, .. }) = self.current.take() else {
+            return;
+        };
+        drop(file);
+
+        if self.rotation.compress {
+            // We are the logger, there is nowhere to report this to. Worst case, the file stays uncompressed.
+            let _ = compress(&self.directory.join(name));
+        }
+    }
+
+    /// Deletes our oldest files until we are within [`Rotation::max_total_size`].
+    fn prune(&self, current: &str) {
+        let Some(max_total_size) = self.rotation.max_total_size else {
+            return;
+        };
+        let budget = max_total_size / 2; // The other half belongs to the appender for the other file extension.
 
-                let ret = cb(&mut file);
+        let Ok(entries) = fs::read_dir(&self.directory) else {
+            return;
+        };
 
-                self.current = Some((file, name));
+        let plain_suffix = format!(".{}", self.file_extension);
+        let compressed_suffix = format!(".{}.gz", self.file_extension);
 
-                ret
+        let mut files = entries
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                let name = entry.file_name().into_string().ok()?;
+
+                if !name.starts_with(LOG_FILE_BASE_NAME)
+                    || !(name.ends_with(&plain_suffix) || name.ends_with(&compressed_suffix))
+                {
+                    return None;
+                }
+
+                Some((name, entry.metadata().ok()?.len()))
+            })
+            .collect::<Vec<_>>();
+        files.sort(); // The timestamp in the name sorts the oldest files first.
+
+        let mut total_size = files.iter().map(|(_, size)| size).sum::<u64>();
+
+        for (name, size) in files {
+            if total_size <= budget {
+                break;
+            }
+            if name == current {
+                continue;
+            }
+
+            if fs::remove_file(self.directory.join(name)).is_ok() {
+                total_size -= size;
             }
-            Some((file, _)) => cb(file),
         }
     }
 
@@ -113,26 +251,41 @@ impl Appender {
             .format(&format)
             .map_err(|_| io::Error::other("Failed to format timestamp"))?;
 
-        let filename = format!("{LOG_FILE_BASE_NAME}.{date}.{}", self.file_extension);
+        fs::create_dir_all(&self.directory)?;
 
-        let path = self.directory.join(&filename);
-        let mut open_options = fs::OpenOptions::new();
-        open_options.append(true).create(true);
+        // Rotating or restarting can create several files within the same second.
+        // Never reuse a name, otherwise we would append to or later overwrite an older file.
+        // The `_` sorts after the `.` so that [`Appender::prune`] still sees the files in order.
+        for sequence in 0..MAX_FILES_PER_SECOND {
+            let filename = match sequence {
+                0 => format!("{LOG_FILE_BASE_NAME}.{date}.{}", self.file_extension),
+                n => format!("{LOG_FILE_BASE_NAME}.{date}_{n:03}.{}", self.file_extension),
+            };
+            let path = self.directory.join(&filename);
 
-        let new_file = open_options.open(path.as_path());
-        if new_file.is_err() {
-            if let Some(parent) = path.parent() {
-                fs::create_dir_all(parent)?;
-                let file = open_options.open(path)?;
+            if compressed_path(&path).exists() {
+                continue;
+            }
 
-                return Ok((file, filename));
+            match fs::OpenOptions::new()
+                .append(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(file) => {
+                    Self::set_permissions(&file)?;
+
+                    return Ok((file, filename));
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
             }
         }
 
-        let file = new_file?;
-        Self::set_permissions(&file)?;
-
-        Ok((file, filename))
+        Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "Too many log files created within one second",
+        ))
     }
 
     /// Make the logs group-readable so that the GUI, running as a user in the `firezone`
@@ -155,12 +308,172 @@ impl Appender {
     }
 }
 
+fn compressed_path(path: &Path) -> PathBuf {
+    let mut compressed_path = OsString::from(path);
+    compressed_path.push(".gz");
+
+    PathBuf::from(compressed_path)
+}
+
+/// Replaces the file at `path` with a gzip-compressed copy.
+///
+/// Fails instead of overwriting an existing compressed file.
+fn compress(path: &Path) -> io::Result<()> {
+    let mut input = fs::File::open(path)?;
+    let output = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(compressed_path(path))?;
+    Appender::set_permissions(&output)?;
+
+    let mut encoder = GzEncoder::new(output, flate2::Compression::default());
+    io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?;
+
+    fs::remove_file(path)
+}
+
 impl io::Write for Appender {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.with_current_file(|f| f.write(buf))
+        self.maybe_rotate(buf.len() as u64);
+
+        let current = self.current_file()?;
+        let written = current.file.write(buf)?;
+        current.size += written as u64;
+
+        Ok(written)
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        self.with_current_file(|f| f.flush())
+        self.current_file()?.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read as _, Write as _};
+
+    #[test]
+    fn never_rotates_by_default() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut appender = appender(dir.path(), Rotation::default());
+
+        for _ in 0..100 {
+            appender.write_all(&[b'a'; 1024]).unwrap();
+        }
+
+        assert_eq!(log_files(dir.path()).len(), 1);
+    }
+
+    #[test]
+    fn rotates_and_compresses_large_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut appender = appender(
+            dir.path(),
+            Rotation {
+                max_file_size: Some(1024),
+                compress: true,
+                ..Default::default()
+            },
+        );
+
+        let path = dir.path().join("connlib.0000-old.log");
+        fs::write(&path, [b'a'; 1000]).unwrap();
+        appender.current = Some(CurrentFile {
+            file: fs::OpenOptions::new().append(true).open(&path).unwrap(),
+            name: "connlib.0000-old.log".to_owned(),
+            size: 1000,
+            opened_at: Instant::now(),
+        });
+
+        appender.write_all(&[b'a'; 100]).unwrap();
+
+        let files = log_files(dir.path());
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0], "connlib.0000-old.log.gz");
+    }
+
+    #[test]
+    fn rotating_twice_within_one_second_keeps_all_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut appender = appender(
+            dir.path(),
+            Rotation {
+                max_file_size: Some(10),
+                compress: true,
+                ..Default::default()
+            },
+        );
+
+        appender.write_all(b"first-file").unwrap();
+        appender.write_all(b"secondfile").unwrap();
+        appender.write_all(b"third-file").unwrap();
+
+        let files = log_files(dir.path());
+        assert_eq!(files.len(), 3);
+
+        let mut contents = files
+            .iter()
+            .map(|name| {
+                let file = fs::File::open(dir.path().join(name)).unwrap();
+                let mut reader: Box<dyn io::Read> = if name.ends_with(".gz") {
+                    Box::new(flate2::read::GzDecoder::new(file))
+                } else {
+                    Box::new(file)
+                };
+
+                let mut content = String::new();
+                reader.read_to_string(&mut content).unwrap();
+
+                content
+            })
+            .collect::<Vec<_>>();
+        contents.sort();
+
+        assert_eq!(contents, ["first-file", "secondfile", "third-file"]);
+    }
+
+    #[test]
+    fn prunes_oldest_files() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("connlib.0000-oldest.log"), [0; 600]).unwrap();
+        fs::write(dir.path().join("connlib.0001-older.log.gz"), [0; 600]).unwrap();
+        fs::write(dir.path().join("connlib.0001-other.jsonl"), [0; 600]).unwrap();
+        fs::write(dir.path().join("unrelated.txt"), [0; 600]).unwrap();
+
+        let mut appender = appender(
+            dir.path(),
+            Rotation {
+                max_total_size: Some(2 * 1000),
+                ..Default::default()
+            },
+        );
+        appender.write_all(b"hello").unwrap();
+
+        let files = log_files(dir.path());
+        assert!(!files.contains(&"connlib.0000-oldest.log".to_owned()));
+        assert!(files.contains(&"connlib.0001-older.log.gz".to_owned()));
+        assert!(files.contains(&"connlib.0001-other.jsonl".to_owned()));
+        assert!(files.contains(&"unrelated.txt".to_owned()));
+    }
+
+    fn appender(dir: &Path, rotation: Rotation) -> Appender {
+        Appender {
+            directory: dir.to_path_buf(),
+            file_extension: "log",
+            rotation,
+            current: None,
+        }
+    }
+
+    fn log_files(dir: &Path) -> Vec<String> {
+        let mut files = fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        files.sort();
+
+        files
     }
 }
@@ -3,17 +3,24 @@
 mod dyn_err;
 pub mod file;
 mod format;
+pub mod redact;
 #[macro_use]
 mod unwrap_or;
 mod err_with_sources;
 
 use anyhow::{Context, Result};
+use redact::{Redact, Redactor};
 use sentry_tracing::EventFilter;
-use tracing::{subscriber::DefaultGuard, Subscriber};
+use tracing::{subscriber::DefaultGuard, Metadata, Subscriber};
 use tracing_log::LogTracer;
 use tracing_subscriber::{
-    filter::ParseError, fmt, layer::SubscriberExt as _, registry::LookupSpan,
-    util::SubscriberInitExt, EnvFilter, Layer, Registry,
+    filter::{filter_fn, FilterExt as _, ParseError},
+    fmt,
+    layer::SubscriberExt as _,
+    registry::LookupSpan,
+    reload,
+    util::SubscriberInitExt,
+    EnvFilter, Layer, Registry,
 };
 
 pub use dyn_err::{anyhow_dyn_err, std_dyn_err};
@@ -21,27 +28,71 @@ pub use err_with_sources::{err_with_sources, ErrorWithSources};
 pub use format::Format;
 
 /// Registers a global subscriber with stdout logging and `additional_layer`
-pub fn setup_global_subscriber<L>(additional_layer: L) -> Result<()>
+///
+/// The returned [`FilterReloadHandle`] can be used to change the log filter at runtime.
+pub fn setup_global_subscriber<L>(additional_layer: L) -> Result<FilterReloadHandle>
 where
-    L: Layer<Registry> + Send + Sync,
+    L: Layer<Registry> + Send + Sync + 'static,
+{
+    setup_global_subscriber_with_writer(additional_layer, std::io::stdout)
+}
+
+/// Registers a global subscriber like [`setup_global_subscriber`] but writes console logs to `writer`
+///
+/// Useful when stdout is reserved for machine-readable output.
+pub fn setup_global_subscriber_with_writer<L, W>(
+    additional_layer: L,
+    writer: W,
+) -> Result<FilterReloadHandle>
+where
+    L: Layer<Registry> + Send + Sync + 'static,
+    W: for<'w> fmt::MakeWriter<'w> + Send + Sync + 'static,
 {
     let directives = std::env::var("RUST_LOG").unwrap_or_default();
 
+    let (additional_filter, additional_reloader) =
+        reload::Layer::new(try_filter(&directives).context("Failed to parse directives")?);
+    let (fmt_filter, fmt_reloader) =
+        reload::Layer::new(try_filter(&directives).context("Failed to parse directives")?);
+
     let subscriber = Registry::default()
-        .with(
-            additional_layer
-                .with_filter(try_filter(&directives).context("Failed to parse directives")?),
-        )
+        .with(additional_layer.with_filter(additional_filter))
         .with(sentry_layer())
         .with(
             fmt::layer()
                 .event_format(Format::new())
-                .with_filter(try_filter(&directives).context("Failed to parse directives")?),
+                .with_writer(writer)
+                .with_filter(fmt_filter),
         );
     tracing::subscriber::set_global_default(subscriber).context("Could not set global default")?;
     LogTracer::init().context("Failed to init LogTracer")?;
 
-    Ok(())
+    Ok(FilterReloadHandle::new(move |directives| {
+        additional_reloader.reload(try_filter(directives)?)?;
+        fmt_reloader.reload(try_filter(directives)?)?;
+
+        Ok(())
+    }))
+}
+
+/// Changes the log filter of a subscriber at runtime.
+///
+/// Wraps one or more [`reload::Handle`]s without exposing the type of the subscriber they belong to.
+pub struct FilterReloadHandle {
+    reload: Box<dyn Fn(&str) -> Result<()> + Send + Sync>,
+}
+
+impl FilterReloadHandle {
+    pub fn new(reload: impl Fn(&str) -> Result<()> + Send + Sync + 'static) -> Self {
+        Self {
+            reload: Box::new(reload),
+        }
+    }
+
+    /// Applies the given directives, e.g. `info,firezone_tunnel=debug`.
+    pub fn reload(&self, directives: &str) -> Result<()> {
+        (self.reload)(directives)
+    }
 }
 
 /// Constructs an opinionated [`EnvFilter`] with some crates already silenced.
@@ -102,32 +153,55 @@ pub fn test_global(directives: &str) {
 ///
 /// Only spans with the `telemetry` target on level `TRACE` will be submitted to Sentry.
 /// Similar to telemetry events, these should be created with [`telemetry_span`] to ensure they are sampled correctly.
+///
+/// ## Redaction
+///
+/// IP addresses, domain names and device identifiers are always redacted before being sent to Sentry, see [`redact`].
 pub fn sentry_layer<S>() -> impl Layer<S> + Send + Sync
 where
     S: Subscriber + for<'a> LookupSpan<'a>,
 {
-    const IGNORED_TARGETS: &[IgnoredEvent] = &[IgnoredEvent::warn(
-        "tao::platform_impl::platform::event_loop::runner",
-    )];
-
-    sentry_tracing::layer()
-        .event_filter(move |md| {
-            let level = *md.level();
-
-            if IGNORED_TARGETS.contains(&IgnoredEvent::new(*md.level(), md.target())) {
-                return EventFilter::Ignore;
-            }
-
-            match level {
-                tracing::Level::ERROR | tracing::Level::WARN => EventFilter::Exception,
-                tracing::Level::INFO | tracing::Level::DEBUG => EventFilter::Breadcrumb,
-                tracing::Level::TRACE if md.target() == TELEMETRY_TARGET => EventFilter::Event,
-                _ => EventFilter::Ignore,
-            }
-        })
-        .span_filter(|md| *md.level() == tracing::Level::TRACE && md.target() == TELEMETRY_TARGET)
-        .enable_span_attributes()
-        .with_filter(try_filter("trace").expect("static filter always parses")) // Filter out noisy crates but pass all events otherwise.
+    let layer = sentry_tracing::layer()
+        .event_filter(sentry_event_filter)
+        .span_filter(sentry_span_filter)
+        .enable_span_attributes();
+
+    // Filter outside of `Redact` so we only redact what Sentry actually looks at.
+    Redact::new(layer, Some(Redactor::sensitive())).with_filter(
+        filter_fn(reaches_sentry).and(try_filter("trace").expect("static filter always parses")), // Filter out noisy crates.
+    )
+}
+
+const IGNORED_TARGETS: &[IgnoredEvent] = &[IgnoredEvent::warn(
+    "tao::platform_impl::platform::event_loop::runner",
+)];
+
+fn sentry_event_filter(md: &Metadata<'_>) -> EventFilter {
+    let level = *md.level();
+
+    if IGNORED_TARGETS.contains(&IgnoredEvent::new(level, md.target())) {
+        return EventFilter::Ignore;
+    }
+
+    match level {
+        tracing::Level::ERROR | tracing::Level::WARN => EventFilter::Exception,
+        tracing::Level::INFO | tracing::Level::DEBUG => EventFilter::Breadcrumb,
+        tracing::Level::TRACE if md.target() == TELEMETRY_TARGET => EventFilter::Event,
+        _ => EventFilter::Ignore,
+    }
+}
+
+fn sentry_span_filter(md: &Metadata<'_>) -> bool {
+    *md.level() == tracing::Level::TRACE && md.target() == TELEMETRY_TARGET
+}
+
+/// Whether the Sentry layer does anything with this span or event, see [`sentry_event_filter`] and [`sentry_span_filter`].
+fn reaches_sentry(md: &Metadata<'_>) -> bool {
+    if md.is_span() {
+        return sentry_span_filter(md);
+    }
+
+    !matches!(sentry_event_filter(md), EventFilter::Ignore)
 }
 
 #[doc(hidden)]
@@ -0,0 +1,715 @@
+//! Redaction of sensitive values before they reach log files or Sentry.
+//!
+//! [`Redact`] wraps another [`Layer`] and rewrites the fields of every event and span before
+//! handing them to it. Redacted values are replaced with a keyed hash so the same IP or domain
+//! still shows up as the same placeholder throughout a log file, without revealing the value.
+//! The key is fixed, so a value also keeps its placeholder across restarts and Sentry can group issues by it.
+
+use std::any::TypeId;
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use std::hash::{BuildHasher as _, BuildHasherDefault, DefaultHasher};
+use std::net::{IpAddr, SocketAddr};
+
+use tracing::field::{display, DisplayValue, Field, FieldSet, Value, ValueSet, Visit};
+use tracing::metadata::LevelFilter;
+use tracing::span::{Attributes, Id, Record};
+use tracing::subscriber::Interest;
+use tracing::{Event, Metadata, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// Setting this env var to `true` or `1` redacts the file logs, see [`Redactor::from_env`].
+pub const REDACT_LOGS_ENV: &str = "FIREZONE_REDACT_LOGS";
+
+/// Fields that identify the user's device and are always redacted by [`Redactor::sensitive`].
+const SENSITIVE_FIELDS: &[&str] = &[
+    "device_name",
+    "device_serial",
+    "device_uuid",
+    "firebase_installation_id",
+    "hostname",
+    "identifier_for_vendor",
+    "mac_address",
+    "ssid",
+];
+
+/// Extensions of files we commonly log, which would otherwise look like a domain, e.g. `settings.json`.
+const FILE_EXTENSIONS: &[&str] = &[
+    "bak", "bin", "conf", "crt", "csv", "dat", "db", "deb", "dll", "exe", "gz", "ini", "js",
+    "json", "jsonl", "key", "lock", "log", "md", "msi", "pem", "pid", "plist", "png", "rs", "sh",
+    "so", "sock", "tmp", "toml", "ts", "txt", "xml", "yaml", "yml", "zip",
+];
+
+/// The upper bound for the number of fields of a single callsite, imposed by [`ValueSet`].
+const MAX_FIELDS: usize = 32;
+
+/// Decides which values get redacted.
+pub struct Redactor {
+    fields: HashSet<String>,
+    ip_addresses: bool,
+    domain_names: bool,
+    ice_candidates: bool,
+    key: BuildHasherDefault<DefaultHasher>,
+}
+
+impl Default for Redactor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Redactor {
+    /// Creates a [`Redactor`] that doesn't redact anything until configured to.
+    pub fn new() -> Self {
+        Self {
+            fields: HashSet::default(),
+            ip_addresses: false,
+            domain_names: false,
+            ice_candidates: false,
+            key: BuildHasherDefault::default(),
+        }
+    }
+
+    /// Redacts device identifiers, IP addresses, domain names and ICE candidates.
+    pub fn sensitive() -> Self {
+        SENSITIVE_FIELDS
+            .iter()
+            .fold(Self::new(), |redactor, field| redactor.with_field(*field))
+            .with_ip_addresses()
+            .with_domain_names()
+            .with_ice_candidates()
+    }
+
+    /// Returns [`Redactor::sensitive`] if [`REDACT_LOGS_ENV`] is set.
+    pub fn from_env() -> Option<Self> {
+        let value = std::env::var(REDACT_LOGS_ENV).ok()?;
+
+        matches!(value.as_str(), "1" | "true").then(Self::sensitive)
+    }
+
+    /// Always redacts the entire value of fields with this name.
+    pub fn with_field(mut self, name: impl Into<String>) -> Self {
+        self.fields.insert(name.into());
+        self
+    }
+
+    /// Redacts IPv4 and IPv6 addresses, keeping the port of socket addresses.
+    pub fn with_ip_addresses(mut self) -> Self {
+        self.ip_addresses = true;
+        self
+    }
+
+    /// Redacts anything that looks like a fully-qualified domain name.
+    pub fn with_domain_names(mut self) -> Self {
+        self.domain_names = true;
+        self
+    }
+
+    /// Redacts values that are entire ICE candidates, e.g. `candidate:1 1 udp ...`.
+    pub fn with_ice_candidates(mut self) -> Self {
+        self.ice_candidates = true;
+        self
+    }
+
+    /// Redacts `value` of the field `field`, returning `None` if there is nothing to redact.
+    pub fn redact(&self, field: &str, value: &str) -> Option<String> {
+        if self.fields.contains(field) {
+            return Some(self.placeholder(value));
+        }
+
+        if self.ice_candidates && value.trim_start().starts_with("candidate:") {
+            return Some(self.placeholder(value));
+        }
+
+        if !self.ip_addresses && !self.domain_names {
+            return None;
+        }
+
+        let mut redacted = String::with_capacity(value.len());
+        let mut changed = false;
+        let mut rest = value;
+
+        while let Some(start) = rest.find(is_token_char) {
+            let (before, token) = rest.split_at(start);
+            let end = token
+                .find(|c: char| !is_token_char(c))
+                .unwrap_or(token.len());
+            let (token, after) = token.split_at(end);
+
+            redacted.push_str(before);
+            match self.redact_token(token) {
+                Some(replacement) => {
+                    redacted.push_str(&replacement);
+                    changed = true;
+                }
+                None => redacted.push_str(token),
+            }
+            rest = after;
+        }
+        redacted.push_str(rest);
+
+        changed.then_some(redacted)
+    }
+
+    fn redact_token(&self, token: &str) -> Option<String> {
+        if let Some(replacement) = self.redact_address(token) {
+            return Some(replacement);
+        }
+
+        // Tokens at the end of a sentence or before a list separator.
+        let trimmed = token.trim_end_matches(['.', ':']);
+        let suffix = &token[trimmed.len()..];
+
+        let replacement = self.redact_address(trimmed)?;
+
+        Some(format!("{replacement}{suffix}"))
+    }
+
+    fn redact_address(&self, token: &str) -> Option<String> {
+        if self.ip_addresses {
+            if let Ok(ip) = token.trim_matches(['[', ']']).parse::<IpAddr>() {
+                return Some(self.placeholder(&ip.to_string()));
+            }
+            if let Ok(socket) = token.parse::<SocketAddr>() {
+                return Some(format!(
+                    "{}:{}",
+                    self.placeholder(&socket.ip().to_string()),
+                    socket.port()
+                ));
+            }
+        }
+
+        if self.domain_names {
+            if is_domain(token) {
+                return Some(self.placeholder(&token.to_ascii_lowercase()));
+            }
+            if let Some((domain, port)) = token.rsplit_once(':') {
+                if is_domain(domain) && port.parse::<u16>().is_ok() {
+                    return Some(format!(
+                        "{}:{port}",
+                        self.placeholder(&domain.to_ascii_lowercase())
+                    ));
+                }
+            }
+        }
+
+        None
+    }
+
+    fn placeholder(&self, value: &str) -> String {
+        format!("<redacted:{:08x}>", self.key.hash_one(value) as u32)
+    }
+}
+
+fn is_token_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '.' | ':' | '-' | '_' | '%' | '[' | ']')
+}
+
+/// Whether `token` looks like `example.com`, i.e. at least two labels and an alphabetic TLD that isn't a common file extension.
+fn is_domain(token: &str) -> bool {
+    let mut labels = token.split('.').rev();
+
+    let Some(tld) = labels.next() else {
+        return false;
+    };
+    if tld.len() < 2 || !tld.chars().all(|c| c.is_ascii_alphabetic()) {
+        return false;
+    }
+    if FILE_EXTENSIONS.contains(&tld.to_ascii_lowercase().as_str()) {
+        return false;
+    }
+
+    let mut others = labels.peekable();
+    if others.peek().is_none() {
+        return false;
+    }
+
+    others.all(|label| {
+        !label.is_empty()
+            && !label.starts_with('-')
+            && label
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'))
+    })
+}
+
+/// A [`Layer`] that redacts the fields of all events and spans before passing them to `L`.
+///
+/// Without a [`Redactor`], everything is passed through unchanged.
+pub struct Redact<L> {
+    inner: L,
+    redactor: Option<Redactor>,
+}
+
+impl<L> Redact<L> {
+    pub fn new(inner: L, redactor: Option<Redactor>) -> Self {
+        Self { inner, redactor }
+    }
+
+    fn redact_fields(&self, record: impl FnOnce(&mut dyn Visit)) -> Option<Vec<(Field, Recorded)>> {
+        let redactor = self.redactor.as_ref()?;
+
+        let mut visitor = RedactingVisitor {
+            redactor,
+            fields: Vec::new(),
+            redacted: false,
+        };
+        record(&mut visitor);
+
+        if !visitor.redacted {
+            return None;
+        }
+
+        // We can't pass on more than this, drop the rest rather than leaking unredacted values.
+        visitor.fields.truncate(MAX_FIELDS);
+
+        Some(visitor.fields)
+    }
+}
+
+impl<S, L> Layer<S> for Redact<L>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    L: Layer<S>,
+{
+    fn on_layer(&mut self, subscriber: &mut S) {
+        self.inner.on_layer(subscriber)
+    }
+
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        self.inner.register_callsite(metadata)
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>, ctx: Context<'_, S>) -> bool {
+        self.inner.enabled(metadata, ctx)
+    }
+
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(fields) = self.redact_fields(|visitor| attrs.record(visitor)) else {
+            self.inner.on_new_span(attrs, id, ctx);
+            return;
+        };
+
+        let metadata = attrs.metadata();
+        with_value_set(metadata.fields(), &fields, |values| {
+            let attrs = if attrs.is_contextual() {
+                Attributes::new(metadata, values)
+            } else if let Some(parent) = attrs.parent() {
+                Attributes::child_of(parent.clone(), metadata, values)
+            } else {
+                Attributes::new_root(metadata, values)
+            };
+
+            self.inner.on_new_span(&attrs, id, ctx)
+        })
+    }
+
+    fn on_record(&self, span: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let Some(metadata) = ctx.metadata(span) else {
+            self.inner.on_record(span, values, ctx);
+            return;
+        };
+        let Some(fields) = self.redact_fields(|visitor| values.record(visitor)) else {
+            self.inner.on_record(span, values, ctx);
+            return;
+        };
+
+        with_value_set(metadata.fields(), &fields, |values| {
+            self.inner.on_record(span, &Record::new(values), ctx)
+        })
+    }
+
+    fn on_follows_from(&self, span: &Id, follows: &Id, ctx: Context<'_, S>) {
+        self.inner.on_follows_from(span, follows, ctx)
+    }
+
+    fn event_enabled(&self, event: &Event<'_>, ctx: Context<'_, S>) -> bool {
+        self.inner.event_enabled(event, ctx)
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let Some(fields) = self.redact_fields(|visitor| event.record(visitor)) else {
+            self.inner.on_event(event, ctx);
+            return;
+        };
+
+        let metadata = event.metadata();
+        with_value_set(metadata.fields(), &fields, |values| {
+            let event = if event.is_contextual() {
+                Event::new(metadata, values)
+            } else {
+                Event::new_child_of(event.parent().cloned(), metadata, values)
+            };
+
+            self.inner.on_event(&event, ctx)
+        })
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        self.inner.on_enter(id, ctx)
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        self.inner.on_exit(id, ctx)
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        self.inner.on_close(id, ctx)
+    }
+
+    fn on_id_change(&self, old: &Id, new: &Id, ctx: Context<'_, S>) {
+        self.inner.on_id_change(old, new, ctx)
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        self.inner.max_level_hint()
+    }
+
+    #[doc(hidden)]
+    unsafe fn downcast_raw(&self, id: TypeId) -> Option<*const ()> {
+        if id == TypeId::of::<Self>() {
+            return Some(self as *const _ as *const ());
+        }
+
+        // Per-layer filters rely on being able to find themselves inside of us.
+        self.inner.downcast_raw(id)
+    }
+}
+
+/// Builds a [`ValueSet`] for `fields` from the recorded values.
+///
+/// [`ValueSet`]s can only be created from arrays, so we pad with empty values.
+fn with_value_set<R>(
+    fields: &FieldSet,
+    recorded: &[(Field, Recorded)],
+    f: impl FnOnce(&ValueSet<'_>) -> R,
+) -> R {
+    let errors = recorded
+        .iter()
+        .map(|(_, value)| value.as_error())
+        .collect::<Vec<_>>();
+
+    let Some((first, _)) = recorded.first() else {
+        let empty: [(&Field, Option<&dyn Value>); 0] = [];
+
+        return f(&fields.value_set(&empty));
+    };
+
+    let mut values: [(&Field, Option<&dyn Value>); MAX_FIELDS] = [(first, None); MAX_FIELDS];
+    for (slot, ((field, value), error)) in values.iter_mut().zip(recorded.iter().zip(&errors)) {
+        let value = match error {
+            Some(error) => Some(error as &dyn Value),
+            None => value.as_value(),
+        };
+
+        *slot = (field, value);
+    }
+
+    f(&fields.value_set(&values))
+}
+
+enum Recorded {
+    Str(String),
+    Debug(DisplayValue<String>),
+    Error(RedactedError),
+    I64(i64),
+    U64(u64),
+    I128(i128),
+    U128(u128),
+    F64(f64),
+    Bool(bool),
+}
+
+impl Recorded {
+    fn as_value(&self) -> Option<&dyn Value> {
+        match self {
+            Recorded::Str(v) => Some(v),
+            Recorded::Debug(v) => Some(v),
+            Recorded::Error(_) => None,
+            Recorded::I64(v) => Some(v),
+            Recorded::U64(v) => Some(v),
+            Recorded::I128(v) => Some(v),
+            Recorded::U128(v) => Some(v),
+            Recorded::F64(v) => Some(v),
+            Recorded::Bool(v) => Some(v),
+        }
+    }
+
+    fn as_error(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Recorded::Error(e) => Some(e),
+            Recorded::Str(_)
+            | Recorded::Debug(_)
+            | Recorded::I64(_)
+            | Recorded::U64(_)
+            | Recorded::I128(_)
+            | Recorded::U128(_)
+            | Recorded::F64(_)
+            | Recorded::Bool(_) => None,
+        }
+    }
+}
+
+/// An error whose message and sources have been redacted.
+#[derive(Debug)]
+struct RedactedError {
+    message: String,
+    source: Option<Box<RedactedError>>,
+}
+
+impl RedactedError {
+    fn new(redactor: &Redactor, field: &str, error: &(dyn Error + 'static)) -> (Self, bool) {
+        let message = error.to_string();
+        let (source, source_redacted) = match error.source() {
+            Some(source) => {
+                let (source, redacted) = Self::new(redactor, field, source);
+
+                (Some(Box::new(source)), redacted)
+            }
+            None => (None, false),
+        };
+
+        match redactor.redact(field, &message) {
+            Some(message) => (Self { message, source }, true),
+            None => (Self { message, source }, source_redacted),
+        }
+    }
+}
+
+impl fmt::Display for RedactedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for RedactedError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source.as_deref().map(|e| e as &(dyn Error + 'static))
+    }
+}
+
+struct RedactingVisitor<'a> {
+    redactor: &'a Redactor,
+    fields: Vec<(Field, Recorded)>,
+    redacted: bool,
+}
+
+impl RedactingVisitor<'_> {
+    fn record_sensitive(&mut self, field: &Field, value: impl fmt::Display) -> bool {
+        if !self.redactor.fields.contains(field.name()) {
+            return false;
+        }
+
+        let value = self.redactor.placeholder(&value.to_string());
+        self.fields.push((field.clone(), Recorded::Str(value)));
+        self.redacted = true;
+
+        true
+    }
+}
+
+impl Visit for RedactingVisitor<'_> {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        if !self.record_sensitive(field, value) {
+            self.fields.push((field.clone(), Recorded::F64(value)));
+        }
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        if !self.record_sensitive(field, value) {
+            self.fields.push((field.clone(), Recorded::I64(value)));
+        }
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        if !self.record_sensitive(field, value) {
+            self.fields.push((field.clone(), Recorded::U64(value)));
+        }
+    }
+
+    fn record_i128(&mut self, field: &Field, value: i128) {
+        if !self.record_sensitive(field, value) {
+            self.fields.push((field.clone(), Recorded::I128(value)));
+        }
+    }
+
+    fn record_u128(&mut self, field: &Field, value: u128) {
+        if !self.record_sensitive(field, value) {
+            self.fields.push((field.clone(), Recorded::U128(value)));
+        }
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        if !self.record_sensitive(field, value) {
+            self.fields.push((field.clone(), Recorded::Bool(value)));
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        let value = match self.redactor.redact(field.name(), value) {
+            Some(redacted) => {
+                self.redacted = true;
+                redacted
+            }
+            None => value.to_owned(),
+        };
+
+        self.fields.push((field.clone(), Recorded::Str(value)));
+    }
+
+    fn record_error(&mut self, field: &Field, value: &(dyn Error + 'static)) {
+        let (error, redacted) = RedactedError::new(self.redactor, field.name(), value);
+        self.redacted |= redacted;
+
+        self.fields.push((field.clone(), Recorded::Error(error)));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        let value = format!("{value:?}");
+        let value = match self.redactor.redact(field.name(), &value) {
+            Some(redacted) => {
+                self.redacted = true;
+                redacted
+            }
+            None => value,
+        };
+
+        self.fields
+            .push((field.clone(), Recorded::Debug(display(value))));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+    use tracing_subscriber::layer::SubscriberExt as _;
+
+    #[test]
+    fn redacts_ip_addresses_and_keeps_ports() {
+        let redactor = Redactor::new().with_ip_addresses();
+
+        let redacted = redactor
+            .redact(
+                "message",
+                "Sending to 10.0.0.1:3478 and [fd00::1]:443, not 1.2.3",
+            )
+            .unwrap();
+
+        assert!(!redacted.contains("10.0.0.1"));
+        assert!(!redacted.contains("fd00::1"));
+        assert!(redacted.contains(":3478 and "));
+        assert!(redacted.ends_with(":443, not 1.2.3"));
+    }
+
+    #[test]
+    fn same_value_gets_same_placeholder() {
+        let redactor = Redactor::new().with_domain_names();
+
+        let first = redactor.redact("domain", "example.com").unwrap();
+        let second = redactor.redact("message", "Resolved example.com.").unwrap();
+        let other = redactor.redact("domain", "example.org").unwrap();
+
+        assert_eq!(second, format!("Resolved {first}."));
+        assert_ne!(first, other);
+    }
+
+    #[test]
+    fn ignores_things_that_only_look_like_domains() {
+        let redactor = Redactor::new().with_domain_names();
+
+        assert_eq!(redactor.redact("message", "Version 1.2.3 took 1.5s"), None);
+        assert_eq!(
+            redactor.redact("message", "firezone_tunnel::client e.g. here"),
+            None
+        );
+    }
+
+    #[test]
+    fn ignores_file_names() {
+        let redactor = Redactor::new().with_domain_names();
+
+        assert_eq!(
+            redactor.redact(
+                "message",
+                "Failed to read settings.json and firezone-id.json in main.rs"
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn placeholders_are_stable_across_redactors() {
+        let first = Redactor::new().with_domain_names();
+        let second = Redactor::new().with_domain_names();
+
+        assert_eq!(
+            first.redact("domain", "example.com"),
+            second.redact("domain", "example.com")
+        );
+    }
+
+    #[test]
+    fn redacts_configured_fields_and_candidates() {
+        let redactor = Redactor::new().with_field("ssid").with_ice_candidates();
+
+        assert!(redactor.redact("ssid", "Home").is_some());
+        assert!(redactor
+            .redact(
+                "candidate",
+                "candidate:1 1 udp 2130706431 10.0.0.1 5000 typ host"
+            )
+            .is_some());
+        assert_eq!(redactor.redact("name", "Home"), None);
+    }
+
+    #[test]
+    fn layer_redacts_events_and_spans() {
+        let buffer = Buffer::default();
+        let make_writer = {
+            let buffer = buffer.clone();
+            move || buffer.clone()
+        };
+        let subscriber = tracing_subscriber::registry().with(Redact::new(
+            tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .with_writer(make_writer),
+            Some(Redactor::sensitive()),
+        ));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let _span = tracing::info_span!("connect", ssid = "Home").entered();
+
+            tracing::info!(
+                peer = %"10.0.0.1:51820".parse::<SocketAddr>().unwrap(),
+                error = crate::std_dyn_err(&io::Error::other("no route to 10.0.0.1")),
+                attempts = 3,
+                "Resolved example.com"
+            );
+        });
+
+        let logs = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert!(!logs.contains("Home"), "{logs}");
+        assert!(!logs.contains("10.0.0.1"), "{logs}");
+        assert!(!logs.contains("example.com"), "{logs}");
+        assert!(logs.contains(":51820"), "{logs}");
+        assert!(logs.contains("attempts=3"), "{logs}");
+    }
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+}
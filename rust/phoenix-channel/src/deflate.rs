@@ -0,0 +1,773 @@
+//! [RFC 7692](https://www.rfc-editor.org/rfc/rfc7692) `permessage-deflate` for the portal connection.
+//!
+//! `tungstenite` doesn't implement any websocket extensions and rejects frames with the RSV1 bit set.
+//! [`PerMessageDeflate`] therefore sits between the (TLS) stream and `tungstenite`:
+//! It inflates compressed frames from the portal before `tungstenite` sees them and compresses the frames `tungstenite` writes.
+
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress};
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// The value of the `Sec-WebSocket-Extensions` header we send when compression is enabled.
+///
+/// We don't offer `client_max_window_bits` because our compressor always uses the full 32KB window.
+pub(crate) const OFFER: &str = "permessage-deflate";
+
+/// Same as `tungstenite`'s default `max_message_size`, guards against decompression bombs.
+const MAX_MESSAGE_SIZE: usize = 64 << 20;
+/// Stop accepting writes from `tungstenite` if this many bytes are waiting for the underlying stream.
+const MAX_BUFFERED_WRITES: usize = 64 * 1024;
+/// Upper bound for the HTTP response to our upgrade request.
+const MAX_RESPONSE_HEAD_SIZE: usize = 16 * 1024;
+
+/// Removed from the end of each compressed message, see <https://www.rfc-editor.org/rfc/rfc7692#section-7.2.1>.
+const TRAILER: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+
+pub(crate) struct PerMessageDeflate<S> {
+    inner: S,
+    mode: Mode,
+
+    /// Bytes read from `inner` that we didn't process yet.
+    read_raw: Vec<u8>,
+    /// Processed bytes, waiting to be read by `tungstenite`.
+    read_ready: Vec<u8>,
+    /// The opcode and payload of a compressed message that is split across multiple frames.
+    read_fragments: Option<(u8, Vec<u8>)>,
+
+    /// Bytes written by `tungstenite` that don't form a complete frame yet.
+    write_raw: Vec<u8>,
+    /// Processed bytes, waiting to be written to `inner`.
+    write_ready: Vec<u8>,
+
+    decompress: Decompress,
+    compress: Compress,
+}
+
+enum Mode {
+    /// We offered `permessage-deflate` and wait for the response to our upgrade request.
+    Negotiating,
+    /// The server accepted `permessage-deflate`.
+    Compressing { client_no_context_takeover: bool },
+    /// Either we didn't offer `permessage-deflate` or the server declined it.
+    Passthrough,
+}
+
+impl<S> PerMessageDeflate<S> {
+    /// Wraps `inner`, `offered` must be `true` if our upgrade request contained [`OFFER`].
+    pub(crate) fn new(inner: S, offered: bool) -> Self {
+        Self {
+            inner,
+            mode: if offered {
+                Mode::Negotiating
+            } else {
+                Mode::Passthrough
+            },
+            read_raw: Vec::new(),
+            read_ready: Vec::new(),
+            read_fragments: None,
+            write_raw: Vec::new(),
+            write_ready: Vec::new(),
+            decompress: Decompress::new(false),
+            compress: Compress::new(Compression::default(), false),
+        }
+    }
+
+    /// Whether the server accepted our offer.
+    pub(crate) fn is_compressing(&self) -> bool {
+        matches!(self.mode, Mode::Compressing { .. })
+    }
+
+    fn process_read(&mut self) -> io::Result<()> {
+        if matches!(self.mode, Mode::Negotiating) {
+            let Some(end) = find_head_end(&self.read_raw) else {
+                if self.read_raw.len() <= MAX_RESPONSE_HEAD_SIZE {
+                    return Ok(());
+                }
+
+                // Not a response we understand, let `tungstenite` deal with it.
+                self.mode = Mode::Passthrough;
+                self.read_ready.append(&mut self.read_raw);
+
+                return Ok(());
+            };
+
+            let head = self.read_raw.drain(..end).collect::<Vec<_>>();
+            let (mode, head) = negotiate(&head)?;
+
+            self.mode = mode;
+            self.read_ready.extend_from_slice(&head);
+        }
+
+        if matches!(self.mode, Mode::Passthrough) {
+            self.read_ready.append(&mut self.read_raw);
+
+            return Ok(());
+        }
+
+        while let Some(frame) = Frame::parse(&self.read_raw)? {
+            let bytes = self.read_raw.drain(..frame.len).collect::<Vec<_>>();
+
+            if frame.is_control() {
+                self.read_ready.extend_from_slice(&bytes);
+                continue;
+            }
+
+            let (opcode, payload) = match (frame.opcode, frame.rsv1, self.read_fragments.take()) {
+                (OPCODE_CONTINUATION, false, Some((opcode, mut payload))) => {
+                    payload.extend_from_slice(&frame.payload(&bytes));
+
+                    (opcode, payload)
+                }
+                (OPCODE_TEXT | OPCODE_BINARY, true, None) => (frame.opcode, frame.payload(&bytes)),
+                (_, _, fragments) => {
+                    // Uncompressed or invalid, either way `tungstenite` will handle it.
+                    self.read_fragments = fragments;
+                    self.read_ready.extend_from_slice(&bytes);
+                    continue;
+                }
+            };
+
+            if payload.len() > MAX_MESSAGE_SIZE {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "compressed message is too large",
+                ));
+            }
+
+            if !frame.fin {
+                self.read_fragments = Some((opcode, payload));
+                continue;
+            }
+
+            let message = self.inflate(payload)?;
+
+            write_header(&mut self.read_ready, false, opcode, None, message.len());
+            self.read_ready.extend_from_slice(&message);
+        }
+
+        Ok(())
+    }
+
+    fn process_write(&mut self) -> io::Result<()> {
+        let Mode::Compressing {
+            client_no_context_takeover,
+        } = self.mode
+        else {
+            self.write_ready.append(&mut self.write_raw);
+
+            return Ok(());
+        };
+
+        while let Some(frame) = Frame::parse(&self.write_raw)? {
+            let bytes = self.write_raw.drain(..frame.len).collect::<Vec<_>>();
+
+            // Fragmented messages are rare and may be sent uncompressed.
+            let is_whole_message = frame.fin && matches!(frame.opcode, OPCODE_TEXT | OPCODE_BINARY);
+
+            if !is_whole_message || frame.rsv1 {
+                self.write_ready.extend_from_slice(&bytes);
+                continue;
+            }
+
+            let mut payload = self.deflate(&frame.payload(&bytes))?;
+            if client_no_context_takeover {
+                self.compress.reset();
+            }
+
+            write_header(
+                &mut self.write_ready,
+                true,
+                frame.opcode,
+                frame.mask,
+                payload.len(),
+            );
+            if let Some(mask) = frame.mask {
+                apply_mask(&mut payload, mask);
+            }
+            self.write_ready.extend_from_slice(&payload);
+        }
+
+        Ok(())
+    }
+
+    fn inflate(&mut self, mut payload: Vec<u8>) -> io::Result<Vec<u8>> {
+        payload.extend_from_slice(&TRAILER);
+
+        let mut message = Vec::with_capacity(payload.len() * 2);
+        let start = self.decompress.total_in();
+
+        loop {
+            let consumed = (self.decompress.total_in() - start) as usize;
+            let produced = message.len();
+
+            if message.len() == message.capacity() {
+                if message.len() >= MAX_MESSAGE_SIZE {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "decompressed message is too large",
+                    ));
+                }
+
+                message.reserve(message.capacity());
+            }
+
+            self.decompress.decompress_vec(
+                payload.get(consumed..).unwrap_or_default(),
+                &mut message,
+                FlushDecompress::Sync,
+            )?;
+
+            let now_consumed = (self.decompress.total_in() - start) as usize;
+
+            if now_consumed == payload.len() && message.len() < message.capacity() {
+                return Ok(message);
+            }
+
+            if now_consumed == consumed && message.len() == produced {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "invalid compressed message",
+                ));
+            }
+        }
+    }
+
+    fn deflate(&mut self, message: &[u8]) -> io::Result<Vec<u8>> {
+        let mut payload = Vec::with_capacity(message.len() / 2 + 64);
+        let start = self.compress.total_in();
+
+        loop {
+            let consumed = (self.compress.total_in() - start) as usize;
+
+            if payload.len() == payload.capacity() {
+                payload.reserve(payload.capacity());
+            }
+
+            self.compress.compress_vec(
+                message.get(consumed..).unwrap_or_default(),
+                &mut payload,
+                FlushCompress::Sync,
+            )?;
+
+            let now_consumed = (self.compress.total_in() - start) as usize;
+
+            // The flush is complete once all input is consumed and there was space left in the output.
+            if now_consumed == message.len() && payload.len() < payload.capacity() {
+                break;
+            }
+        }
+
+        if payload.ends_with(&TRAILER) {
+            payload.truncate(payload.len() - TRAILER.len());
+        }
+
+        Ok(payload)
+    }
+}
+
+impl<S> PerMessageDeflate<S>
+where
+    S: AsyncWrite + Unpin,
+{
+    /// Writes as much of `write_ready` to `inner` as possible.
+    fn poll_write_ready(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !self.write_ready.is_empty() {
+            let written = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.write_ready))?;
+
+            if written == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+
+            self.write_ready.drain(..written);
+        }
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S> AsyncRead for PerMessageDeflate<S>
+where
+    S: AsyncRead + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        loop {
+            if !this.read_ready.is_empty() {
+                let len = this.read_ready.len().min(buf.remaining());
+                buf.put_slice(&this.read_ready[..len]);
+                this.read_ready.drain(..len);
+
+                return Poll::Ready(Ok(()));
+            }
+
+            let mut chunk = [0u8; 8192];
+            let mut chunk = ReadBuf::new(&mut chunk);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut chunk))?;
+
+            if chunk.filled().is_empty() {
+                return Poll::Ready(Ok(())); // EOF
+            }
+
+            this.read_raw.extend_from_slice(chunk.filled());
+            this.process_read()?;
+        }
+    }
+}
+
+impl<S> AsyncWrite for PerMessageDeflate<S>
+where
+    S: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        if let Poll::Ready(Err(e)) = this.poll_write_ready(cx) {
+            return Poll::Ready(Err(e));
+        }
+        if this.write_ready.len() >= MAX_BUFFERED_WRITES {
+            return Poll::Pending; // `poll_write_ready` registered the waker.
+        }
+
+        this.write_raw.extend_from_slice(buf);
+        this.process_write()?;
+
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        ready!(this.poll_write_ready(cx))?;
+
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        ready!(this.poll_write_ready(cx))?;
+
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+/// Inspects the server's response to our upgrade request.
+///
+/// Returns the mode to continue in and the response with the `Sec-WebSocket-Extensions` header removed, because `tungstenite` doesn't know about it.
+fn negotiate(head: &[u8]) -> io::Result<(Mode, Vec<u8>)> {
+    let Ok(text) = std::str::from_utf8(head) else {
+        return Ok((Mode::Passthrough, head.to_vec()));
+    };
+    let text = text.trim_end_matches("\r\n");
+
+    let mut lines = text.split("\r\n");
+    let status_line = lines.next().unwrap_or_default();
+
+    if status_line.split_whitespace().nth(1) != Some("101") {
+        return Ok((Mode::Passthrough, head.to_vec()));
+    }
+
+    let mut rewritten = format!("{status_line}\r\n");
+    let mut extensions = Vec::new();
+
+    for line in lines {
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("sec-websocket-extensions") {
+                extensions.extend(value.split(',').map(str::trim).map(str::to_owned));
+                continue;
+            }
+        }
+
+        rewritten.push_str(line);
+        rewritten.push_str("\r\n");
+    }
+    rewritten.push_str("\r\n");
+
+    let mut mode = Mode::Passthrough;
+
+    for extension in extensions.iter().filter(|e| !e.is_empty()) {
+        let mut params = extension.split(';').map(str::trim);
+
+        let name = params.next().unwrap_or_default();
+        if name != OFFER {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("server accepted extension `{name}` which we didn't offer"),
+            ));
+        }
+
+        let mut client_no_context_takeover = false;
+
+        for param in params {
+            let (key, value) = match param.split_once('=') {
+                Some((key, value)) => (key.trim(), Some(value.trim().trim_matches('"'))),
+                None => (param, None),
+            };
+
+            match (key, value) {
+                ("server_no_context_takeover", None) => {}
+                ("server_max_window_bits", Some(_)) => {} // Our decompressor handles all window sizes.
+                ("client_max_window_bits", Some("15")) => {}
+                ("client_no_context_takeover", None) => client_no_context_takeover = true,
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("unsupported `permessage-deflate` parameter `{param}`"),
+                    ))
+                }
+            }
+        }
+
+        mode = Mode::Compressing {
+            client_no_context_takeover,
+        };
+    }
+
+    Ok((mode, rewritten.into_bytes()))
+}
+
+/// Returns the length of the HTTP head in `buf`, including the empty line.
+fn find_head_end(buf: &[u8]) -> Option<usize> {
+    buf.windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .map(|pos| pos + 4)
+}
+
+/// The header of a websocket frame, see <https://www.rfc-editor.org/rfc/rfc6455#section-5.2>.
+struct Frame {
+    fin: bool,
+    rsv1: bool,
+    opcode: u8,
+    mask: Option<[u8; 4]>,
+    header_len: usize,
+    /// The length of the entire frame, including the header.
+    len: usize,
+}
+
+impl Frame {
+    /// Parses the header of the frame at the start of `buf`, returns `None` until the entire frame is buffered.
+    fn parse(buf: &[u8]) -> io::Result<Option<Self>> {
+        let [first, second, rest @ ..] = buf else {
+            return Ok(None);
+        };
+
+        let (payload_len, rest) = match second & 0x7f {
+            126 => match rest {
+                [a, b, rest @ ..] => (u16::from_be_bytes([*a, *b]) as u64, rest),
+                _ => return Ok(None),
+            },
+            127 => match rest.split_first_chunk::<8>() {
+                Some((len, rest)) => (u64::from_be_bytes(*len), rest),
+                None => return Ok(None),
+            },
+            len => (len as u64, rest),
+        };
+
+        let mask = if second & 0x80 != 0 {
+            match rest.first_chunk::<4>() {
+                Some(mask) => Some(*mask),
+                None => return Ok(None),
+            }
+        } else {
+            None
+        };
+
+        if payload_len > MAX_MESSAGE_SIZE as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "websocket frame is too large",
+            ));
+        }
+
+        let header_len = buf.len() - rest.len() + mask.map_or(0, |m| m.len());
+        let len = header_len + payload_len as usize;
+
+        if buf.len() < len {
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            fin: first & 0x80 != 0,
+            rsv1: first & 0x40 != 0,
+            opcode: first & 0x0f,
+            mask,
+            header_len,
+            len,
+        }))
+    }
+
+    fn is_control(&self) -> bool {
+        self.opcode & 0x8 != 0
+    }
+
+    /// The unmasked payload of this frame.
+    fn payload(&self, frame: &[u8]) -> Vec<u8> {
+        let mut payload = frame[self.header_len..self.len].to_vec();
+
+        if let Some(mask) = self.mask {
+            apply_mask(&mut payload, mask);
+        }
+
+        payload
+    }
+}
+
+/// Writes the header of a final frame.
+fn write_header(out: &mut Vec<u8>, rsv1: bool, opcode: u8, mask: Option<[u8; 4]>, len: usize) {
+    out.push(0x80 | if rsv1 { 0x40 } else { 0x00 } | opcode);
+
+    let mask_bit = if mask.is_some() { 0x80 } else { 0x00 };
+
+    match len {
+        0..=125 => out.push(mask_bit | len as u8),
+        126..=0xffff => {
+            out.push(mask_bit | 126);
+            out.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        _ => {
+            out.push(mask_bit | 127);
+            out.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+
+    if let Some(mask) = mask {
+        out.extend_from_slice(&mask);
+    }
+}
+
+fn apply_mask(data: &mut [u8], mask: [u8; 4]) {
+    for (byte, key) in data.iter_mut().zip(mask.iter().cycle()) {
+        *byte ^= key;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{SinkExt as _, StreamExt as _};
+    use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest as _;
+    use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+    use tokio_tungstenite::tungstenite::Message;
+
+    const INIT: &str =
+        r#"{"event":"init","payload":{"resources":["a","a","a","a","a","a","a","a"]}}"#;
+
+    #[tokio::test]
+    async fn compresses_messages_in_both_directions() {
+        let (server, url) = StandInServer::bind(true).await;
+        let server = tokio::spawn(server.echo_once());
+
+        let (mut client, compressing) = connect(&url, true).await;
+        assert!(compressing);
+
+        client.send(Message::text(INIT)).await.unwrap();
+        let reply = client.next().await.unwrap().unwrap();
+
+        assert_eq!(reply, Message::text(INIT));
+
+        let received = server.await.unwrap();
+        assert!(received.compressed);
+        assert_eq!(received.payload, INIT.as_bytes());
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_uncompressed_if_server_declines() {
+        let (server, url) = StandInServer::bind(false).await;
+        let server = tokio::spawn(server.echo_once());
+
+        let (mut client, compressing) = connect(&url, true).await;
+        assert!(!compressing);
+
+        client.send(Message::text(INIT)).await.unwrap();
+        let reply = client.next().await.unwrap().unwrap();
+
+        assert_eq!(reply, Message::text(INIT));
+        assert!(!server.await.unwrap().compressed);
+    }
+
+    #[tokio::test]
+    async fn does_not_offer_compression_if_disabled() {
+        let (server, url) = StandInServer::bind(true).await;
+        let server = tokio::spawn(server.echo_once());
+
+        let (mut client, compressing) = connect(&url, false).await;
+        assert!(!compressing);
+
+        client.send(Message::text(INIT)).await.unwrap();
+        let reply = client.next().await.unwrap().unwrap();
+
+        assert_eq!(reply, Message::text(INIT));
+        assert!(!server.await.unwrap().compressed);
+    }
+
+    #[test]
+    fn rejects_unsupported_window_bits() {
+        let head = b"HTTP/1.1 101 Switching Protocols\r\nSec-WebSocket-Extensions: permessage-deflate; client_max_window_bits=10\r\n\r\n";
+
+        assert!(negotiate(head).is_err());
+    }
+
+    #[test]
+    fn strips_extension_header_from_response() {
+        let head = b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nSec-WebSocket-Extensions: permessage-deflate; client_no_context_takeover\r\n\r\n";
+
+        let (mode, head) = negotiate(head).unwrap();
+
+        assert!(matches!(
+            mode,
+            Mode::Compressing {
+                client_no_context_takeover: true
+            }
+        ));
+        assert_eq!(
+            head,
+            b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\r\n"
+        );
+    }
+
+    async fn connect(
+        url: &str,
+        compression: bool,
+    ) -> (
+        tokio_tungstenite::WebSocketStream<PerMessageDeflate<TcpStream>>,
+        bool,
+    ) {
+        let mut request = url.into_client_request().unwrap();
+        if compression {
+            request
+                .headers_mut()
+                .insert("Sec-WebSocket-Extensions", OFFER.parse().unwrap());
+        }
+
+        let stream = TcpStream::connect(url.trim_start_matches("ws://"))
+            .await
+            .unwrap();
+        let (client, _) =
+            tokio_tungstenite::client_async(request, PerMessageDeflate::new(stream, compression))
+                .await
+                .unwrap();
+        let compressing = client.get_ref().is_compressing();
+
+        (client, compressing)
+    }
+
+    /// A minimal websocket server that stands in for the portal.
+    ///
+    /// It accepts one connection, reads one message and echoes it back, compressed if negotiated.
+    struct StandInServer {
+        listener: TcpListener,
+        accept_deflate: bool,
+    }
+
+    struct Received {
+        compressed: bool,
+        payload: Vec<u8>,
+    }
+
+    impl StandInServer {
+        async fn bind(accept_deflate: bool) -> (Self, String) {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("ws://{}", listener.local_addr().unwrap());
+
+            (
+                Self {
+                    listener,
+                    accept_deflate,
+                },
+                url,
+            )
+        }
+
+        async fn echo_once(self) -> Received {
+            let (mut stream, _) = self.listener.accept().await.unwrap();
+
+            let mut request = Vec::new();
+            while find_head_end(&request).is_none() {
+                request.push(stream.read_u8().await.unwrap());
+            }
+            let request = String::from_utf8(request).unwrap();
+
+            let header = |name: &str| {
+                request.lines().find_map(|line| {
+                    let (key, value) = line.split_once(':')?;
+                    key.eq_ignore_ascii_case(name)
+                        .then(|| value.trim().to_owned())
+                })
+            };
+
+            let key = header("sec-websocket-key").unwrap();
+            let deflate = self.accept_deflate
+                && header("sec-websocket-extensions").is_some_and(|e| e.contains(OFFER));
+
+            let mut response = format!(
+                "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n",
+                derive_accept_key(key.as_bytes())
+            );
+            if deflate {
+                response.push_str("Sec-WebSocket-Extensions: permessage-deflate\r\n");
+            }
+            response.push_str("\r\n");
+            stream.write_all(response.as_bytes()).await.unwrap();
+
+            let mut frame = Vec::new();
+            let header = loop {
+                frame.push(stream.read_u8().await.unwrap());
+
+                if let Some(header) = Frame::parse(&frame).unwrap() {
+                    break header;
+                }
+            };
+            assert!(header.mask.is_some(), "client frames must be masked");
+
+            let payload = header.payload(&frame);
+            let payload = if header.rsv1 {
+                inflate_raw(&payload)
+            } else {
+                payload
+            };
+
+            let mut reply = Vec::new();
+            if deflate {
+                let compressed = deflate_raw(&payload);
+                write_header(&mut reply, true, header.opcode, None, compressed.len());
+                reply.extend_from_slice(&compressed);
+            } else {
+                write_header(&mut reply, false, header.opcode, None, payload.len());
+                reply.extend_from_slice(&payload);
+            }
+            stream.write_all(&reply).await.unwrap();
+
+            Received {
+                compressed: header.rsv1,
+                payload,
+            }
+        }
+    }
+
+    fn deflate_raw(data: &[u8]) -> Vec<u8> {
+        PerMessageDeflate::new((), true).deflate(data).unwrap()
+    }
+
+    fn inflate_raw(data: &[u8]) -> Vec<u8> {
+        PerMessageDeflate::new((), true)
+            .inflate(data.to_vec())
+            .unwrap()
+    }
+}
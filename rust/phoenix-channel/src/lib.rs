@@ -1,8 +1,13 @@
 #![cfg_attr(test, allow(clippy::unwrap_used))]
 
+#[cfg(feature = "permessage-deflate")]
+mod deflate;
 mod get_user_agent;
 mod heartbeat;
 mod login_url;
+mod outbound;
+mod proxy;
+mod tls;
 
 use std::collections::{HashSet, VecDeque};
 use std::net::{IpAddr, SocketAddr, ToSocketAddrs as _};
@@ -17,14 +22,18 @@ use backoff::ExponentialBackoff;
 use base64::Engine;
 use firezone_logging::{err_with_sources, std_dyn_err, telemetry_span};
 use futures::future::BoxFuture;
+use futures::stream::FuturesUnordered;
 use futures::{FutureExt, SinkExt, StreamExt};
 use heartbeat::{Heartbeat, MissedLastHeartbeat};
+use outbound::OutboundQueue;
 use rand_core::{OsRng, RngCore};
+use rustls::pki_types::ServerName;
 use secrecy::{ExposeSecret, Secret};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use socket_factory::{SocketFactory, TcpSocket, TcpStream};
 use std::task::{Context, Poll, Waker};
-use tokio_tungstenite::client_async_tls;
+use tokio_rustls::TlsConnector;
+use tokio_tungstenite::client_async_with_config;
 use tokio_tungstenite::tungstenite::http::StatusCode;
 use tokio_tungstenite::{
     tungstenite::{handshake::client::Request, Message},
@@ -34,13 +43,21 @@ use url::Url;
 
 pub use get_user_agent::get_user_agent;
 pub use login_url::{DeviceInfo, LoginUrl, LoginUrlError, NoParams, PublicKeyParam};
+pub use outbound::{Delivery, DropReason};
+pub use proxy::{Proxy, ProxyError};
+pub use tls::{InvalidPin, SpkiPin, TlsConfig, TlsConfigError};
 
-const MAX_BUFFERED_MESSAGES: usize = 32; // Chosen pretty arbitrarily. If we are connected, these should never build up.
+/// How long we wait for a connection attempt before starting the next one in parallel.
+///
+/// See <https://www.rfc-editor.org/rfc/rfc8305#section-5>.
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+/// How long we wait for a single TCP connection to be established.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
 
 pub struct PhoenixChannel<TInitReq, TInboundMsg, TOutboundRes, TFinish> {
     state: State,
     waker: Option<Waker>,
-    pending_messages: VecDeque<String>,
+    outbound: OutboundQueue,
     next_request_id: Arc<AtomicU64>,
     socket_factory: Arc<dyn SocketFactory<TcpSocket>>,
 
@@ -56,18 +73,34 @@ pub struct PhoenixChannel<TInitReq, TInboundMsg, TOutboundRes, TFinish> {
     user_agent: String,
     reconnect_backoff: ExponentialBackoff,
 
+    /// The proxy to tunnel the connection through, if any.
+    proxy: Option<Arc<Proxy>>,
+    /// How to authenticate the portal, uses the default roots if not set.
+    tls: Option<TlsConfig>,
+    /// Whether we offer `permessage-deflate` to the portal.
+    compression: bool,
+    /// The addresses of the portal or, if we use one, the proxy.
     resolved_addresses: Vec<IpAddr>,
+    /// Whether the last successful connection was over IPv6, see [`happy_eyeballs_order`].
+    prefer_ipv6: bool,
 
     login: &'static str,
     init_req: TInitReq,
 }
 
+#[cfg(feature = "permessage-deflate")]
+type Transport = deflate::PerMessageDeflate<MaybeTlsStream<TcpStream>>;
+#[cfg(not(feature = "permessage-deflate"))]
+type Transport = MaybeTlsStream<TcpStream>;
+
+type Stream = WebSocketStream<Transport>;
+
+type ConnectResult = Result<(Stream, SocketAddr), InternalError>;
+
 enum State {
-    Connected(WebSocketStream<MaybeTlsStream<TcpStream>>),
-    Connecting(
-        BoxFuture<'static, Result<WebSocketStream<MaybeTlsStream<TcpStream>>, InternalError>>,
-    ),
-    Closing(WebSocketStream<MaybeTlsStream<TcpStream>>),
+    Connected(Stream),
+    Connecting(BoxFuture<'static, ConnectResult>),
+    Closing(Stream),
     Closed,
 }
 
@@ -76,57 +109,195 @@ impl State {
         url: Url,
         addresses: Vec<SocketAddr>,
         user_agent: String,
+        proxy: Option<Arc<Proxy>>,
+        tls: Option<TlsConfig>,
+        compression: bool,
         socket_factory: Arc<dyn SocketFactory<TcpSocket>>,
     ) -> Self {
         Self::Connecting(
-            create_and_connect_websocket(url, addresses, user_agent, socket_factory).boxed(),
+            create_and_connect_websocket(
+                url,
+                addresses,
+                user_agent,
+                proxy,
+                tls,
+                compression,
+                socket_factory,
+            )
+            .boxed(),
         )
     }
 }
 
+/// Connects to the first of `addresses` that accepts our websocket connection.
+///
+/// As per [RFC 8305](https://www.rfc-editor.org/rfc/rfc8305), we don't wait for an attempt to fail before we try the next address.
+/// Instead, a new attempt starts every [`CONNECTION_ATTEMPT_DELAY`] or as soon as the previous one failed.
+/// The first attempt to complete the websocket handshake wins, all others are dropped.
+///
+/// With a [`Proxy`], `addresses` are those of the proxy.
 async fn create_and_connect_websocket(
     url: Url,
     addresses: Vec<SocketAddr>,
     user_agent: String,
+    proxy: Option<Arc<Proxy>>,
+    tls: Option<TlsConfig>,
+    compression: bool,
     socket_factory: Arc<dyn SocketFactory<TcpSocket>>,
-) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>, InternalError> {
+) -> ConnectResult {
     tracing::debug!(host = url.host().map(tracing::field::display), %user_agent, "Connecting to portal");
 
-    let duration = Duration::from_secs(5);
-    let socket = tokio::time::timeout(duration, connect(addresses, &*socket_factory))
-        .await
-        .map_err(|_| InternalError::Timeout { duration })??;
-
-    let (stream, _) = client_async_tls(make_request(url, user_agent)?, socket)
-        .await
-        .map_err(InternalError::WebSocket)?;
-
-    Ok(stream)
-}
-
-async fn connect(
-    addresses: Vec<SocketAddr>,
-    socket_factory: &dyn SocketFactory<TcpSocket>,
-) -> Result<TcpStream, InternalError> {
+    let mut addresses = addresses.into_iter();
+    let mut attempts = FuturesUnordered::new();
+    let mut next_attempt = Box::pin(tokio::time::sleep(Duration::ZERO));
     let mut last_error = None;
-    for addr in addresses {
-        let Ok(socket) = socket_factory(&addr) else {
-            continue;
-        };
+    let mut start_next = true;
+
+    future::poll_fn(|cx| loop {
+        if start_next || next_attempt.as_mut().poll(cx).is_ready() {
+            start_next = false;
+
+            if let Some(addr) = addresses.next() {
+                tracing::debug!(%addr, "Attempting to connect");
+
+                attempts.push(connect_websocket(
+                    url.clone(),
+                    addr,
+                    user_agent.clone(),
+                    proxy.clone(),
+                    tls.clone(),
+                    compression,
+                    socket_factory.clone(),
+                ));
+                next_attempt
+                    .as_mut()
+                    .reset(tokio::time::Instant::now() + CONNECTION_ATTEMPT_DELAY);
+
+                continue;
+            }
+        }
+
+        match attempts.poll_next_unpin(cx) {
+            Poll::Ready(Some(Ok(connected))) => return Poll::Ready(Ok(connected)),
+            // The portal rejected us, trying other addresses won't help.
+            Poll::Ready(Some(Err(InternalError::WebSocket(
+                tokio_tungstenite::tungstenite::Error::Http(r),
+            )))) if r.status().is_client_error() => {
+                return Poll::Ready(Err(InternalError::WebSocket(
+                    tokio_tungstenite::tungstenite::Error::Http(r),
+                )))
+            }
+            // The same certificate is served on all addresses.
+            Poll::Ready(Some(Err(InternalError::PinMismatch))) => {
+                return Poll::Ready(Err(InternalError::PinMismatch))
+            }
+            Poll::Ready(Some(Err(e))) => {
+                tracing::debug!("Connection attempt failed: {}", err_with_sources(&e));
 
-        match socket.connect(addr).await {
-            Ok(socket) => return Ok(socket),
-            Err(e) => {
                 last_error = Some(e);
+                start_next = true; // Don't wait for the delay after a failure.
+                continue;
+            }
+            Poll::Ready(None) => {
+                return Poll::Ready(Err(last_error.take().unwrap_or(InternalError::InvalidUrl)))
             }
+            Poll::Pending => return Poll::Pending,
         }
+    })
+    .await
+}
+
+async fn connect_websocket(
+    url: Url,
+    addr: SocketAddr,
+    user_agent: String,
+    proxy: Option<Arc<Proxy>>,
+    tls: Option<TlsConfig>,
+    compression: bool,
+    socket_factory: Arc<dyn SocketFactory<TcpSocket>>,
+) -> ConnectResult {
+    let socket = socket_factory(&addr).map_err(InternalError::SocketConnection)?;
+    let mut socket = tokio::time::timeout(CONNECT_TIMEOUT, socket.connect(addr))
+        .await
+        .map_err(|_| InternalError::Timeout {
+            duration: CONNECT_TIMEOUT,
+        })?
+        .map_err(InternalError::SocketConnection)?;
+
+    if let Some(proxy) = proxy {
+        let host = url.host_str().ok_or(InternalError::InvalidUrl)?;
+        let port = url
+            .port_or_known_default()
+            .ok_or(InternalError::InvalidUrl)?;
+
+        tokio::time::timeout(CONNECT_TIMEOUT, proxy.handshake(&mut socket, host, port))
+            .await
+            .map_err(|_| InternalError::Timeout {
+                duration: CONNECT_TIMEOUT,
+            })?
+            .map_err(InternalError::Proxy)?;
     }
 
-    let Some(err) = last_error else {
-        return Err(InternalError::InvalidUrl);
+    let request = make_request(&url, user_agent, compression)?;
+
+    let stream = match url.scheme() {
+        "wss" => {
+            let server_name = match url.host().ok_or(InternalError::InvalidUrl)? {
+                url::Host::Domain(domain) => ServerName::try_from(domain.to_owned())
+                    .map_err(|_| InternalError::InvalidUrl)?,
+                url::Host::Ipv4(ip) => ServerName::from(IpAddr::from(ip)),
+                url::Host::Ipv6(ip) => ServerName::from(IpAddr::from(ip)),
+            };
+            let connector = TlsConnector::from(tls.unwrap_or_default().client_config);
+
+            let stream = connector.connect(server_name, socket).await.map_err(|e| {
+                let e = tokio_tungstenite::tungstenite::Error::Io(e);
+
+                if tls::is_pin_mismatch(&e) {
+                    return InternalError::PinMismatch;
+                }
+
+                InternalError::WebSocket(e)
+            })?;
+
+            MaybeTlsStream::Rustls(stream)
+        }
+        _ => MaybeTlsStream::Plain(socket),
     };
 
-    Err(InternalError::SocketConnection(err))
+    #[cfg(feature = "permessage-deflate")]
+    let stream = deflate::PerMessageDeflate::new(stream, compression);
+
+    let (stream, _) = client_async_with_config(request, stream, None)
+        .await
+        .map_err(InternalError::WebSocket)?;
+
+    #[cfg(feature = "permessage-deflate")]
+    tracing::debug!(
+        compression = stream.get_ref().is_compressing(),
+        "Websocket handshake complete"
+    );
+
+    Ok((stream, addr))
+}
+
+/// Interleaves IPv6 and IPv4 addresses, starting with the preferred family.
+///
+/// See <https://www.rfc-editor.org/rfc/rfc8305#section-4>.
+fn happy_eyeballs_order(addresses: &[IpAddr], prefer_ipv6: bool) -> Vec<IpAddr> {
+    let (mut preferred, mut other): (VecDeque<IpAddr>, VecDeque<IpAddr>) = addresses
+        .iter()
+        .copied()
+        .partition(|ip| ip.is_ipv6() == prefer_ipv6);
+
+    let mut ordered = Vec::with_capacity(addresses.len());
+
+    loop {
+        match (preferred.pop_front(), other.pop_front()) {
+            (None, None) => return ordered,
+            (first, second) => ordered.extend(first.into_iter().chain(second)),
+        }
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -139,6 +310,8 @@ pub enum Error {
     MaxRetriesReached,
     #[error("login failed: {0}")]
     LoginFailed(ErrorReply),
+    #[error("portal certificate does not match any pinned public key")]
+    PinMismatch,
 }
 
 impl Error {
@@ -148,6 +321,7 @@ impl Error {
             Error::TokenExpired => true,
             Error::MaxRetriesReached => false,
             Error::LoginFailed(_) => false,
+            Error::PinMismatch => false,
         }
     }
 }
@@ -162,6 +336,8 @@ enum InternalError {
     InvalidUrl,
     FailedToBuildRequest(tokio_tungstenite::tungstenite::http::Error),
     SocketConnection(std::io::Error),
+    Proxy(std::io::Error),
+    PinMismatch,
     Timeout { duration: Duration },
 }
 
@@ -185,6 +361,8 @@ impl fmt::Display for InternalError {
             InternalError::StreamClosed => write!(f, "websocket stream was closed"),
             InternalError::InvalidUrl => write!(f, "failed to resolve url"),
             InternalError::SocketConnection(_) => write!(f, "failed to connect socket"),
+            InternalError::Proxy(_) => write!(f, "failed to connect through proxy"),
+            InternalError::PinMismatch => write!(f, "certificate does not match pinned key"),
             InternalError::Timeout { duration, .. } => {
                 write!(f, "operation timed out after {duration:?}")
             }
@@ -202,11 +380,13 @@ impl std::error::Error for InternalError {
             InternalError::WebSocket(e) => Some(e),
             InternalError::Serde(e) => Some(e),
             InternalError::SocketConnection(e) => Some(e),
+            InternalError::Proxy(e) => Some(e),
             InternalError::FailedToBuildRequest(e) => Some(e),
             InternalError::MissedHeartbeat => None,
             InternalError::CloseMessage => None,
             InternalError::StreamClosed => None,
             InternalError::InvalidUrl => None,
+            InternalError::PinMismatch => None,
             InternalError::Timeout { .. } => None,
         }
     }
@@ -254,26 +434,42 @@ where
     ///
     /// The provided URL must contain a host.
     /// Additionally, you must already provide any query parameters required for authentication.
+    ///
+    /// We only connect through a proxy if one is passed, we never read one from the environment on our own.
+    /// Binaries that want to honour `HTTPS_PROXY` or `ALL_PROXY` can use [`Proxy::from_env`].
     pub fn disconnected(
         url: Secret<LoginUrl<TFinish>>,
         user_agent: String,
         login: &'static str,
         init_req: TInitReq,
         reconnect_backoff: ExponentialBackoff,
+        proxy: Option<Proxy>,
         socket_factory: Arc<dyn SocketFactory<TcpSocket>>,
     ) -> io::Result<Self> {
         let next_request_id = Arc::new(AtomicU64::new(0));
 
         let host_and_port = url.expose_secret().host_and_port();
 
-        let _span = telemetry_span!("resolve_portal_url", host = %host_and_port.0).entered();
-
-        // Statically resolve the host in the URL to a set of addresses.
+        // Statically resolve the host in the URL (or of the proxy) to a set of addresses.
         // We use these when connecting the socket to avoid a dependency on DNS resolution later on.
-        let resolved_addresses = host_and_port
-            .to_socket_addrs()?
-            .map(|addr| addr.ip())
-            .collect();
+        let resolved_addresses = match &proxy {
+            Some(proxy) => {
+                let _span =
+                    telemetry_span!("resolve_proxy_url", host = %proxy.host_and_port().0).entered();
+                tracing::info!(proxy = %proxy.host_and_port().0, "Connecting to portal through proxy");
+
+                proxy.resolve()?
+            }
+            None => {
+                let _span =
+                    telemetry_span!("resolve_portal_url", host = %host_and_port.0).entered();
+
+                host_and_port
+                    .to_socket_addrs()?
+                    .map(|addr| addr.ip())
+                    .collect()
+            }
+        };
 
         Ok(Self {
             reconnect_backoff,
@@ -282,7 +478,7 @@ where
             state: State::Closed,
             socket_factory,
             waker: None,
-            pending_messages: VecDeque::with_capacity(MAX_BUFFERED_MESSAGES),
+            outbound: OutboundQueue::new(),
             _phantom: PhantomData,
             heartbeat: Heartbeat::new(
                 heartbeat::INTERVAL,
@@ -293,32 +489,79 @@ where
             pending_join_requests: Default::default(),
             login,
             init_req,
+            proxy: proxy.map(Arc::new),
+            tls: None,
+            compression: false,
             resolved_addresses,
+            prefer_ipv6: true,
             last_url: None,
         })
     }
 
+    /// Authenticates the portal according to `tls`, `None` uses the default roots.
+    pub fn with_tls(mut self, tls: Option<TlsConfig>) -> Self {
+        self.tls = tls;
+        self
+    }
+
+    /// Whether to offer `permessage-deflate` compression to the portal, disabled by default.
+    ///
+    /// Our implementation sits underneath `tungstenite` and re-implements parts of the websocket framing, so it is experimental.
+    #[cfg(feature = "permessage-deflate")]
+    pub fn with_compression(mut self, enabled: bool) -> Self {
+        self.compression = enabled;
+        self
+    }
+
     /// Join the provided room.
     ///
     /// If successful, a [`Event::JoinedRoom`] event will be emitted.
     pub fn join(&mut self, topic: impl Into<String>, payload: impl Serialize) {
-        let (request_id, msg) = self.make_message(topic, EgressControlMessage::PhxJoin(payload));
-        self.pending_messages.push_front(msg); // Must send the join message before all others.
+        let topic = topic.into();
+        let (request_id, msg) =
+            self.make_message(topic.clone(), EgressControlMessage::PhxJoin(payload));
+
+        // Must send the join message before all others.
+        self.outbound.push_front(
+            topic.clone(),
+            None,
+            Delivery::ReplaceByKey(format!("phx_join/{topic}")),
+            msg,
+        );
 
         self.pending_join_requests.insert(request_id);
     }
 
     /// Send a message to a topic.
+    ///
+    /// The message is kept across reconnects until it is sent, see [`Delivery::MustDeliver`].
     pub fn send(&mut self, topic: impl Into<String>, message: impl Serialize) -> OutboundRequestId {
-        if self.pending_messages.len() > MAX_BUFFERED_MESSAGES {
-            self.pending_messages.clear();
+        self.send_with(topic, message, Delivery::MustDeliver)
+    }
 
-            tracing::warn!("Dropping pending messages to portal because we exceeded the maximum of {MAX_BUFFERED_MESSAGES}");
+    /// Send a message to a topic with the given [`Delivery`] class.
+    ///
+    /// If the message doesn't reach the portal, we emit [`Event::MessageDropped`].
+    pub fn send_with(
+        &mut self,
+        topic: impl Into<String>,
+        message: impl Serialize,
+        delivery: Delivery,
+    ) -> OutboundRequestId {
+        let topic = topic.into();
+        let (id, msg) = self.make_message(topic.clone(), message);
+        self.outbound
+            .push_back(topic, Some(id.copy()), delivery, msg);
+
+        if !matches!(self.state, State::Connected(_)) {
+            self.outbound.disconnected();
+
+            // Report the dropped message even if we are waiting to reconnect.
+            if let Some(waker) = self.waker.take() {
+                waker.wake();
+            }
         }
 
-        let (id, msg) = self.make_message(topic, message);
-        self.pending_messages.push_back(msg);
-
         id
     }
 
@@ -331,8 +574,9 @@ where
             return;
         }
 
-        // 1. Reset the backoff.
+        // 1. Reset the backoff and drop messages meant for the previous connection.
         self.reconnect_backoff.reset();
+        self.outbound.disconnected();
 
         // 2. Set state to `Connecting` without a timer.
         let user_agent = self.user_agent.clone();
@@ -340,6 +584,9 @@ where
             url.clone(),
             self.socket_addresses(),
             user_agent,
+            self.proxy.clone(),
+            self.tls.clone(),
+            self.compression,
             self.socket_factory.clone(),
         );
         self.last_url = Some(url);
@@ -350,7 +597,29 @@ where
         }
     }
 
+    /// Replaces the URL used to connect to the portal, e.g. to log in with a new token.
+    ///
+    /// Takes effect on the next reconnect, either through [`PhoenixChannel::connect`] or after a transient error.
+    /// The host must not change because we only resolve it once, in [`PhoenixChannel::disconnected`].
+    pub fn set_login_url(&mut self, url: Secret<LoginUrl<TFinish>>) {
+        debug_assert_eq!(
+            url.expose_secret().host_and_port(),
+            self.url_prototype.expose_secret().host_and_port()
+        );
+
+        if let Some(last_url) = self.last_url.as_ref() {
+            let new_url = url
+                .expose_secret()
+                .refinish(self.url_prototype.expose_secret(), last_url);
+            self.last_url = Some(new_url);
+        }
+
+        self.url_prototype = url;
+    }
+
     /// Initiate a graceful close of the connection.
+    ///
+    /// Messages that are already queued are still sent before we close the websocket.
     pub fn close(&mut self) -> Result<(), Connecting> {
         tracing::info!("Closing connection to portal");
 
@@ -370,34 +639,62 @@ where
         cx: &mut Context,
     ) -> Poll<Result<Event<TInboundMsg, TOutboundRes>, Error>> {
         loop {
+            if let Some(dropped) = self.outbound.poll_dropped() {
+                return Poll::Ready(Ok(Event::MessageDropped {
+                    topic: dropped.topic,
+                    req_id: dropped.req_id,
+                    reason: dropped.reason,
+                }));
+            }
+
             // First, check if we are connected.
             let stream = match &mut self.state {
                 State::Closed => return Poll::Ready(Ok(Event::Closed)),
-                State::Closing(stream) => match stream.poll_close_unpin(cx) {
-                    Poll::Ready(Ok(())) => {
-                        tracing::info!("Closed websocket connection to portal");
-
-                        self.state = State::Closed;
+                State::Closing(stream) => {
+                    if let Poll::Ready(Ok(())) = stream.poll_ready_unpin(cx) {
+                        if let Some(message) = self.outbound.pop_front() {
+                            if let Err(e) = stream.start_send_unpin(Message::Text(message.payload))
+                            {
+                                tracing::warn!(
+                                    error = std_dyn_err(&e),
+                                    "Failed to send message while closing websocket"
+                                );
+                            }
 
-                        return Poll::Ready(Ok(Event::Closed));
+                            continue;
+                        }
                     }
-                    Poll::Ready(Err(e)) => {
-                        tracing::warn!(error = std_dyn_err(&e), "Error while closing websocket");
 
-                        return Poll::Ready(Ok(Event::Closed));
+                    match stream.poll_close_unpin(cx) {
+                        Poll::Ready(Ok(())) => {
+                            tracing::info!("Closed websocket connection to portal");
+
+                            self.state = State::Closed;
+
+                            return Poll::Ready(Ok(Event::Closed));
+                        }
+                        Poll::Ready(Err(e)) => {
+                            tracing::warn!(
+                                error = std_dyn_err(&e),
+                                "Error while closing websocket"
+                            );
+
+                            return Poll::Ready(Ok(Event::Closed));
+                        }
+                        Poll::Pending => return Poll::Pending,
                     }
-                    Poll::Pending => return Poll::Pending,
-                },
+                }
                 State::Connected(stream) => stream,
                 State::Connecting(future) => match future.poll_unpin(cx) {
-                    Poll::Ready(Ok(stream)) => {
+                    Poll::Ready(Ok((stream, addr))) => {
                         self.reconnect_backoff.reset();
                         self.heartbeat.reset();
                         self.state = State::Connected(stream);
+                        self.prefer_ipv6 = addr.is_ipv6();
 
                         let (host, _) = self.url_prototype.expose_secret().host_and_port();
 
-                        tracing::info!(%host, "Connected to portal");
+                        tracing::info!(%host, %addr, "Connected to portal");
                         self.join(self.login, self.init_req.clone());
 
                         continue;
@@ -405,11 +702,21 @@ where
                     Poll::Ready(Err(InternalError::WebSocket(
                         tokio_tungstenite::tungstenite::Error::Http(r),
                     ))) if r.status().is_client_error() => {
+                        self.state = State::Closed; // Don't poll the completed future again, the user has to call `connect`.
+
                         return Poll::Ready(Err(Error::Client(r.status())));
                     }
+                    // Retrying won't help, somebody is intercepting our traffic or the portal's key changed.
+                    Poll::Ready(Err(InternalError::PinMismatch)) => {
+                        self.state = State::Closed;
+
+                        return Poll::Ready(Err(Error::PinMismatch));
+                    }
                     Poll::Ready(Err(e)) => {
                         let Some(backoff) = self.reconnect_backoff.next_backoff() else {
                             tracing::warn!("Reconnect backoff expired");
+                            self.state = State::Closed;
+
                             return Poll::Ready(Err(Error::MaxRetriesReached));
                         };
 
@@ -419,6 +726,9 @@ where
                             .expect("should have last URL if we receive connection error")
                             .clone();
                         let user_agent = self.user_agent.clone();
+                        let proxy = self.proxy.clone();
+                        let tls = self.tls.clone();
+                        let compression = self.compression;
                         let socket_factory = self.socket_factory.clone();
                         let socket_addresses = self.socket_addresses();
 
@@ -430,6 +740,9 @@ where
                                 secret_url,
                                 socket_addresses,
                                 user_agent,
+                                proxy,
+                                tls,
+                                compression,
                                 socket_factory,
                             )
                             .await
@@ -448,10 +761,10 @@ where
             // Priority 1: Keep local buffers small and send pending messages.
             match stream.poll_ready_unpin(cx) {
                 Poll::Ready(Ok(())) => {
-                    if let Some(message) = self.pending_messages.pop_front() {
-                        match stream.start_send_unpin(Message::Text(message.clone())) {
+                    if let Some(message) = self.outbound.pop_front() {
+                        match stream.start_send_unpin(Message::Text(message.payload.clone())) {
                             Ok(()) => {
-                                tracing::trace!(target: "wire::api::send", %message);
+                                tracing::trace!(target: "wire::api::send", message = %message.payload);
 
                                 match stream.poll_flush_unpin(cx) {
                                     Poll::Ready(Ok(())) => {
@@ -467,7 +780,7 @@ where
                                 }
                             }
                             Err(e) => {
-                                self.pending_messages.push_front(message);
+                                self.outbound.retry(message);
                                 self.reconnect_on_transient_error(InternalError::WebSocket(e));
                             }
                         }
@@ -594,11 +907,17 @@ where
             // Priority 3: Handle heartbeats.
             match self.heartbeat.poll(cx) {
                 Poll::Ready(Ok(id)) => {
-                    self.pending_messages.push_back(serialize_msg(
-                        "phoenix",
-                        EgressControlMessage::<()>::Heartbeat(Empty {}),
-                        id.copy(),
-                    ));
+                    // A heartbeat is meaningless on any other connection.
+                    self.outbound.push_back(
+                        "phoenix".to_owned(),
+                        None,
+                        Delivery::DropIfDisconnected,
+                        serialize_msg(
+                            "phoenix",
+                            EgressControlMessage::<()>::Heartbeat(Empty {}),
+                            id.copy(),
+                        ),
+                    );
 
                     return Poll::Ready(Ok(Event::HeartbeatSent));
                 }
@@ -617,7 +936,8 @@ where
     ///
     /// The [`PhoenixChannel::poll`] function will handle the reconnect if appropriate for the given error.
     fn reconnect_on_transient_error(&mut self, e: InternalError) {
-        self.state = State::Connecting(future::ready(Err(e)).boxed())
+        self.state = State::Connecting(future::ready(Err(e)).boxed());
+        self.outbound.disconnected();
     }
 
     fn make_message(
@@ -642,11 +962,14 @@ where
     }
 
     fn socket_addresses(&self) -> Vec<SocketAddr> {
-        let port = self.url_prototype.expose_secret().host_and_port().1;
+        let port = match &self.proxy {
+            Some(proxy) => proxy.host_and_port().1,
+            None => self.url_prototype.expose_secret().host_and_port().1,
+        };
 
-        self.resolved_addresses
-            .iter()
-            .map(|ip| SocketAddr::new(*ip, port))
+        happy_eyeballs_order(&self.resolved_addresses, self.prefer_ipv6)
+            .into_iter()
+            .map(|ip| SocketAddr::new(ip, port))
             .collect()
     }
 }
@@ -675,6 +998,12 @@ pub enum Event<TInboundMsg, TOutboundRes> {
     },
     /// The connection was closed successfully.
     Closed,
+    /// A message we were asked to send will never reach the portal.
+    MessageDropped {
+        topic: String,
+        req_id: OutboundRequestId,
+        reason: DropReason,
+    },
 }
 
 #[derive(Debug, PartialEq, Eq, Deserialize, Serialize)]
@@ -795,7 +1124,11 @@ impl<T, R> PhoenixMessage<T, R> {
 }
 
 // This is basically the same as tungstenite does but we add some new headers (namely user-agent)
-fn make_request(url: Url, user_agent: String) -> Result<Request, InternalError> {
+fn make_request(
+    url: &Url,
+    user_agent: String,
+    compression: bool,
+) -> Result<Request, InternalError> {
     let mut r = [0u8; 16];
     OsRng.fill_bytes(&mut r);
     let key = base64::engine::general_purpose::STANDARD.encode(r);
@@ -810,7 +1143,18 @@ fn make_request(url: Url, user_agent: String) -> Result<Request, InternalError>
         .header("Upgrade", "websocket")
         .header("Sec-WebSocket-Version", "13")
         .header("Sec-WebSocket-Key", key)
-        .header("User-Agent", user_agent)
+        .header("User-Agent", user_agent);
+
+    #[cfg(feature = "permessage-deflate")]
+    let request = if compression {
+        request.header("Sec-WebSocket-Extensions", deflate::OFFER)
+    } else {
+        request
+    };
+    #[cfg(not(feature = "permessage-deflate"))]
+    debug_assert!(!compression, "`permessage-deflate` feature is disabled");
+
+    let request = request
         .uri(url.to_string())
         .body(())
         .map_err(InternalError::FailedToBuildRequest)?;
@@ -848,6 +1192,24 @@ mod tests {
         Shout { hello: String },
     }
 
+    #[test]
+    fn happy_eyeballs_interleaves_address_families() {
+        let v4_1 = IpAddr::from([1, 1, 1, 1]);
+        let v4_2 = IpAddr::from([1, 0, 0, 1]);
+        let v6_1 = IpAddr::from([0x2606, 0x4700, 0, 0, 0, 0, 0, 0x1111]);
+        let v6_2 = IpAddr::from([0x2606, 0x4700, 0, 0, 0, 0, 0, 0x1001]);
+
+        assert_eq!(
+            happy_eyeballs_order(&[v4_1, v4_2, v6_1, v6_2], true),
+            vec![v6_1, v4_1, v6_2, v4_2]
+        );
+        assert_eq!(
+            happy_eyeballs_order(&[v6_1, v6_2, v4_1], false),
+            vec![v4_1, v6_1, v6_2]
+        );
+        assert_eq!(happy_eyeballs_order(&[v4_1, v4_2], true), vec![v4_1, v4_2]);
+    }
+
     #[test]
     fn can_deserialize_inbound_message() {
         let msg = r#"{
@@ -193,6 +193,19 @@ impl<TFinish> LoginUrl<TFinish> {
     pub fn host_and_port(&self) -> (&str, u16) {
         (&self.host, self.port)
     }
+
+    /// Applies the params that were used to finish `finished` from `previous` to this URL instead.
+    ///
+    /// [`LoginUrl::to_url`] appends the params, so they are the trailing query pairs of `finished`.
+    pub(crate) fn refinish(&self, previous: &Self, finished: &Url) -> Url {
+        let num_prototype_params = previous.url.query_pairs().count();
+
+        let mut url = self.url.clone();
+        url.query_pairs_mut()
+            .extend_pairs(finished.query_pairs().skip(num_prototype_params));
+
+        url
+    }
 }
 
 /// Parse the host from a URL, including port if present. e.g. `example.com:8080`.
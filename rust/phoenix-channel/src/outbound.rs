@@ -0,0 +1,351 @@
+//! The queue of messages waiting to be sent to the portal.
+//!
+//! Each message has a [`Delivery`] class that decides what happens to it while we are not connected.
+
+use crate::OutboundRequestId;
+use opentelemetry::metrics::{Counter, UpDownCounter};
+use opentelemetry::KeyValue;
+use std::collections::VecDeque;
+use std::fmt;
+
+/// Upper bound for the number of queued messages, so we don't grow without limits while we can't reach the portal.
+///
+/// Once full, we drop messages in the order [`Delivery::DropIfDisconnected`], [`Delivery::ReplaceByKey`] and [`Delivery::MustDeliver`], oldest first.
+const MAX_QUEUED_MESSAGES: usize = 1024;
+
+/// What should happen to a message if we can't send it right away.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Delivery {
+    /// The message is only useful on the current connection.
+    ///
+    /// It is dropped if we are not connected or lose the connection before sending it.
+    DropIfDisconnected,
+    /// Only the latest message with this key is useful.
+    ///
+    /// A new message with the same key replaces the queued one. Survives reconnects.
+    ReplaceByKey(String),
+    /// The message must reach the portal, even if we have to reconnect first.
+    MustDeliver,
+}
+
+/// Why a message didn't reach the portal, see [`Event::MessageDropped`](crate::Event::MessageDropped).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropReason {
+    /// The message was [`Delivery::DropIfDisconnected`] and we weren't connected.
+    Disconnected,
+    /// A newer message with the same [`Delivery::ReplaceByKey`] key was sent.
+    Replaced,
+    /// We queued too many messages without being able to send them.
+    QueueFull,
+}
+
+impl fmt::Display for DropReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DropReason::Disconnected => write!(f, "disconnected"),
+            DropReason::Replaced => write!(f, "replaced"),
+            DropReason::QueueFull => write!(f, "queue full"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Dropped {
+    pub(crate) topic: String,
+    pub(crate) req_id: OutboundRequestId,
+    pub(crate) reason: DropReason,
+}
+
+pub(crate) struct OutboundQueue {
+    messages: VecDeque<Queued>,
+    /// Messages we dropped but didn't report yet.
+    dropped: VecDeque<Dropped>,
+
+    queued_gauge: UpDownCounter<i64>,
+    dropped_counter: Counter<u64>,
+}
+
+pub(crate) struct Queued {
+    topic: String,
+    /// `None` for messages sent by [`PhoenixChannel`](crate::PhoenixChannel) itself, we don't report those when dropped.
+    req_id: Option<OutboundRequestId>,
+    delivery: Delivery,
+    pub(crate) payload: String,
+}
+
+impl OutboundQueue {
+    pub(crate) fn new() -> Self {
+        let meter = opentelemetry::global::meter("phoenix-channel");
+
+        Self {
+            messages: VecDeque::with_capacity(32),
+            dropped: VecDeque::default(),
+            queued_gauge: meter
+                .i64_up_down_counter("portal_queued_messages")
+                .with_description("The number of messages waiting to be sent to the portal")
+                .init(),
+            dropped_counter: meter
+                .u64_counter("portal_dropped_messages_total")
+                .with_description(
+                    "The number of messages we dropped without sending them to the portal",
+                )
+                .init(),
+        }
+    }
+
+    pub(crate) fn push_back(
+        &mut self,
+        topic: String,
+        req_id: Option<OutboundRequestId>,
+        delivery: Delivery,
+        payload: String,
+    ) {
+        self.push(
+            Queued {
+                topic,
+                req_id,
+                delivery,
+                payload,
+            },
+            false,
+        )
+    }
+
+    /// Queues a message that must be sent before all others, e.g. a `phx_join`.
+    pub(crate) fn push_front(
+        &mut self,
+        topic: String,
+        req_id: Option<OutboundRequestId>,
+        delivery: Delivery,
+        payload: String,
+    ) {
+        self.push(
+            Queued {
+                topic,
+                req_id,
+                delivery,
+                payload,
+            },
+            true,
+        )
+    }
+
+    /// Returns the next message to send.
+    pub(crate) fn pop_front(&mut self) -> Option<Queued> {
+        let message = self.messages.pop_front()?;
+        self.queued_gauge
+            .add(-1, &[delivery_attr(&message.delivery)]);
+
+        Some(message)
+    }
+
+    /// Puts a message we failed to send back to the front of the queue.
+    pub(crate) fn retry(&mut self, message: Queued) {
+        self.push(message, true)
+    }
+
+    /// Drops all [`Delivery::DropIfDisconnected`] messages, to be called when we lose the connection.
+    pub(crate) fn disconnected(&mut self) {
+        let (stale, keep) = std::mem::take(&mut self.messages)
+            .into_iter()
+            .partition::<VecDeque<_>, _>(|m| m.delivery == Delivery::DropIfDisconnected);
+
+        self.messages = keep;
+
+        for message in stale {
+            self.drop_message(message, DropReason::Disconnected);
+        }
+    }
+
+    pub(crate) fn poll_dropped(&mut self) -> Option<Dropped> {
+        self.dropped.pop_front()
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    fn push(&mut self, message: Queued, front: bool) {
+        if let Delivery::ReplaceByKey(key) = &message.delivery {
+            let existing = self
+                .messages
+                .iter()
+                .position(|m| matches!(&m.delivery, Delivery::ReplaceByKey(other) if other == key));
+
+            if let Some(replaced) = existing.and_then(|i| self.messages.remove(i)) {
+                self.drop_message(replaced, DropReason::Replaced);
+            }
+        }
+
+        if self.messages.len() >= MAX_QUEUED_MESSAGES {
+            self.evict_one();
+        }
+
+        self.queued_gauge
+            .add(1, &[delivery_attr(&message.delivery)]);
+
+        if front {
+            self.messages.push_front(message);
+        } else {
+            self.messages.push_back(message);
+        }
+    }
+
+    fn evict_one(&mut self) {
+        let position = self
+            .messages
+            .iter()
+            .position(|m| m.delivery == Delivery::DropIfDisconnected)
+            .or_else(|| {
+                self.messages
+                    .iter()
+                    .position(|m| matches!(m.delivery, Delivery::ReplaceByKey(_)))
+            })
+            .unwrap_or(0);
+
+        let Some(evicted) = self.messages.remove(position) else {
+            return;
+        };
+
+        tracing::warn!(topic = %evicted.topic, "Dropping message to portal because we exceeded the maximum of {MAX_QUEUED_MESSAGES} queued messages");
+
+        self.drop_message(evicted, DropReason::QueueFull);
+    }
+
+    fn drop_message(&mut self, message: Queued, reason: DropReason) {
+        self.queued_gauge
+            .add(-1, &[delivery_attr(&message.delivery)]);
+        self.dropped_counter
+            .add(1, &[KeyValue::new("reason", reason.to_string())]);
+
+        let Some(req_id) = message.req_id else {
+            return;
+        };
+
+        self.dropped.push_back(Dropped {
+            topic: message.topic,
+            req_id,
+            reason,
+        });
+    }
+}
+
+fn delivery_attr(delivery: &Delivery) -> KeyValue {
+    let class = match delivery {
+        Delivery::DropIfDisconnected => "drop_if_disconnected",
+        Delivery::ReplaceByKey(_) => "replace_by_key",
+        Delivery::MustDeliver => "must_deliver",
+    };
+
+    KeyValue::new("delivery", class)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_message_with_same_key() {
+        let mut queue = OutboundQueue::new();
+
+        queue.push_back(
+            "topic".to_owned(),
+            Some(OutboundRequestId::for_test(1)),
+            Delivery::ReplaceByKey("key".to_owned()),
+            "first".to_owned(),
+        );
+        queue.push_back(
+            "topic".to_owned(),
+            Some(OutboundRequestId::for_test(2)),
+            Delivery::ReplaceByKey("key".to_owned()),
+            "second".to_owned(),
+        );
+
+        assert_eq!(
+            queue.pop_front().map(|m| m.payload).as_deref(),
+            Some("second")
+        );
+        assert!(queue.pop_front().is_none());
+
+        let dropped = queue.poll_dropped().unwrap();
+        assert_eq!(dropped.req_id, OutboundRequestId::for_test(1));
+        assert_eq!(dropped.reason, DropReason::Replaced);
+    }
+
+    #[test]
+    fn keeps_must_deliver_messages_across_disconnects() {
+        let mut queue = OutboundQueue::new();
+
+        queue.push_back(
+            "topic".to_owned(),
+            Some(OutboundRequestId::for_test(1)),
+            Delivery::DropIfDisconnected,
+            "stale".to_owned(),
+        );
+        queue.push_back(
+            "topic".to_owned(),
+            Some(OutboundRequestId::for_test(2)),
+            Delivery::MustDeliver,
+            "important".to_owned(),
+        );
+        queue.disconnected();
+
+        assert_eq!(
+            queue.pop_front().map(|m| m.payload).as_deref(),
+            Some("important")
+        );
+
+        let dropped = queue.poll_dropped().unwrap();
+        assert_eq!(dropped.req_id, OutboundRequestId::for_test(1));
+        assert_eq!(dropped.reason, DropReason::Disconnected);
+    }
+
+    #[test]
+    fn evicts_least_important_message_when_full() {
+        let mut queue = OutboundQueue::new();
+
+        for i in 0..MAX_QUEUED_MESSAGES as u64 {
+            let delivery = if i == 10 {
+                Delivery::DropIfDisconnected
+            } else {
+                Delivery::MustDeliver
+            };
+
+            queue.push_back(
+                "topic".to_owned(),
+                Some(OutboundRequestId::for_test(i)),
+                delivery,
+                i.to_string(),
+            );
+        }
+        queue.push_back(
+            "topic".to_owned(),
+            Some(OutboundRequestId::for_test(9999)),
+            Delivery::MustDeliver,
+            "new".to_owned(),
+        );
+
+        assert_eq!(queue.len(), MAX_QUEUED_MESSAGES);
+
+        let dropped = queue.poll_dropped().unwrap();
+        assert_eq!(dropped.req_id, OutboundRequestId::for_test(10));
+        assert_eq!(dropped.reason, DropReason::QueueFull);
+    }
+
+    #[test]
+    fn does_not_report_internal_messages() {
+        let mut queue = OutboundQueue::new();
+
+        queue.push_back(
+            "phoenix".to_owned(),
+            None,
+            Delivery::DropIfDisconnected,
+            "heartbeat".to_owned(),
+        );
+        queue.disconnected();
+
+        assert!(queue.poll_dropped().is_none());
+        assert_eq!(queue.len(), 0);
+    }
+}
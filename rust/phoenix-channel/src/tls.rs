@@ -0,0 +1,362 @@
+//! Configurable trust for the portal's TLS certificate.
+//!
+//! By default, we trust the roots from `webpki-roots`.
+//! Self-hosted deployments can add their own CA and optionally distrust all others.
+//! On top of that, the portal's certificate chain can be pinned to a set of public keys.
+
+use base64::Engine as _;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{CertificateError, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use sha2::Digest as _;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// How we authenticate the portal.
+///
+/// Construct it with [`TlsConfig::new`] and hand it to [`PhoenixChannel::with_tls`](crate::PhoenixChannel::with_tls).
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    pub(crate) client_config: Arc<rustls::ClientConfig>,
+}
+
+impl TlsConfig {
+    /// Trusts the certificates in the PEM files `ca_files` in addition to (or, with `only_supplied_roots`, instead of) the built-in roots.
+    ///
+    /// If `pins` is not empty, the portal's certificate chain must additionally contain one of these public keys.
+    pub fn new(
+        ca_files: &[PathBuf],
+        only_supplied_roots: bool,
+        pins: Vec<SpkiPin>,
+    ) -> Result<Self, TlsConfigError> {
+        let mut roots = if only_supplied_roots {
+            RootCertStore::empty()
+        } else {
+            RootCertStore::from_iter(webpki_roots::TLS_SERVER_ROOTS.iter().cloned())
+        };
+
+        for path in ca_files {
+            for cert in read_pem_file(path)? {
+                roots
+                    .add(cert)
+                    .map_err(|e| TlsConfigError::InvalidCertificate(path.clone(), e))?;
+            }
+        }
+
+        if roots.is_empty() {
+            return Err(TlsConfigError::NoRoots);
+        }
+
+        let builder = rustls::ClientConfig::builder();
+
+        let client_config = if pins.is_empty() {
+            builder.with_root_certificates(roots).with_no_client_auth()
+        } else {
+            let inner = WebPkiServerVerifier::builder(Arc::new(roots))
+                .build()
+                .map_err(TlsConfigError::Verifier)?;
+
+            builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(PinningVerifier { inner, pins }))
+                .with_no_client_auth()
+        };
+
+        Ok(Self {
+            client_config: Arc::new(client_config),
+        })
+    }
+}
+
+impl Default for TlsConfig {
+    /// Trusts the built-in roots from `webpki-roots`.
+    fn default() -> Self {
+        let roots = RootCertStore::from_iter(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+
+        Self {
+            client_config: Arc::new(
+                rustls::ClientConfig::builder()
+                    .with_root_certificates(roots)
+                    .with_no_client_auth(),
+            ),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TlsConfigError {
+    #[error("failed to read certificates from `{}`", .0.display())]
+    ReadCaFile(PathBuf, #[source] std::io::Error),
+    #[error("`{}` does not contain any certificates", .0.display())]
+    NoCertificates(PathBuf),
+    #[error("`{}` contains an invalid certificate", .0.display())]
+    InvalidCertificate(PathBuf, #[source] rustls::Error),
+    #[error("no root certificates to trust")]
+    NoRoots,
+    #[error("failed to build certificate verifier")]
+    Verifier(#[source] rustls::client::VerifierBuilderError),
+}
+
+/// The SHA-256 hash of a DER-encoded `SubjectPublicKeyInfo`, as used by HPKP.
+///
+/// Parsed from its base64 encoding, optionally prefixed with `sha256/` or `sha256//` like curl's `--pinnedpubkey`.
+/// Compute it with `openssl x509 -pubkey -noout | openssl pkey -pubin -outform der | openssl dgst -sha256 -binary | base64`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct SpkiPin([u8; 32]);
+
+impl SpkiPin {
+    fn of(spki: &[u8]) -> Self {
+        Self(sha2::Sha256::digest(spki).into())
+    }
+}
+
+impl FromStr for SpkiPin {
+    type Err = InvalidPin;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let encoded = s
+            .trim()
+            .trim_start_matches("sha256/")
+            .trim_start_matches('/');
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(encoded)
+            .map_err(|_| InvalidPin)?;
+
+        Ok(Self(bytes.try_into().map_err(|_| InvalidPin)?))
+    }
+}
+
+impl fmt::Debug for SpkiPin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "sha256/{}",
+            base64::engine::general_purpose::STANDARD.encode(self.0)
+        )
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("expected the base64-encoded SHA-256 hash of a public key")]
+pub struct InvalidPin;
+
+/// The portal's certificate chain did not contain any of the pinned keys.
+#[derive(Debug, thiserror::Error)]
+#[error("certificate chain does not contain a pinned public key")]
+pub(crate) struct PinMismatch;
+
+/// Whether `e` is caused by our [`PinningVerifier`] rejecting the certificate.
+pub(crate) fn is_pin_mismatch(e: &tokio_tungstenite::tungstenite::Error) -> bool {
+    use tokio_tungstenite::tungstenite::{error::TlsError, Error};
+
+    let rustls_error = match e {
+        Error::Io(e) => e.get_ref().and_then(|e| e.downcast_ref::<rustls::Error>()),
+        Error::Tls(TlsError::Rustls(e)) => Some(e),
+        _ => None,
+    };
+
+    matches!(
+        rustls_error,
+        Some(rustls::Error::InvalidCertificate(CertificateError::Other(other)))
+            if other.0.downcast_ref::<PinMismatch>().is_some()
+    )
+}
+
+/// Verifies the certificate as usual and then checks that one of the pinned keys is part of the chain.
+#[derive(Debug)]
+struct PinningVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    pins: Vec<SpkiPin>,
+}
+
+impl ServerCertVerifier for PinningVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+
+        let is_pinned = std::iter::once(end_entity)
+            .chain(intermediates)
+            .filter_map(|cert| subject_public_key_info(cert.as_ref()))
+            .any(|spki| self.pins.contains(&SpkiPin::of(spki)));
+
+        if !is_pinned {
+            return Err(rustls::Error::InvalidCertificate(CertificateError::Other(
+                rustls::OtherError(Arc::new(PinMismatch)),
+            )));
+        }
+
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+fn read_pem_file(path: &Path) -> Result<Vec<CertificateDer<'static>>, TlsConfigError> {
+    let file =
+        std::fs::File::open(path).map_err(|e| TlsConfigError::ReadCaFile(path.to_owned(), e))?;
+
+    let certs = rustls_pemfile::certs(&mut std::io::BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| TlsConfigError::ReadCaFile(path.to_owned(), e))?;
+
+    if certs.is_empty() {
+        return Err(TlsConfigError::NoCertificates(path.to_owned()));
+    }
+
+    Ok(certs)
+}
+
+/// Extracts the DER-encoded `SubjectPublicKeyInfo` (including its header) from an X.509 certificate.
+///
+/// See <https://www.rfc-editor.org/rfc/rfc5280#section-4.1>.
+fn subject_public_key_info(cert: &[u8]) -> Option<&[u8]> {
+    const SEQUENCE: u8 = 0x30;
+    const EXPLICIT_VERSION: u8 = 0xA0;
+
+    let (tag, certificate, _) = der_element(cert)?;
+    if tag != SEQUENCE {
+        return None;
+    }
+
+    let (tag, tbs_certificate, _) = der_element(certificate)?;
+    if tag != SEQUENCE {
+        return None;
+    }
+
+    let mut rest = tbs_certificate;
+    if *rest.first()? == EXPLICIT_VERSION {
+        rest = der_element(rest)?.2;
+    }
+
+    // serialNumber, signature, issuer, validity, subject
+    for _ in 0..5 {
+        rest = der_element(rest)?.2;
+    }
+
+    let (tag, _, remaining) = der_element(rest)?;
+    if tag != SEQUENCE {
+        return None;
+    }
+
+    Some(&rest[..rest.len() - remaining.len()])
+}
+
+/// Splits a DER element into its tag, contents and the remaining input.
+fn der_element(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = input.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+
+    let (len, rest) = if first < 0x80 {
+        (first as usize, rest)
+    } else {
+        let num_bytes = (first & 0x7F) as usize;
+        if num_bytes == 0 || num_bytes > 4 || rest.len() < num_bytes {
+            return None;
+        }
+        let (len, rest) = rest.split_at(num_bytes);
+
+        (
+            len.iter().fold(0usize, |acc, b| (acc << 8) | *b as usize),
+            rest,
+        )
+    };
+
+    if rest.len() < len {
+        return None;
+    }
+    let (contents, rest) = rest.split_at(len);
+
+    Some((tag, contents, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_pin_with_and_without_prefix() {
+        let hash = [7u8; 32];
+        let encoded = base64::engine::general_purpose::STANDARD.encode(hash);
+
+        for input in [
+            encoded.clone(),
+            format!("sha256/{encoded}"),
+            format!("sha256//{encoded}"),
+        ] {
+            assert_eq!(input.parse::<SpkiPin>().unwrap(), SpkiPin(hash));
+        }
+    }
+
+    #[test]
+    fn rejects_pin_of_wrong_length() {
+        let encoded = base64::engine::general_purpose::STANDARD.encode([7u8; 20]);
+
+        assert!(encoded.parse::<SpkiPin>().is_err());
+        assert!("not base64!".parse::<SpkiPin>().is_err());
+    }
+
+    #[test]
+    fn extracts_subject_public_key_info() {
+        let spki = [0x30, 0x03, 0x02, 0x01, 0x2A];
+        let mut tbs_certificate = vec![
+            0xA0, 0x03, 0x02, 0x01, 0x02, // version
+            0x02, 0x01, 0x01, // serialNumber
+            0x30, 0x00, // signature
+            0x30, 0x00, // issuer
+            0x30, 0x00, // validity
+            0x30, 0x00, // subject
+        ];
+        tbs_certificate.extend_from_slice(&spki);
+        tbs_certificate.extend_from_slice(&[0xA3, 0x00]); // extensions
+
+        let mut certificate = vec![0x30, tbs_certificate.len() as u8];
+        certificate.extend_from_slice(&tbs_certificate);
+        certificate.extend_from_slice(&[0x30, 0x00, 0x03, 0x01, 0x00]); // signatureAlgorithm, signatureValue
+
+        let mut cert = vec![0x30, 0x81, certificate.len() as u8];
+        cert.extend_from_slice(&certificate);
+
+        assert_eq!(subject_public_key_info(&cert), Some(spki.as_slice()));
+    }
+
+    #[test]
+    fn rejects_truncated_certificate() {
+        assert_eq!(subject_public_key_info(&[0x30, 0x82, 0x01]), None);
+        assert_eq!(subject_public_key_info(&[0x30, 0x05, 0x30, 0x03]), None);
+    }
+}
@@ -5,15 +5,23 @@ mod server;
 mod sleep;
 
 pub mod auth;
+pub mod messages;
 #[cfg(feature = "proptest")]
 #[allow(clippy::unwrap_used)]
 pub mod proptest;
 pub mod sockets;
+pub mod workers;
+
+/// Internals exposed for the fuzz targets in `rust/fuzz`.
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing {
+    pub use crate::server::{DecodeError, Decoder};
+}
 
 pub use net_ext::IpAddrExt;
 pub use server::{
-    Allocate, AllocationPort, Attribute, Binding, ChannelBind, ChannelData, ClientMessage, Command,
-    CreatePermission, Refresh, Server,
+    Allocate, AllocationPort, AllocationUsage, Attribute, Binding, ChannelBind, ChannelData,
+    ClientMessage, Command, CreatePermission, Refresh, Routes, Server, Traffic, UsageReport,
 };
 pub use sleep::Sleep;
 pub use stun_codec::rfc8656::attributes::AddressFamily;
@@ -0,0 +1,38 @@
+//! Messages exchanged with the portal
+
+use crate::UsageReport;
+use std::net::SocketAddr;
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "snake_case", tag = "event", content = "payload")]
+pub enum IngressMessage {
+    Init(Init),
+    Drain(Drain),
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct Init {}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct Drain {
+    /// Another relay that clients should use instead of us.
+    #[serde(default)]
+    pub alternate_server: Option<SocketAddr>,
+}
+
+#[derive(serde::Serialize, PartialEq, Debug, Clone)]
+pub struct JoinMessage {
+    pub stamp_secret: String,
+}
+
+#[derive(serde::Serialize, Debug)]
+#[serde(rename_all = "snake_case", tag = "event", content = "payload")]
+pub enum EgressMessage {
+    Usage(UsageReport),
+    DrainStatus(DrainStatus),
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct DrainStatus {
+    pub remaining_allocations: usize,
+}
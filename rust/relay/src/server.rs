@@ -1,12 +1,18 @@
 mod channel_data;
 mod client_message;
+mod routes;
+mod usage;
 
 pub use crate::server::channel_data::ChannelData;
 pub use crate::server::client_message::{
     Allocate, Binding, ChannelBind, ClientMessage, CreatePermission, Refresh,
 };
+#[cfg(feature = "fuzzing")]
+pub use crate::server::client_message::{Decoder, Error as DecodeError};
+pub use crate::server::routes::Routes;
+pub use crate::server::usage::{AllocationUsage, Traffic, UsageReport};
 
-use crate::auth::{MessageIntegrityExt, Nonces, FIREZONE};
+use crate::auth::{split_username, MessageIntegrityExt, Nonces, FIREZONE};
 use crate::net_ext::IpAddrExt;
 use crate::{ClientSocket, IpStack, PeerSocket};
 use anyhow::Result;
@@ -25,9 +31,10 @@ use std::net::{IpAddr, SocketAddr};
 use std::ops::RangeInclusive;
 use std::time::{Duration, Instant, SystemTime};
 use stun_codec::rfc5389::attributes::{
-    ErrorCode, MessageIntegrity, Nonce, Realm, Software, Username, XorMappedAddress,
+    AlternateServer, ErrorCode, MessageIntegrity, Nonce, Realm, Software, Username,
+    XorMappedAddress,
 };
-use stun_codec::rfc5389::errors::{BadRequest, StaleNonce, Unauthorized};
+use stun_codec::rfc5389::errors::{BadRequest, StaleNonce, TryAlternate, Unauthorized};
 use stun_codec::rfc5389::methods::BINDING;
 use stun_codec::rfc5766::attributes::{
     ChannelNumber, Lifetime, RequestedTransport, XorPeerAddress, XorRelayAddress,
@@ -72,9 +79,17 @@ pub struct Server<R> {
     channels_by_client_and_number: BTreeMap<(ClientSocket, ChannelNumber), Channel>,
     /// Channel numbers are unique between clients and peers, thus indexed by both.
     channel_numbers_by_client_and_peer: HashMap<(ClientSocket, PeerSocket), ChannelNumber>,
+    /// Incremented whenever the result of [`Server::routes`] changes.
+    routes_version: u64,
 
     pending_commands: VecDeque<Command>,
 
+    /// Set once we are draining, see [`Server::drain`].
+    drain: Option<Drain>,
+
+    /// Traffic of allocations that were deleted since the last [`UsageReport`].
+    unreported_traffic_by_username: HashMap<String, Traffic>,
+
     rng: R,
 
     auth_secret: SecretString,
@@ -112,6 +127,12 @@ pub enum Command {
     },
 }
 
+#[derive(Debug, Clone, Copy)]
+struct Drain {
+    deadline: Instant,
+    alternate_server: Option<SocketAddr>,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
 pub struct AllocationPort(u16);
 
@@ -190,7 +211,10 @@ where
             ports,
             channels_by_client_and_number: Default::default(),
             channel_numbers_by_client_and_peer: Default::default(),
+            routes_version: 0,
             pending_commands: Default::default(),
+            drain: None,
+            unreported_traffic_by_username: Default::default(),
             auth_secret: SecretString::from(hex::encode(rng.gen::<[u8; 32]>())),
             rng,
             nonces: Default::default(),
@@ -237,6 +261,105 @@ where
         self.allocations.len()
     }
 
+    /// Stops granting new allocations and frees the remaining ones at `deadline`.
+    ///
+    /// Existing allocations keep relaying but can only be refreshed up until `deadline`.
+    /// New `ALLOCATE` requests are answered with 300 (Try Alternate) if we know an `alternate_server`, otherwise with 508 (Insufficient Capacity).
+    pub fn drain(&mut self, deadline: Instant, alternate_server: Option<SocketAddr>) {
+        tracing::info!(target: "relay", num_allocations = %self.num_allocations(), ?alternate_server, "Draining allocations");
+
+        self.drain = Some(Drain {
+            deadline,
+            alternate_server,
+        });
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.drain.is_some()
+    }
+
+    pub fn port_range(&self) -> RangeInclusive<u16> {
+        self.ports.clone()
+    }
+
+    /// Allocates ports for new allocations from `ports` from now on.
+    ///
+    /// Existing allocations keep their port, even if it is outside of the new range, until they expire.
+    pub fn set_port_range(&mut self, ports: RangeInclusive<u16>) {
+        if ports == self.ports {
+            return;
+        }
+
+        tracing::info!(target: "relay", lowest_port = %ports.start(), highest_port = %ports.end(), "Changed allocation port range");
+
+        self.ports = ports;
+    }
+
+    /// Reports the usage of all active allocations and the traffic by username since the last report.
+    pub fn take_usage_report(&mut self, now: Instant) -> UsageReport {
+        let mut usernames = BTreeMap::from_iter(self.unreported_traffic_by_username.drain());
+        let mut allocations = Vec::with_capacity(self.allocations.len());
+
+        for allocation in self.allocations.values_mut() {
+            *usernames.entry(allocation.username.clone()).or_default() +=
+                allocation.traffic.since(&allocation.reported_traffic);
+            allocation.reported_traffic = allocation.traffic;
+
+            let num_channels = self
+                .channels_by_client_and_number
+                .values()
+                .filter(|c| c.allocation == allocation.port && c.bound)
+                .count();
+
+            allocations.push(AllocationUsage {
+                port: allocation.port.value(),
+                username: allocation.username.clone(),
+                traffic: allocation.traffic,
+                num_channels,
+                lifetime_secs: now.duration_since(allocation.created_at).as_secs(),
+            });
+        }
+
+        usernames.retain(|_, traffic| !traffic.is_empty());
+
+        UsageReport {
+            allocations,
+            usernames,
+        }
+    }
+
+    /// A snapshot of all channels that we currently relay data for.
+    ///
+    /// Allows relaying [`ChannelData`] on other threads, see [`Routes`].
+    pub fn routes(&self) -> Routes {
+        Routes::new(
+            self.channels_by_client_and_number
+                .iter()
+                .filter(|(_, c)| c.bound)
+                .map(|((client, number), c)| ((*client, *number), (c.allocation, c.peer_address))),
+            self.channel_and_client_by_port_and_peer.clone(),
+        )
+    }
+
+    /// Changes whenever a new snapshot of [`Server::routes`] is needed.
+    pub fn routes_version(&self) -> u64 {
+        self.routes_version
+    }
+
+    /// Accounts for traffic that was relayed outside of the [`Server`], e.g. on another thread using [`Routes`].
+    ///
+    /// Traffic of allocations that no longer exist is only counted towards the total.
+    pub fn record_relayed_traffic(&mut self, client: ClientSocket, traffic: Traffic) {
+        let num_bytes = traffic.bytes_from_client + traffic.bytes_to_client;
+
+        self.data_relayed_counter.add(num_bytes, &[]);
+        self.data_relayed += num_bytes;
+
+        if let Some(allocation) = self.allocations.get_mut(&client) {
+            allocation.traffic += traffic;
+        }
+    }
+
     pub fn num_active_channels(&self) -> usize {
         self.channels_by_client_and_number
             .iter()
@@ -346,15 +469,19 @@ where
         self.data_relayed_counter.add(msg.len() as u64, &[]);
         self.data_relayed += msg.len() as u64;
 
+        if let Some(allocation) = self.allocations.get_mut(client) {
+            allocation.traffic.record_to_client(msg.len());
+        }
+
         tracing::trace!(target: "wire", num_bytes = %msg.len());
 
         Some((*client, *channel_number))
     }
 
     /// An allocation failed.
-    #[tracing::instrument(level = "debug", skip(self), fields(%allocation))]
-    pub fn handle_allocation_failed(&mut self, allocation: AllocationPort) {
-        self.delete_allocation(allocation)
+    #[tracing::instrument(level = "debug", skip(self, now), fields(%allocation))]
+    pub fn handle_allocation_failed(&mut self, allocation: AllocationPort, now: Instant) {
+        self.delete_allocation(allocation, now)
     }
 
     /// Return the next command to be executed.
@@ -372,21 +499,28 @@ where
             }
         });
         let allocation_expiries = self.allocations.values().map(|a| a.expires_at);
+        let drain_deadline = self
+            .drain
+            .filter(|_| !self.allocations.is_empty())
+            .map(|d| d.deadline);
 
         channel_expiries
             .chain(allocation_expiries)
+            .chain(drain_deadline)
             .fold(None, |current, next| earliest(current, Some(next)))
     }
 
     pub fn handle_timeout(&mut self, now: Instant) {
+        let drain_deadline_passed = self.drain.is_some_and(|d| now >= d.deadline);
+
         let expired_allocations = self
             .allocations
             .values()
-            .filter_map(|a| a.is_expired(now).then_some(a.port))
+            .filter_map(|a| (a.is_expired(now) || drain_deadline_passed).then_some(a.port))
             .collect::<Vec<_>>();
 
         for id in expired_allocations {
-            self.delete_allocation(id);
+            self.delete_allocation(id, now);
         }
 
         for ((client, number), channel) in self
@@ -397,6 +531,7 @@ where
             tracing::info!(target: "relay", channel = %number.value(), %client, peer = %channel.peer_address, allocation = %channel.allocation, "Channel is now expired");
 
             channel.bound = false;
+            self.routes_version += 1;
             if let Some((cs, n)) = self
                 .channel_and_client_by_port_and_peer
                 .remove(&(channel.allocation, channel.peer_address))
@@ -454,8 +589,24 @@ where
             ));
         }
 
+        if let Some(drain) = self.drain {
+            let Some(alternate_server) = drain.alternate_server else {
+                return Err(self.make_error_response(
+                    InsufficientCapacity,
+                    &request,
+                    ResponseErrorLevel::Debug,
+                ));
+            };
+
+            let mut message =
+                self.make_error_response(TryAlternate, &request, ResponseErrorLevel::Debug);
+            message.add_attribute(AlternateServer::new(alternate_server));
+
+            return Err(message);
+        }
+
         let max_available_ports = self.max_available_ports() as usize;
-        if self.clients_by_allocation.len() == max_available_ports {
+        if self.num_ports_in_use() >= max_available_ports {
             tracing::warn!(target: "relay", %max_available_ports, "No more ports available");
 
             return Err(self.make_error_response(
@@ -483,11 +634,21 @@ where
         // TODO: Do we need to handle EVEN/ODD-PORT?
         let effective_lifetime = request.effective_lifetime();
 
+        // `verify_auth` already checked that the username is present and valid.
+        let username = request
+            .username()
+            .map(|u| match split_username(u.name()) {
+                Ok((_, salt)) => salt.to_owned(),
+                Err(_) => u.name().to_owned(),
+            })
+            .unwrap_or_default();
+
         let allocation = self.create_new_allocation(
             now,
             &effective_lifetime,
             first_relay_address,
             maybe_second_relay_addr,
+            username,
         );
 
         let mut message = Message::new(
@@ -578,7 +739,7 @@ where
         if effective_lifetime.lifetime().is_zero() {
             let port = allocation.port;
 
-            self.delete_allocation(port);
+            self.delete_allocation(port, now);
             self.send_message(
                 refresh_success_response(effective_lifetime, request.transaction_id()),
                 sender,
@@ -587,6 +748,17 @@ where
             return Ok(());
         }
 
+        // Whilst draining, don't let allocations outlive the deadline.
+        let effective_lifetime = match self.drain {
+            Some(Drain { deadline, .. }) => {
+                let remaining = deadline.saturating_duration_since(now);
+
+                Lifetime::new(effective_lifetime.lifetime().min(remaining))
+                    .expect("lifetime is at most the requested lifetime which is valid")
+            }
+            None => effective_lifetime,
+        };
+
         allocation.expires_at = now + effective_lifetime.lifetime();
 
         tracing::info!(target: "relay", "Refreshed allocation");
@@ -671,13 +843,18 @@ where
 
             // Binding requests for existing channels act as a refresh for the binding.
 
+            let was_bound = channel.bound;
             channel.refresh(now);
 
-            // Update the fast-path map in case the binding expired in the cooldown period and got removed.
-            self.channel_and_client_by_port_and_peer.insert(
-                (channel.allocation, channel.peer_address),
-                (sender, requested_channel),
-            );
+            // Restore the fast-path map in case the binding expired in the cooldown period and got removed.
+            // Refreshing a bound channel doesn't change any routes, so we don't bump the version to spare the workers a new snapshot.
+            if !was_bound {
+                self.channel_and_client_by_port_and_peer.insert(
+                    (channel.allocation, channel.peer_address),
+                    (sender, requested_channel),
+                );
+                self.routes_version += 1;
+            }
 
             tracing::info!(target: "relay", "Refreshed channel binding");
 
@@ -762,6 +939,10 @@ where
         self.data_relayed_counter.add(data.len() as u64, &[]);
         self.data_relayed += data.len() as u64;
 
+        if let Some(allocation) = self.allocations.get_mut(&sender) {
+            allocation.traffic.record_from_client(data.len());
+        }
+
         Some((channel.allocation, channel.peer_address))
     }
 
@@ -807,9 +988,10 @@ where
         lifetime: &Lifetime,
         first_relay_addr: IpAddr,
         second_relay_addr: Option<IpAddr>,
+        username: String,
     ) -> Allocation {
         assert!(
-            self.clients_by_allocation.len() < self.max_available_ports() as usize,
+            self.num_ports_in_use() < self.max_available_ports() as usize,
             "No more ports available; this would loop forever"
         );
 
@@ -824,8 +1006,12 @@ where
         Allocation {
             port,
             expires_at: now + lifetime.lifetime(),
+            created_at: now,
             first_relay_addr,
             second_relay_addr,
+            username,
+            traffic: Traffic::default(),
+            reported_traffic: Traffic::default(),
         }
     }
 
@@ -833,6 +1019,14 @@ where
         self.ports.clone().count() as u16
     }
 
+    /// The number of allocations whose port is within our current port range.
+    fn num_ports_in_use(&self) -> usize {
+        self.clients_by_allocation
+            .keys()
+            .filter(|port| self.ports.contains(&port.value()))
+            .count()
+    }
+
     fn create_channel_binding(
         &mut self,
         client: ClientSocket,
@@ -865,6 +1059,8 @@ where
             .insert((id, peer), (client, requested_channel));
 
         debug_assert!(existing.is_none());
+
+        self.routes_version += 1;
     }
 
     fn send_message(&mut self, message: Message<Attribute>, recipient: ClientSocket) {
@@ -913,7 +1109,12 @@ where
         self.responses_counter.add(1, &attributes);
     }
 
-    fn delete_allocation(&mut self, port: AllocationPort) {
+    /// Deletes the allocation on the given port, e.g. because we failed to bind its socket.
+    pub fn free_allocation(&mut self, port: AllocationPort, now: Instant) {
+        self.delete_allocation(port, now);
+    }
+
+    fn delete_allocation(&mut self, port: AllocationPort, now: Instant) {
         let Some(client) = self.clients_by_allocation.remove(&port) else {
             tracing::debug!(target: "relay", "Unable to delete unknown allocation");
 
@@ -925,6 +1126,7 @@ where
             .expect("internal state mismatch");
 
         let port = allocation.port;
+        let mut num_channels = 0;
 
         self.channels_by_client_and_number
             .retain(|(cs, number), c| {
@@ -932,6 +1134,8 @@ where
                     return true;
                 }
 
+                num_channels += 1;
+
                 debug_assert_eq!(cs, &client, "internal state should be consistent");
 
                 let peer = c.peer_address;
@@ -955,6 +1159,9 @@ where
 
                 false
             });
+        if num_channels > 0 {
+            self.routes_version += 1;
+        }
 
         self.allocations_up_down_counter.add(-1, &[]);
         self.pending_commands.push_back(Command::FreeAllocation {
@@ -968,7 +1175,33 @@ where
             })
         }
 
-        tracing::info!(target: "relay", %port, "Deleted allocation");
+        let unreported_traffic = allocation.traffic.since(&allocation.reported_traffic);
+        if !unreported_traffic.is_empty() {
+            *self
+                .unreported_traffic_by_username
+                .entry(allocation.username.clone())
+                .or_default() += unreported_traffic;
+        }
+
+        let Traffic {
+            bytes_from_client,
+            packets_from_client,
+            bytes_to_client,
+            packets_to_client,
+        } = allocation.traffic;
+
+        tracing::info!(
+            target: "relay",
+            %port,
+            username = %allocation.username,
+            %bytes_from_client,
+            %packets_from_client,
+            %bytes_to_client,
+            %packets_to_client,
+            %num_channels,
+            lifetime = ?now.duration_since(allocation.created_at),
+            "Deleted allocation"
+        );
     }
 
     fn delete_channel_binding(&mut self, client: ClientSocket, chan: ChannelNumber) {
@@ -986,6 +1219,7 @@ where
             debug_assert_eq!(_peer_channel, chan, "internal state should be consistent");
         }
 
+        // Only unbound channels get deleted, so this doesn't change any routes.
         self.channels_by_client_and_number.remove(&(client, chan));
 
         tracing::info!(target: "relay", channel = %chan.value(), %client, %peer, %allocation, "Channel binding is now deleted (and can be rebound)");
@@ -1069,9 +1303,16 @@ struct Allocation {
     /// Data arriving on this port will be forwarded to the client iff there is an active data channel.
     port: AllocationPort,
     expires_at: Instant,
+    created_at: Instant,
 
     first_relay_addr: IpAddr,
     second_relay_addr: Option<IpAddr>,
+
+    /// The salt of the username the allocation was created with.
+    username: String,
+    traffic: Traffic,
+    /// The value of `traffic` at the time of the last [`UsageReport`].
+    reported_traffic: Traffic,
 }
 
 #[derive(Debug, Clone)]
@@ -1282,7 +1523,8 @@ stun_codec::define_attribute_enums!(
         Username,
         RequestedAddressFamily,
         AdditionalAddressFamily,
-        Software
+        Software,
+        AlternateServer
     ]
 );
 
@@ -1298,6 +1540,7 @@ fn earliest(left: Option<Instant>, right: Option<Instant>) -> Option<Instant> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rand::{rngs::StdRng, SeedableRng as _};
     use std::net::{Ipv4Addr, Ipv6Addr};
 
     // Tests for requirements listed in https://www.rfc-editor.org/rfc/rfc8656#name-receiving-an-allocate-reque.
@@ -1355,4 +1598,24 @@ mod tests {
 
         assert_eq!(error_code.code(), BadRequest::CODEPOINT)
     }
+
+    #[test]
+    fn allocations_outside_of_new_port_range_dont_take_up_capacity() {
+        let mut server = Server::new(
+            Ipv4Addr::LOCALHOST,
+            StdRng::seed_from_u64(0),
+            3478,
+            49152..=49152,
+        );
+        server.clients_by_allocation.insert(
+            AllocationPort::new(49152),
+            ClientSocket::new(SocketAddr::from((Ipv4Addr::LOCALHOST, 50000))),
+        );
+        assert_eq!(server.num_ports_in_use(), 1);
+
+        server.set_port_range(50000..=50001);
+
+        assert_eq!(server.num_ports_in_use(), 0);
+        assert_eq!(server.max_available_ports(), 2);
+    }
 }
@@ -0,0 +1,44 @@
+use crate::server::AllocationPort;
+use crate::{ClientSocket, PeerSocket};
+use std::collections::HashMap;
+use stun_codec::rfc5766::attributes::ChannelNumber;
+
+/// A read-only snapshot of the channels of a [`Server`](crate::Server).
+///
+/// Relaying [`ChannelData`](crate::ChannelData) only requires looking up the channel.
+/// With a snapshot, this can happen on other threads whilst the [`Server`](crate::Server) handles allocations and authentication.
+#[derive(Debug, Default, Clone)]
+pub struct Routes {
+    to_peer: HashMap<(ClientSocket, ChannelNumber), (AllocationPort, PeerSocket)>,
+    to_client: HashMap<(AllocationPort, PeerSocket), (ClientSocket, ChannelNumber)>,
+}
+
+impl Routes {
+    pub(crate) fn new(
+        to_peer: impl IntoIterator<Item = ((ClientSocket, ChannelNumber), (AllocationPort, PeerSocket))>,
+        to_client: HashMap<(AllocationPort, PeerSocket), (ClientSocket, ChannelNumber)>,
+    ) -> Self {
+        Self {
+            to_peer: HashMap::from_iter(to_peer),
+            to_client,
+        }
+    }
+
+    /// Where to relay the payload of a [`ChannelData`](crate::ChannelData) message from a client to.
+    pub fn to_peer(
+        &self,
+        client: ClientSocket,
+        channel: ChannelNumber,
+    ) -> Option<(AllocationPort, PeerSocket)> {
+        self.to_peer.get(&(client, channel)).copied()
+    }
+
+    /// Which client and channel to relay data from a peer to.
+    pub fn to_client(
+        &self,
+        allocation: AllocationPort,
+        peer: PeerSocket,
+    ) -> Option<(ClientSocket, ChannelNumber)> {
+        self.to_client.get(&(allocation, peer)).copied()
+    }
+}
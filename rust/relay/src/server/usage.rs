@@ -0,0 +1,67 @@
+use std::collections::BTreeMap;
+use std::ops::AddAssign;
+
+/// Bytes and packets relayed through an allocation, in both directions.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub struct Traffic {
+    pub bytes_from_client: u64,
+    pub packets_from_client: u64,
+    pub bytes_to_client: u64,
+    pub packets_to_client: u64,
+}
+
+impl Traffic {
+    pub(crate) fn record_from_client(&mut self, num_bytes: usize) {
+        self.bytes_from_client += num_bytes as u64;
+        self.packets_from_client += 1;
+    }
+
+    pub(crate) fn record_to_client(&mut self, num_bytes: usize) {
+        self.bytes_to_client += num_bytes as u64;
+        self.packets_to_client += 1;
+    }
+
+    /// The traffic that happened since `earlier` was recorded.
+    pub(crate) fn since(&self, earlier: &Traffic) -> Traffic {
+        Traffic {
+            bytes_from_client: self.bytes_from_client - earlier.bytes_from_client,
+            packets_from_client: self.packets_from_client - earlier.packets_from_client,
+            bytes_to_client: self.bytes_to_client - earlier.bytes_to_client,
+            packets_to_client: self.packets_to_client - earlier.packets_to_client,
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self == &Traffic::default()
+    }
+}
+
+impl AddAssign for Traffic {
+    fn add_assign(&mut self, rhs: Self) {
+        self.bytes_from_client += rhs.bytes_from_client;
+        self.packets_from_client += rhs.packets_from_client;
+        self.bytes_to_client += rhs.bytes_to_client;
+        self.packets_to_client += rhs.packets_to_client;
+    }
+}
+
+/// The usage of a single, active allocation since it was created.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct AllocationUsage {
+    pub port: u16,
+    /// The salt of the TURN username, identifying the client.
+    pub username: String,
+    #[serde(flatten)]
+    pub traffic: Traffic,
+    pub num_channels: usize,
+    pub lifetime_secs: u64,
+}
+
+/// Returned from [`Server::take_usage_report`](crate::Server::take_usage_report).
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct UsageReport {
+    /// All active allocations.
+    pub allocations: Vec<AllocationUsage>,
+    /// Traffic by username since the last report, including allocations that have been deleted since then.
+    pub usernames: BTreeMap<String, Traffic>,
+}
@@ -1,8 +1,10 @@
 use anyhow::{bail, Result};
+use firezone_logging::std_dyn_err;
 use std::{
     collections::HashMap,
     io,
     net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{Arc, RwLock},
     task::{ready, Context, Poll},
     time::Duration,
 };
@@ -25,10 +27,19 @@ pub struct Sockets {
     /// We must read from it until it returns [`io::ErrorKind::WouldBlock`].
     current_ready_socket: Option<mio::Token>,
 
+    /// Where to publish a handle of every new socket, see [`Senders`].
+    senders: Option<Senders>,
+
     cmd_tx: mpsc::Sender<Command>,
     event_rx: mpsc::Receiver<Event>,
 }
 
+/// Send-only handles to the sockets of one or more [`Sockets`], e.g. to send from a socket that is owned by another thread.
+#[derive(Debug, Clone, Default)]
+pub struct Senders {
+    inner: Arc<RwLock<HashMap<mio::Token, std::net::UdpSocket>>>,
+}
+
 impl Default for Sockets {
     fn default() -> Self {
         Self::new()
@@ -51,9 +62,19 @@ impl Sockets {
             cmd_tx,
             event_rx,
             current_ready_socket: None,
+            senders: None,
         }
     }
 
+    /// Publishes all sockets bound from now on to `senders`.
+    ///
+    /// If several [`Sockets`] bind the same port with [`Sockets::bind_shared`], the first one is published.
+    pub fn with_senders(mut self, senders: Senders) -> Self {
+        self.senders = Some(senders);
+
+        self
+    }
+
     /// Attempts to bind a new socket on the given port and address family.
     ///
     /// Fails if the channel is:
@@ -61,7 +82,17 @@ impl Sockets {
     ///  - disconnected (we can't operate without the [`mio`] worker thread)
     pub fn bind(&mut self, port: u16, address_family: AddressFamily) -> Result<()> {
         self.cmd_tx
-            .try_send(Command::NewSocket((port, address_family)))?;
+            .try_send(Command::NewSocket((port, address_family, false)))?;
+
+        Ok(())
+    }
+
+    /// Like [`Sockets::bind`] but sets `SO_REUSEPORT`, allowing other threads to bind the same port.
+    ///
+    /// The kernel then distributes incoming packets across all sockets bound to the port by their source address.
+    pub fn bind_shared(&mut self, port: u16, address_family: AddressFamily) -> Result<()> {
+        self.cmd_tx
+            .try_send(Command::NewSocket((port, address_family, true)))?;
 
         Ok(())
     }
@@ -77,6 +108,9 @@ impl Sockets {
         let Some(socket) = self.inner.remove(&token) else {
             return Ok(());
         };
+        if let Some(senders) = self.senders.as_ref() {
+            senders.remove(token);
+        }
 
         self.cmd_tx.try_send(Command::DisposeSocket(socket))?;
 
@@ -134,6 +168,10 @@ impl Sockets {
 
             match ready!(self.event_rx.poll_recv(cx)) {
                 Some(Event::NewSocket(token, socket)) => {
+                    if let Some(senders) = self.senders.as_ref() {
+                        senders.insert(token, &socket);
+                    }
+
                     self.inner.insert(token, socket);
                     continue;
                 }
@@ -152,6 +190,54 @@ impl Sockets {
     }
 }
 
+impl Senders {
+    pub fn try_send(&self, port: u16, dest: SocketAddr, msg: &[u8]) -> io::Result<()> {
+        let address_family = match dest {
+            SocketAddr::V4(_) => AddressFamily::V4,
+            SocketAddr::V6(_) => AddressFamily::V6,
+        };
+        let token = token_from_port_and_address_family(port, address_family);
+
+        let inner = self.inner.read().unwrap_or_else(|e| e.into_inner());
+        let socket = inner
+            .get(&token)
+            .ok_or_else(|| not_connected(port, address_family))?;
+
+        let num_sent = socket.send_to(msg, dest)?;
+
+        debug_assert_eq!(num_sent, msg.len());
+
+        Ok(())
+    }
+
+    fn insert(&self, token: mio::Token, socket: &mio::net::UdpSocket) {
+        let mut inner = self.inner.write().unwrap_or_else(|e| e.into_inner());
+
+        if inner.contains_key(&token) {
+            return;
+        }
+
+        // The duplicated socket shares the non-blocking flag with the original one.
+        match socket2::SockRef::from(socket).try_clone() {
+            Ok(socket) => {
+                inner.insert(token, std::net::UdpSocket::from(socket));
+            }
+            Err(e) => {
+                let (port, address_family) = token_to_port_and_address_family(token);
+
+                tracing::warn!(target: "relay", error = std_dyn_err(&e), %port, %address_family, "Failed to duplicate socket");
+            }
+        }
+    }
+
+    fn remove(&self, token: mio::Token) {
+        self.inner
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&token);
+    }
+}
+
 /// A packet read from a socket.
 #[derive(Debug)]
 pub struct Received<'a> {
@@ -167,7 +253,8 @@ pub enum Error {
 }
 
 enum Command {
-    NewSocket((u16, AddressFamily)),
+    /// Port, address family and whether to set `SO_REUSEPORT`.
+    NewSocket((u16, AddressFamily, bool)),
     DisposeSocket(mio::net::UdpSocket),
 }
 
@@ -206,8 +293,9 @@ fn mio_worker_task(
             match cmd_rx.try_recv() {
                 Err(mpsc::error::TryRecvError::Empty) => break, // Drain all events from the channel until it is empty.
 
-                Ok(Command::NewSocket((port, af))) => {
-                    let mut socket = mio::net::UdpSocket::from_std(make_wildcard_socket(af, port)?);
+                Ok(Command::NewSocket((port, af, reuse_port))) => {
+                    let mut socket =
+                        mio::net::UdpSocket::from_std(make_wildcard_socket(af, port, reuse_port)?);
                     let token = token_from_port_and_address_family(port, af);
 
                     poll.registry()
@@ -255,7 +343,11 @@ fn token_to_port_and_address_family(token: mio::Token) -> (u16, AddressFamily) {
 /// Creates an [std::net::UdpSocket] via the [socket2] library that is configured for our needs.
 ///
 /// Most importantly, this sets the `IPV6_V6ONLY` flag to ensure we disallow IP4-mapped IPv6 addresses and can bind to IP4 and IP6 addresses on the same port.
-fn make_wildcard_socket(family: AddressFamily, port: u16) -> io::Result<std::net::UdpSocket> {
+fn make_wildcard_socket(
+    family: AddressFamily,
+    port: u16,
+    reuse_port: bool,
+) -> io::Result<std::net::UdpSocket> {
     use socket2::*;
 
     let domain = match family {
@@ -271,6 +363,16 @@ fn make_wildcard_socket(family: AddressFamily, port: u16) -> io::Result<std::net
     if family == AddressFamily::V6 {
         socket.set_only_v6(true)?;
     }
+    if reuse_port {
+        #[cfg(unix)]
+        socket.set_reuse_port(true)?;
+
+        #[cfg(not(unix))]
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "`SO_REUSEPORT` is only supported on unix",
+        ));
+    }
 
     socket.set_nonblocking(true)?;
     socket.bind(&SockAddr::from(SocketAddr::new(address, port)))?;
@@ -0,0 +1,471 @@
+//! Relaying [`ChannelData`] on multiple threads.
+//!
+//! The [`Server`] remains the single source of truth for allocations, channels and authentication and runs on the main thread.
+//! Each worker binds the listen port with `SO_REUSEPORT` and every n-th allocation port, meaning the kernel spreads the clients across the workers.
+//!
+//! Workers relay [`ChannelData`] using a snapshot of the [`Server`]'s [`Routes`] and hand all other messages to the main thread.
+//! The relayed traffic is periodically reported back to the main thread, see [`Event::Traffic`].
+
+use crate::sockets::{self, Senders, Sockets};
+use crate::{
+    AllocationPort, ChannelData, ClientSocket, IpStack, PeerSocket, Routes, Server, Traffic,
+};
+use anyhow::{Context as _, Result};
+use firezone_logging::{anyhow_dyn_err, std_dyn_err};
+use rand::Rng;
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use stun_codec::rfc8656::attributes::AddressFamily;
+use tokio::sync::mpsc;
+
+/// How often workers report the traffic they relayed.
+const TRAFFIC_REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+const MAX_UDP_SIZE: usize = 65536;
+
+/// Handle to the worker threads.
+pub struct Workers {
+    workers: Vec<mpsc::UnboundedSender<WorkerCommand>>,
+    events: mpsc::Receiver<Event>,
+
+    routes: Arc<SharedRoutes>,
+    senders: Senders,
+}
+
+pub enum Event {
+    /// A message from a client that needs to be handled by the [`Server`], i.e. anything but [`ChannelData`] on a bound channel.
+    ClientInput { from: ClientSocket, packet: Vec<u8> },
+    /// Traffic relayed by a worker since its last report.
+    Traffic(HashMap<ClientSocket, Traffic>),
+    /// A worker failed to bind the port of an allocation, the allocation should be freed.
+    BindFailed(AllocationPort),
+    /// A worker failed and no longer relays any traffic.
+    Crashed(anyhow::Error),
+}
+
+#[derive(Debug)]
+enum WorkerCommand {
+    Bind(u16, AddressFamily),
+    Unbind(u16, AddressFamily),
+}
+
+#[derive(Default)]
+struct SharedRoutes {
+    version: AtomicU64,
+    routes: RwLock<Arc<Routes>>,
+}
+
+impl Workers {
+    /// Spawns `num_workers` threads, each listening on `listen_port` for the IP families of `public_address`.
+    pub fn spawn(num_workers: usize, listen_port: u16, public_address: IpStack) -> Result<Self> {
+        let (event_tx, event_rx) = mpsc::channel(10_000);
+        let routes = Arc::new(SharedRoutes::default());
+        let senders = Senders::default();
+
+        let workers = (0..num_workers)
+            .map(|id| {
+                let (cmd_tx, cmd_rx) = mpsc::unbounded_channel();
+                let mut sockets = Sockets::new().with_senders(senders.clone());
+
+                if public_address.as_v4().is_some() {
+                    sockets.bind_shared(listen_port, AddressFamily::V4)?;
+                }
+                if public_address.as_v6().is_some() {
+                    sockets.bind_shared(listen_port, AddressFamily::V6)?;
+                }
+
+                let worker = Worker {
+                    sockets,
+                    senders: senders.clone(),
+                    listen_port,
+                    commands: cmd_rx,
+                    events: event_tx.clone(),
+                    shared_routes: routes.clone(),
+                    routes: Arc::default(),
+                    routes_version: 0,
+                    traffic: HashMap::default(),
+                    traffic_report_interval: None,
+                    buffer: Box::new([0u8; MAX_UDP_SIZE]),
+                };
+
+                std::thread::Builder::new()
+                    .name(format!("relay-worker-{id}"))
+                    .spawn(move || worker.run())
+                    .context("Failed to spawn worker thread")?;
+
+                Ok(cmd_tx)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            workers,
+            events: event_rx,
+            routes,
+            senders,
+        })
+    }
+
+    /// Binds the port of a new allocation on one of the workers.
+    pub fn bind(&mut self, port: AllocationPort, family: AddressFamily) -> Result<()> {
+        self.worker_for(port)
+            .send(WorkerCommand::Bind(port.value(), family))
+            .context("Worker is gone")?;
+
+        Ok(())
+    }
+
+    pub fn unbind(&mut self, port: AllocationPort, family: AddressFamily) -> Result<()> {
+        self.worker_for(port)
+            .send(WorkerCommand::Unbind(port.value(), family))
+            .context("Worker is gone")?;
+
+        Ok(())
+    }
+
+    /// Sends from any socket of any worker.
+    pub fn try_send(&self, port: u16, dest: SocketAddr, msg: &[u8]) -> io::Result<()> {
+        self.senders.try_send(port, dest, msg)
+    }
+
+    /// Hands the current [`Routes`] of the [`Server`] to the workers, if they changed.
+    pub fn update_routes<R>(&mut self, server: &Server<R>)
+    where
+        R: Rng,
+    {
+        let version = server.routes_version();
+
+        if self.routes.version.load(Ordering::Acquire) == version {
+            return;
+        }
+
+        self.set_routes(version, server.routes());
+    }
+
+    fn set_routes(&mut self, version: u64, routes: Routes) {
+        *self
+            .routes
+            .routes
+            .write()
+            .unwrap_or_else(|e| e.into_inner()) = Arc::new(routes);
+        self.routes.version.store(version, Ordering::Release);
+    }
+
+    pub fn poll_event(&mut self, cx: &mut Context<'_>) -> Poll<Event> {
+        match self.events.poll_recv(cx) {
+            Poll::Ready(Some(event)) => Poll::Ready(event),
+            Poll::Ready(None) => Poll::Ready(Event::Crashed(anyhow::anyhow!("All workers exited"))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn worker_for(&self, port: AllocationPort) -> &mpsc::UnboundedSender<WorkerCommand> {
+        &self.workers[port.value() as usize % self.workers.len()]
+    }
+}
+
+struct Worker {
+    sockets: Sockets,
+    /// For sending on allocation ports, these may be bound by other workers.
+    senders: Senders,
+    listen_port: u16,
+
+    commands: mpsc::UnboundedReceiver<WorkerCommand>,
+    events: mpsc::Sender<Event>,
+
+    shared_routes: Arc<SharedRoutes>,
+    routes: Arc<Routes>,
+    routes_version: u64,
+
+    traffic: HashMap<ClientSocket, Traffic>,
+    traffic_report_interval: Option<tokio::time::Interval>,
+
+    buffer: Box<[u8; MAX_UDP_SIZE]>,
+}
+
+impl Worker {
+    fn run(mut self) {
+        let runtime = match tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+        {
+            Ok(runtime) => runtime,
+            Err(e) => {
+                let _ = self.events.blocking_send(Event::Crashed(e.into()));
+                return;
+            }
+        };
+
+        runtime.block_on(async move {
+            self.traffic_report_interval = Some(tokio::time::interval(TRAFFIC_REPORT_INTERVAL));
+
+            if let Err(e) = std::future::poll_fn(|cx| self.poll(cx)).await {
+                let _ = self.events.send(Event::Crashed(e)).await;
+            }
+        });
+    }
+
+    /// Returns `Ok` once the [`Workers`] handle is dropped.
+    fn poll(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        loop {
+            match self.commands.poll_recv(cx) {
+                Poll::Ready(Some(WorkerCommand::Bind(port, family))) => {
+                    // A single allocation failing must not take down all others, so we only fail this one.
+                    if let Err(e) = self.sockets.bind(port, family) {
+                        tracing::warn!(target: "relay", error = anyhow_dyn_err(&e), %port, %family, "Failed to bind allocation port");
+
+                        if self
+                            .events
+                            .try_send(Event::BindFailed(AllocationPort::new(port)))
+                            .is_err()
+                        {
+                            tracing::warn!(target: "relay", %port, "Main thread is busy, cannot free allocation");
+                        }
+                    }
+                    continue;
+                }
+                Poll::Ready(Some(WorkerCommand::Unbind(port, family))) => {
+                    if let Err(e) = self.sockets.unbind(port, family) {
+                        tracing::warn!(target: "relay", error = anyhow_dyn_err(&e), %port, %family, "Failed to unbind allocation port");
+                    }
+                    continue;
+                }
+                Poll::Ready(None) => return Poll::Ready(Ok(())),
+                Poll::Pending => {}
+            }
+
+            let version = self.shared_routes.version.load(Ordering::Acquire);
+            if version != self.routes_version {
+                self.routes = self
+                    .shared_routes
+                    .routes
+                    .read()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .clone();
+                self.routes_version = version;
+            }
+
+            // See the main eventloop for why we read with an offset of 4 bytes.
+            let (header, payload) = self.buffer.split_at_mut(4);
+
+            match self.sockets.poll_recv_from(payload, cx) {
+                Poll::Ready(Ok(sockets::Received { port, from, packet }))
+                    if port == self.listen_port =>
+                {
+                    let client = ClientSocket::new(from);
+
+                    if let Some((allocation, peer, data)) =
+                        ChannelData::parse(packet).ok().and_then(|msg| {
+                            let (allocation, peer) = self.routes.to_peer(client, msg.channel())?;
+
+                            Some((allocation, peer, msg.data()))
+                        })
+                    {
+                        if let Err(e) =
+                            self.senders
+                                .try_send(allocation.value(), peer.into_socket(), data)
+                        {
+                            tracing::warn!(target: "relay", error = std_dyn_err(&e), %peer, "Failed to relay data to peer");
+                        }
+
+                        self.traffic
+                            .entry(client)
+                            .or_default()
+                            .record_from_client(data.len());
+                        continue;
+                    }
+
+                    if self
+                        .events
+                        .try_send(Event::ClientInput {
+                            from: client,
+                            packet: packet.to_vec(),
+                        })
+                        .is_err()
+                    {
+                        tracing::debug!(target: "relay", %client, "Main thread is busy, dropping message");
+                    }
+                    continue;
+                }
+                Poll::Ready(Ok(sockets::Received { port, from, packet })) => {
+                    let Some((client, channel)) = self
+                        .routes
+                        .to_client(AllocationPort::new(port), PeerSocket::new(from))
+                    else {
+                        tracing::debug!(target: "relay", allocation = %port, sender = %from, "no channel");
+                        continue;
+                    };
+
+                    let num_bytes = packet.len();
+                    let total_length =
+                        ChannelData::encode_header_to_slice(channel, num_bytes as u16, header);
+
+                    if let Err(e) = self.sockets.try_send(
+                        self.listen_port,
+                        client.into_socket(),
+                        &self.buffer[..total_length],
+                    ) {
+                        tracing::warn!(target: "relay", error = std_dyn_err(&e), %client, "Failed to relay data to client");
+                    };
+
+                    self.traffic
+                        .entry(client)
+                        .or_default()
+                        .record_to_client(num_bytes);
+                    continue;
+                }
+                Poll::Ready(Err(sockets::Error::Io(e))) => {
+                    tracing::warn!(target: "relay", error = std_dyn_err(&e), "Error while receiving message");
+                    continue;
+                }
+                Poll::Ready(Err(sockets::Error::MioTaskCrashed(e))) => return Poll::Ready(Err(e)),
+                Poll::Pending => {}
+            }
+
+            if self
+                .traffic_report_interval
+                .as_mut()
+                .is_some_and(|i| i.poll_tick(cx).is_ready())
+            {
+                // If the channel is full, we try again on the next tick.
+                if let (false, Ok(permit)) = (self.traffic.is_empty(), self.events.try_reserve()) {
+                    permit.send(Event::Traffic(std::mem::take(&mut self.traffic)));
+                }
+
+                continue;
+            }
+
+            return Poll::Pending;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, UdpSocket};
+    use stun_codec::rfc5766::attributes::ChannelNumber;
+
+    #[test]
+    fn relays_channel_data_between_client_and_peer() {
+        let listen_port = free_port();
+        let allocation = AllocationPort::new(free_port());
+
+        let mut workers =
+            Workers::spawn(2, listen_port, IpStack::Ip4(Ipv4Addr::LOCALHOST)).unwrap();
+        workers.bind(allocation, AddressFamily::V4).unwrap();
+
+        let client = udp_socket();
+        let peer = udp_socket();
+        let channel = ChannelNumber::new(ChannelNumber::MIN).unwrap();
+        workers.set_routes(1, routes(&client, channel, allocation, &peer));
+
+        let (data, from) =
+            send_until_received(&client, listen_port, &channel_data(channel, b"ping"), &peer);
+        assert_eq!(data, b"ping");
+        assert_eq!(
+            from,
+            SocketAddr::from((Ipv4Addr::LOCALHOST, allocation.value()))
+        );
+
+        let (data, from) = send_until_received(&peer, allocation.value(), b"pong", &client);
+        assert_eq!(data, channel_data(channel, b"pong"));
+        assert_eq!(from, SocketAddr::from((Ipv4Addr::LOCALHOST, listen_port)));
+    }
+
+    #[tokio::test]
+    async fn hands_channel_data_without_route_to_main_thread() {
+        let listen_port = free_port();
+        let mut workers =
+            Workers::spawn(2, listen_port, IpStack::Ip4(Ipv4Addr::LOCALHOST)).unwrap();
+
+        let client = udp_socket();
+        let channel = ChannelNumber::new(ChannelNumber::MIN).unwrap();
+        let msg = channel_data(channel, b"ping");
+
+        let event = loop {
+            client
+                .send_to(&msg, (Ipv4Addr::LOCALHOST, listen_port))
+                .unwrap();
+
+            if let Ok(event) = tokio::time::timeout(
+                Duration::from_millis(100),
+                std::future::poll_fn(|cx| workers.poll_event(cx)),
+            )
+            .await
+            {
+                break event;
+            }
+        };
+
+        let Event::ClientInput { from, packet } = event else {
+            panic!("Unexpected event");
+        };
+        assert_eq!(from, ClientSocket::new(client.local_addr().unwrap()));
+        assert_eq!(packet, msg);
+    }
+
+    fn routes(
+        client: &UdpSocket,
+        channel: ChannelNumber,
+        allocation: AllocationPort,
+        peer: &UdpSocket,
+    ) -> Routes {
+        let client = ClientSocket::new(client.local_addr().unwrap());
+        let peer = PeerSocket::new(peer.local_addr().unwrap());
+
+        Routes::new(
+            [((client, channel), (allocation, peer))],
+            HashMap::from([((allocation, peer), (client, channel))]),
+        )
+    }
+
+    /// Sends `msg` until `receiver` gets something, as the workers bind their sockets asynchronously.
+    fn send_until_received(
+        sender: &UdpSocket,
+        port: u16,
+        msg: &[u8],
+        receiver: &UdpSocket,
+    ) -> (Vec<u8>, SocketAddr) {
+        let mut buf = [0u8; 1024];
+
+        for _ in 0..50 {
+            sender.send_to(msg, (Ipv4Addr::LOCALHOST, port)).unwrap();
+
+            if let Ok((len, from)) = receiver.recv_from(&mut buf) {
+                return (buf[..len].to_vec(), from);
+            }
+        }
+
+        panic!("Did not receive message");
+    }
+
+    fn channel_data(channel: ChannelNumber, data: &[u8]) -> Vec<u8> {
+        let mut msg = vec![0u8; 4 + data.len()];
+        ChannelData::encode_header_to_slice(channel, data.len() as u16, &mut msg[..4]);
+        msg[4..].copy_from_slice(data);
+
+        msg
+    }
+
+    fn udp_socket() -> UdpSocket {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+
+        socket
+    }
+
+    fn free_port() -> u16 {
+        UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+}
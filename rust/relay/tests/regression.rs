@@ -3,7 +3,7 @@
 use bytecodec::{DecodeExt, EncodeExt};
 use firezone_relay::{
     AddressFamily, Allocate, AllocationPort, Attribute, Binding, ChannelBind, ChannelData,
-    ClientMessage, ClientSocket, Command, IpStack, PeerSocket, Refresh, Server,
+    ClientMessage, ClientSocket, Command, IpStack, PeerSocket, Refresh, Server, Traffic,
 };
 use rand::rngs::mock::StepRng;
 use secrecy::SecretString;
@@ -14,6 +14,7 @@ use stun_codec::rfc5389::attributes::{ErrorCode, Nonce, Realm, Username, XorMapp
 use stun_codec::rfc5389::errors::Unauthorized;
 use stun_codec::rfc5389::methods::BINDING;
 use stun_codec::rfc5766::attributes::{ChannelNumber, Lifetime, XorPeerAddress, XorRelayAddress};
+use stun_codec::rfc5766::errors::InsufficientCapacity;
 use stun_codec::rfc5766::methods::{ALLOCATE, CHANNEL_BIND, REFRESH};
 use stun_codec::{Message, MessageClass, MessageDecoder, MessageEncoder, TransactionId};
 use test_strategy::proptest;
@@ -302,6 +303,82 @@ fn when_receiving_lifetime_0_for_existing_allocation_then_delete(
     server.assert_commands(forward_time_to(first_wake + Duration::from_secs(1)), []);
 }
 
+#[proptest]
+fn when_draining_rejects_new_allocations_and_frees_existing_ones_at_deadline(
+    #[strategy(firezone_relay::proptest::transaction_id())] allocate_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::transaction_id())]
+    second_allocate_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::allocation_lifetime())] allocate_lifetime: Lifetime,
+    #[strategy(firezone_relay::proptest::username_salt())] username_salt: String,
+    source: SocketAddrV4,
+    public_relay_addr: Ipv4Addr,
+    #[strategy(firezone_relay::proptest::nonce())] nonce: Uuid,
+) {
+    let now = Instant::now();
+
+    let mut server = TestServer::new(public_relay_addr).with_nonce(nonce);
+    let secret = server.auth_secret().to_owned();
+    let second_source = SocketAddrV4::new(*source.ip(), source.port().wrapping_add(1));
+
+    server.assert_commands(
+        from_client(
+            source,
+            Allocate::new_authenticated_udp_implicit_ip4(
+                allocate_transaction_id,
+                Some(allocate_lifetime.clone()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            )
+            .unwrap(),
+            now,
+        ),
+        [
+            create_allocation(49152, AddressFamily::V4),
+            send_message(
+                source,
+                allocate_response(
+                    allocate_transaction_id,
+                    public_relay_addr,
+                    49152,
+                    source,
+                    &allocate_lifetime,
+                ),
+            ),
+        ],
+    );
+
+    let deadline = now + allocate_lifetime.lifetime() / 2;
+    server.server.drain(deadline, None);
+
+    server.assert_commands(
+        from_client(
+            second_source,
+            Allocate::new_authenticated_udp_implicit_ip4(
+                second_allocate_transaction_id,
+                Some(allocate_lifetime.clone()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            )
+            .unwrap(),
+            now,
+        ),
+        [send_message(
+            second_source,
+            insufficient_capacity_allocate_response(second_allocate_transaction_id),
+        )],
+    );
+
+    assert_eq!(server.server.poll_timeout(), Some(deadline));
+
+    server.assert_commands(
+        forward_time_to(deadline),
+        [free_allocation(49152, AddressFamily::V4)],
+    );
+    assert_eq!(server.server.num_allocations(), 0);
+}
+
 #[proptest]
 fn freeing_allocation_clears_all_channels(
     #[strategy(firezone_relay::proptest::transaction_id())] allocate_transaction_id: TransactionId,
@@ -369,6 +446,118 @@ fn freeing_allocation_clears_all_channels(
     assert_eq!(server.server.num_active_channels(), 0);
 }
 
+#[proptest]
+fn routes_contain_bound_channels_until_allocation_is_freed(
+    #[strategy(firezone_relay::proptest::transaction_id())] allocate_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::transaction_id())]
+    channel_bind_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::transaction_id())]
+    channel_refresh_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::transaction_id())] refresh_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::channel_number())] channel: ChannelNumber,
+    #[strategy(firezone_relay::proptest::username_salt())] username_salt: String,
+    source: SocketAddr,
+    peer: SocketAddrV4,
+    public_relay_addr: Ipv4Addr,
+    #[strategy(firezone_relay::proptest::nonce())] nonce: Uuid,
+) {
+    let now = Instant::now();
+
+    let _ = env_logger::try_init();
+
+    let mut server = TestServer::new(public_relay_addr).with_nonce(nonce);
+    let secret = server.auth_secret().to_owned();
+
+    let _ = server.server.handle_client_message(
+        ClientMessage::Allocate(
+            Allocate::new_authenticated_udp_implicit_ip4(
+                allocate_transaction_id,
+                None,
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            )
+            .unwrap(),
+        ),
+        ClientSocket::new(source),
+        now,
+    );
+    let version_before_bind = server.server.routes_version();
+    let _ = server.server.handle_client_message(
+        ClientMessage::ChannelBind(
+            ChannelBind::new(
+                channel_bind_transaction_id,
+                channel,
+                XorPeerAddress::new(peer.into()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            )
+            .unwrap(),
+        ),
+        ClientSocket::new(source),
+        now,
+    );
+
+    let routes = server.server.routes();
+    assert_ne!(server.server.routes_version(), version_before_bind);
+    assert_eq!(
+        routes.to_peer(ClientSocket::new(source), channel),
+        Some((AllocationPort::new(49152), PeerSocket::new(peer.into())))
+    );
+    assert_eq!(
+        routes.to_client(AllocationPort::new(49152), PeerSocket::new(peer.into())),
+        Some((ClientSocket::new(source), channel))
+    );
+
+    let version_before_channel_refresh = server.server.routes_version();
+    let _ = server.server.handle_client_message(
+        ClientMessage::ChannelBind(
+            ChannelBind::new(
+                channel_refresh_transaction_id,
+                channel,
+                XorPeerAddress::new(peer.into()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            )
+            .unwrap(),
+        ),
+        ClientSocket::new(source),
+        now,
+    );
+
+    // Refreshing a bound channel doesn't change any routes, so the workers don't need a new snapshot.
+    assert_eq!(
+        server.server.routes_version(),
+        version_before_channel_refresh
+    );
+
+    let version_before_refresh = server.server.routes_version();
+    let _ = server.server.handle_client_message(
+        ClientMessage::Refresh(
+            Refresh::new(
+                refresh_transaction_id,
+                Some(Lifetime::new(Duration::ZERO).unwrap()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            )
+            .unwrap(),
+        ),
+        ClientSocket::new(source),
+        now,
+    );
+
+    let routes = server.server.routes();
+    assert_ne!(server.server.routes_version(), version_before_refresh);
+    assert_eq!(routes.to_peer(ClientSocket::new(source), channel), None);
+    assert_eq!(
+        routes.to_client(AllocationPort::new(49152), PeerSocket::new(peer.into())),
+        None
+    );
+}
+
 // #[test]
 // fn server_waits_for_5_minutes_before_allowing_reuse_of_channel_number_after_expiry() {
 //     // todo!()
@@ -481,6 +670,24 @@ fn ping_pong_relay(
             client_to_peer_ping.channel()
         ))
     );
+
+    let expected_traffic = Traffic {
+        bytes_from_client: client_to_peer_ping.data().len() as u64,
+        packets_from_client: 1,
+        bytes_to_client: peer_to_client_ping.len() as u64,
+        packets_to_client: 1,
+    };
+
+    let report = server.server.take_usage_report(now);
+    assert_eq!(report.allocations.len(), 1);
+    assert_eq!(report.allocations[0].username, username_salt);
+    assert_eq!(report.allocations[0].traffic, expected_traffic);
+    assert_eq!(report.allocations[0].num_channels, 1);
+    assert_eq!(report.usernames[&username_salt], expected_traffic);
+
+    // Traffic by username is only reported once.
+    let report = server.server.take_usage_report(now);
+    assert!(report.usernames.is_empty());
 }
 
 #[proptest]
@@ -863,6 +1070,14 @@ fn unauthorized_allocate_response(
     message
 }
 
+fn insufficient_capacity_allocate_response(transaction_id: TransactionId) -> Message<Attribute> {
+    let mut message =
+        Message::<Attribute>::new(MessageClass::ErrorResponse, ALLOCATE, transaction_id);
+    message.add_attribute(ErrorCode::from(InsufficientCapacity));
+
+    message
+}
+
 fn refresh_response(transaction_id: TransactionId, lifetime: Lifetime) -> Message<Attribute> {
     let mut message =
         Message::<Attribute>::new(MessageClass::SuccessResponse, REFRESH, transaction_id);
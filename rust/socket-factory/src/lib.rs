@@ -204,6 +204,10 @@ pub struct DatagramOut<'a> {
     pub src: Option<SocketAddr>,
     pub dst: SocketAddr,
     pub packet: Cow<'a, [u8]>,
+    /// If set, `packet` is a batch of datagrams of this size that are sent using GSO (generic segmentation offload).
+    ///
+    /// Only the last datagram in the batch may be shorter.
+    pub segment_size: Option<usize>,
 }
 
 impl UdpSocket {
@@ -274,12 +278,48 @@ impl UdpSocket {
         self.inner.poll_send_ready(cx)
     }
 
-    pub fn send(&mut self, datagram: DatagramOut) -> io::Result<()> {
-        tracing::trace!(target: "wire::net::send", src = ?datagram.src, dst = %datagram.dst, num_bytes = %datagram.packet.len());
+    /// The maximum number of datagrams we can send in a single GSO batch.
+    ///
+    /// This is 1 if the platform doesn't support GSO or `quinn_udp` disabled it after the kernel rejected a batch.
+    pub fn max_gso_segments(&self) -> usize {
+        self.state.max_gso_segments()
+    }
 
-        self.try_send(datagram)?;
+    /// Sends `datagram`, returning how many bytes of it were sent.
+    ///
+    /// Without GSO, we send the segments of a batch one by one and the socket may become busy before we are done.
+    /// In that case, the caller has to send the remaining bytes again once the socket is ready.
+    pub fn send(&mut self, datagram: DatagramOut) -> io::Result<usize> {
+        tracing::trace!(target: "wire::net::send", src = ?datagram.src, dst = %datagram.dst, num_bytes = %datagram.packet.len(), segment_size = ?datagram.segment_size);
+
+        let src_ip = datagram.src.map(|s| s.ip());
+
+        match datagram.segment_size {
+            Some(segment_size)
+                if datagram.packet.len() > segment_size && self.max_gso_segments() == 1 =>
+            {
+                // GSO is not available (anymore), send each datagram of the batch individually.
+                let mut sent = 0;
+
+                for segment in datagram.packet.chunks(segment_size) {
+                    match self.try_send(datagram.dst, src_ip, segment, None) {
+                        Ok(()) => sent += segment.len(),
+                        Err(e) if sent > 0 && e.kind() == io::ErrorKind::WouldBlock => {
+                            tracing::debug!(dst = %datagram.dst, %sent, "Socket busy; sent only part of batch");
+                            break;
+                        }
+                        Err(e) => return Err(e),
+                    }
+                }
 
-        Ok(())
+                Ok(sent)
+            }
+            segment_size => {
+                self.try_send(datagram.dst, src_ip, &datagram.packet, segment_size)?;
+
+                Ok(datagram.packet.len())
+            }
+        }
     }
 
     /// Performs a single request-response handshake with the specified destination socket address.
@@ -299,7 +339,7 @@ impl UdpSocket {
         payload: &[u8],
     ) -> io::Result<Vec<u8>> {
         let transmit = self
-            .prepare_transmit(dst, None, payload)?
+            .prepare_transmit(dst, None, payload, None)?
             .ok_or_else(|| io::Error::other("Failed to prepare `Transmit`"))?;
 
         self.inner
@@ -323,10 +363,14 @@ impl UdpSocket {
         Ok(buffer)
     }
 
-    fn try_send(&mut self, datagram: DatagramOut) -> io::Result<()> {
-        let Some(transmit) =
-            self.prepare_transmit(datagram.dst, datagram.src.map(|s| s.ip()), &datagram.packet)?
-        else {
+    fn try_send(
+        &mut self,
+        dst: SocketAddr,
+        src_ip: Option<IpAddr>,
+        packet: &[u8],
+        segment_size: Option<usize>,
+    ) -> io::Result<()> {
+        let Some(transmit) = self.prepare_transmit(dst, src_ip, packet, segment_size)? else {
             return Ok(());
         };
 
@@ -340,6 +384,7 @@ impl UdpSocket {
         dst: SocketAddr,
         src_ip: Option<IpAddr>,
         packet: &'a [u8],
+        segment_size: Option<usize>,
     ) -> io::Result<Option<quinn_udp::Transmit<'a>>> {
         let src_ip = match src_ip {
             Some(src_ip) => Some(src_ip),
@@ -360,7 +405,7 @@ impl UdpSocket {
             destination: dst,
             ecn: None,
             contents: packet,
-            segment_size: None,
+            segment_size: segment_size.filter(|s| packet.len() > *s), // A single datagram doesn't need GSO.
             src_ip,
         };
 
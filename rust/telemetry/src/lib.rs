@@ -1,3 +1,9 @@
+#![cfg_attr(test, allow(clippy::unwrap_used))]
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write as _};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 use sentry::protocol::SessionStatus;
@@ -5,7 +11,7 @@ pub use sentry_anyhow::capture_anyhow;
 
 pub struct Dsn(&'static str);
 
-// TODO: Dynamic DSN
+// These are the defaults, self-hosted deployments can override them via [`Config::dsn`].
 // Sentry docs say this does not need to be protected:
 // > DSNs are safe to keep public because they only allow submission of new events and related event data; they do not allow read access to any information.
 // <https://docs.sentry.io/concepts/key-terms/dsn-explainer/#dsn-utilization>
@@ -17,6 +23,23 @@ pub const GUI_DSN: Dsn = Dsn("https://2e17bf5ed24a78c0ac9e84a5de2bd6fc@o45079711
 pub const HEADLESS_DSN: Dsn = Dsn("https://bc27dca8bb37be0142c48c4f89647c13@o4507971108339712.ingest.us.sentry.io/4508010028728320");
 pub const IPC_SERVICE_DSN: Dsn = Dsn("https://0590b89fd4479494a1e7ffa4dc705001@o4507971108339712.ingest.us.sentry.io/4508008896069632");
 
+/// Overrides for where telemetry is sent and how much of it.
+///
+/// Everything is optional, the defaults report to Firezone's Sentry.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Config {
+    /// A Sentry-compatible DSN to use instead of the built-in one, e.g. of a self-hosted Sentry or GlitchTip.
+    pub dsn: Option<String>,
+    /// The environment to report, instead of deriving it from the API URL.
+    pub environment: Option<String>,
+    /// The fraction of error events to submit, between 0.0 and 1.0.
+    pub sample_rate: Option<f32>,
+    /// The fraction of `telemetry` spans to submit, between 0.0 and 1.0.
+    pub traces_sample_rate: Option<f32>,
+    /// Append events as JSON lines to this file instead of sending them anywhere.
+    pub file: Option<PathBuf>,
+}
+
 #[derive(Default)]
 pub struct Telemetry {
     /// The sentry guard itself
@@ -49,6 +72,11 @@ impl Drop for Telemetry {
 
 impl Telemetry {
     pub fn start(&mut self, api_url: &str, release: &str, dsn: Dsn) {
+        self.start_with_config(api_url, release, dsn, &Config::default())
+    }
+
+    /// Starts telemetry like [`Telemetry::start`] but applies the overrides in `config`.
+    pub fn start_with_config(&mut self, api_url: &str, release: &str, dsn: Dsn, config: &Config) {
         // Since it's `arc_swap` and not `Option`, there is a TOCTOU here,
         // but in practice it should never trigger
         if self.inner.is_some() {
@@ -57,27 +85,56 @@ impl Telemetry {
 
         // Can't use URLs as `environment` directly, because Sentry doesn't allow slashes in environments.
         // <https://docs.sentry.io/platforms/rust/configuration/environments/>
-        let environment = match api_url {
-            "wss://api.firezone.dev" | "wss://api.firezone.dev/" => "production",
-            "wss://api.firez.one" | "wss://api.firez.one/" => "staging",
-            "ws://api:8081" | "ws://api:8081/" => "docker-compose",
-            _ => "self-hosted",
+        let environment = config.environment.clone().unwrap_or_else(|| {
+            match api_url {
+                "wss://api.firezone.dev" | "wss://api.firezone.dev/" => "production",
+                "wss://api.firez.one" | "wss://api.firez.one/" => "staging",
+                "ws://api:8081" | "ws://api:8081/" => "docker-compose",
+                _ => "self-hosted",
+            }
+            .to_owned()
+        });
+
+        // Sentry needs a DSN to be enabled at all, even if we only write events to a file.
+        let dsn = match config.dsn.as_deref().unwrap_or(dsn.0).parse() {
+            Ok(dsn) => dsn,
+            Err(e) => {
+                tracing::error!(error = %e, "Invalid telemetry DSN, not starting telemetry");
+                return;
+            }
+        };
+
+        let transport = match config.file.as_deref().map(FileTransport::new).transpose() {
+            Ok(transport) => transport,
+            Err(e) => {
+                tracing::error!(
+                    error = %e,
+                    path = ?config.file,
+                    "Failed to open telemetry file, not starting telemetry"
+                );
+                return;
+            }
         };
 
         tracing::info!("Starting telemetry");
-        let inner = sentry::init((
-            dsn.0,
-            sentry::ClientOptions {
-                environment: Some(environment.into()),
-                // We can't get the release number ourselves because we don't know if we're embedded in a GUI Client or a Headless Client.
-                release: Some(release.to_owned().into()),
-                // We submit all spans but only send the ones with `target: telemetry`.
-                // Those spans are created further down and are throttled at creation time to save CPU.
-                traces_sample_rate: 1.0,
-                max_breadcrumbs: 500,
-                ..Default::default()
-            },
-        ));
+        let inner = sentry::init(sentry::ClientOptions {
+            dsn: Some(dsn),
+            environment: Some(environment.into()),
+            // We can't get the release number ourselves because we don't know if we're embedded in a GUI Client or a Headless Client.
+            release: Some(release.to_owned().into()),
+            sample_rate: config.sample_rate.unwrap_or(1.0),
+            // We submit all spans but only send the ones with `target: telemetry`.
+            // Those spans are created further down and are throttled at creation time to save CPU.
+            traces_sample_rate: config.traces_sample_rate.unwrap_or(1.0),
+            max_breadcrumbs: 500,
+            transport: transport.map(|transport| {
+                let transport: Arc<dyn sentry::Transport> = Arc::new(transport);
+
+                Arc::new(move |_: &sentry::ClientOptions| transport.clone())
+                    as Arc<dyn sentry::TransportFactory>
+            }),
+            ..Default::default()
+        });
         // Configure scope on the main hub so that all threads will get the tags
         sentry::Hub::main().configure_scope(|scope| {
             scope.set_tag("api_url", api_url);
@@ -117,7 +174,7 @@ impl Telemetry {
             // `flush`'s return value is flipped from the docs
             // <https://github.com/getsentry/sentry-rust/issues/677>
             if inner.flush(Some(Duration::from_secs(5))) {
-                tracing::error!("Failed to flush telemetry events to sentry.io");
+                tracing::error!("Failed to flush telemetry events");
                 return;
             };
 
@@ -152,6 +209,48 @@ impl Telemetry {
     }
 }
 
+/// A [`sentry::Transport`] that appends events to a file as JSON lines, for offline collection.
+struct FileTransport {
+    file: Mutex<File>,
+}
+
+impl FileTransport {
+    fn new(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+}
+
+impl sentry::Transport for FileTransport {
+    fn send_envelope(&self, envelope: sentry::Envelope) {
+        // Sessions and transactions are only useful to a Sentry server.
+        let Some(event) = envelope.event() else {
+            return;
+        };
+        let Ok(mut file) = self.file.lock() else {
+            return;
+        };
+
+        let result = serde_json::to_writer(&mut *file, event)
+            .map_err(io::Error::from)
+            .and_then(|()| file.write_all(b"\n"));
+
+        if let Err(e) = result {
+            // Don't log this with `tracing`, because the error would end up right back here.
+            let _ = writeln!(io::stderr(), "Failed to write telemetry event: {e}");
+        }
+    }
+
+    // Like `ClientInitGuard::flush`, this returns `false` on success.
+    // <https://github.com/getsentry/sentry-rust/issues/677>
+    fn flush(&self, _: Duration) -> bool {
+        !self.file.lock().is_ok_and(|mut file| file.flush().is_ok())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -206,6 +305,31 @@ mod tests {
         }
     }
 
+    #[test]
+    fn file_transport_writes_events_as_json_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.jsonl");
+        let transport = FileTransport::new(&path).unwrap();
+
+        for message in ["first", "second"] {
+            sentry::Transport::send_envelope(
+                &transport,
+                sentry::protocol::Event {
+                    message: Some(message.to_owned()),
+                    ..Default::default()
+                }
+                .into(),
+            );
+        }
+
+        let lines = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["message"].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(lines, ["first", "second"]);
+    }
+
     #[derive(Debug, thiserror::Error)]
     enum Error {
         #[error("Test error #{0}, this should appear in the sentry.io portal")]
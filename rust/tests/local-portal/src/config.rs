@@ -0,0 +1,247 @@
+//! The YAML file describing the account that the local portal serves
+//!
+//! IDs are derived from names, so they stay the same across restarts of the portal.
+
+use anyhow::{bail, Context as _, Result};
+use connlib_model::{ResourceId, SiteId};
+use ip_network::IpNetwork;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, net::SocketAddr, path::Path};
+use uuid::Uuid;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Token that relays connect with
+    pub relay_token: String,
+    /// DNS servers for Clients to forward non-resource queries to, defaults to the system's
+    #[serde(default)]
+    pub upstream_dns: Vec<SocketAddr>,
+    pub sites: Vec<Site>,
+    pub resources: Vec<Resource>,
+    pub actors: Vec<Actor>,
+    pub policies: Vec<Policy>,
+}
+
+/// A group of Gateways, all of which can serve the Site's resources
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Site {
+    pub name: String,
+    /// Token that Gateways of this Site connect with
+    pub token: String,
+}
+
+/// A user, Clients connect as an actor
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Actor {
+    pub name: String,
+    /// Token that Clients of this actor connect with
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Resource {
+    pub name: String,
+    #[serde(flatten)]
+    pub kind: ResourceKind,
+    #[serde(default)]
+    pub address_description: Option<String>,
+    /// Names of the Sites that serve this resource
+    pub sites: Vec<String>,
+    /// Traffic that Gateways let through, all traffic if empty
+    #[serde(default)]
+    pub filters: Vec<Filter>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResourceKind {
+    Dns { address: String },
+    Cidr { address: IpNetwork },
+    Internet,
+}
+
+/// Same format as the portal's filters, passed to Gateways as-is
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(tag = "protocol", rename_all = "snake_case")]
+pub enum Filter {
+    Udp {
+        port_range_start: u16,
+        port_range_end: u16,
+    },
+    Tcp {
+        port_range_start: u16,
+        port_range_end: u16,
+    },
+    Icmp,
+}
+
+/// Allows an actor to access a resource
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    pub actor: String,
+    pub resource: String,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self> {
+        let s = std::fs::read_to_string(path)
+            .with_context(|| format!("Couldn't read config file `{}`", path.display()))?;
+
+        Self::parse(&s)
+    }
+
+    pub fn parse(s: &str) -> Result<Self> {
+        let config = serde_yaml::from_str::<Self>(s).context("Couldn't parse config file")?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    pub fn site(&self, name: &str) -> Option<&Site> {
+        self.sites.iter().find(|site| site.name == name)
+    }
+
+    pub fn resource(&self, id: ResourceId) -> Option<&Resource> {
+        self.resources.iter().find(|resource| resource.id() == id)
+    }
+
+    /// The resources that `actor` may access
+    pub fn resources_for(&self, actor: &str) -> impl Iterator<Item = &Resource> {
+        self.resources
+            .iter()
+            .filter(move |resource| self.is_allowed(actor, resource.id()))
+    }
+
+    /// The resource with `id`, if `actor` may access it
+    pub fn allowed_resource(&self, actor: &str, id: ResourceId) -> Option<&Resource> {
+        if !self.is_allowed(actor, id) {
+            return None;
+        }
+
+        self.resource(id)
+    }
+
+    pub fn is_allowed(&self, actor: &str, resource: ResourceId) -> bool {
+        self.policies.iter().any(|policy| {
+            policy.actor == actor
+                && self
+                    .resources
+                    .iter()
+                    .any(|r| r.name == policy.resource && r.id() == resource)
+        })
+    }
+
+    fn validate(&self) -> Result<()> {
+        unique("site", self.sites.iter().map(|s| s.name.as_str()))?;
+        unique("resource", self.resources.iter().map(|r| r.name.as_str()))?;
+        unique("actor", self.actors.iter().map(|a| a.name.as_str()))?;
+        unique(
+            "token",
+            self.sites
+                .iter()
+                .map(|s| s.token.as_str())
+                .chain(self.actors.iter().map(|a| a.token.as_str()))
+                .chain([self.relay_token.as_str()]),
+        )?;
+
+        for resource in &self.resources {
+            for site in &resource.sites {
+                if self.site(site).is_none() {
+                    bail!(
+                        "Resource `{}` refers to unknown site `{site}`",
+                        resource.name
+                    );
+                }
+            }
+        }
+
+        for policy in &self.policies {
+            if !self.actors.iter().any(|a| a.name == policy.actor) {
+                bail!("Policy refers to unknown actor `{}`", policy.actor);
+            }
+            if !self.resources.iter().any(|r| r.name == policy.resource) {
+                bail!("Policy refers to unknown resource `{}`", policy.resource);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Site {
+    pub fn id(&self) -> SiteId {
+        SiteId::from_u128(stable_id("site", &self.name))
+    }
+}
+
+impl Resource {
+    pub fn id(&self) -> ResourceId {
+        ResourceId::from_u128(stable_id("resource", &self.name))
+    }
+
+    pub fn sites<'a>(&'a self, config: &'a Config) -> impl Iterator<Item = &'a Site> {
+        self.sites.iter().filter_map(|name| config.site(name))
+    }
+}
+
+/// Derives a UUID from `name`, so the same config always produces the same IDs
+pub fn stable_id(kind: &str, name: &str) -> u128 {
+    Uuid::new_v5(&Uuid::NAMESPACE_OID, format!("{kind}/{name}").as_bytes()).as_u128()
+}
+
+fn unique<'a>(kind: &str, names: impl Iterator<Item = &'a str>) -> Result<()> {
+    let mut seen = HashSet::new();
+
+    for name in names {
+        if !seen.insert(name) {
+            bail!("Duplicate {kind} `{name}`");
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = include_str!("../example.yaml");
+
+    #[test]
+    fn parses_example() {
+        let config = Config::parse(EXAMPLE).unwrap();
+
+        let alice = config.resources_for("alice").map(|r| r.name.as_str());
+        assert_eq!(
+            alice.collect::<Vec<_>>(),
+            ["GitLab", "Private network", "Internet"]
+        );
+        let bob = config.resources_for("bob").map(|r| r.name.as_str());
+        assert_eq!(bob.collect::<Vec<_>>(), ["GitLab"]);
+    }
+
+    #[test]
+    fn ids_are_stable() {
+        let a = Config::parse(EXAMPLE).unwrap();
+        let b = Config::parse(EXAMPLE).unwrap();
+
+        assert_eq!(a.resources[0].id(), b.resources[0].id());
+        assert_ne!(a.resources[0].id(), a.resources[1].id());
+    }
+
+    #[test]
+    fn rejects_unknown_site() {
+        let config = EXAMPLE.replace("sites: [office]", "sites: [office, moon]");
+
+        let error = Config::parse(&config).unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "Resource `GitLab` refers to unknown site `moon`"
+        );
+    }
+}
@@ -0,0 +1,206 @@
+//! A stand-in for the portal, for end-to-end tests of Clients, Gateways and relays without the real control plane
+//!
+//! Serves a fixed account described by a YAML file, see `example.yaml`.
+
+#![cfg_attr(test, allow(clippy::unwrap_used))]
+
+mod config;
+mod messages;
+mod portal;
+
+use anyhow::{Context as _, Result};
+use clap::Parser;
+use config::Config;
+use futures::{SinkExt as _, StreamExt as _};
+use portal::{ConnectionId, Login, Portal};
+use std::{collections::HashMap, net::SocketAddr, path::PathBuf, sync::Arc, time::SystemTime};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+};
+use tokio_tungstenite::tungstenite::{
+    handshake::server::{ErrorResponse, Request, Response},
+    http::StatusCode,
+    Message,
+};
+
+#[derive(Debug, Parser)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Path to the YAML file describing sites, resources, actors and policies
+    #[arg(long, env = "LOCAL_PORTAL_CONFIG")]
+    config: PathBuf,
+    /// Address to accept websocket connections on
+    #[arg(long, env = "LOCAL_PORTAL_LISTEN_ADDR", default_value = "0.0.0.0:8081")]
+    listen_addr: SocketAddr,
+}
+
+enum Command {
+    Connect(
+        ConnectionId,
+        Login,
+        mpsc::UnboundedSender<messages::Outbound>,
+    ),
+    Message(ConnectionId, String),
+    Disconnect(ConnectionId),
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    firezone_logging::setup_global_subscriber(tracing_subscriber::layer::Identity::default())?;
+
+    let config = Arc::new(Config::load(&args.config)?);
+    let listener = TcpListener::bind(args.listen_addr)
+        .await
+        .with_context(|| format!("Failed to listen on {}", args.listen_addr))?;
+    tracing::info!(addr = %args.listen_addr, "Listening for websocket connections");
+
+    let (commands_tx, commands_rx) = mpsc::unbounded_channel();
+    tokio::spawn(broker(Portal::new(config.clone()), commands_rx));
+
+    for id in 0.. {
+        let (stream, remote) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                tracing::warn!("Failed to accept connection: {e}");
+                continue;
+            }
+        };
+
+        tokio::spawn(handle_connection(
+            ConnectionId(id),
+            stream,
+            remote,
+            config.clone(),
+            commands_tx.clone(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Owns the [`Portal`] and routes its messages to the websocket connections
+async fn broker(mut portal: Portal, mut commands: mpsc::UnboundedReceiver<Command>) {
+    let mut connections = HashMap::new();
+
+    while let Some(command) = commands.recv().await {
+        match command {
+            Command::Connect(conn, login, tx) => {
+                connections.insert(conn, tx);
+                portal.connect(conn, login);
+            }
+            Command::Message(conn, text) => {
+                if let Err(e) = portal.handle_message(conn, &text, SystemTime::now()) {
+                    tracing::warn!(?conn, %text, "Failed to handle message: {e:#}");
+                }
+            }
+            Command::Disconnect(conn) => {
+                connections.remove(&conn);
+                portal.disconnect(conn, SystemTime::now());
+            }
+        }
+
+        while let Some((conn, msg)) = portal.poll_transmit() {
+            let Some(tx) = connections.get(&conn) else {
+                continue;
+            };
+
+            // The connection task will send `Disconnect` if the receiver is gone.
+            let _ = tx.send(msg);
+        }
+    }
+}
+
+async fn handle_connection(
+    conn: ConnectionId,
+    stream: TcpStream,
+    remote: SocketAddr,
+    config: Arc<Config>,
+    commands: mpsc::UnboundedSender<Command>,
+) {
+    let mut login = None;
+    let callback = |request: &Request, response: Response| match Login::authenticate(
+        &config,
+        request.uri().path(),
+        request.uri().query(),
+        remote.ip(),
+    ) {
+        Ok(l) => {
+            login = Some(l);
+
+            Ok(response)
+        }
+        Err(e) => {
+            tracing::info!(%remote, path = %request.uri().path(), "Rejecting connection: {e:#}");
+
+            let mut response = ErrorResponse::new(Some(format!("{e:#}")));
+            *response.status_mut() = StatusCode::UNAUTHORIZED;
+
+            Err(response)
+        }
+    };
+
+    let ws = match tokio_tungstenite::accept_hdr_async(stream, callback).await {
+        Ok(ws) => ws,
+        Err(e) => {
+            tracing::debug!(%remote, "Websocket handshake failed: {e}");
+            return;
+        }
+    };
+    let Some(login) = login else {
+        return;
+    };
+
+    let (outbound_tx, mut outbound_rx) = mpsc::unbounded_channel();
+    if commands
+        .send(Command::Connect(conn, login, outbound_tx))
+        .is_err()
+    {
+        return;
+    }
+
+    let (mut sink, mut stream) = ws.split();
+
+    loop {
+        tokio::select! {
+            msg = outbound_rx.recv() => {
+                let Some(msg) = msg else {
+                    break;
+                };
+                let text = match serde_json::to_string(&msg) {
+                    Ok(text) => text,
+                    Err(e) => {
+                        tracing::warn!("Failed to serialize message: {e}");
+                        continue;
+                    }
+                };
+
+                if let Err(e) = sink.send(Message::Text(text)).await {
+                    tracing::debug!(?conn, "Failed to send message: {e}");
+                    break;
+                }
+            }
+            msg = stream.next() => {
+                match msg {
+                    Some(Ok(Message::Text(text))) => {
+                        if commands.send(Command::Message(conn, text)).is_err() {
+                            break;
+                        }
+                    }
+                    Some(Ok(Message::Close(_))) | None => break,
+                    // `tungstenite` answers pings by itself.
+                    Some(Ok(
+                        Message::Binary(_) | Message::Ping(_) | Message::Pong(_) | Message::Frame(_),
+                    )) => {}
+                    Some(Err(e)) => {
+                        tracing::debug!(?conn, "Websocket error: {e}");
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    let _ = commands.send(Command::Disconnect(conn));
+}
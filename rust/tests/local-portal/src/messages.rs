@@ -0,0 +1,247 @@
+//! The portal's side of the phoenix-channel protocol
+//!
+//! These mirror `firezone_tunnel::messages` and `firezone_relay::messages`, but in the opposite direction:
+//! we deserialize what they send and serialize what they receive.
+//! Payloads that we only forward between Clients and Gateways are kept as [`Value`].
+//!
+//! The tests in `portal.rs` round-trip these through the real types to catch any drift.
+
+use crate::config::{Filter, ResourceKind};
+use connlib_model::{ClientId, GatewayId, RelayId, ResourceId, Site, SiteId};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::BTreeSet,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+/// A message from a Client, Gateway or relay
+#[derive(Debug, Deserialize)]
+pub struct Inbound<T> {
+    pub topic: String,
+    #[serde(flatten)]
+    pub payload: T,
+    #[serde(rename = "ref")]
+    pub reference: Option<u64>,
+}
+
+/// A message, reply or push to a Client, Gateway or relay
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Outbound {
+    pub topic: String,
+    pub event: String,
+    pub payload: Value,
+    #[serde(rename = "ref")]
+    pub reference: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "event", content = "payload")]
+pub enum ClientEgress {
+    PhxJoin(Value),
+    Heartbeat(Value),
+    PrepareConnection {
+        resource_id: ResourceId,
+        #[serde(default)]
+        connected_gateway_ids: BTreeSet<GatewayId>,
+    },
+    RequestConnection(RequestConnection),
+    ReuseConnection(ReuseConnection),
+    BroadcastIceCandidates(GatewaysIceCandidates),
+    BroadcastInvalidatedIceCandidates(GatewaysIceCandidates),
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RequestConnection {
+    pub gateway_id: GatewayId,
+    pub resource_id: ResourceId,
+    pub client_preshared_key: Value,
+    pub client_payload: Value,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReuseConnection {
+    pub resource_id: ResourceId,
+    pub gateway_id: GatewayId,
+    pub payload: Option<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GatewaysIceCandidates {
+    pub gateway_ids: Vec<GatewayId>,
+    pub candidates: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "event", content = "payload")]
+pub enum GatewayEgress {
+    PhxJoin(Value),
+    Heartbeat(Value),
+    ConnectionReady {
+        #[serde(rename = "ref")]
+        reference: String,
+        gateway_payload: Value,
+    },
+    BroadcastIceCandidates(ClientsIceCandidates),
+    BroadcastInvalidatedIceCandidates(ClientsIceCandidates),
+    FlowAuthorized {
+        #[serde(rename = "ref")]
+        reference: String,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ClientsIceCandidates {
+    pub client_ids: Vec<ClientId>,
+    pub candidates: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "event", content = "payload")]
+pub enum RelayEgress {
+    PhxJoin(RelayJoin),
+    Heartbeat(Value),
+    Usage(Value),
+    DrainStatus(Value),
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RelayJoin {
+    pub stamp_secret: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Interface {
+    pub ipv4: Ipv4Addr,
+    pub ipv6: Ipv6Addr,
+    pub upstream_dns: Vec<DnsServer>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "protocol", rename_all = "snake_case")]
+pub enum DnsServer {
+    IpPort { address: SocketAddr },
+}
+
+#[derive(Debug, Serialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Relay {
+    Turn {
+        id: RelayId,
+        /// Unix timestamp in seconds
+        expires_at: u64,
+        addr: SocketAddr,
+        username: String,
+        password: String,
+    },
+}
+
+#[derive(Debug, Serialize)]
+pub struct RelaysPresence {
+    pub disconnected_ids: Vec<RelayId>,
+    pub connected: Vec<Relay>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InitClient<'a> {
+    pub interface: Interface,
+    pub resources: Vec<ClientResource<'a>>,
+    pub relays: Vec<Relay>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClientResource<'a> {
+    pub id: ResourceId,
+    pub name: &'a str,
+    #[serde(flatten)]
+    pub kind: &'a ResourceKind,
+    pub address_description: Option<&'a str>,
+    pub gateway_groups: Vec<Site>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ConnectionDetails {
+    pub resource_id: ResourceId,
+    pub gateway_id: GatewayId,
+    pub gateway_remote_ip: IpAddr,
+    pub gateway_group_id: SiteId,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Connect {
+    pub gateway_payload: Value,
+    pub resource_id: ResourceId,
+    pub gateway_public_key: String,
+    pub persistent_keepalive: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GatewayIceCandidates {
+    pub gateway_id: GatewayId,
+    pub candidates: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InitGateway {
+    pub interface: Interface,
+    pub config: GatewayConfig,
+    pub relays: Vec<Relay>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GatewayConfig {
+    pub ipv4_masquerade_enabled: bool,
+    pub ipv6_masquerade_enabled: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GatewayResource<'a> {
+    pub id: ResourceId,
+    pub name: &'a str,
+    #[serde(flatten)]
+    pub kind: &'a ResourceKind,
+    pub filters: &'a [Filter],
+}
+
+#[derive(Debug, Serialize)]
+pub struct GatewayRequestConnection<'a> {
+    pub resource: GatewayResource<'a>,
+    pub client: LegacyClient,
+    #[serde(rename = "ref")]
+    pub reference: String,
+    pub expires_at: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LegacyClient {
+    pub id: ClientId,
+    pub payload: Value,
+    pub peer: Peer,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Peer {
+    pub persistent_keepalive: Option<u16>,
+    pub public_key: String,
+    pub ipv4: Ipv4Addr,
+    pub ipv6: Ipv6Addr,
+    pub preshared_key: Value,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AllowAccess<'a> {
+    pub client_id: ClientId,
+    pub resource: GatewayResource<'a>,
+    pub expires_at: Option<u64>,
+    pub payload: Option<Value>,
+    #[serde(rename = "ref")]
+    pub reference: String,
+    pub client_ipv4: Ipv4Addr,
+    pub client_ipv6: Ipv6Addr,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClientIceCandidates {
+    pub client_id: ClientId,
+    pub candidates: Vec<String>,
+}
@@ -0,0 +1,1207 @@
+//! The sans-IO broker between Clients, Gateways and relays
+//!
+//! Speaks the same protocol as the real portal, but only the parts that connlib needs to establish connections:
+//! Clients and Gateways get their `init` on join, relay presence is pushed as relays come and go,
+//! and connection requests and ICE candidates are forwarded between Clients and Gateways.
+
+use crate::{
+    config::{stable_id, Config, Resource},
+    messages::{
+        AllowAccess, ClientEgress, ClientIceCandidates, ClientResource, ClientsIceCandidates,
+        Connect, ConnectionDetails, DnsServer, GatewayConfig, GatewayEgress, GatewayIceCandidates,
+        GatewayRequestConnection, GatewayResource, GatewaysIceCandidates, Inbound, InitClient,
+        InitGateway, Interface, LegacyClient, Outbound, Peer, Relay, RelayEgress, RelaysPresence,
+        RequestConnection, ReuseConnection,
+    },
+};
+use anyhow::{bail, ensure, Context as _, Result};
+use connlib_model::{ClientId, GatewayId, RelayId, ResourceId, Site, SiteId};
+use firezone_relay::auth::generate_password;
+use secrecy::SecretString;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+/// How long the TURN credentials we hand out are valid for
+const RELAY_CREDENTIALS_LIFETIME: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+const PERSISTENT_KEEPALIVE: u16 = 25;
+const DEFAULT_RELAY_PORT: u16 = 3478;
+
+const IPV4_TUNNEL: Ipv4Addr = Ipv4Addr::new(100, 64, 0, 0);
+const IPV6_TUNNEL: Ipv6Addr = Ipv6Addr::new(0xfd00, 0x2021, 0x1111, 0, 0, 0, 0, 0);
+
+/// Identifies a websocket connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ConnectionId(pub u64);
+
+/// Who connected, as authenticated from the websocket URL
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Login {
+    Client {
+        actor: String,
+        id: ClientId,
+        public_key: String,
+    },
+    Gateway {
+        site: String,
+        id: GatewayId,
+        public_key: String,
+        remote_ip: IpAddr,
+    },
+    Relay {
+        id: RelayId,
+        addresses: Vec<SocketAddr>,
+    },
+}
+
+impl Login {
+    /// Authenticates a websocket request from its path and query parameters, like the portal does
+    pub fn authenticate(
+        config: &Config,
+        path: &str,
+        query: Option<&str>,
+        remote_ip: IpAddr,
+    ) -> Result<Self> {
+        let params = url::form_urlencoded::parse(query.unwrap_or_default().as_bytes())
+            .into_owned()
+            .collect::<HashMap<_, _>>();
+        let param = |name: &str| {
+            params
+                .get(name)
+                .with_context(|| format!("Missing query parameter `{name}`"))
+        };
+        let token = param("token")?;
+
+        match path.trim_end_matches('/') {
+            "/client/websocket" => {
+                let actor = config
+                    .actors
+                    .iter()
+                    .find(|actor| &actor.token == token)
+                    .context("Unknown Client token")?;
+
+                Ok(Self::Client {
+                    actor: actor.name.clone(),
+                    id: ClientId::from_u128(stable_id("client", param("external_id")?)),
+                    public_key: param("public_key")?.clone(),
+                })
+            }
+            "/gateway/websocket" => {
+                let site = config
+                    .sites
+                    .iter()
+                    .find(|site| &site.token == token)
+                    .context("Unknown Gateway token")?;
+
+                Ok(Self::Gateway {
+                    site: site.name.clone(),
+                    id: GatewayId::from_u128(stable_id("gateway", param("external_id")?)),
+                    public_key: param("public_key")?.clone(),
+                    remote_ip,
+                })
+            }
+            "/relay/websocket" => {
+                ensure!(token == &config.relay_token, "Unknown relay token");
+
+                let port = params
+                    .get("port")
+                    .map(|port| port.parse())
+                    .transpose()
+                    .context("Invalid `port`")?
+                    .unwrap_or(DEFAULT_RELAY_PORT);
+                let ipv4 = params
+                    .get("ipv4")
+                    .map(|ip| ip.parse::<Ipv4Addr>())
+                    .transpose()
+                    .context("Invalid `ipv4`")?;
+                let ipv6 = params
+                    .get("ipv6")
+                    .map(|ip| ip.parse::<Ipv6Addr>())
+                    .transpose()
+                    .context("Invalid `ipv6`")?;
+
+                let mut addresses = ipv4
+                    .map(IpAddr::from)
+                    .into_iter()
+                    .chain(ipv6.map(IpAddr::from))
+                    .map(|ip| SocketAddr::new(ip, port))
+                    .collect::<Vec<_>>();
+                if addresses.is_empty() {
+                    addresses.push(SocketAddr::new(remote_ip, port));
+                }
+
+                Ok(Self::Relay {
+                    id: RelayId::from_u128(stable_id("relay", &format!("{addresses:?}"))),
+                    addresses,
+                })
+            }
+            other => bail!("Unknown path `{other}`"),
+        }
+    }
+}
+
+pub struct Portal {
+    config: Arc<Config>,
+    connections: BTreeMap<ConnectionId, Connection>,
+    tunnel_addresses: TunnelAddresses,
+    /// Connection requests that we forwarded to a Gateway, by the `ref` we sent along
+    pending_connections: HashMap<String, PendingConnection>,
+    next_reference: u64,
+
+    transmits: VecDeque<(ConnectionId, Outbound)>,
+}
+
+struct Connection {
+    login: Login,
+    joined: bool,
+    /// The secret that a relay derives TURN credentials from, known once it joined
+    stamp_secret: Option<SecretString>,
+}
+
+struct PendingConnection {
+    client: ConnectionId,
+    /// The `ref` of the Client's `request_connection`, for the reply
+    reference: Option<u64>,
+    resource_id: ResourceId,
+    gateway: ConnectionId,
+}
+
+impl Portal {
+    pub fn new(config: Arc<Config>) -> Self {
+        Self {
+            config,
+            connections: Default::default(),
+            tunnel_addresses: Default::default(),
+            pending_connections: Default::default(),
+            next_reference: 0,
+            transmits: Default::default(),
+        }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn connect(&mut self, conn: ConnectionId, login: Login) {
+        tracing::info!(?conn, ?login, "Connected");
+
+        self.connections.insert(
+            conn,
+            Connection {
+                login,
+                joined: false,
+                stamp_secret: None,
+            },
+        );
+    }
+
+    pub fn disconnect(&mut self, conn: ConnectionId, now: SystemTime) {
+        let Some(connection) = self.connections.remove(&conn) else {
+            return;
+        };
+        tracing::info!(?conn, login = ?connection.login, "Disconnected");
+
+        match connection.login {
+            Login::Client { .. } => {
+                self.pending_connections
+                    .retain(|_, pending| pending.client != conn);
+            }
+            Login::Gateway { .. } => {
+                let (failed, pending) =
+                    std::mem::take(&mut self.pending_connections)
+                        .into_iter()
+                        .partition::<HashMap<_, _>, _>(|(_, pending)| pending.gateway == conn);
+                self.pending_connections = pending;
+
+                for pending in failed.into_values() {
+                    self.reply_error(pending.client, "client", pending.reference, "offline");
+                }
+            }
+            Login::Relay { id, .. } => {
+                if connection.stamp_secret.is_some() {
+                    self.broadcast_relays_presence(vec![id], now);
+                }
+            }
+        }
+    }
+
+    /// Handles a websocket text message from `conn`
+    pub fn handle_message(
+        &mut self,
+        conn: ConnectionId,
+        text: &str,
+        now: SystemTime,
+    ) -> Result<()> {
+        let login = self
+            .connections
+            .get(&conn)
+            .context("Unknown connection")?
+            .login
+            .clone();
+
+        match login {
+            Login::Client {
+                actor,
+                id,
+                public_key,
+            } => {
+                let msg = serde_json::from_str(text).context("Unknown message from Client")?;
+                self.handle_client_message(conn, &actor, id, &public_key, msg, now)
+            }
+            Login::Gateway { id, public_key, .. } => {
+                let msg = serde_json::from_str(text).context("Unknown message from Gateway")?;
+                self.handle_gateway_message(conn, id, &public_key, msg, now)
+            }
+            Login::Relay { .. } => {
+                let msg = serde_json::from_str(text).context("Unknown message from relay")?;
+                self.handle_relay_message(conn, msg, now)
+            }
+        }
+    }
+
+    pub fn poll_transmit(&mut self) -> Option<(ConnectionId, Outbound)> {
+        self.transmits.pop_front()
+    }
+
+    fn handle_client_message(
+        &mut self,
+        conn: ConnectionId,
+        actor: &str,
+        client_id: ClientId,
+        public_key: &str,
+        msg: Inbound<ClientEgress>,
+        now: SystemTime,
+    ) -> Result<()> {
+        let Inbound {
+            topic,
+            payload,
+            reference,
+        } = msg;
+
+        match payload {
+            ClientEgress::Heartbeat(_) => self.reply_ok(conn, &topic, reference, json!({})),
+            ClientEgress::PhxJoin(_) => {
+                self.reply_ok(conn, &topic, reference, json!({}));
+                self.join(conn);
+
+                let (ipv4, ipv6) = self.tunnel_addresses.get(client_id.to_string());
+                let init = InitClient {
+                    interface: self.interface(ipv4, ipv6),
+                    resources: self
+                        .config
+                        .resources_for(actor)
+                        .map(|resource| ClientResource {
+                            id: resource.id(),
+                            name: &resource.name,
+                            kind: &resource.kind,
+                            address_description: resource.address_description.as_deref(),
+                            gateway_groups: resource
+                                .sites(&self.config)
+                                .map(|site| Site {
+                                    id: site.id(),
+                                    name: site.name.clone(),
+                                })
+                                .collect(),
+                        })
+                        .collect(),
+                    relays: self.relays(&client_id.to_string(), now),
+                };
+                let init = serde_json::to_value(init)?;
+
+                self.push(conn, "client", "init", init);
+            }
+            ClientEgress::PrepareConnection {
+                resource_id,
+                connected_gateway_ids,
+            } => {
+                let Some(resource) = self.config.allowed_resource(actor, resource_id) else {
+                    self.reply_error(conn, &topic, reference, "not_found");
+                    return Ok(());
+                };
+
+                // Prefer Gateways that the Client is already connected to, like the portal does.
+                let gateway = self
+                    .gateways_for(resource)
+                    .min_by_key(|(_, gateway)| !connected_gateway_ids.contains(&gateway.id));
+                let Some((_, gateway)) = gateway else {
+                    self.reply_error(conn, &topic, reference, "offline");
+                    return Ok(());
+                };
+                let details = ConnectionDetails {
+                    resource_id,
+                    gateway_id: gateway.id,
+                    gateway_remote_ip: gateway.remote_ip,
+                    gateway_group_id: gateway.site_id,
+                };
+
+                self.reply_ok(conn, &topic, reference, details);
+            }
+            ClientEgress::RequestConnection(RequestConnection {
+                gateway_id,
+                resource_id,
+                client_preshared_key,
+                client_payload,
+            }) => {
+                let Some(resource) = self.config.allowed_resource(actor, resource_id) else {
+                    self.reply_error(conn, &topic, reference, "not_found");
+                    return Ok(());
+                };
+                let Some((gateway, _)) = self
+                    .gateways_for(resource)
+                    .find(|(_, gateway)| gateway.id == gateway_id)
+                else {
+                    self.reply_error(conn, &topic, reference, "offline");
+                    return Ok(());
+                };
+
+                let (ipv4, ipv6) = self.tunnel_addresses.get(client_id.to_string());
+                self.next_reference += 1;
+                let gateway_reference = self.next_reference.to_string();
+                let request = GatewayRequestConnection {
+                    resource: gateway_resource(resource),
+                    client: LegacyClient {
+                        id: client_id,
+                        payload: client_payload,
+                        peer: Peer {
+                            persistent_keepalive: Some(PERSISTENT_KEEPALIVE),
+                            public_key: public_key.to_owned(),
+                            ipv4,
+                            ipv6,
+                            preshared_key: client_preshared_key,
+                        },
+                    },
+                    reference: gateway_reference.clone(),
+                    expires_at: None,
+                };
+                let request = serde_json::to_value(request)?;
+
+                self.pending_connections.insert(
+                    gateway_reference,
+                    PendingConnection {
+                        client: conn,
+                        reference,
+                        resource_id,
+                        gateway,
+                    },
+                );
+                self.push(gateway, "gateway", "request_connection", request);
+            }
+            ClientEgress::ReuseConnection(ReuseConnection {
+                resource_id,
+                gateway_id,
+                payload,
+            }) => {
+                let Some(resource) = self.config.allowed_resource(actor, resource_id) else {
+                    self.reply_error(conn, &topic, reference, "not_found");
+                    return Ok(());
+                };
+                let Some((gateway, _)) = self
+                    .gateways_for(resource)
+                    .find(|(_, gateway)| gateway.id == gateway_id)
+                else {
+                    self.reply_error(conn, &topic, reference, "offline");
+                    return Ok(());
+                };
+
+                let (client_ipv4, client_ipv6) = self.tunnel_addresses.get(client_id.to_string());
+                self.next_reference += 1;
+                let allow_access = AllowAccess {
+                    client_id,
+                    resource: gateway_resource(resource),
+                    expires_at: None,
+                    payload,
+                    reference: self.next_reference.to_string(),
+                    client_ipv4,
+                    client_ipv6,
+                };
+                let allow_access = serde_json::to_value(allow_access)?;
+
+                self.push(gateway, "gateway", "allow_access", allow_access);
+                self.reply_ok(conn, &topic, reference, json!({}));
+            }
+            ClientEgress::BroadcastIceCandidates(GatewaysIceCandidates {
+                gateway_ids,
+                candidates,
+            }) => self.forward_to_gateways(client_id, gateway_ids, "ice_candidates", candidates),
+            ClientEgress::BroadcastInvalidatedIceCandidates(GatewaysIceCandidates {
+                gateway_ids,
+                candidates,
+            }) => self.forward_to_gateways(
+                client_id,
+                gateway_ids,
+                "invalidate_ice_candidates",
+                candidates,
+            ),
+        }
+
+        Ok(())
+    }
+
+    fn handle_gateway_message(
+        &mut self,
+        conn: ConnectionId,
+        gateway_id: GatewayId,
+        public_key: &str,
+        msg: Inbound<GatewayEgress>,
+        now: SystemTime,
+    ) -> Result<()> {
+        let Inbound {
+            topic,
+            payload,
+            reference,
+        } = msg;
+
+        match payload {
+            GatewayEgress::Heartbeat(_) => self.reply_ok(conn, &topic, reference, json!({})),
+            GatewayEgress::PhxJoin(_) => {
+                self.reply_ok(conn, &topic, reference, json!({}));
+                self.join(conn);
+
+                let (ipv4, ipv6) = self.tunnel_addresses.get(gateway_id.to_string());
+                let init = InitGateway {
+                    interface: self.interface(ipv4, ipv6),
+                    config: GatewayConfig {
+                        ipv4_masquerade_enabled: true,
+                        ipv6_masquerade_enabled: true,
+                    },
+                    relays: self.relays(&gateway_id.to_string(), now),
+                };
+                let init = serde_json::to_value(init)?;
+
+                self.push(conn, "gateway", "init", init);
+            }
+            GatewayEgress::ConnectionReady {
+                reference: gateway_reference,
+                gateway_payload,
+            } => {
+                let pending = self
+                    .pending_connections
+                    .remove(&gateway_reference)
+                    .context("Unknown connection request")?;
+                let connect = Connect {
+                    gateway_payload,
+                    resource_id: pending.resource_id,
+                    gateway_public_key: public_key.to_owned(),
+                    persistent_keepalive: PERSISTENT_KEEPALIVE.into(),
+                };
+
+                self.reply_ok(pending.client, "client", pending.reference, connect);
+            }
+            GatewayEgress::BroadcastIceCandidates(ClientsIceCandidates {
+                client_ids,
+                candidates,
+            }) => self.forward_to_clients(gateway_id, client_ids, "ice_candidates", candidates),
+            GatewayEgress::BroadcastInvalidatedIceCandidates(ClientsIceCandidates {
+                client_ids,
+                candidates,
+            }) => self.forward_to_clients(
+                gateway_id,
+                client_ids,
+                "invalidate_ice_candidates",
+                candidates,
+            ),
+            GatewayEgress::FlowAuthorized { reference } => {
+                // We never send `authorize_flow`, Clients still request connections the legacy way.
+                tracing::debug!(%reference, "Ignoring unexpected `flow_authorized`");
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_relay_message(
+        &mut self,
+        conn: ConnectionId,
+        msg: Inbound<RelayEgress>,
+        now: SystemTime,
+    ) -> Result<()> {
+        let Inbound {
+            topic,
+            payload,
+            reference,
+        } = msg;
+
+        match payload {
+            RelayEgress::Heartbeat(_) => self.reply_ok(conn, &topic, reference, json!({})),
+            RelayEgress::PhxJoin(join) => {
+                self.reply_ok(conn, &topic, reference, json!({}));
+                self.join(conn);
+                if let Some(connection) = self.connections.get_mut(&conn) {
+                    connection.stamp_secret = Some(SecretString::new(join.stamp_secret));
+                }
+
+                self.push(conn, "relay", "init", json!({}));
+                self.broadcast_relays_presence(Vec::new(), now);
+            }
+            RelayEgress::Usage(_) | RelayEgress::DrainStatus(_) => {}
+        }
+
+        Ok(())
+    }
+
+    fn join(&mut self, conn: ConnectionId) {
+        if let Some(connection) = self.connections.get_mut(&conn) {
+            connection.joined = true;
+        }
+    }
+
+    /// The joined Gateways in any of the Sites of `resource`
+    fn gateways_for<'a>(
+        &'a self,
+        resource: &'a Resource,
+    ) -> impl Iterator<Item = (ConnectionId, OnlineGateway)> + 'a {
+        self.connections.iter().filter_map(|(conn, connection)| {
+            let Login::Gateway {
+                site,
+                id,
+                remote_ip,
+                ..
+            } = &connection.login
+            else {
+                return None;
+            };
+            if !connection.joined || !resource.sites.contains(site) {
+                return None;
+            }
+
+            Some((
+                *conn,
+                OnlineGateway {
+                    id: *id,
+                    remote_ip: *remote_ip,
+                    site_id: self.config.site(site)?.id(),
+                },
+            ))
+        })
+    }
+
+    fn forward_to_gateways(
+        &mut self,
+        client_id: ClientId,
+        gateway_ids: Vec<GatewayId>,
+        event: &str,
+        candidates: Vec<String>,
+    ) {
+        let gateway_ids = BTreeSet::from_iter(gateway_ids);
+        let conns = self
+            .joined(|login| matches!(login, Login::Gateway { id, .. } if gateway_ids.contains(id)));
+
+        for conn in conns {
+            let payload = ClientIceCandidates {
+                client_id,
+                candidates: candidates.clone(),
+            };
+            self.push(conn, "gateway", event, to_value(payload));
+        }
+    }
+
+    fn forward_to_clients(
+        &mut self,
+        gateway_id: GatewayId,
+        client_ids: Vec<ClientId>,
+        event: &str,
+        candidates: Vec<String>,
+    ) {
+        let client_ids = BTreeSet::from_iter(client_ids);
+        let conns = self
+            .joined(|login| matches!(login, Login::Client { id, .. } if client_ids.contains(id)));
+
+        for conn in conns {
+            let payload = GatewayIceCandidates {
+                gateway_id,
+                candidates: candidates.clone(),
+            };
+            self.push(conn, "client", event, to_value(payload));
+        }
+    }
+
+    /// Tells all Clients and Gateways about the relays, each with their own credentials
+    fn broadcast_relays_presence(&mut self, disconnected_ids: Vec<RelayId>, now: SystemTime) {
+        let conns = self.joined(|login| !matches!(login, Login::Relay { .. }));
+
+        for conn in conns {
+            let (topic, salt) = match &self.connections[&conn].login {
+                Login::Client { id, .. } => ("client", id.to_string()),
+                Login::Gateway { id, .. } => ("gateway", id.to_string()),
+                Login::Relay { .. } => continue,
+            };
+            let presence = RelaysPresence {
+                disconnected_ids: disconnected_ids.clone(),
+                connected: self.relays(&salt, now),
+            };
+
+            self.push(conn, topic, "relays_presence", to_value(presence));
+        }
+    }
+
+    /// TURN credentials for all joined relays, in the format that relays verify
+    fn relays(&self, salt: &str, now: SystemTime) -> Vec<Relay> {
+        let expires_at = (now + RELAY_CREDENTIALS_LIFETIME)
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let expiry = SystemTime::UNIX_EPOCH + Duration::from_secs(expires_at);
+
+        self.connections
+            .values()
+            .filter_map(|connection| {
+                let Login::Relay { id, addresses } = &connection.login else {
+                    return None;
+                };
+                let secret = connection.stamp_secret.as_ref()?;
+                let password = generate_password(secret, expiry, salt);
+
+                Some(addresses.iter().map(move |addr| Relay::Turn {
+                    id: *id,
+                    expires_at,
+                    addr: *addr,
+                    username: format!("{expires_at}:{salt}"),
+                    password: password.clone(),
+                }))
+            })
+            .flatten()
+            .collect()
+    }
+
+    fn joined(&self, filter: impl Fn(&Login) -> bool) -> Vec<ConnectionId> {
+        self.connections
+            .iter()
+            .filter(|(_, connection)| connection.joined && filter(&connection.login))
+            .map(|(conn, _)| *conn)
+            .collect()
+    }
+
+    fn interface(&self, ipv4: Ipv4Addr, ipv6: Ipv6Addr) -> Interface {
+        Interface {
+            ipv4,
+            ipv6,
+            upstream_dns: self
+                .config
+                .upstream_dns
+                .iter()
+                .map(|address| DnsServer::IpPort { address: *address })
+                .collect(),
+        }
+    }
+
+    fn push(&mut self, conn: ConnectionId, topic: &str, event: &str, payload: Value) {
+        self.transmits.push_back((
+            conn,
+            Outbound {
+                topic: topic.to_owned(),
+                event: event.to_owned(),
+                payload,
+                reference: None,
+            },
+        ));
+    }
+
+    fn reply_ok(
+        &mut self,
+        conn: ConnectionId,
+        topic: &str,
+        reference: Option<u64>,
+        response: impl Serialize,
+    ) {
+        self.reply(
+            conn,
+            topic,
+            reference,
+            json!({ "status": "ok", "response": to_value(response) }),
+        );
+    }
+
+    fn reply_error(
+        &mut self,
+        conn: ConnectionId,
+        topic: &str,
+        reference: Option<u64>,
+        reason: &str,
+    ) {
+        self.reply(
+            conn,
+            topic,
+            reference,
+            json!({ "status": "error", "response": { "reason": reason } }),
+        );
+    }
+
+    fn reply(&mut self, conn: ConnectionId, topic: &str, reference: Option<u64>, payload: Value) {
+        self.transmits.push_back((
+            conn,
+            Outbound {
+                topic: topic.to_owned(),
+                event: "phx_reply".to_owned(),
+                payload,
+                reference,
+            },
+        ));
+    }
+}
+
+/// Tunnel addresses of Clients and Gateways, stable across reconnects
+#[derive(Default)]
+struct TunnelAddresses(HashMap<String, (Ipv4Addr, Ipv6Addr)>);
+
+impl TunnelAddresses {
+    fn get(&mut self, id: String) -> (Ipv4Addr, Ipv6Addr) {
+        let next = self.0.len() as u32 + 1;
+
+        *self.0.entry(id).or_insert_with(|| {
+            (
+                Ipv4Addr::from(u32::from(IPV4_TUNNEL) + next),
+                Ipv6Addr::from(u128::from(IPV6_TUNNEL) + u128::from(next)),
+            )
+        })
+    }
+}
+
+struct OnlineGateway {
+    id: GatewayId,
+    remote_ip: IpAddr,
+    site_id: SiteId,
+}
+
+fn gateway_resource(resource: &Resource) -> GatewayResource<'_> {
+    GatewayResource {
+        id: resource.id(),
+        name: &resource.name,
+        kind: &resource.kind,
+        filters: &resource.filters,
+    }
+}
+
+fn to_value(payload: impl Serialize) -> Value {
+    serde_json::to_value(payload).expect("our messages always serialize to JSON")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = include_str!("../example.yaml");
+    const CLIENT: ConnectionId = ConnectionId(1);
+    const GATEWAY: ConnectionId = ConnectionId(2);
+    const RELAY: ConnectionId = ConnectionId(3);
+
+    #[test]
+    fn client_gets_allowed_resources_on_join() {
+        let mut portal = portal();
+        connect(
+            &mut portal,
+            CLIENT,
+            "/client/websocket",
+            "token=bob-client-token&external_id=bob-laptop&public_key=Y2xpZW50",
+        );
+
+        let transmits = join(&mut portal, CLIENT, "client");
+
+        assert_eq!(transmits[0].1.event, "phx_reply");
+        assert_eq!(transmits[1].1.event, "init");
+        let resources = transmits[1].1.payload["resources"].as_array().unwrap();
+        assert_eq!(resources.len(), 1);
+        assert_eq!(resources[0]["name"], "GitLab");
+        assert_eq!(resources[0]["type"], "dns");
+        assert_eq!(resources[0]["gateway_groups"][0]["name"], "office");
+        assert_eq!(
+            transmits[1].1.payload["interface"]["upstream_dns"][0]["address"],
+            "1.1.1.1:53"
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_tokens() {
+        let config = Config::parse(EXAMPLE).unwrap();
+        let remote_ip = IpAddr::from([10, 0, 0, 1]);
+
+        Login::authenticate(
+            &config,
+            "/client/websocket",
+            Some("token=office-gateway-token&external_id=a&public_key=b"),
+            remote_ip,
+        )
+        .unwrap_err();
+        Login::authenticate(&config, "/relay/websocket", Some("token=nope"), remote_ip)
+            .unwrap_err();
+    }
+
+    #[test]
+    fn offline_without_gateway() {
+        let mut portal = portal();
+        connect_alice(&mut portal);
+        let gitlab = resource_id(&portal, "GitLab");
+
+        let transmits = send(
+            &mut portal,
+            CLIENT,
+            json!({"topic": "client", "event": "prepare_connection", "payload": {"resource_id": gitlab, "connected_gateway_ids": []}, "ref": 7}),
+        );
+
+        assert_eq!(transmits[0].1.reference, Some(7));
+        assert_eq!(transmits[0].1.payload["status"], "error");
+        assert_eq!(transmits[0].1.payload["response"]["reason"], "offline");
+    }
+
+    #[test]
+    fn not_found_without_policy() {
+        let mut portal = portal();
+        connect(
+            &mut portal,
+            CLIENT,
+            "/client/websocket",
+            "token=bob-client-token&external_id=bob-laptop&public_key=Y2xpZW50",
+        );
+        join(&mut portal, CLIENT, "client");
+        let private_network = resource_id(&portal, "Private network");
+
+        let transmits = send(
+            &mut portal,
+            CLIENT,
+            json!({"topic": "client", "event": "prepare_connection", "payload": {"resource_id": private_network}, "ref": 1}),
+        );
+
+        assert_eq!(transmits[0].1.payload["response"]["reason"], "not_found");
+    }
+
+    #[test]
+    fn brokers_connection_between_client_and_gateway() {
+        let mut portal = portal();
+        connect_alice(&mut portal);
+        connect(
+            &mut portal,
+            GATEWAY,
+            "/gateway/websocket",
+            "token=office-gateway-token&external_id=office-1&public_key=Z2F0ZXdheQ%3D%3D",
+        );
+        join(&mut portal, GATEWAY, "gateway");
+        let gitlab = resource_id(&portal, "GitLab");
+
+        let transmits = send(
+            &mut portal,
+            CLIENT,
+            json!({"topic": "client", "event": "prepare_connection", "payload": {"resource_id": gitlab, "connected_gateway_ids": []}, "ref": 1}),
+        );
+        let details = &transmits[0].1.payload["response"];
+        assert_eq!(details["gateway_remote_ip"], "10.0.0.2");
+        let gateway_id = details["gateway_id"].clone();
+
+        let transmits = send(
+            &mut portal,
+            CLIENT,
+            json!({"topic": "client", "event": "request_connection", "payload": {
+                "gateway_id": gateway_id,
+                "resource_id": gitlab,
+                "client_preshared_key": "cHNr",
+                "client_payload": {"ice_parameters": {"username": "u", "password": "p"}, "domain": null}
+            }, "ref": 2}),
+        );
+        let (conn, request) = &transmits[0];
+        assert_eq!(*conn, GATEWAY);
+        assert_eq!(request.event, "request_connection");
+        assert_eq!(request.payload["client"]["peer"]["public_key"], "Y2xpZW50");
+        assert_eq!(request.payload["resource"]["filters"][0]["protocol"], "tcp");
+        let reference = request.payload["ref"].clone();
+
+        let transmits = send(
+            &mut portal,
+            GATEWAY,
+            json!({"topic": "gateway", "event": "connection_ready", "payload": {
+                "ref": reference,
+                "gateway_payload": {"ConnectionAccepted": {"ice_parameters": {"username": "gu", "password": "gp"}}}
+            }, "ref": 5}),
+        );
+        let (conn, reply) = &transmits[0];
+        assert_eq!(*conn, CLIENT);
+        assert_eq!(reply.reference, Some(2));
+        assert_eq!(
+            reply.payload["response"]["gateway_public_key"],
+            "Z2F0ZXdheQ=="
+        );
+        assert_eq!(reply.payload["response"]["resource_id"], json!(gitlab));
+    }
+
+    #[test]
+    fn relay_presence_is_pushed_with_valid_credentials() {
+        let mut portal = portal();
+        connect_alice(&mut portal);
+        connect(
+            &mut portal,
+            RELAY,
+            "/relay/websocket",
+            "token=relay-token&ipv4=10.0.0.3&port=3478",
+        );
+
+        let transmits = send(
+            &mut portal,
+            RELAY,
+            json!({"topic": "relay", "event": "phx_join", "payload": {"stamp_secret": "secret"}, "ref": 1}),
+        );
+
+        let (conn, presence) = &transmits[2];
+        assert_eq!(*conn, CLIENT);
+        assert_eq!(presence.event, "relays_presence");
+        let relay = &presence.payload["connected"][0];
+        assert_eq!(relay["addr"], "10.0.0.3:3478");
+        let expires_at = relay["expires_at"].as_u64().unwrap();
+        let (expiry, salt) = relay["username"].as_str().unwrap().split_once(':').unwrap();
+        assert_eq!(expiry, expires_at.to_string());
+        assert_eq!(
+            relay["password"],
+            generate_password(
+                &SecretString::new("secret".to_owned()),
+                SystemTime::UNIX_EPOCH + Duration::from_secs(expires_at),
+                salt
+            )
+        );
+
+        portal.disconnect(RELAY, SystemTime::now());
+        let (_, presence) = portal.poll_transmit().unwrap();
+        assert_eq!(presence.payload["disconnected_ids"][0], relay["id"]);
+        assert!(presence.payload["connected"].as_array().unwrap().is_empty());
+    }
+
+    #[test]
+    fn client_and_gateway_messages_match_connlib() {
+        use firezone_tunnel::messages::{
+            client, gateway, Answer, ClientPayload, ConnectionAccepted, GatewayResponse, Key,
+            Offer, RequestConnection,
+        };
+
+        let mut portal = portal();
+        connect(
+            &mut portal,
+            CLIENT,
+            "/client/websocket",
+            &format!(
+                "token=alice-client-token&external_id=alice-laptop&public_key={}",
+                public_key(1)
+            ),
+        );
+        let transmits = join(&mut portal, CLIENT, "client");
+        assert!(matches!(
+            parse(event(&transmits, CLIENT, "init")),
+            client::IngressMessages::Init(_)
+        ));
+
+        connect(
+            &mut portal,
+            GATEWAY,
+            "/gateway/websocket",
+            &format!(
+                "token=office-gateway-token&external_id=office-1&public_key={}",
+                public_key(2)
+            ),
+        );
+        let transmits = join(&mut portal, GATEWAY, "gateway");
+        assert!(matches!(
+            parse(event(&transmits, GATEWAY, "init")),
+            gateway::IngressMessages::Init(_)
+        ));
+
+        let gitlab = resource_id(&portal, "GitLab");
+        let transmits = send_egress(
+            &mut portal,
+            CLIENT,
+            "client",
+            client::EgressMessages::PrepareConnection {
+                resource_id: gitlab,
+                connected_gateway_ids: BTreeSet::new(),
+            },
+        );
+        let client::ReplyMessages::ConnectionDetails(details) = parse_reply(&transmits[0].1) else {
+            panic!("Expected connection details");
+        };
+
+        let transmits = send_egress(
+            &mut portal,
+            CLIENT,
+            "client",
+            client::EgressMessages::RequestConnection(RequestConnection {
+                gateway_id: details.gateway_id,
+                resource_id: gitlab,
+                client_preshared_key: secrecy::Secret::new(Key([3; 32])),
+                client_payload: ClientPayload {
+                    ice_parameters: Offer {
+                        username: "u".to_owned(),
+                        password: "p".to_owned(),
+                    },
+                    domain: None,
+                },
+            }),
+        );
+        let gateway::IngressMessages::RequestConnection(request) =
+            parse(event(&transmits, GATEWAY, "request_connection"))
+        else {
+            panic!("Expected a connection request");
+        };
+        assert_eq!(request.client.peer.public_key, Key([1; 32]));
+
+        let transmits = send_egress(
+            &mut portal,
+            GATEWAY,
+            "gateway",
+            gateway::EgressMessages::ConnectionReady(gateway::ConnectionReady {
+                reference: request.reference,
+                gateway_payload: GatewayResponse::ConnectionAccepted(ConnectionAccepted {
+                    ice_parameters: Answer {
+                        username: "gu".to_owned(),
+                        password: "gp".to_owned(),
+                    },
+                }),
+            }),
+        );
+        let client::ReplyMessages::Connect(connect) = parse_reply(&transmits[0].1) else {
+            panic!("Expected the Gateway's answer");
+        };
+        assert_eq!(connect.resource_id, gitlab);
+        assert_eq!(connect.gateway_public_key, Key([2; 32]));
+    }
+
+    #[test]
+    fn relay_messages_match_the_relay() {
+        use firezone_relay::messages::{DrainStatus, EgressMessage, IngressMessage, JoinMessage};
+        use firezone_relay::UsageReport;
+        use firezone_tunnel::messages::client;
+
+        let mut portal = portal();
+        connect_alice(&mut portal);
+        connect(
+            &mut portal,
+            RELAY,
+            "/relay/websocket",
+            "token=relay-token&ipv4=10.0.0.3&port=3478",
+        );
+
+        let join = JoinMessage {
+            stamp_secret: "secret".to_owned(),
+        };
+        let transmits = send(
+            &mut portal,
+            RELAY,
+            json!({"topic": "relay", "event": "phx_join", "payload": join, "ref": 1}),
+        );
+        assert!(matches!(
+            parse(event(&transmits, RELAY, "init")),
+            IngressMessage::Init(_)
+        ));
+        assert!(matches!(
+            parse(event(&transmits, CLIENT, "relays_presence")),
+            client::IngressMessages::RelaysPresence(_)
+        ));
+
+        send_egress(
+            &mut portal,
+            RELAY,
+            "relay",
+            EgressMessage::Usage(UsageReport::default()),
+        );
+        send_egress(
+            &mut portal,
+            RELAY,
+            "relay",
+            EgressMessage::DrainStatus(DrainStatus {
+                remaining_allocations: 1,
+            }),
+        );
+    }
+
+    fn portal() -> Portal {
+        Portal::new(Arc::new(Config::parse(EXAMPLE).unwrap()))
+    }
+
+    fn connect(portal: &mut Portal, conn: ConnectionId, path: &str, query: &str) {
+        let login = Login::authenticate(
+            portal.config(),
+            path,
+            Some(query),
+            IpAddr::from([10, 0, 0, conn.0 as u8]),
+        )
+        .unwrap();
+
+        portal.connect(conn, login);
+    }
+
+    fn connect_alice(portal: &mut Portal) {
+        connect(
+            portal,
+            CLIENT,
+            "/client/websocket",
+            "token=alice-client-token&external_id=alice-laptop&public_key=Y2xpZW50",
+        );
+        join(portal, CLIENT, "client");
+    }
+
+    fn join(portal: &mut Portal, conn: ConnectionId, topic: &str) -> Vec<(ConnectionId, Outbound)> {
+        send(
+            portal,
+            conn,
+            json!({"topic": topic, "event": "phx_join", "payload": {}, "ref": 0}),
+        )
+    }
+
+    fn send(portal: &mut Portal, conn: ConnectionId, msg: Value) -> Vec<(ConnectionId, Outbound)> {
+        portal
+            .handle_message(conn, &msg.to_string(), SystemTime::now())
+            .unwrap();
+
+        std::iter::from_fn(|| portal.poll_transmit()).collect()
+    }
+
+    /// Sends a message the way connlib or the relay would, i.e. serialized from their own types.
+    fn send_egress(
+        portal: &mut Portal,
+        conn: ConnectionId,
+        topic: &str,
+        msg: impl Serialize,
+    ) -> Vec<(ConnectionId, Outbound)> {
+        let mut msg = serde_json::to_value(msg).unwrap();
+        msg["topic"] = json!(topic);
+        msg["ref"] = json!(1);
+
+        send(portal, conn, msg)
+    }
+
+    fn event<'a>(
+        transmits: &'a [(ConnectionId, Outbound)],
+        conn: ConnectionId,
+        event: &str,
+    ) -> &'a Outbound {
+        transmits
+            .iter()
+            .find(|(c, msg)| *c == conn && msg.event == event)
+            .map(|(_, msg)| msg)
+            .unwrap()
+    }
+
+    /// Parses a push like connlib or the relay would.
+    fn parse<T: serde::de::DeserializeOwned>(msg: &Outbound) -> T {
+        serde_json::from_value(json!({"event": msg.event, "payload": msg.payload})).unwrap()
+    }
+
+    fn parse_reply(msg: &Outbound) -> firezone_tunnel::messages::client::ReplyMessages {
+        assert_eq!(msg.payload["status"], "ok", "{msg:?}");
+
+        serde_json::from_value(msg.payload["response"].clone()).unwrap()
+    }
+
+    fn public_key(byte: u8) -> String {
+        firezone_tunnel::messages::Key([byte; 32])
+            .to_string()
+            .replace('=', "%3D")
+    }
+
+    fn resource_id(portal: &Portal, name: &str) -> ResourceId {
+        portal
+            .config()
+            .resources
+            .iter()
+            .find(|resource| resource.name == name)
+            .unwrap()
+            .id()
+    }
+}
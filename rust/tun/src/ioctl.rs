@@ -40,6 +40,12 @@ impl Request<SetTunFlagsPayload> {
             },
         }
     }
+
+    /// Prefixes every packet with a `virtio_net_hdr`, required for offloads like TSO.
+    pub fn with_vnet_hdr(mut self) -> Self {
+        self.payload.flags |= libc::IFF_VNET_HDR as std::ffi::c_short;
+        self
+    }
 }
 
 impl Request<GetInterfaceNamePayload> {
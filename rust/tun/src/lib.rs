@@ -7,10 +7,18 @@ use std::{
 pub mod ioctl;
 #[cfg(target_family = "unix")]
 pub mod unix;
+pub mod vnet;
 
 pub trait Tun: Send + Sync + 'static {
     fn write4(&self, buf: &[u8]) -> io::Result<usize>;
     fn write6(&self, buf: &[u8]) -> io::Result<usize>;
     fn poll_read(&mut self, buf: &mut [u8], cx: &mut Context<'_>) -> Poll<io::Result<usize>>;
     fn name(&self) -> &str;
+
+    /// Whether this device was opened with `IFF_VNET_HDR`.
+    ///
+    /// If so, every buffer read from or written to it starts with a [`vnet::Header`] and may be a GSO batch much larger than the MTU.
+    fn vnet_hdr(&self) -> bool {
+        false
+    }
 }
@@ -0,0 +1,54 @@
+//! The `virtio_net_hdr` that prefixes every packet on a TUN device opened with `IFF_VNET_HDR`.
+//!
+//! See `include/uapi/linux/virtio_net.h` in the Linux kernel.
+
+/// The size of the header on the wire.
+pub const HEADER_LEN: usize = 10;
+
+pub const FLAG_NEEDS_CSUM: u8 = 1;
+
+pub const GSO_NONE: u8 = 0;
+pub const GSO_TCPV4: u8 = 1;
+pub const GSO_UDP: u8 = 3;
+pub const GSO_TCPV6: u8 = 4;
+pub const GSO_UDP_L4: u8 = 5;
+pub const GSO_ECN: u8 = 0x80;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub flags: u8,
+    pub gso_type: u8,
+    /// Length of the IP and transport headers.
+    pub hdr_len: u16,
+    /// Size of each segment's payload when splitting a GSO packet.
+    pub gso_size: u16,
+    pub csum_start: u16,
+    pub csum_offset: u16,
+}
+
+impl Header {
+    /// Parses the header from the start of `buf`.
+    ///
+    /// Legacy virtio headers, as used by TUN devices, are in native endianness.
+    pub fn parse(buf: &[u8]) -> Option<Self> {
+        let buf = buf.get(..HEADER_LEN)?;
+
+        Some(Self {
+            flags: buf[0],
+            gso_type: buf[1],
+            hdr_len: u16::from_ne_bytes([buf[2], buf[3]]),
+            gso_size: u16::from_ne_bytes([buf[4], buf[5]]),
+            csum_start: u16::from_ne_bytes([buf[6], buf[7]]),
+            csum_offset: u16::from_ne_bytes([buf[8], buf[9]]),
+        })
+    }
+
+    pub fn write_to(&self, buf: &mut [u8; HEADER_LEN]) {
+        buf[0] = self.flags;
+        buf[1] = self.gso_type;
+        buf[2..4].copy_from_slice(&self.hdr_len.to_ne_bytes());
+        buf[4..6].copy_from_slice(&self.gso_size.to_ne_bytes());
+        buf[6..8].copy_from_slice(&self.csum_start.to_ne_bytes());
+        buf[8..10].copy_from_slice(&self.csum_offset.to_ne_bytes());
+    }
+}
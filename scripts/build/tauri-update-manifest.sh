@@ -0,0 +1,59 @@
+#!/usr/bin/env bash
+#
+# Generates and signs the update manifest for a GUI Client release.
+# The manifest format and signature are verified by `rust/gui-client/src-common/src/updates.rs`.
+#
+# Needs `TAG_NAME`, `VERSION`, `REPOSITORY` and `MINISIGN_SECRET_KEY_FILE`.
+# `CHANNEL` is `stable` or `beta`, and defaults to `stable`. Each run publishes the manifest of one channel,
+# `.github/workflows/_tauri.yml` runs this once per channel.
+
+set -euox pipefail
+
+channel="${CHANNEL:-stable}"
+manifest="manifest-$channel.json"
+
+dir=$(mktemp -d)
+trap 'rm -rf "$dir"' EXIT
+
+# Only clobber existing release assets if the release is a draft
+is_draft=$(gh release view "$TAG_NAME" --json isDraft --jq '.isDraft' | tr -d '\n')
+if [[ "$is_draft" == "true" ]]; then
+    clobber="--clobber"
+else
+    clobber=""
+fi
+
+gh release download "$TAG_NAME" \
+    --pattern "*.sha256sum.txt" \
+    --dir "$dir" \
+    --repo "$REPOSITORY"
+
+artifacts=()
+for platform in "linux x86_64 deb" "linux aarch64 deb" "windows x86_64 msi"; do
+    read -r os arch ext <<<"$platform"
+    name="firezone-client-gui-${os}_${VERSION}_${arch}.${ext}"
+    sha256=$(cut -d ' ' -f 1 "$dir/$name.sha256sum.txt")
+    url="https://www.github.com/$REPOSITORY/releases/download/$TAG_NAME/$name"
+
+    artifacts+=("$(jq -n \
+        --arg key "$os-$arch" \
+        --arg url "$url" \
+        --arg sha256 "$sha256" \
+        '{($key): {url: $url, sha256: $sha256}}')")
+done
+
+printf '%s\n' "${artifacts[@]}" | jq -s \
+    --arg version "$VERSION" \
+    --arg channel "$channel" \
+    '{version: $version, channel: $channel, artifacts: add}' >"$dir/$manifest"
+
+minisign -S \
+    -s "$MINISIGN_SECRET_KEY_FILE" \
+    -m "$dir/$manifest" \
+    -t "firezone-client-gui $VERSION $channel"
+
+gh release upload "$TAG_NAME" \
+    "$dir/$manifest" \
+    "$dir/$manifest.minisig" \
+    $clobber \
+    --repo "$REPOSITORY"
@@ -13,6 +13,42 @@ module.exports = [
       "https://www.github.com/firezone/firezone/releases/download/gui-client-1.3.13/firezone-client-gui-windows_1.3.13_x86_64.msi",
     permanent: false,
   },
+  /*
+   *
+   * GUI Client update manifests, signed with minisign
+   *
+   * Stable releases publish both manifests, so beta follows stable until we publish a pre-release.
+   * `make gui-version` points these at each new release via the `mark:current-gui-version` markers.
+   *
+   */
+  {
+    source: "/dl/firezone-client-gui/stable/manifest.json",
+    destination:
+      // mark:current-gui-version
+      "https://www.github.com/firezone/firezone/releases/download/gui-client-1.3.13/manifest-stable.json",
+    permanent: false,
+  },
+  {
+    source: "/dl/firezone-client-gui/stable/manifest.json.minisig",
+    destination:
+      // mark:current-gui-version
+      "https://www.github.com/firezone/firezone/releases/download/gui-client-1.3.13/manifest-stable.json.minisig",
+    permanent: false,
+  },
+  {
+    source: "/dl/firezone-client-gui/beta/manifest.json",
+    destination:
+      // mark:current-gui-version
+      "https://www.github.com/firezone/firezone/releases/download/gui-client-1.3.13/manifest-beta.json",
+    permanent: false,
+  },
+  {
+    source: "/dl/firezone-client-gui/beta/manifest.json.minisig",
+    destination:
+      // mark:current-gui-version
+      "https://www.github.com/firezone/firezone/releases/download/gui-client-1.3.13/manifest-beta.json.minisig",
+    permanent: false,
+  },
   /*
    *
    * Linux Clients
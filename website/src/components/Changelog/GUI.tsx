@@ -14,7 +14,40 @@ export default function GUI({ title }: { title: string }) {
   return (
     <Entries href={href} arches={arches} title={title}>
       {/* When you cut a release, remove any solved issues from the "known issues" lists over in `client-apps`. This must not be done when the issue's PR merges. */}
-      <Unreleased></Unreleased>
+      <Unreleased>
+        <ChangeItem>
+          Adds a proxy URL to the advanced settings for connecting to the portal
+          through HTTP and SOCKS5 proxies. Without it, the IPC service uses
+          `HTTPS_PROXY` / `ALL_PROXY` from its environment.
+        </ChangeItem>
+        <ChangeItem>
+          Allows trusting a custom CA or pinning the portal's public key via
+          `portal_tls` in the advanced settings file.
+        </ChangeItem>
+        <ChangeItem>
+          Keeps messages to the portal queued across reconnects instead of
+          dropping them, e.g. ICE candidates during a brief portal outage.
+        </ChangeItem>
+        <ChangeItem>
+          Only applies the resources and routes that changed when the portal
+          pushes a configuration update, avoiding stalls on large accounts.
+        </ChangeItem>
+        <ChangeItem>
+          Redacts IP addresses, domain names and device identifiers before
+          sending errors to Sentry.
+        </ChangeItem>
+        <ChangeItem>
+          Allows sending error reports to a self-hosted Sentry or GlitchTip
+          instance, or to a local file, via the `telemetry` advanced setting.
+          The IPC service uses the same overrides but only writes to a file
+          set via its own `FIREZONE_TELEMETRY_FILE` environment variable.
+        </ChangeItem>
+        <ChangeItem>
+          Only notifies about updates described by a signed release manifest,
+          and verifies the installer's hash before opening it. Adds a beta
+          update channel to the advanced settings.
+        </ChangeItem>
+      </Unreleased>
       <Entry version="1.3.13" date={new Date("2024-11-15")}>
         <ChangeItem pull="7334">
           Fixes an issue where symmetric NATs would generate unnecessary
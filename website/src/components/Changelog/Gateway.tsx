@@ -10,7 +10,63 @@ export default function Gateway() {
 
   return (
     <Entries href={href} arches={arches} title="Gateway">
-      <Unreleased></Unreleased>
+      <Unreleased>
+        <ChangeItem>
+          Adds an experimental `--portal-compression` flag to compress the
+          portal connection with `permessage-deflate`.
+        </ChangeItem>
+        <ChangeItem>
+          Connects to the portal through HTTP and SOCKS5 proxies from
+          `--proxy` or `HTTPS_PROXY` / `ALL_PROXY`.
+        </ChangeItem>
+        <ChangeItem>
+          Adds `--token-stdin` and `--token-command` to read the token from
+          stdin or a secrets manager.
+        </ChangeItem>
+        <ChangeItem>
+          Reloads `RUST_LOG`, `FIREZONE_TOKEN` and `HEALTH_CHECK_ADDR` from
+          `FIREZONE_CONFIG_FILE` on SIGHUP.
+        </ChangeItem>
+        <ChangeItem>
+          Batches outgoing packets using UDP segmentation offload (GSO) where
+          supported, reducing CPU usage for bulk transfers.
+        </ChangeItem>
+        <ChangeItem>
+          Adds `FIREZONE_TUN_OFFLOAD` to enable TSO / USO on the TUN device for
+          higher throughput.
+        </ChangeItem>
+        <ChangeItem>
+          Adds `--shards` to process packets of different Clients on multiple
+          CPU cores.
+        </ChangeItem>
+        <ChangeItem>
+          Connects to the portal over IPv4 and IPv6 in parallel, avoiding long
+          delays when one of them is broken.
+        </ChangeItem>
+        <ChangeItem>
+          Adds `--portal-ca-file`, `--portal-ca-only` and `--portal-pin-sha256`
+          to trust a custom CA or pin the portal's public key.
+        </ChangeItem>
+        <ChangeItem>
+          Keeps messages to the portal queued across reconnects instead of
+          dropping them, e.g. ICE candidates during a brief portal outage.
+        </ChangeItem>
+        <ChangeItem>
+          Redacts IP addresses, domain names and device identifiers before
+          sending errors to Sentry.
+        </ChangeItem>
+        <ChangeItem>
+          Adds `--telemetry-dsn`, `--telemetry-environment`,
+          `--telemetry-sample-rate`, `--telemetry-traces-sample-rate` and
+          `--telemetry-file` to send error reports to a self-hosted Sentry or
+          GlitchTip instance, or to a local file.
+        </ChangeItem>
+        <ChangeItem>
+          Adds an `export-logs` subcommand that writes the journal, the running
+          Gateway's sanitised config and routes to a zip file for support
+          requests.
+        </ChangeItem>
+      </Unreleased>
       <Entry version="1.4.1" date={new Date("2024-11-15")}>
         <ChangeItem pull="7263">
           Mitigates a crash in case the maximum packet size is not respected.
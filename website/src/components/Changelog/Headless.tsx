@@ -12,9 +12,68 @@ export default function Headless() {
     <Entries href={href} arches={arches} title="Linux headless">
       {/* When you cut a release, remove any solved issues from the "known issues" lists over in `client-apps`. This must not be done when the issue's PR merges. */}
       <Unreleased>
+        <ChangeItem>
+          Adds an experimental `--portal-compression` flag to compress the
+          portal connection with `permessage-deflate`.
+        </ChangeItem>
         <ChangeItem pull="7350">
           Allows disabling telemetry by setting `FIREZONE_NO_TELEMETRY=true`.
         </ChangeItem>
+        <ChangeItem>
+          Adds `--status json` and `--status json-lines` to print the tunnel's
+          status and Resources as machine-readable JSON. Use `--wait-for` to
+          only print the status once the given Resources are online and
+          `--wait-for-timeout` to give up after a while.
+        </ChangeItem>
+        <ChangeItem>
+          Adds `--token-stdin` and `--token-command` to read the token from
+          stdin or a secrets manager.
+        </ChangeItem>
+        <ChangeItem>
+          Batches outgoing packets using UDP segmentation offload (GSO) where
+          supported, reducing CPU usage for bulk transfers.
+        </ChangeItem>
+        <ChangeItem>
+          Connects to the portal over IPv4 and IPv6 in parallel, avoiding long
+          delays when one of them is broken.
+        </ChangeItem>
+        <ChangeItem>
+          Connects to the portal through HTTP and SOCKS5 proxies, configured via
+          `--proxy` or `HTTPS_PROXY` / `ALL_PROXY`.
+        </ChangeItem>
+        <ChangeItem>
+          Adds `--portal-ca-file`, `--portal-ca-only` and `--portal-pin-sha256`
+          to trust a custom CA or pin the portal's public key.
+        </ChangeItem>
+        <ChangeItem>
+          Keeps messages to the portal queued across reconnects instead of
+          dropping them, e.g. ICE candidates during a brief portal outage.
+        </ChangeItem>
+        <ChangeItem>
+          Only applies the resources and routes that changed when the portal
+          pushes a configuration update, avoiding stalls on large accounts.
+        </ChangeItem>
+        <ChangeItem>
+          Adds opt-in rotation, retention and compression of log files via
+          `--log-max-file-size-mib`, `--log-max-file-age`,
+          `--log-max-total-size-mib` and `--log-compress`.
+        </ChangeItem>
+        <ChangeItem>
+          Redacts IP addresses, domain names and device identifiers before
+          sending errors to Sentry. Set `FIREZONE_REDACT_LOGS=true` to also
+          redact them in log files.
+        </ChangeItem>
+        <ChangeItem>
+          Adds `--telemetry-dsn`, `--telemetry-environment`,
+          `--telemetry-sample-rate`, `--telemetry-traces-sample-rate` and
+          `--telemetry-file` to send error reports to a self-hosted Sentry or
+          GlitchTip instance, or to a local file.
+        </ChangeItem>
+        <ChangeItem>
+          Adds an `export-logs` subcommand that writes the logs and sanitised
+          config of the running Client, routes and DNS state to a zip file for
+          support requests.
+        </ChangeItem>
       </Unreleased>
       <Entry version="1.3.7" date={new Date("2024-11-15")}>
         <ChangeItem pull="7334">